@@ -0,0 +1,450 @@
+// One step of an edit script turning `a` into `b`. Indices refer to lines of `a` and `b`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Edit {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+// A group of nearby changes with their surrounding context lines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+    pub edits: Vec<Edit>,
+}
+
+impl Hunk {
+    // The "@@ -l,s +l,s @@" line, where a length of one is left out as git does.
+    pub fn header(&self) -> String {
+        let range = |start: usize, len: usize| {
+            if len == 1 {
+                format!("{}", start)
+            } else {
+                format!("{},{}", start, len)
+            }
+        };
+        format!(
+            "@@ -{} +{} @@",
+            range(self.old_start, self.old_len),
+            range(self.new_start, self.new_len)
+        )
+    }
+
+    // Splits the hunk at its unchanged lines, one hunk per run of changes. The context between
+    // two runs goes to both of them.
+    pub fn split(&self) -> Vec<Hunk> {
+        let mut runs = Vec::new();
+        for (i, edit) in self.edits.iter().enumerate() {
+            let change = !matches!(edit, Edit::Equal(..));
+            match runs.last_mut() {
+                Some((_, end)) if change && *end == i => *end = i + 1,
+                _ if change => runs.push((i, i + 1)),
+                _ => {}
+            }
+        }
+        if runs.len() < 2 {
+            return vec![self.clone()];
+        }
+        let old_before = self.old_start - usize::from(self.old_len > 0);
+        let new_before = self.new_start - usize::from(self.new_len > 0);
+        (0..runs.len())
+            .map(|r| {
+                let start = if r == 0 { 0 } else { runs[r - 1].1 };
+                let end = runs.get(r + 1).map_or(self.edits.len(), |run| run.0);
+                let before = &self.edits[..start];
+                let old = before
+                    .iter()
+                    .filter(|edit| !matches!(edit, Edit::Insert(_)))
+                    .count();
+                let new = before
+                    .iter()
+                    .filter(|edit| !matches!(edit, Edit::Delete(_)))
+                    .count();
+                make_hunk(&self.edits[start..end], old_before + old, new_before + new)
+            })
+            .collect()
+    }
+
+    // Renders the hunk's lines with their ' ', '-' and '+' prefixes.
+    pub fn render(&self, a: &[&[u8]], b: &[&[u8]], out: &mut Vec<u8>) {
+        for edit in &self.edits {
+            let (prefix, line) = match *edit {
+                Edit::Equal(i, _) => (b' ', a[i]),
+                Edit::Delete(i) => (b'-', a[i]),
+                Edit::Insert(j) => (b'+', b[j]),
+            };
+            out.push(prefix);
+            out.extend(line);
+            if !line.ends_with(b"\n") {
+                out.extend(b"\n\\ No newline at end of file\n");
+            }
+        }
+    }
+}
+
+// Splits data into lines, each keeping its trailing newline.
+pub fn split_lines(data: &[u8]) -> Vec<&[u8]> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    for (i, b) in data.iter().enumerate() {
+        if *b == b'\n' {
+            lines.push(&data[start..=i]);
+            start = i + 1;
+        }
+    }
+    if start < data.len() {
+        lines.push(&data[start..]);
+    }
+    lines
+}
+
+// Git treats content with a NUL byte in its first 8000 bytes as binary.
+pub fn is_binary(data: &[u8]) -> bool {
+    data.iter().take(8000).any(|b| *b == 0)
+}
+
+// Computes a shortest edit script with Myers' O((N+M)D) algorithm, in linear space: the middle
+// snake of the script is found searching from both ends at once, and the parts before and
+// after it are diffed the same way.
+pub fn myers<T: PartialEq>(a: &[T], b: &[T]) -> Vec<Edit> {
+    let mut edits = Vec::with_capacity(a.len().max(b.len()));
+    diff_range(a, b, 0, 0, &mut edits);
+    // Within a run of changes deletions come first, as git shows them.
+    let mut start = 0;
+    while start < edits.len() {
+        if matches!(edits[start], Edit::Equal(..)) {
+            start += 1;
+            continue;
+        }
+        let end = edits[start..]
+            .iter()
+            .position(|edit| matches!(edit, Edit::Equal(..)))
+            .map_or(edits.len(), |i| start + i);
+        edits[start..end].sort_by_key(|edit| matches!(edit, Edit::Insert(_)));
+        start = end;
+    }
+    edits
+}
+
+// Appends the edits turning `a` into `b`, which start at lines `a_off` and `b_off`.
+fn diff_range<T: PartialEq>(a: &[T], b: &[T], a_off: usize, b_off: usize, out: &mut Vec<Edit>) {
+    // Common prefixes and suffixes never need the search, so strip them first.
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    out.extend((0..prefix).map(|i| Edit::Equal(a_off + i, b_off + i)));
+    let (a_mid, b_mid) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+    let (a_start, b_start) = (a_off + prefix, b_off + prefix);
+
+    if a_mid.is_empty() {
+        out.extend((0..b_mid.len()).map(|j| Edit::Insert(b_start + j)));
+    } else if b_mid.is_empty() {
+        out.extend((0..a_mid.len()).map(|i| Edit::Delete(a_start + i)));
+    } else {
+        let ((x0, y0), (x1, y1)) = middle_snake(a_mid, b_mid);
+        diff_range(&a_mid[..x0], &b_mid[..y0], a_start, b_start, out);
+        diff_range(
+            &a_mid[x0..x1],
+            &b_mid[y0..y1],
+            a_start + x0,
+            b_start + y0,
+            out,
+        );
+        diff_range(&a_mid[x1..], &b_mid[y1..], a_start + x1, b_start + y1, out);
+    }
+
+    let (a_end, b_end) = (a_off + a.len() - suffix, b_off + b.len() - suffix);
+    out.extend((0..suffix).map(|s| Edit::Equal(a_end + s, b_end + s)));
+}
+
+// The middle snake of a shortest edit script between two sequences that differ: the point
+// where a forward and a backward search meet, as a start and an end point. Between them lies
+// at most one insertion or deletion and a run of equal lines.
+fn middle_snake<T: PartialEq>(a: &[T], b: &[T]) -> ((usize, usize), (usize, usize)) {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let delta = n - m;
+    let max = (n + m + 1) / 2;
+    let index = |k: isize| (k + max + 1) as usize;
+    // The furthest x reached on each diagonal k = x - y going forward, and the furthest y
+    // reached on each diagonal c = k - delta going backward.
+    let mut forward = vec![0isize; 2 * max as usize + 3];
+    let mut backward = vec![0isize; 2 * max as usize + 3];
+    backward[index(1)] = m;
+    for d in 0..=max {
+        for k in (-d..=d).rev().step_by(2) {
+            let (px, mut x) =
+                if k == -d || (k != d && forward[index(k - 1)] < forward[index(k + 1)]) {
+                    (forward[index(k + 1)], forward[index(k + 1)])
+                } else {
+                    (forward[index(k - 1)], forward[index(k - 1)] + 1)
+                };
+            let mut y = x - k;
+            let py = if d == 0 || x != px { y } else { y - 1 };
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            forward[index(k)] = x;
+            let c = k - delta;
+            if delta % 2 != 0 && (-(d - 1)..=d - 1).contains(&c) && y >= backward[index(c)] {
+                return ((px as usize, py as usize), (x as usize, y as usize));
+            }
+        }
+        for c in (-d..=d).rev().step_by(2) {
+            let (py, mut y) =
+                if c == -d || (c != d && backward[index(c - 1)] > backward[index(c + 1)]) {
+                    (backward[index(c + 1)], backward[index(c + 1)])
+                } else {
+                    (backward[index(c - 1)], backward[index(c - 1)] - 1)
+                };
+            let k = c + delta;
+            let mut x = y + k;
+            let px = if d == 0 || y != py { x } else { x + 1 };
+            while x > 0 && y > 0 && a[(x - 1) as usize] == b[(y - 1) as usize] {
+                x -= 1;
+                y -= 1;
+            }
+            backward[index(c)] = y;
+            if delta % 2 == 0 && (-d..=d).contains(&k) && x <= forward[index(k)] {
+                return ((x as usize, y as usize), (px as usize, py as usize));
+            }
+        }
+    }
+    unreachable!("the searches meet by the middle of the edit script")
+}
+
+// Groups an edit script into hunks with `context` unchanged lines around each change.
+pub fn hunks(edits: &[Edit], context: usize) -> Vec<Hunk> {
+    // Lines of a and b that precede each edit.
+    let mut positions = Vec::with_capacity(edits.len() + 1);
+    let (mut old, mut new) = (0, 0);
+    for edit in edits {
+        positions.push((old, new));
+        match edit {
+            Edit::Equal(..) => {
+                old += 1;
+                new += 1;
+            }
+            Edit::Delete(_) => old += 1,
+            Edit::Insert(_) => new += 1,
+        }
+    }
+    positions.push((old, new));
+
+    let changes: Vec<usize> = edits
+        .iter()
+        .enumerate()
+        .filter(|(_, edit)| !matches!(edit, Edit::Equal(..)))
+        .map(|(i, _)| i)
+        .collect();
+
+    let mut result = Vec::new();
+    let mut i = 0;
+    while i < changes.len() {
+        let first = changes[i];
+        let mut last = first;
+        while i + 1 < changes.len() && changes[i + 1] - last <= 2 * context + 1 {
+            i += 1;
+            last = changes[i];
+        }
+        i += 1;
+
+        let start = first.saturating_sub(context);
+        let end = (last + context + 1).min(edits.len());
+        let (old_before, new_before) = positions[start];
+        result.push(make_hunk(&edits[start..end], old_before, new_before));
+    }
+    result
+}
+
+// A hunk of `edits`, preceded by `old_before` and `new_before` lines of a and b.
+fn make_hunk(edits: &[Edit], old_before: usize, new_before: usize) -> Hunk {
+    let old_len = edits
+        .iter()
+        .filter(|edit| !matches!(edit, Edit::Insert(_)))
+        .count();
+    let new_len = edits
+        .iter()
+        .filter(|edit| !matches!(edit, Edit::Delete(_)))
+        .count();
+    Hunk {
+        old_start: if old_len == 0 {
+            old_before
+        } else {
+            old_before + 1
+        },
+        old_len,
+        new_start: if new_len == 0 {
+            new_before
+        } else {
+            new_before + 1
+        },
+        new_len,
+        edits: edits.to_vec(),
+    }
+}
+
+// Renders the hunks of a unified diff between two buffers, without any file headers.
+pub fn unified(a: &[u8], b: &[u8], context: usize) -> Vec<u8> {
+    let (a_lines, b_lines) = (split_lines(a), split_lines(b));
+    let mut out = Vec::new();
+    for hunk in hunks(&myers(&a_lines, &b_lines), context) {
+        write_hunk(&hunk, &a_lines, &b_lines, &mut out);
+    }
+    out
+}
+
+// Renders a hunk with its header, which carries the function context git shows after it.
+pub fn write_hunk(hunk: &Hunk, a: &[&[u8]], b: &[&[u8]], out: &mut Vec<u8>) {
+    out.extend(hunk.header().as_bytes());
+    if let Some(line) = function_context(a, hunk.old_start) {
+        out.push(b' ');
+        out.extend(line);
+    }
+    out.push(b'\n');
+    hunk.render(a, b, out);
+}
+
+// How `merge` writes out conflicts: "merge" shows both sides, "diff3" also shows the base.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictStyle {
+    Merge,
+    Diff3,
+}
+
+impl ConflictStyle {
+    pub fn parse(style: &str) -> Option<ConflictStyle> {
+        match style {
+            "merge" => Some(ConflictStyle::Merge),
+            "diff3" => Some(ConflictStyle::Diff3),
+            _ => None,
+        }
+    }
+}
+
+// The names written after the conflict markers of each side.
+#[derive(Debug, Clone, Copy)]
+pub struct MergeLabels<'a> {
+    pub ours: &'a str,
+    pub base: &'a str,
+    pub theirs: &'a str,
+}
+
+// Merges line by line the changes made from `base` to `ours` and from `base` to `theirs`.
+// Returns the merged content and how many conflicts are marked in it.
+pub fn merge(
+    base: &[u8],
+    ours: &[u8],
+    theirs: &[u8],
+    labels: &MergeLabels,
+    style: ConflictStyle,
+) -> (Vec<u8>, usize) {
+    let (b, o, t) = (split_lines(base), split_lines(ours), split_lines(theirs));
+    // For each base line, the line it is kept as on each side.
+    let matched = |side: &[&[u8]]| {
+        let mut lines = vec![None; b.len()];
+        for edit in myers(&b, side) {
+            if let Edit::Equal(i, j) = edit {
+                lines[i] = Some(j);
+            }
+        }
+        lines
+    };
+    let (in_ours, in_theirs) = (matched(&o), matched(&t));
+
+    let mut out = Vec::new();
+    let mut conflicts = 0;
+    let (mut i, mut j, mut k) = (0, 0, 0);
+    loop {
+        while i < b.len() && in_ours[i] == Some(j) && in_theirs[i] == Some(k) {
+            out.extend(b[i]);
+            (i, j, k) = (i + 1, j + 1, k + 1);
+        }
+        // The chunk up to the next base line both sides kept.
+        let (next_i, next_j, next_k) = (i..b.len())
+            .find_map(|x| Some((x, in_ours[x]?, in_theirs[x]?)))
+            .unwrap_or((b.len(), o.len(), t.len()));
+        if (next_i, next_j, next_k) == (i, j, k) {
+            break;
+        }
+        let (base, ours, theirs) = (&b[i..next_i], &o[j..next_j], &t[k..next_k]);
+        if ours == base {
+            theirs.iter().for_each(|line| out.extend(*line));
+        } else if theirs == base || ours == theirs {
+            ours.iter().for_each(|line| out.extend(*line));
+        } else {
+            conflicts += 1;
+            write_conflict(&mut out, base, ours, theirs, labels, style);
+        }
+        (i, j, k) = (next_i, next_j, next_k);
+    }
+    (out, conflicts)
+}
+
+fn write_conflict(
+    out: &mut Vec<u8>,
+    base: &[&[u8]],
+    ours: &[&[u8]],
+    theirs: &[&[u8]],
+    labels: &MergeLabels,
+    style: ConflictStyle,
+) {
+    // Without the base shown, lines both sides agree on can stay outside the markers.
+    let (prefix, suffix) = match style {
+        ConflictStyle::Merge => {
+            let prefix = ours.iter().zip(theirs).take_while(|(a, b)| a == b).count();
+            let suffix = ours[prefix..]
+                .iter()
+                .rev()
+                .zip(theirs[prefix..].iter().rev())
+                .take_while(|(a, b)| a == b)
+                .count();
+            (prefix, suffix)
+        }
+        ConflictStyle::Diff3 => (0, 0),
+    };
+    let section = |out: &mut Vec<u8>, lines: &[&[u8]]| {
+        for line in lines {
+            out.extend(*line);
+        }
+        // The marker after an unterminated last line still goes on a line of its own.
+        if lines.last().is_some_and(|line| !line.ends_with(b"\n")) {
+            out.push(b'\n');
+        }
+    };
+    section(out, &ours[..prefix]);
+    out.extend(format!("<<<<<<< {}\n", labels.ours).as_bytes());
+    section(out, &ours[prefix..ours.len() - suffix]);
+    if style == ConflictStyle::Diff3 {
+        out.extend(format!("||||||| {}\n", labels.base).as_bytes());
+        section(out, base);
+    }
+    out.extend(b"=======\n");
+    section(out, &theirs[prefix..theirs.len() - suffix]);
+    out.extend(format!(">>>>>>> {}\n", labels.theirs).as_bytes());
+    ours[ours.len() - suffix..]
+        .iter()
+        .for_each(|line| out.extend(*line));
+}
+
+// Git's default funcname rule: the last line before the hunk that starts with a letter, '_' or
+// '$', without trailing whitespace and cut to 80 bytes.
+fn function_context<'a>(lines: &[&'a [u8]], old_start: usize) -> Option<&'a [u8]> {
+    let before = old_start.saturating_sub(1).min(lines.len());
+    let line = lines[..before]
+        .iter()
+        .rev()
+        .find(|line| matches!(line.first(), Some(c) if c.is_ascii_alphabetic() || *c == b'_' || *c == b'$'))?;
+    let end = line
+        .iter()
+        .rposition(|c| !c.is_ascii_whitespace())
+        .map_or(0, |i| i + 1);
+    Some(&line[..end.min(80)])
+}
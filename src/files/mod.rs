@@ -8,7 +8,7 @@ use std::{
 };
 
 pub fn is_dir_empty(path: &Path) -> bool {
-    read_dir(&path).unwrap().map(|_l| 1).sum::<i32>() == 0
+    read_dir(path).unwrap().map(|_l| 1).sum::<i32>() == 0
 }
 
 pub fn create_write_file(path: &Path, contents: &str) -> Result<(), GitError> {
@@ -34,13 +34,135 @@ pub fn cwd() -> Result<PathBuf, GitError> {
 pub fn read_data(path: &Path) -> Result<Vec<u8>, GitError> {
     let mut data = Vec::new();
 
-    File::open(&path)
+    File::open(path)
         .and_then(|mut file| file.read_to_end(&mut data))
         .and(Ok(data))
-        .map_err(|e| {
-            GitError::PathError(
-                format!("Could not read file {}", e),
-                path.to_path_buf(),
-            )
-        })
+        .map_err(|e| GitError::PathError(format!("Could not read file {}", e), path.to_path_buf()))
+}
+
+// Matches text against a glob the way git's wildmatch does: '*', '?', "[...]" classes and
+// '\' escapes. With `pathname`, wildcards stop at '/' except in "**" path components.
+pub fn wildmatch(pattern: &str, text: &str, pathname: bool) -> bool {
+    glob_match(pattern.as_bytes(), text.as_bytes(), pathname)
+}
+
+fn glob_match(p: &[u8], t: &[u8], pathname: bool) -> bool {
+    let (mut pi, mut ti) = (0, 0);
+    while pi < p.len() {
+        match p[pi] {
+            b'*' => {
+                let mut rest = pi;
+                while rest < p.len() && p[rest] == b'*' {
+                    rest += 1;
+                }
+                // "**" is only special as a whole component, where it spans directories.
+                let component =
+                    (pi == 0 || p[pi - 1] == b'/') && (rest == p.len() || p[rest] == b'/');
+                if pathname && rest - pi > 1 && component {
+                    if rest == p.len() {
+                        return true;
+                    }
+                    let after = &p[rest + 1..];
+                    return glob_match(after, &t[ti..], true)
+                        || (ti..t.len())
+                            .any(|i| t[i] == b'/' && glob_match(after, &t[i + 1..], true));
+                }
+                for i in ti..=t.len() {
+                    if glob_match(&p[rest..], &t[i..], pathname) {
+                        return true;
+                    }
+                    if pathname && t.get(i) == Some(&b'/') {
+                        return false;
+                    }
+                }
+                return false;
+            }
+            b'?' => {
+                if ti >= t.len() || (pathname && t[ti] == b'/') {
+                    return false;
+                }
+                pi += 1;
+            }
+            b'[' if ti < t.len() => match match_class(&p[pi..], t[ti]) {
+                Some((length, matched)) => {
+                    if !matched || (pathname && t[ti] == b'/') {
+                        return false;
+                    }
+                    pi += length;
+                }
+                // An unterminated class is a literal '['.
+                None if t[ti] == b'[' => pi += 1,
+                None => return false,
+            },
+            b'\\' if pi + 1 < p.len() => {
+                if t.get(ti) != Some(&p[pi + 1]) {
+                    return false;
+                }
+                pi += 2;
+            }
+            c => {
+                if t.get(ti) != Some(&c) {
+                    return false;
+                }
+                pi += 1;
+            }
+        }
+        ti += 1;
+    }
+    ti == t.len()
+}
+
+// Matches a byte against the bracket expression at the start of `p`, returning the length of
+// the expression and whether it matched, or None if it is not terminated.
+fn match_class(p: &[u8], c: u8) -> Option<(usize, bool)> {
+    let mut i = 1;
+    let negated = matches!(p.get(i), Some(b'!') | Some(b'^'));
+    if negated {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let b = *p.get(i)?;
+        if b == b']' && !first {
+            break;
+        }
+        first = false;
+        if b == b'[' && p.get(i + 1) == Some(&b':') {
+            let end = i + 2 + p[i + 2..].windows(2).position(|w| w == b":]")?;
+            matched |= match &p[i + 2..end] {
+                b"alnum" => c.is_ascii_alphanumeric(),
+                b"alpha" => c.is_ascii_alphabetic(),
+                b"blank" => c == b' ' || c == b'\t',
+                b"cntrl" => c.is_ascii_control(),
+                b"digit" => c.is_ascii_digit(),
+                b"graph" => c.is_ascii_graphic(),
+                b"lower" => c.is_ascii_lowercase(),
+                b"print" => c.is_ascii_graphic() || c == b' ',
+                b"punct" => c.is_ascii_punctuation(),
+                b"space" => c.is_ascii_whitespace(),
+                b"upper" => c.is_ascii_uppercase(),
+                b"xdigit" => c.is_ascii_hexdigit(),
+                _ => false,
+            };
+            i = end + 2;
+            continue;
+        }
+        let (low, next) = match b {
+            b'\\' => (*p.get(i + 1)?, i + 2),
+            _ => (b, i + 1),
+        };
+        if p.get(next) == Some(&b'-') && p.get(next + 1).is_some_and(|b| *b != b']') {
+            let (high, after) = match p[next + 1] {
+                b'\\' => (*p.get(next + 2)?, next + 3),
+                high => (high, next + 2),
+            };
+            matched |= low <= c && c <= high;
+            i = after;
+        } else {
+            matched |= low == c;
+            i = next;
+        }
+    }
+    Some((i + 1, matched != negated))
 }
@@ -0,0 +1,275 @@
+use flate2::read::GzDecoder;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+
+// An http:// URL split into what a request needs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Url {
+    pub host: String,
+    pub port: u16,
+    // Always starts with '/' and never ends with one, unless it is the root.
+    pub path: String,
+}
+
+impl Url {
+    pub fn parse(url: &str) -> Option<Url> {
+        let rest = url.strip_prefix("http://")?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().ok()?),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return None;
+        }
+        let path = match path.trim_end_matches('/') {
+            "" => "/",
+            path => path,
+        };
+        Some(Url {
+            host: host.to_owned(),
+            port,
+            path: path.to_owned(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Request {
+    pub method: String,
+    // The path and query as sent, e.g. "/repo/info/refs?service=git-upload-pack".
+    pub target: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+
+    // The path without the query.
+    pub fn path(&self) -> &str {
+        self.target.split('?').next().unwrap_or_default()
+    }
+
+    // The value of a query parameter; values are not percent-decoded.
+    pub fn query(&self, name: &str) -> Option<&str> {
+        let (_, query) = self.target.split_once('?')?;
+        query
+            .split('&')
+            .filter_map(|param| param.split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &str, body: Vec<u8>) -> Response {
+        Response {
+            status,
+            headers: vec![("Content-Type".to_owned(), content_type.to_owned())],
+            body,
+        }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+}
+
+// Sends a request on a new connection and reads the whole response.
+pub fn send(url: &Url, request: &Request) -> io::Result<Response> {
+    let mut stream = TcpStream::connect((url.host.as_str(), url.port))?;
+    let mut head = format!(
+        "{} {} HTTP/1.1\r\nHost: {}:{}\r\nConnection: close\r\nContent-Length: {}\r\n",
+        request.method,
+        request.target,
+        url.host,
+        url.port,
+        request.body.len()
+    );
+    for (name, value) in &request.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(&request.body)?;
+    stream.flush()?;
+
+    let mut reader = BufReader::new(stream);
+    let status_line = read_line(&mut reader)?.ok_or_else(|| invalid("Empty response"))?;
+    let status = status_line
+        .split(' ')
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| invalid("Invalid status line"))?;
+    let headers = read_headers(&mut reader)?;
+    // Without a length the body runs to the end of the connection.
+    let body = match find_header(&headers, "Content-Length") {
+        None if find_header(&headers, "Transfer-Encoding").is_none() => {
+            let mut body = Vec::new();
+            reader.read_to_end(&mut body)?;
+            body
+        }
+        _ => read_body(&mut reader, &headers)?,
+    };
+    Ok(Response {
+        status,
+        headers,
+        body,
+    })
+}
+
+// Reads the next request on a connection, or None once the client has closed it.
+pub fn read_request<R: BufRead>(input: &mut R) -> io::Result<Option<Request>> {
+    let line = match read_line(input)? {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+    let mut parts = line.split(' ');
+    let (method, target) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(version)) if version.starts_with("HTTP/1.") => {
+            (method.to_owned(), target.to_owned())
+        }
+        _ => return Err(invalid("Invalid request line")),
+    };
+    let headers = read_headers(input)?;
+    let body = read_body(input, &headers)?;
+    Ok(Some(Request {
+        method,
+        target,
+        headers,
+        body,
+    }))
+}
+
+pub fn write_response<W: Write>(output: &mut W, response: &Response) -> io::Result<()> {
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        reason(response.status),
+        response.body.len()
+    );
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    output.write_all(head.as_bytes())?;
+    output.write_all(&response.body)?;
+    output.flush()
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    }
+}
+
+fn find_header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+// A line without its CRLF, or None at the end of the stream.
+fn read_line<R: BufRead>(input: &mut R) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(line.trim_end_matches(['\r', '\n']).to_owned()))
+}
+
+fn read_headers<R: BufRead>(input: &mut R) -> io::Result<Vec<(String, String)>> {
+    let mut headers = Vec::new();
+    loop {
+        let line = read_line(input)?.ok_or_else(|| invalid("Truncated headers"))?;
+        if line.is_empty() {
+            return Ok(headers);
+        }
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| invalid("Invalid header"))?;
+        headers.push((name.trim().to_owned(), value.trim().to_owned()));
+    }
+}
+
+// The largest body read from a request or a response, before or after gzip decoding, so a
+// peer cannot make us allocate whatever it claims to send.
+const MAX_BODY: u64 = 1 << 30;
+
+// Reads a body sent with a Content-Length or in chunks, undoing gzip content encoding.
+fn read_body<R: BufRead>(input: &mut R, headers: &[(String, String)]) -> io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    if find_header(headers, "Transfer-Encoding").is_some_and(|e| e.eq_ignore_ascii_case("chunked"))
+    {
+        loop {
+            let line = read_line(input)?.ok_or_else(|| invalid("Truncated chunk"))?;
+            let size = line.split(';').next().unwrap_or_default().trim();
+            let size = u64::from_str_radix(size, 16).map_err(|_| invalid("Invalid chunk"))?;
+            if size == 0 {
+                // Skip any trailers up to the blank line.
+                while read_line(input)?.is_some_and(|line| !line.is_empty()) {}
+                break;
+            }
+            if size > MAX_BODY - body.len() as u64 {
+                return Err(invalid("Body too large"));
+            }
+            read_exactly(input, size, &mut body, "Truncated chunk")?;
+            read_line(input)?;
+        }
+    } else if let Some(length) = find_header(headers, "Content-Length") {
+        let length: u64 = length
+            .parse()
+            .map_err(|_| invalid("Invalid Content-Length"))?;
+        if length > MAX_BODY {
+            return Err(invalid("Body too large"));
+        }
+        read_exactly(input, length, &mut body, "Truncated body")?;
+    }
+    if find_header(headers, "Content-Encoding").is_some_and(|e| e.eq_ignore_ascii_case("gzip")) {
+        let mut decoded = Vec::new();
+        GzDecoder::new(&body[..])
+            .take(MAX_BODY + 1)
+            .read_to_end(&mut decoded)?;
+        if decoded.len() as u64 > MAX_BODY {
+            return Err(invalid("Body too large"));
+        }
+        body = decoded;
+    }
+    Ok(body)
+}
+
+// Appends `length` bytes to `body`, growing it only as the bytes arrive.
+fn read_exactly<R: Read>(
+    input: &mut R,
+    length: u64,
+    body: &mut Vec<u8>,
+    truncated: &str,
+) -> io::Result<()> {
+    if input.take(length).read_to_end(body)? as u64 != length {
+        return Err(invalid(truncated));
+    }
+    Ok(())
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -3,19 +3,64 @@ use crate::repository::GitRepository;
 use clap::{arg, App};
 use clap::{AppSettings, Arg, ArgMatches};
 
+use diff::ConflictStyle;
+use repository::add::{AddChange, AddOptions};
+use repository::config::{
+    edit_file, global_paths, global_write_path, parse_bool, parse_int, parse_name, system_path,
+    ConfigEdit, ConfigEntry, ConfigScope, GitConfig,
+};
+use repository::http_backend::serve_http;
+use repository::ident::{ident_date, parse_ident_date, strip_crud, Ident, Role};
+use repository::init::InitOptions;
+use repository::log::{GraphRenderer, LogOrder};
+use repository::merge::{FastForward, MergeResult};
 use repository::object::{GitObject, ObjType, Serializable};
-use std::path::Path;
+use repository::oid::ObjectId;
+use repository::pretty::{format_commit, PrettyFormat};
+use repository::reflog::parse_expiry;
+use repository::remote::{short_ref_name, RefUpdate, Refspec, UpdateStatus};
+use repository::status::Status;
+use repository::tree::Tree;
+use std::collections::{BTreeSet, HashMap};
+use std::io::Write;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
 
 #[macro_use]
 pub mod macros;
+pub mod diff;
 pub mod error;
 pub mod files;
+pub mod http;
+pub mod pktline;
 pub mod repository;
 pub mod test;
 fn main() {
     let mut matches = App::new(env!("CARGO_CRATE_NAME"))
         .setting(AppSettings::ArgRequiredElseHelp)
         .about("implementation of git in rust.")
+        .arg(
+            Arg::new("C")
+                .short('C')
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .value_name("path")
+                .help("run as if started in <path>"),
+        )
+        .arg(
+            Arg::new("git-dir")
+                .long("git-dir")
+                .takes_value(true)
+                .value_name("path")
+                .help("set the path to the repository, as $GIT_DIR does"),
+        )
+        .arg(
+            Arg::new("work-tree")
+                .long("work-tree")
+                .takes_value(true)
+                .value_name("path")
+                .help("set the path to the worktree, as $GIT_WORK_TREE does"),
+        )
         .subcommand(
             App::new("init")
                 .about("Creates a new git repository or reinitializes an existing one.")
@@ -25,6 +70,41 @@ fn main() {
                         .short('p')
                         .value_name("path")
                         .help("specify the repository's path"),
+                )
+                .arg(
+                    Arg::new("bare")
+                        .long("bare")
+                        .takes_value(false)
+                        .help("create a repository without a worktree"),
+                )
+                .arg(
+                    Arg::new("initial-branch")
+                        .short('b')
+                        .long("initial-branch")
+                        .takes_value(true)
+                        .value_name("name")
+                        .help("the name of the first branch (default init.defaultBranch or master)"),
+                )
+                .arg(
+                    Arg::new("template")
+                        .long("template")
+                        .takes_value(true)
+                        .value_name("dir")
+                        .help("copy the files of <dir> into the new repository"),
+                )
+                .arg(
+                    Arg::new("separate-git-dir")
+                        .long("separate-git-dir")
+                        .takes_value(true)
+                        .value_name("dir")
+                        .help("put the repository in <dir> and link to it from .git"),
+                )
+                .arg(
+                    Arg::new("quiet")
+                        .short('q')
+                        .long("quiet")
+                        .takes_value(false)
+                        .help("only print errors and warnings"),
                 ),
         )
         .subcommand(
@@ -45,6 +125,408 @@ fn main() {
                         .help("The name of the object to show"),
                 ),
         )
+        .subcommand(
+            App::new("rev-parse")
+                .about("Resolve revisions to object names")
+                .arg(
+                    Arg::new("short")
+                        .long("short")
+                        .takes_value(false)
+                        .help("abbreviate object names to 7 characters"),
+                )
+                .arg(arg!(<rev> ... "Revisions to resolve")),
+        )
+        .subcommand(
+            App::new("symbolic-ref")
+                .about("Read, modify and delete symbolic refs")
+                .arg(
+                    Arg::new("short")
+                        .long("short")
+                        .takes_value(false)
+                        .help("shorten the ref name when reading it"),
+                )
+                .arg(
+                    Arg::new("delete")
+                        .short('d')
+                        .long("delete")
+                        .takes_value(false)
+                        .help("delete the symbolic ref"),
+                )
+                .arg(
+                    Arg::new("message")
+                        .short('m')
+                        .takes_value(true)
+                        .help("the reason for the update, recorded in the reflog"),
+                )
+                .arg(arg!(<name> "The symbolic ref, such as HEAD"))
+                .arg(arg!([ref] "The ref to point it at")),
+        )
+        .subcommand(
+            App::new("update-ref")
+                .about("Update the object name stored in a ref safely")
+                .arg(
+                    Arg::new("delete")
+                        .short('d')
+                        .takes_value(false)
+                        .help("delete the ref, after checking it still has <oldvalue>"),
+                )
+                .arg(
+                    Arg::new("no-deref")
+                        .long("no-deref")
+                        .takes_value(false)
+                        .help("update the ref itself rather than the ref it points to"),
+                )
+                .arg(
+                    Arg::new("message")
+                        .short('m')
+                        .takes_value(true)
+                        .help("the reason for the update, recorded in the reflog"),
+                )
+                .arg(arg!(<ref> "The ref to update"))
+                .arg(arg!([newvalue] "The new value of the ref"))
+                .arg(arg!([oldvalue] "The value the ref must currently have")),
+        )
+        .subcommand(
+            App::new("branch")
+                .about("List, create, rename or delete branches")
+                .arg(
+                    Arg::new("verbose")
+                        .short('v')
+                        .long("verbose")
+                        .takes_value(false)
+                        .help("show the tip commit and subject of each branch"),
+                )
+                .arg(
+                    Arg::new("move")
+                        .short('m')
+                        .long("move")
+                        .takes_value(false)
+                        .help("rename a branch"),
+                )
+                .arg(
+                    Arg::new("delete")
+                        .short('d')
+                        .long("delete")
+                        .takes_value(false)
+                        .help("delete a fully merged branch"),
+                )
+                .arg(
+                    Arg::new("force-delete")
+                        .short('D')
+                        .takes_value(false)
+                        .help("delete a branch even if it is not merged"),
+                )
+                .arg(
+                    Arg::new("force")
+                        .short('f')
+                        .long("force")
+                        .takes_value(false)
+                        .help("reset an existing branch, or rename over one"),
+                )
+                .arg(arg!([args] ... "Branch names, and the start point when creating")),
+        )
+        .subcommand(
+            App::new("checkout")
+                .about("Switch branches or check out a commit into the working tree")
+                .arg(
+                    Arg::new("branch")
+                        .short('b')
+                        .takes_value(true)
+                        .value_name("new-branch")
+                        .help("create a new branch at <target> and switch to it"),
+                )
+                .arg(
+                    Arg::new("detach")
+                        .long("detach")
+                        .takes_value(false)
+                        .help("detach HEAD at the commit even if <target> is a branch"),
+                )
+                .arg(
+                    Arg::new("force")
+                        .short('f')
+                        .long("force")
+                        .takes_value(false)
+                        .help("throw away local changes"),
+                )
+                .arg(arg!([target] "The branch or commit to check out")),
+        )
+        .subcommand(
+            App::new("switch")
+                .about("Switch branches")
+                .arg(
+                    Arg::new("create")
+                        .short('c')
+                        .long("create")
+                        .takes_value(true)
+                        .value_name("new-branch")
+                        .help("create a new branch at <target> and switch to it"),
+                )
+                .arg(
+                    Arg::new("detach")
+                        .short('d')
+                        .long("detach")
+                        .takes_value(false)
+                        .help("switch to a commit, detaching HEAD"),
+                )
+                .arg(
+                    Arg::new("force")
+                        .short('f')
+                        .long("force")
+                        .alias("discard-changes")
+                        .takes_value(false)
+                        .help("throw away local changes"),
+                )
+                .arg(arg!([target] "The branch to switch to")),
+        )
+        .subcommand(
+            App::new("status")
+                .about("Show the working tree status")
+                .arg(
+                    Arg::new("short")
+                        .short('s')
+                        .long("short")
+                        .takes_value(false)
+                        .help("give the output in the short format"),
+                )
+                .arg(
+                    Arg::new("porcelain")
+                        .long("porcelain")
+                        .takes_value(true)
+                        .min_values(0)
+                        .require_equals(true)
+                        .default_missing_value("v1")
+                        .possible_values(["v1", "v2"])
+                        .value_name("version")
+                        .help("give the output in a stable, machine-readable format"),
+                )
+                .arg(
+                    Arg::new("branch")
+                        .short('b')
+                        .long("branch")
+                        .takes_value(false)
+                        .help("show the branch in the short and porcelain formats"),
+                )
+                .arg(
+                    Arg::new("untracked-files")
+                        .short('u')
+                        .long("untracked-files")
+                        .takes_value(true)
+                        .min_values(0)
+                        .default_missing_value("all")
+                        .possible_values(["no", "normal", "all"])
+                        .value_name("mode")
+                        .help("show untracked files"),
+                ),
+        )
+        .subcommand(
+            App::new("diff")
+                .about("Show changes between the worktree, the index and commits")
+                .arg(
+                    Arg::new("cached")
+                        .long("cached")
+                        .alias("staged")
+                        .takes_value(false)
+                        .help("show staged changes, relative to HEAD or the given commit"),
+                )
+                .arg(
+                    Arg::new("unified")
+                        .short('U')
+                        .long("unified")
+                        .takes_value(true)
+                        .value_name("n")
+                        .default_value("3")
+                        .help("generate diffs with <n> lines of context"),
+                )
+                .arg(arg!([commits] ... "Commits to compare, as <a> <b> or <a>..<b>"))
+                .arg(
+                    Arg::new("paths")
+                        .last(true)
+                        .multiple_values(true)
+                        .help("limit the diff to these paths"),
+                ),
+        )
+        .subcommand(
+            App::new("reflog")
+                .about("Manage reflog information")
+                .subcommand(
+                    App::new("show")
+                        .about("Show the entries of a reflog, newest first")
+                        .arg(arg!([ref] "The ref whose reflog to show (default HEAD)")),
+                )
+                .subcommand(
+                    App::new("expire")
+                        .about("Prune old reflog entries")
+                        .arg(
+                            Arg::new("expire")
+                                .long("expire")
+                                .takes_value(true)
+                                .help("prune entries older than this (default gc.reflogExpire or 90 days)"),
+                        )
+                        .arg(
+                            Arg::new("expire-unreachable")
+                                .long("expire-unreachable")
+                                .takes_value(true)
+                                .help("prune entries older than this that are not reachable from the ref's tip (default gc.reflogExpireUnreachable or 30 days)"),
+                        )
+                        .arg(
+                            Arg::new("all")
+                                .long("all")
+                                .takes_value(false)
+                                .help("prune the reflogs of all refs"),
+                        )
+                        .arg(
+                            Arg::new("dry-run")
+                                .short('n')
+                                .long("dry-run")
+                                .takes_value(false)
+                                .help("only check what would be pruned"),
+                        )
+                        .arg(
+                            Arg::new("verbose")
+                                .long("verbose")
+                                .takes_value(false)
+                                .help("list the pruned entries"),
+                        )
+                        .arg(arg!([refs] ... "The refs whose reflogs to prune")),
+                ),
+        )
+        .subcommand(
+            App::new("log")
+                .about("Show commit logs")
+                .arg(
+                    Arg::new("oneline")
+                        .long("oneline")
+                        .takes_value(false)
+                        .help("shorthand for --format=oneline --abbrev-commit"),
+                )
+                .arg(
+                    Arg::new("max-count")
+                        .short('n')
+                        .long("max-count")
+                        .takes_value(true)
+                        .value_name("number")
+                        .help("limit the number of commits to output"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .alias("pretty")
+                        .takes_value(true)
+                        .help("oneline, short, medium, full, fuller, raw or a format string"),
+                )
+                .arg(
+                    Arg::new("abbrev-commit")
+                        .long("abbrev-commit")
+                        .takes_value(false)
+                        .help("show abbreviated commit ids"),
+                )
+                .arg(
+                    Arg::new("decorate")
+                        .long("decorate")
+                        .takes_value(false)
+                        .help("show the refs pointing at each commit"),
+                )
+                .arg(
+                    Arg::new("graph")
+                        .long("graph")
+                        .takes_value(false)
+                        .help("draw an ASCII graph of the history"),
+                )
+                .arg(
+                    Arg::new("date-order")
+                        .long("date-order")
+                        .takes_value(false)
+                        .conflicts_with("topo-order")
+                        .help("show commits in commit date order"),
+                )
+                .arg(
+                    Arg::new("topo-order")
+                        .long("topo-order")
+                        .takes_value(false)
+                        .help("avoid interleaving commits from different lines of history"),
+                )
+                .arg(arg!([revisions] ... "Commits to start from, ^<rev> and <a>..<b> exclude"))
+                .arg(
+                    Arg::new("paths")
+                        .last(true)
+                        .multiple_values(true)
+                        .help("only show commits that changed these paths"),
+                ),
+        )
+        .subcommand(
+            App::new("repack")
+                .about("Pack objects into a packfile")
+                .arg(
+                    Arg::new("all")
+                        .short('a')
+                        .takes_value(false)
+                        .help("pack every reachable object into a single pack"),
+                )
+                .arg(
+                    Arg::new("delete")
+                        .short('d')
+                        .takes_value(false)
+                        .help("remove redundant packs and loose objects afterwards"),
+                ),
+        )
+        .subcommand(
+            App::new("gc").about("Pack all reachable objects and remove redundant loose objects"),
+        )
+        .subcommand(
+            App::new("clone")
+                .about("Clone a repository into a new directory")
+                .arg(arg!(<repository> "The repository to clone from"))
+                .arg(arg!([directory] "The directory to clone into")),
+        )
+        .subcommand(
+            App::new("fetch")
+                .about("Download objects and refs from another repository")
+                .arg(
+                    Arg::new("force")
+                        .short('f')
+                        .long("force")
+                        .takes_value(false)
+                        .help("allow updates that are not fast-forwards"),
+                )
+                .arg(arg!([remote] "The remote or repository path to fetch from"))
+                .arg(arg!([refspecs] ... "The refs to fetch and where to store them")),
+        )
+        .subcommand(
+            App::new("push")
+                .about("Update remote refs along with associated objects")
+                .arg(
+                    Arg::new("force")
+                        .short('f')
+                        .long("force")
+                        .takes_value(false)
+                        .help("allow updates that are not fast-forwards"),
+                )
+                .arg(arg!([remote] "The remote or repository path to push to"))
+                .arg(arg!([refspecs] ... "The refs to push and where to")),
+        )
+        .subcommand(
+            App::new("upload-pack")
+                .about("Send objects packed back to git fetch, speaking protocol v2 on stdio")
+                .arg(arg!(<directory> "The repository to serve")),
+        )
+        .subcommand(
+            App::new("receive-pack")
+                .about("Receive what is pushed into the repository, on stdio")
+                .arg(arg!(<directory> "The repository to update")),
+        )
+        .subcommand(
+            App::new("http-backend")
+                .about("Serve the repositories in a directory over smart HTTP")
+                .arg(
+                    Arg::new("listen")
+                        .long("listen")
+                        .takes_value(true)
+                        .value_name("address")
+                        .default_value("127.0.0.1:8080")
+                        .help("the address and port to listen on"),
+                )
+                .arg(arg!([directory] "The directory to serve, the current one by default")),
+        )
         .subcommand(
             App::new("hash-object")
                 .about("Compute object ID and optionally creates a blob from a file")
@@ -70,91 +552,1119 @@ fn main() {
                         .help("The path of the object"),
                 ),
         )
-        .subcommand(App::new("ls-files").about("Lists the files in the git index"))
         .subcommand(
-            App::new("commit")
-                .about("Record changes to the repository")
+            App::new("ls-files")
+                .about("Lists the files in the git index")
+                .arg(
+                    Arg::new("cached")
+                        .short('c')
+                        .long("cached")
+                        .takes_value(false)
+                        .help("show the files in the index (the default)"),
+                )
+                .arg(
+                    Arg::new("others")
+                        .short('o')
+                        .long("others")
+                        .takes_value(false)
+                        .help("show untracked files"),
+                )
+                .arg(
+                    Arg::new("exclude-standard")
+                        .long("exclude-standard")
+                        .takes_value(false)
+                        .help("leave out files ignored by .gitignore, info/exclude and core.excludesFile"),
+                )
+                .arg(
+                    Arg::new("directory")
+                        .long("directory")
+                        .takes_value(false)
+                        .help("show untracked directories once instead of their files"),
+                ),
+        )
+        .subcommand(
+            App::new("check-ignore")
+                .about("Debug gitignore / exclude files")
+                .arg(
+                    Arg::new("verbose")
+                        .short('v')
+                        .long("verbose")
+                        .takes_value(false)
+                        .help("show the pattern that matched each path"),
+                )
+                .arg(
+                    Arg::new("non-matching")
+                        .short('n')
+                        .long("non-matching")
+                        .takes_value(false)
+                        .requires("verbose")
+                        .help("show paths that match no pattern as well"),
+                )
+                .arg(
+                    Arg::new("no-index")
+                        .long("no-index")
+                        .takes_value(false)
+                        .help("check tracked files too"),
+                )
+                .arg(arg!(<paths> ... "The paths to check")),
+        )
+        .subcommand(
+            App::new("clean")
+                .about("Remove untracked files from the working tree")
+                .arg(
+                    Arg::new("dry-run")
+                        .short('n')
+                        .long("dry-run")
+                        .takes_value(false)
+                        .help("only show what would be removed"),
+                )
+                .arg(
+                    Arg::new("force")
+                        .short('f')
+                        .long("force")
+                        .takes_value(false)
+                        .help("remove files, as clean.requireForce asks"),
+                )
+                .arg(
+                    Arg::new("directories")
+                        .short('d')
+                        .takes_value(false)
+                        .help("remove untracked directories too"),
+                )
+                .arg(
+                    Arg::new("ignored")
+                        .short('x')
+                        .takes_value(false)
+                        .help("remove ignored files too"),
+                )
+                .arg(arg!([paths] ... "Only clean below these paths")),
+        )
+        .subcommand(
+            App::new("merge")
+                .about("Join another line of development into the current branch")
+                .arg(
+                    Arg::new("no-ff")
+                        .long("no-ff")
+                        .takes_value(false)
+                        .help("create a merge commit even when a fast-forward is possible"),
+                )
+                .arg(
+                    Arg::new("ff-only")
+                        .long("ff-only")
+                        .takes_value(false)
+                        .conflicts_with("no-ff")
+                        .help("refuse to merge unless fast-forwarding"),
+                )
                 .arg(
                     Arg::new("message")
                         .short('m')
                         .takes_value(true)
                         .value_name("message")
-                        .help("use the given message as the commit message"),
+                        .help("use the given message for the merge commit"),
                 )
                 .arg(
                     Arg::new("author")
                         .short('a')
+                        .long("author")
                         .takes_value(true)
                         .value_name("author")
-                        .help("use the given author as the author of the commit"),
-                ),
+                        .help("use the given \"Name <email>\" as the author of the merge commit"),
+                )
+                .arg(
+                    Arg::new("conflict")
+                        .long("conflict")
+                        .takes_value(true)
+                        .value_name("style")
+                        .possible_values(["merge", "diff3"])
+                        .help("how to show conflicts, overriding merge.conflictStyle"),
+                )
+                .arg(
+                    Arg::new("abort")
+                        .long("abort")
+                        .takes_value(false)
+                        .conflicts_with("commit")
+                        .help("abandon the merge and restore HEAD's state"),
+                )
+                .arg(arg!([commit] "The commit to merge into the current branch")),
         )
         .subcommand(
-            App::new("add")
-                .about("Add file contents to the index")
-                .arg(arg!(<path> ... "Stuff to add").short('p')),
-        );
-    let get = matches.get_matches_mut();
-
-    match get.subcommand() {
-        Some(("init", sub_matches)) => {
-            init(sub_matches);
-        }
-        Some(("cat-file", sub_matches)) => {
-            cat_file(sub_matches).unwrap();
-        }
-        Some(("hash-object", sub_matches)) => {
-            hash_object(sub_matches).unwrap();
-        }
-        Some(("ls-files", _sub_matches)) => {
-            ls_files().unwrap();
-        }
-        Some(("commit", sub_matches)) => {
-            commit(sub_matches);
-        }
-        Some(("add", sub_matches)) => {
-            git_add(sub_matches);
-        }
-        _ => {
-            matches.print_help().unwrap();
-        }
-    }
-}
-
-fn init(matches: &ArgMatches) {
-    if matches.is_present("path") {
-        let repo_path = matches.value_of("path").unwrap();
-        let result = GitRepository::write_to_path(Path::new(repo_path));
-        if result.is_err() {
-            println!("{}", result.unwrap_err());
-        }
-    } else {
-        let repo_path = ".";
-        let result = GitRepository::write_to_path(Path::new(repo_path));
-        if result.is_err() {
-            println!("{}", result.unwrap_err());
-        }
-    }
-}
+            App::new("merge-base")
+                .about("Find as good common ancestors as possible for a merge")
+                .arg(
+                    Arg::new("all")
+                        .short('a')
+                        .long("all")
+                        .takes_value(false)
+                        .help("output all common ancestors"),
+                )
+                .arg(
+                    Arg::new("octopus")
+                        .long("octopus")
+                        .takes_value(false)
+                        .help("find the best common ancestors of all the commits"),
+                )
+                .arg(
+                    Arg::new("is-ancestor")
+                        .long("is-ancestor")
+                        .takes_value(false)
+                        .conflicts_with_all(&["all", "octopus"])
+                        .help("exit with 0 if the first commit is an ancestor of the second"),
+                )
+                .arg(arg!(<commits> ... "The commits to compare")),
+        )
+        .subcommand(
+            App::new("tag")
+                .about("Create, list or delete tags")
+                .arg(
+                    Arg::new("annotate")
+                        .short('a')
+                        .takes_value(false)
+                        .help("make an annotated tag object"),
+                )
+                .arg(
+                    Arg::new("message")
+                        .short('m')
+                        .takes_value(true)
+                        .help("tag message, making the tag annotated"),
+                )
+                .arg(
+                    Arg::new("force")
+                        .short('f')
+                        .long("force")
+                        .takes_value(false)
+                        .help("replace an existing tag"),
+                )
+                .arg(
+                    Arg::new("delete")
+                        .short('d')
+                        .long("delete")
+                        .takes_value(false)
+                        .conflicts_with_all(&["annotate", "message", "force", "list"])
+                        .help("delete the given tags"),
+                )
+                .arg(
+                    Arg::new("list")
+                        .short('l')
+                        .long("list")
+                        .takes_value(false)
+                        .help("list tags matching the given patterns"),
+                )
+                .arg(arg!([args] ... "The tag name and commit, or patterns with -l")),
+        )
+        .subcommand(
+            App::new("describe")
+                .about("Give a commit a name based on the nearest tag")
+                .arg(
+                    Arg::new("tags")
+                        .long("tags")
+                        .takes_value(false)
+                        .help("use lightweight tags as well"),
+                )
+                .arg(
+                    Arg::new("long")
+                        .long("long")
+                        .takes_value(false)
+                        .help("always show the distance and commit, even on a tag"),
+                )
+                .arg(
+                    Arg::new("always")
+                        .long("always")
+                        .takes_value(false)
+                        .help("show the abbreviated commit when no tag is found"),
+                )
+                .arg(
+                    Arg::new("dirty")
+                        .long("dirty")
+                        .takes_value(false)
+                        .help("append \"-dirty\" if the worktree has changes"),
+                )
+                .arg(
+                    Arg::new("abbrev")
+                        .long("abbrev")
+                        .takes_value(true)
+                        .default_value("7")
+                        .help("digits of the commit id to show"),
+                )
+                .arg(arg!([commits] ... "The commits to describe (default HEAD)")),
+        )
+        .subcommand(
+            App::new("config")
+                .about("Get and set repository or global options")
+                .arg(
+                    Arg::new("get")
+                        .long("get")
+                        .takes_value(false)
+                        .help("get the value for a key"),
+                )
+                .arg(
+                    Arg::new("get-all")
+                        .long("get-all")
+                        .takes_value(false)
+                        .help("get every value of a multi-valued key"),
+                )
+                .arg(
+                    Arg::new("add")
+                        .long("add")
+                        .takes_value(false)
+                        .help("add a new value without replacing the existing ones"),
+                )
+                .arg(
+                    Arg::new("unset")
+                        .long("unset")
+                        .takes_value(false)
+                        .help("remove a key"),
+                )
+                .arg(
+                    Arg::new("unset-all")
+                        .long("unset-all")
+                        .takes_value(false)
+                        .help("remove every value of a key"),
+                )
+                .arg(
+                    Arg::new("list")
+                        .short('l')
+                        .long("list")
+                        .takes_value(false)
+                        .help("list all variables and their values"),
+                )
+                .arg(
+                    Arg::new("show-origin")
+                        .long("show-origin")
+                        .takes_value(false)
+                        .help("show the file each value comes from"),
+                )
+                .arg(
+                    Arg::new("system")
+                        .long("system")
+                        .takes_value(false)
+                        .help("use the system-wide config file"),
+                )
+                .arg(
+                    Arg::new("global")
+                        .long("global")
+                        .takes_value(false)
+                        .help("use the user's config file"),
+                )
+                .arg(
+                    Arg::new("local")
+                        .long("local")
+                        .takes_value(false)
+                        .help("use the repository config file"),
+                )
+                .arg(
+                    Arg::new("worktree")
+                        .long("worktree")
+                        .takes_value(false)
+                        .help("use the per-worktree config file"),
+                )
+                .arg(
+                    Arg::new("file")
+                        .short('f')
+                        .long("file")
+                        .takes_value(true)
+                        .value_name("file")
+                        .help("use the given config file"),
+                )
+                .arg(
+                    Arg::new("type")
+                        .long("type")
+                        .takes_value(true)
+                        .possible_values(["bool", "int"])
+                        .help("check and canonicalize values as the given type"),
+                )
+                .arg(arg!([name] "The key, as section[.subsection].key"))
+                .arg(arg!([value] "The value to set")),
+        )
+        .subcommand(
+            App::new("commit")
+                .about("Record changes to the repository")
+                .arg(
+                    Arg::new("message")
+                        .short('m')
+                        .takes_value(true)
+                        .value_name("message")
+                        .help("use the given message as the commit message"),
+                )
+                .arg(
+                    Arg::new("author")
+                        .short('a')
+                        .long("author")
+                        .takes_value(true)
+                        .value_name("author")
+                        .help("use the given \"Name <email>\" as the author of the commit"),
+                )
+                .arg(
+                    Arg::new("date")
+                        .long("date")
+                        .takes_value(true)
+                        .value_name("date")
+                        .help("use the given date as the author date"),
+                ),
+        )
+        .subcommand(
+            App::new("add")
+                .about("Add file contents to the index")
+                .arg(arg!([pathspec] ... "Files to add content from"))
+                .arg(
+                    Arg::new("force")
+                        .short('f')
+                        .long("force")
+                        .takes_value(false)
+                        .help("add ignored files too"),
+                )
+                .arg(
+                    Arg::new("all")
+                        .short('A')
+                        .long("all")
+                        .takes_value(false)
+                        .conflicts_with("update")
+                        .help("stage new, modified and deleted files, in the whole tree without a pathspec"),
+                )
+                .arg(
+                    Arg::new("update")
+                        .short('u')
+                        .long("update")
+                        .takes_value(false)
+                        .help("stage modified and deleted tracked files only"),
+                )
+                .arg(
+                    Arg::new("dry-run")
+                        .short('n')
+                        .long("dry-run")
+                        .takes_value(false)
+                        .help("show what would be added without adding it"),
+                )
+                .arg(
+                    Arg::new("verbose")
+                        .short('v')
+                        .long("verbose")
+                        .takes_value(false)
+                        .help("list the files as they are added"),
+                )
+                .arg(
+                    Arg::new("patch")
+                        .short('p')
+                        .long("patch")
+                        .takes_value(false)
+                        .conflicts_with_all(&["all", "update", "dry-run", "force"])
+                        .help("choose the hunks of each change to stage interactively"),
+                ),
+        );
+    let get = matches.get_matches_mut();
+    // A failing command reports why on stderr and exits with 1 instead of panicking.
+    let result = setup_environment(&get).and_then(|_| match get.subcommand() {
+        Some(("init", sub_matches)) => init(sub_matches),
+        Some(("cat-file", sub_matches)) => cat_file(sub_matches),
+        Some(("rev-parse", sub_matches)) => rev_parse(sub_matches),
+        Some(("symbolic-ref", sub_matches)) => symbolic_ref(sub_matches),
+        Some(("update-ref", sub_matches)) => update_ref(sub_matches),
+        Some(("branch", sub_matches)) => branch(sub_matches),
+        Some(("checkout", sub_matches)) => checkout(sub_matches, "branch", false),
+        Some(("switch", sub_matches)) => checkout(sub_matches, "create", true),
+        Some(("status", sub_matches)) => status(sub_matches),
+        Some(("diff", sub_matches)) => diff(sub_matches),
+        Some(("log", sub_matches)) => log(sub_matches),
+        Some(("repack", sub_matches)) => repack(
+            sub_matches.is_present("all"),
+            sub_matches.is_present("delete"),
+        ),
+        Some(("gc", _sub_matches)) => repack(true, true),
+        Some(("clone", sub_matches)) => clone(sub_matches),
+        Some(("fetch", sub_matches)) => fetch(sub_matches),
+        Some(("push", sub_matches)) => push(sub_matches),
+        Some(("upload-pack", sub_matches)) => upload_pack(sub_matches),
+        Some(("receive-pack", sub_matches)) => receive_pack(sub_matches),
+        Some(("http-backend", sub_matches)) => http_backend(sub_matches),
+        Some(("hash-object", sub_matches)) => hash_object(sub_matches),
+        Some(("ls-files", sub_matches)) => ls_files(sub_matches),
+        Some(("check-ignore", sub_matches)) => check_ignore(sub_matches),
+        Some(("clean", sub_matches)) => clean(sub_matches),
+        Some(("merge", sub_matches)) => merge(sub_matches),
+        Some(("merge-base", sub_matches)) => merge_base(sub_matches),
+        Some(("reflog", sub_matches)) => reflog(sub_matches),
+        Some(("tag", sub_matches)) => tag(sub_matches),
+        Some(("describe", sub_matches)) => describe(sub_matches),
+        Some(("config", sub_matches)) => config(sub_matches),
+        Some(("commit", sub_matches)) => commit(sub_matches),
+        Some(("add", sub_matches)) => git_add(sub_matches),
+        _ => {
+            matches.print_help().unwrap();
+            Ok(())
+        }
+    });
+    if let Err(error) = result {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
+}
+
+// Applies the options that come before the subcommand. Each -C is relative to the previous
+// one; --git-dir and --work-tree are passed on through the environment, as git does.
+fn setup_environment(matches: &ArgMatches) -> Result<(), GitError> {
+    for dir in matches.values_of("C").unwrap_or_default() {
+        if !dir.is_empty() {
+            std::env::set_current_dir(dir).map_err(|_| {
+                GitError::PathError("cannot change to".to_owned(), PathBuf::from(dir))
+            })?;
+        }
+    }
+    if let Some(gitdir) = matches.value_of("git-dir") {
+        std::env::set_var("GIT_DIR", gitdir);
+    }
+    if let Some(worktree) = matches.value_of("work-tree") {
+        std::env::set_var("GIT_WORK_TREE", worktree);
+    }
+    Ok(())
+}
+
+fn clone(matches: &ArgMatches) -> Result<(), GitError> {
+    let source = matches.value_of("repository").unwrap();
+    // Local paths are remembered as absolute ones, so the clone can find its origin.
+    let url = match source.contains("://") {
+        true => source.to_owned(),
+        false => std::fs::canonicalize(source)
+            .map_err(|_| {
+                GitError::PathError("Repository does not exist".to_owned(), source.into())
+            })?
+            .to_string_lossy()
+            .into_owned(),
+    };
+    let directory = match matches.value_of("directory") {
+        Some(directory) => directory.to_owned(),
+        None => {
+            let name = url
+                .trim_end_matches('/')
+                .rsplit('/')
+                .next()
+                .unwrap_or_default();
+            name.strip_suffix(".git").unwrap_or(name).to_owned()
+        }
+    };
+    if Path::new(&directory).exists() && !files::is_dir_empty(Path::new(&directory)) {
+        return Err(GitError::GenericError(format!(
+            "destination path '{}' already exists and is not an empty directory.",
+            directory
+        )));
+    }
+    eprintln!("Cloning into '{}'...", directory);
+    GitRepository::write_to_path(Path::new(&directory))?;
+    let worktree = std::fs::canonicalize(&directory)
+        .map_err(|_| GitError::PathError("Could not open clone".to_owned(), directory.into()))?;
+    let mut repo = GitRepository::load(&worktree)?;
+    match repo.clone_remote(&url)? {
+        (_, Some(oid)) => repo.checkout_commit(&oid, true)?,
+        (_, None) => eprintln!("warning: You appear to have cloned an empty repository."),
+    }
+    Ok(())
+}
+
+fn fetch(matches: &ArgMatches) -> Result<(), GitError> {
+    let repo = GitRepository::find()?;
+    let name = match matches.value_of("remote") {
+        Some(name) => name.to_owned(),
+        None => repo.default_remote(),
+    };
+    let remote = repo.remote(&name)?;
+    let refspecs = matches
+        .values_of("refspecs")
+        .unwrap_or_default()
+        .map(Refspec::parse)
+        .collect::<Result<Vec<Refspec>, GitError>>()?;
+    let updates = repo.fetch(&remote, &refspecs, matches.is_present("force"))?;
+    report_updates("From", &remote.url, &updates, false);
+    Ok(())
+}
+
+fn push(matches: &ArgMatches) -> Result<(), GitError> {
+    let repo = GitRepository::find()?;
+    let name = match matches.value_of("remote") {
+        Some(name) => name.to_owned(),
+        None => repo.default_remote(),
+    };
+    let remote = repo.remote(&name)?;
+    let mut refspecs = matches
+        .values_of("refspecs")
+        .unwrap_or_default()
+        .map(Refspec::parse)
+        .collect::<Result<Vec<Refspec>, GitError>>()?;
+    if refspecs.is_empty() {
+        // Like push.default=simple: the current branch goes to the branch of the same name.
+        let branch = repo.current_branch().ok_or_else(|| {
+            GitError::GenericError("You are not currently on a branch.".to_owned())
+        })?;
+        refspecs.push(Refspec::parse(&format!("refs/heads/{}", branch))?);
+    }
+    let updates = repo.push(&remote, &refspecs, matches.is_present("force"))?;
+    report_updates("To", &remote.url, &updates, true);
+    if updates.iter().any(RefUpdate::is_rejected) {
+        eprintln!("error: failed to push some refs to '{}'", remote.url);
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+// Prints the refs fetch or push changed after a header naming the other repository. Push
+// also says when there was nothing to do.
+fn report_updates(header: &str, url: &str, updates: &[RefUpdate], push: bool) {
+    let shown: Vec<&RefUpdate> = updates
+        .iter()
+        .filter(|update| update.status != UpdateStatus::UpToDate)
+        .collect();
+    if shown.is_empty() {
+        if push {
+            eprintln!("Everything up-to-date");
+        }
+        return;
+    }
+    let width = shown
+        .iter()
+        .map(|update| short_ref_name(&update.src).len())
+        .max()
+        .unwrap_or(0);
+    eprintln!("{} {}", header, url);
+    for update in shown {
+        eprintln!("{}", update.summary(width));
+    }
+}
+
+fn upload_pack(matches: &ArgMatches) -> Result<(), GitError> {
+    let repo = open_served_repository(matches.value_of("directory").unwrap())?;
+    let stdin = std::io::stdin();
+    let stdout = std::io::stdout();
+    repo.upload_pack(&mut stdin.lock(), &mut stdout.lock())
+}
+
+fn receive_pack(matches: &ArgMatches) -> Result<(), GitError> {
+    let repo = open_served_repository(matches.value_of("directory").unwrap())?;
+    let stdin = std::io::stdin();
+    let stdout = std::io::stdout();
+    repo.receive_pack(&mut stdin.lock(), &mut stdout.lock(), protocol_version())
+}
+
+fn http_backend(matches: &ArgMatches) -> Result<(), GitError> {
+    let directory = matches.value_of("directory").unwrap_or(".");
+    let root = std::fs::canonicalize(directory).map_err(|_| {
+        GitError::PathError("Directory does not exist".to_owned(), directory.into())
+    })?;
+    let address = matches.value_of("listen").unwrap();
+    let listener = TcpListener::bind(address)
+        .map_err(|e| GitError::GenericError(format!("Could not listen on {}: {}", address, e)))?;
+    if let Ok(address) = listener.local_addr() {
+        eprintln!("Serving {} at http://{}/", root.display(), address);
+    }
+    serve_http(listener, root)
+}
+
+// The repository a client named, trying "<path>.git" as git does.
+fn open_served_repository(path: &str) -> Result<GitRepository, GitError> {
+    GitRepository::open(Path::new(path))
+        .or_else(|e| GitRepository::open(Path::new(&format!("{}.git", path))).map_err(|_| e))
+}
+
+// The protocol version the client asked for in GIT_PROTOCOL, 0 if none.
+fn protocol_version() -> u8 {
+    std::env::var("GIT_PROTOCOL")
+        .unwrap_or_default()
+        .split(':')
+        .filter_map(|param| param.strip_prefix("version="))
+        .filter_map(|version| version.parse().ok())
+        .max()
+        .unwrap_or(0)
+}
+
+fn init(matches: &ArgMatches) -> Result<(), GitError> {
+    let options = InitOptions {
+        bare: matches.is_present("bare"),
+        initial_branch: matches.value_of("initial-branch").map(str::to_owned),
+        template: matches.value_of("template").map(PathBuf::from),
+        separate_git_dir: matches.value_of("separate-git-dir").map(PathBuf::from),
+    };
+    let path = matches.value_of("path").unwrap_or(".");
+    let (repo, reinit) = GitRepository::init(Path::new(path), &options)?;
+    if !matches.is_present("quiet") {
+        let shared = match reinit {
+            true => "Reinitialized existing",
+            false => "Initialized empty",
+        };
+        println!("{} Git repository in {}/", shared, repo.gitdir.display());
+    }
+    Ok(())
+}
 
 fn cat_file(matches: &ArgMatches) -> Result<(), GitError> {
     let object = matches.value_of("object").unwrap();
-    let object_type: ObjType = ObjType::deserialize(matches.value_of("type").unwrap().as_bytes());
-    GitRepository::load(&path!("."))
+    let object_type: ObjType = ObjType::deserialize(matches.value_of("type").unwrap().as_bytes())?;
+    GitRepository::find()
         .and_then(|repo| {
-            let object = repo.find_object(object, &object_type);
+            let object = repo.find_object(object, &object_type)?;
             repo.read_object(&object)
         })
-        .and_then(|obj| {
-            String::from_utf8(obj.serialize().to_vec()).map_err(|e| {
+        .and_then(|obj| match obj.obj_type {
+            ObjType::Tree => Ok(obj
+                .parse::<Tree>()?
+                .entries
+                .iter()
+                .map(|entry| {
+                    let kind = if entry.is_tree() { "tree" } else { "blob" };
+                    format!("{:06o} {} {}\t{}", entry.mode, kind, entry.oid, entry.name)
+                })
+                .collect::<Vec<String>>()
+                .join("\n")),
+            _ => String::from_utf8(obj.data).map_err(|e| {
                 GitError::GenericError(format!("Error converting object data to string: {}", e))
-            })
+            }),
         })
         .map(|object_as_string| {
             println!("{}", object_as_string);
         })
 }
 
+fn rev_parse(matches: &ArgMatches) -> Result<(), GitError> {
+    let repo = GitRepository::find()?;
+    for rev in matches.values_of("rev").unwrap() {
+        let oid = repo.rev_parse(rev)?;
+        if matches.is_present("short") {
+            println!("{:.7}", oid);
+        } else {
+            println!("{}", oid);
+        }
+    }
+    Ok(())
+}
+
+fn symbolic_ref(matches: &ArgMatches) -> Result<(), GitError> {
+    let repo = GitRepository::find()?;
+    let name = matches.value_of("name").unwrap();
+    if matches.is_present("delete") {
+        return match repo.symbolic_ref(name)? {
+            Some(_) => repo.delete_ref(name, None, false),
+            None => Err(GitError::GenericError(format!(
+                "Cannot delete {}, not a symbolic ref",
+                name
+            ))),
+        };
+    }
+    if let Some(target) = matches.value_of("ref") {
+        return repo.set_symbolic_ref(name, target, matches.value_of("message"));
+    }
+    match repo.symbolic_ref(name)? {
+        Some(target) if matches.is_present("short") => {
+            let short = ["refs/heads/", "refs/tags/", "refs/remotes/"]
+                .iter()
+                .find_map(|prefix| target.strip_prefix(prefix))
+                .unwrap_or(&target);
+            println!("{}", short);
+            Ok(())
+        }
+        Some(target) => {
+            println!("{}", target);
+            Ok(())
+        }
+        None => Err(GitError::GenericError(format!(
+            "ref {} is not a symbolic ref",
+            name
+        ))),
+    }
+}
+
+fn update_ref(matches: &ArgMatches) -> Result<(), GitError> {
+    let repo = GitRepository::find()?;
+    let name = matches.value_of("ref").unwrap();
+    let deref = !matches.is_present("no-deref");
+    // git accepts an empty value or the null id to mean "must not exist".
+    let parse = |value: &str| {
+        if value.is_empty() {
+            Ok(ObjectId::default())
+        } else if value.len() == ObjectId::HEX_LEN {
+            ObjectId::from_hex(value)
+        } else {
+            repo.rev_parse(value)
+        }
+    };
+    if matches.is_present("delete") {
+        // With -d the second argument is the expected old value.
+        let old = matches.value_of("newvalue").map(parse).transpose()?;
+        return repo.delete_ref(name, old.as_ref(), deref);
+    }
+    let new = matches
+        .value_of("newvalue")
+        .ok_or_else(|| GitError::GenericError("update-ref needs a new value".to_owned()))
+        .and_then(parse)?;
+    let old = matches.value_of("oldvalue").map(parse).transpose()?;
+    let message = matches.value_of("message").unwrap_or_default();
+    repo.update_ref(name, &new, old.as_ref(), deref, message)
+}
+
+fn branch(matches: &ArgMatches) -> Result<(), GitError> {
+    let mut repo = GitRepository::find()?;
+    let args: Vec<&str> = matches.values_of("args").unwrap_or_default().collect();
+    let force = matches.is_present("force");
+
+    if matches.is_present("delete") || matches.is_present("force-delete") {
+        if args.is_empty() {
+            return Err(GitError::GenericError("branch name required".to_owned()));
+        }
+        for name in args {
+            let oid = repo.delete_branch(name, matches.is_present("force-delete"))?;
+            println!("Deleted branch {} (was {:.7}).", name, oid);
+        }
+        return Ok(());
+    }
+    if matches.is_present("move") {
+        let (old, new) = match args.as_slice() {
+            [new] => (
+                repo.current_branch()
+                    .ok_or_else(|| GitError::GenericError("HEAD is detached".to_owned()))?,
+                new.to_string(),
+            ),
+            [old, new] => (old.to_string(), new.to_string()),
+            _ => {
+                return Err(GitError::GenericError(
+                    "branch -m takes one or two branch names".to_owned(),
+                ))
+            }
+        };
+        return repo.rename_branch(&old, &new, force);
+    }
+    match args.as_slice() {
+        [] => {}
+        [name] => return repo.create_branch(name, "HEAD", force).map(|_| ()),
+        [name, start] => return repo.create_branch(name, start, force).map(|_| ()),
+        _ => return Err(GitError::GenericError("too many arguments".to_owned())),
+    }
+
+    let current = repo.current_branch();
+    let mut lines: Vec<(String, bool, ObjectId)> = repo
+        .list_branches()?
+        .into_iter()
+        .map(|(name, oid)| {
+            let is_current = current.as_deref() == Some(name.as_str());
+            (name, is_current, oid)
+        })
+        .collect();
+    if current.is_none() {
+        if let Some(head) = repo.resolve_ref("HEAD")? {
+            lines.insert(0, (format!("(HEAD detached at {:.7})", head), true, head));
+        }
+    }
+    let width = lines
+        .iter()
+        .map(|(name, _, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    for (name, is_current, oid) in lines {
+        let marker = if is_current { '*' } else { ' ' };
+        if matches.is_present("verbose") {
+            let subject = repo.read_commit(&oid)?.subject();
+            println!(
+                "{} {:width$} {:.7} {}",
+                marker,
+                name,
+                oid,
+                subject,
+                width = width
+            );
+        } else {
+            println!("{} {}", marker, name);
+        }
+    }
+    Ok(())
+}
+
+// Shared by checkout and switch; `create_arg` is the option naming a branch to create, and
+// switch only detaches HEAD when asked to.
+fn checkout(matches: &ArgMatches, create_arg: &str, branch_only: bool) -> Result<(), GitError> {
+    let repo = GitRepository::find()?;
+    repo.require_worktree()?;
+    let force = matches.is_present("force");
+    let detach = matches.is_present("detach");
+    let message = match matches.value_of(create_arg) {
+        Some(new_branch) => {
+            let start = matches.value_of("target").unwrap_or("HEAD");
+            repo.checkout_new_branch(new_branch, start, force)?
+        }
+        None => {
+            let target = matches
+                .value_of("target")
+                .ok_or_else(|| GitError::GenericError("missing branch or commit".to_owned()))?;
+            let is_branch = repo
+                .resolve_ref(&format!("refs/heads/{}", target))?
+                .is_some();
+            if branch_only && !detach && !is_branch {
+                return Err(GitError::GenericError(format!(
+                    "a branch is expected, got '{}'; use --detach to switch to a commit",
+                    target
+                )));
+            }
+            repo.switch_to(target, detach, force)?
+        }
+    };
+    println!("{}", message);
+    Ok(())
+}
+
+fn status(matches: &ArgMatches) -> Result<(), GitError> {
+    let repo = GitRepository::find()?;
+    repo.require_worktree()?;
+    let untracked = match matches.value_of("untracked-files").unwrap_or("normal") {
+        "no" => None,
+        "all" => Some(true),
+        _ => Some(false),
+    };
+    let status = repo.status(untracked)?;
+    let show_branch = matches.is_present("branch");
+    match matches.value_of("porcelain") {
+        Some("v2") => print!("{}", status.porcelain_v2(show_branch)),
+        Some(_) => print!("{}", status.short_format(show_branch)),
+        None if matches.is_present("short") => print!("{}", status.short_format(show_branch)),
+        None => print_status_long(&status),
+    }
+    Ok(())
+}
+
+fn print_status_long(status: &Status) {
+    match (&status.branch, status.head) {
+        (Some(branch), _) => println!("On branch {}", branch),
+        (None, Some(head)) => println!("HEAD detached at {:.7}", head),
+        (None, None) => println!("Not currently on any branch."),
+    }
+    if status.head.is_none() {
+        println!("\nNo commits yet");
+    }
+    let describe = |code: char| match code {
+        'A' => "new file:   ",
+        'D' => "deleted:    ",
+        'T' => "typechange: ",
+        _ => "modified:   ",
+    };
+    if !status.unmerged.is_empty() {
+        println!("\nUnmerged paths:");
+        for entry in &status.unmerged {
+            println!(
+                "\t{:<16}{}",
+                format!("{}:", entry.description()),
+                entry.path
+            );
+        }
+    }
+    if status.has_staged() {
+        println!("\nChanges to be committed:");
+        for entry in status.entries.iter().filter(|e| e.staged != ' ') {
+            println!("\t{}{}", describe(entry.staged), entry.path);
+        }
+    }
+    if status.has_unstaged() {
+        println!("\nChanges not staged for commit:");
+        for entry in status.entries.iter().filter(|e| e.unstaged != ' ') {
+            println!("\t{}{}", describe(entry.unstaged), entry.path);
+        }
+    }
+    if !status.untracked.is_empty() {
+        println!("\nUntracked files:");
+        for path in &status.untracked {
+            println!("\t{}", path);
+        }
+    }
+    println!();
+    if !status.has_staged() && status.unmerged.is_empty() {
+        if status.has_unstaged() {
+            println!("no changes added to commit");
+        } else if !status.untracked.is_empty() {
+            println!("nothing added to commit but untracked files present");
+        } else {
+            println!("nothing to commit, working tree clean");
+        }
+    }
+}
+
+fn diff(matches: &ArgMatches) -> Result<(), GitError> {
+    let repo = GitRepository::find()?;
+    let context: usize = matches
+        .value_of("unified")
+        .unwrap()
+        .parse()
+        .map_err(|_| GitError::GenericError("-U takes a number of lines".to_owned()))?;
+    let paths: Vec<String> = matches
+        .values_of("paths")
+        .unwrap_or_default()
+        .map(str::to_owned)
+        .collect();
+    let mut commits: Vec<&str> = matches.values_of("commits").unwrap_or_default().collect();
+    if let [range] = commits.as_slice() {
+        if let Some((old, new)) = range.split_once("..") {
+            commits = vec![old, new];
+        }
+    }
+    let commit = |rev: &str| {
+        let rev = if rev.is_empty() { "HEAD" } else { rev };
+        repo.find_object(rev, &ObjType::Commit)
+    };
+
+    let diffs = match (commits.as_slice(), matches.is_present("cached")) {
+        ([], false) => repo.diff_index_worktree(&paths)?,
+        ([], true) => repo.diff_tree_index(None, &paths)?,
+        ([rev], true) => repo.diff_tree_index(Some(&commit(rev)?), &paths)?,
+        ([old, new], false) => repo.diff_commits(&commit(old)?, &commit(new)?, &paths)?,
+        _ => {
+            return Err(GitError::GenericError(
+                "usage: diff [--cached [<commit>]] [<commit> <commit>] [-- <path>...]".to_owned(),
+            ))
+        }
+    };
+    let mut stdout = std::io::stdout();
+    for diff in diffs {
+        stdout
+            .write_all(&diff.to_patch(context))
+            .map_err(|e| GitError::GenericError(format!("Could not write diff: {}", e)))?;
+    }
+    Ok(())
+}
+
+fn log(matches: &ArgMatches) -> Result<(), GitError> {
+    let repo = GitRepository::find()?;
+    let commit = |rev: &str| {
+        let rev = if rev.is_empty() { "HEAD" } else { rev };
+        repo.find_object(rev, &ObjType::Commit)
+    };
+    let (mut include, mut exclude) = (Vec::new(), Vec::new());
+    for rev in matches.values_of("revisions").unwrap_or_default() {
+        if let Some(rev) = rev.strip_prefix('^') {
+            exclude.push(commit(rev)?);
+        } else if let Some((old, new)) = rev.split_once("..") {
+            exclude.push(commit(old)?);
+            include.push(commit(new)?);
+        } else {
+            include.push(commit(rev)?);
+        }
+    }
+    if include.is_empty() {
+        match repo.resolve_ref("HEAD")? {
+            Some(head) => include.push(head),
+            None => {
+                return Err(GitError::GenericError(format!(
+                    "Your current branch '{}' does not have any commits yet",
+                    repo.current_branch().unwrap_or_default()
+                )))
+            }
+        }
+    }
+    let paths: Vec<String> = matches
+        .values_of("paths")
+        .unwrap_or_default()
+        .map(str::to_owned)
+        .collect();
+    let graph = matches.is_present("graph");
+    // Like git, drawing the graph implies topological order unless asked otherwise.
+    let order = if matches.is_present("topo-order") || graph && !matches.is_present("date-order") {
+        LogOrder::Topo
+    } else {
+        LogOrder::Date
+    };
+    let format = match matches.value_of("format") {
+        Some(spec) => PrettyFormat::parse(spec)?,
+        None if matches.is_present("oneline") => PrettyFormat::Oneline,
+        None => PrettyFormat::Medium,
+    };
+    let abbrev = matches.is_present("oneline") || matches.is_present("abbrev-commit");
+    let max_count = match matches.value_of("max-count") {
+        Some(count) => count
+            .parse()
+            .map_err(|_| GitError::GenericError(format!("Invalid count: {}", count)))?,
+        None => usize::MAX,
+    };
+    let decorations = if matches.is_present("decorate") {
+        repo.decorations()?
+    } else {
+        HashMap::new()
+    };
+
+    let mut entries = repo.log(&include, &exclude, &paths, order)?;
+    entries.truncate(max_count);
+    let mut renderer = GraphRenderer::default();
+    let mut pending_row = false;
+    let mut out = String::new();
+    for (i, entry) in entries.iter().enumerate() {
+        let names = decorations
+            .get(&entry.oid)
+            .map(Vec::as_slice)
+            .unwrap_or(&[]);
+        let text = format_commit(&entry.oid, &entry.commit, &format, abbrev, names);
+        if !graph {
+            if i > 0 {
+                out.push_str(format.separator());
+            }
+            out.push_str(&text);
+            continue;
+        }
+
+        let rows = renderer.next(&entry.oid, &entry.parents);
+        let terminated = format.separator().is_empty();
+        if i > 0 && !terminated {
+            // The previous text ended in a newline: the graph continues on the line after it.
+            if pending_row {
+                out.push_str(&rows.before);
+            }
+            out.push_str(format.separator());
+        }
+        let text = match text.strip_suffix('\n') {
+            Some(text) if terminated => text,
+            _ => text.as_str(),
+        };
+        // In separated formats the line after a final newline belongs to the text too.
+        if text.lines().count() > rows.transitions.len() + 1 || !terminated && text.ends_with('\n')
+        {
+            renderer.straighten();
+        }
+        let mut prefixes = std::iter::once(rows.commit)
+            .chain(rows.transitions)
+            .peekable();
+        let mut lines: Vec<String> = text
+            .lines()
+            .map(|line| {
+                let prefix = prefixes.next().unwrap_or_else(|| rows.after.clone());
+                format!("{}{}", prefix, line)
+            })
+            .collect();
+        lines.extend(prefixes);
+        out.push_str(&lines.join("\n"));
+        pending_row = text.ends_with('\n');
+        if pending_row {
+            out.push('\n');
+        }
+        // Terminated formats give the final empty line of the text a row of its own, drawn
+        // after the rest of the graph.
+        if terminated {
+            if pending_row {
+                out.push_str(&rows.after);
+            }
+            out.push('\n');
+        }
+    }
+    print!("{}", out);
+    Ok(())
+}
+
+fn repack(all: bool, delete: bool) -> Result<(), GitError> {
+    let repo = GitRepository::find()?;
+    let summary = repo.repack(all, delete)?;
+    match &summary.pack {
+        Some(pack) => println!(
+            "Packed {} objects ({} deltas) into {}",
+            summary.objects,
+            summary.deltas,
+            pack.file_name().unwrap().to_string_lossy()
+        ),
+        None => println!("Nothing new to pack."),
+    }
+    if summary.removed_packs > 0 {
+        println!("Removed {} redundant packs", summary.removed_packs);
+    }
+    if summary.removed_loose > 0 {
+        println!("Removed {} loose objects", summary.removed_loose);
+    }
+    Ok(())
+}
+
 fn hash_object(matches: &ArgMatches) -> Result<(), GitError> {
     let mut repo = None;
 
@@ -163,7 +1673,7 @@ fn hash_object(matches: &ArgMatches) -> Result<(), GitError> {
             repo = Some(found);
         })?;
     }
-    let objtype = ObjType::deserialize(matches.value_of("type").unwrap().as_bytes());
+    let objtype = ObjType::deserialize(matches.value_of("type").unwrap().as_bytes())?;
     let path = path!(matches.value_of("path").unwrap());
     let data = files::read_data(&path)?;
     let object = GitObject::new(objtype, &data);
@@ -175,29 +1685,709 @@ fn hash_object(matches: &ArgMatches) -> Result<(), GitError> {
     }
     Ok(())
 }
-fn ls_files() -> Result<(), GitError> {
-    let entries = GitRepository::read_index().unwrap();
+fn ls_files(matches: &ArgMatches) -> Result<(), GitError> {
+    let repo = GitRepository::find()?;
+    let entries = repo.read_index()?;
+    if matches.is_present("others") {
+        let tracked: BTreeSet<String> = entries.iter().map(|entry| entry.path.clone()).collect();
+        let collapse = matches.is_present("directory");
+        let untracked = match matches.is_present("exclude-standard") {
+            true => repo.untracked_files(&tracked, collapse, Some(&mut repo.ignores()?)),
+            false => repo.untracked_files(&tracked, collapse, None),
+        };
+        for path in untracked {
+            println!("{}", path);
+        }
+        if !matches.is_present("cached") {
+            return Ok(());
+        }
+    }
     for entry in entries {
         println!("{}", entry.path);
     }
     Ok(())
 }
 
-fn commit(matches: &ArgMatches) {
-    GitRepository::commit(
-        matches.value_of("message").unwrap().to_string(),
-        matches.value_of("author").unwrap().to_string(),
-    );
+fn check_ignore(matches: &ArgMatches) -> Result<(), GitError> {
+    let repo = GitRepository::find()?;
+    repo.require_worktree()?;
+    let verbose = matches.is_present("verbose");
+    let tracked: BTreeSet<String> = match matches.is_present("no-index") {
+        true => BTreeSet::new(),
+        false => repo
+            .read_index()?
+            .into_iter()
+            .map(|entry| entry.path)
+            .collect(),
+    };
+    let mut ignores = repo.ignores()?;
+    let mut matched_any = false;
+    for path in matches.values_of("paths").unwrap_or_default() {
+        let relative = repo.worktree_relative(Path::new(path))?;
+        // Tracked files, and directories holding them, are not subject to exclude rules.
+        let dir_prefix = format!("{}/", relative);
+        let has_tracked = tracked.contains(&relative)
+            || tracked
+                .range(dir_prefix.clone()..)
+                .next()
+                .is_some_and(|first| first.starts_with(&dir_prefix));
+        let pattern = match has_tracked {
+            true => None,
+            false => {
+                let is_dir = path.ends_with('/') || repo.worktree.join(&relative).is_dir();
+                ignores.matched(&relative, is_dir)
+            }
+        };
+        match pattern {
+            Some(pattern) if verbose => {
+                println!(
+                    "{}:{}:{}\t{}",
+                    pattern.source, pattern.line, pattern.text, path
+                );
+                matched_any = true;
+            }
+            Some(pattern) if !pattern.negated => {
+                println!("{}", path);
+                matched_any = true;
+            }
+            None if matches.is_present("non-matching") => println!("::\t{}", path),
+            _ => {}
+        }
+    }
+    if !matched_any {
+        std::process::exit(1);
+    }
+    Ok(())
 }
 
-fn git_add(sub_matches: &ArgMatches) {
-    let paths: Vec<String> = sub_matches
-        .value_of("path")
+fn clean(matches: &ArgMatches) -> Result<(), GitError> {
+    let repo = GitRepository::find()?;
+    repo.require_worktree()?;
+    let dry_run = matches.is_present("dry-run");
+    if !dry_run
+        && !matches.is_present("force")
+        && repo.config.get_bool("clean", None, "requireforce") != Some(false)
+    {
+        return Err(GitError::GenericError(
+            "clean.requireForce defaults to true and neither -n nor -f given; refusing to clean"
+                .to_owned(),
+        ));
+    }
+    let prefixes = matches
+        .values_of("paths")
+        .unwrap_or_default()
+        .map(|path| repo.worktree_relative(Path::new(path)))
+        .collect::<Result<Vec<String>, GitError>>()?;
+    let selected = |path: &str| {
+        prefixes.is_empty()
+            || prefixes.iter().any(|prefix| {
+                prefix.is_empty()
+                    || path.trim_end_matches('/') == prefix
+                    || path.starts_with(&format!("{}/", prefix))
+            })
+    };
+
+    let tracked: BTreeSet<String> = repo
+        .read_index()?
+        .into_iter()
+        .map(|entry| entry.path)
+        .collect();
+    let mut ignores = repo.ignores()?;
+    let mut ignores = match matches.is_present("ignored") {
+        true => None,
+        false => Some(&mut ignores),
+    };
+    let untracked = repo.untracked_files(&tracked, true, ignores.as_deref_mut());
+    // Files one by one, to find directories that hold ignored files, which stay.
+    let kept = repo.untracked_files(&tracked, false, ignores);
+    let every = repo.untracked_files(&tracked, false, None);
+
+    let mut removals = Vec::new();
+    for path in untracked.into_iter().filter(|path| selected(path)) {
+        if !path.ends_with('/') {
+            removals.push(path);
+        } else if matches.is_present("directories") {
+            clean_directory(&path, &kept, &every, &mut removals);
+        }
+    }
+    removals.sort();
+    for path in removals {
+        if dry_run {
+            println!("Would remove {}", path);
+            continue;
+        }
+        println!("Removing {}", path);
+        let full_path = repo.worktree.join(&path);
+        let removed = match path.ends_with('/') {
+            true => std::fs::remove_dir_all(&full_path),
+            false => std::fs::remove_file(&full_path),
+        };
+        removed.map_err(|_| GitError::PathError("Could not remove".to_owned(), full_path))?;
+    }
+    Ok(())
+}
+
+// Removes an untracked directory whole, unless it holds ignored files that must stay; then
+// what is not ignored inside goes, directory by directory.
+fn clean_directory(dir: &str, kept: &[String], every: &[String], removals: &mut Vec<String>) {
+    let inside = |files: &[String]| files.iter().filter(|file| file.starts_with(dir)).count();
+    if inside(kept) == inside(every) {
+        removals.push(dir.to_owned());
+        return;
+    }
+    let mut subdirs = BTreeSet::new();
+    for file in kept.iter().filter(|file| file.starts_with(dir)) {
+        match file[dir.len()..].split_once('/') {
+            Some((name, _)) => {
+                subdirs.insert(format!("{}{}/", dir, name));
+            }
+            None => removals.push(file.clone()),
+        }
+    }
+    for subdir in subdirs {
+        clean_directory(&subdir, kept, every, removals);
+    }
+}
+
+fn merge(matches: &ArgMatches) -> Result<(), GitError> {
+    let repo = GitRepository::find()?;
+    repo.require_worktree()?;
+    if matches.is_present("abort") {
+        return repo.abort_merge();
+    }
+    let name = matches
+        .value_of("commit")
+        .ok_or_else(|| GitError::GenericError("No commit specified to merge".to_owned()))?;
+    let theirs = repo.find_object(name, &ObjType::Commit)?;
+    let fast_forward = if matches.is_present("no-ff") {
+        FastForward::Never
+    } else if matches.is_present("ff-only") {
+        FastForward::Only
+    } else {
+        FastForward::Allow
+    };
+    let style = matches
+        .value_of("conflict")
+        .map(str::to_owned)
+        .or_else(|| repo.config.get("merge", None, "conflictstyle"))
+        .map(|style| {
+            ConflictStyle::parse(&style).ok_or_else(|| {
+                GitError::GenericError(format!("unknown conflict style '{}'", style))
+            })
+        })
+        .transpose()?
+        .unwrap_or(ConflictStyle::Merge);
+
+    match repo.merge(&theirs, name, fast_forward, style)? {
+        MergeResult::UpToDate => println!("Already up to date."),
+        MergeResult::FastForward(from, to) => {
+            if let Some(from) = from {
+                println!("Updating {:.7}..{:.7}", from, to);
+            }
+            println!("Fast-forward");
+        }
+        MergeResult::Merged {
+            messages,
+            conflicts,
+        } => {
+            for message in messages {
+                println!("{}", message);
+            }
+            if !conflicts.is_empty() {
+                println!("Automatic merge failed; fix conflicts and then commit the result.");
+                std::process::exit(1);
+            }
+            let message = match matches.value_of("message") {
+                Some(message) => message.to_owned(),
+                None => merge_message(&repo)?,
+            };
+            let author = commit_author(&repo, matches.value_of("author"), None)?;
+            let oid = repo.commit(message, &author, &repo.ident(Role::Committer)?)?;
+            print_committed(&repo, &oid)?;
+        }
+    }
+    Ok(())
+}
+
+fn merge_base(matches: &ArgMatches) -> Result<(), GitError> {
+    let repo = GitRepository::find()?;
+    let commits = matches
+        .values_of("commits")
+        .unwrap()
+        .map(|name| repo.find_object(name, &ObjType::Commit))
+        .collect::<Result<Vec<ObjectId>, GitError>>()?;
+    if matches.is_present("is-ancestor") {
+        if commits.len() != 2 {
+            return Err(GitError::GenericError(
+                "--is-ancestor takes exactly two commits".to_owned(),
+            ));
+        }
+        let code = if repo.is_ancestor(&commits[0], &commits[1])? {
+            0
+        } else {
+            1
+        };
+        std::process::exit(code);
+    }
+    let bases = if matches.is_present("octopus") {
+        repo.octopus_bases(&commits)?
+    } else if commits.len() < 2 {
+        return Err(GitError::GenericError(
+            "merge-base needs at least two commits".to_owned(),
+        ));
+    } else {
+        repo.merge_bases_many(&commits[0], &commits[1..])?
+    };
+    if bases.is_empty() {
+        std::process::exit(1);
+    }
+    let shown = if matches.is_present("all") {
+        bases.len()
+    } else {
+        1
+    };
+    for base in &bases[..shown] {
+        println!("{}", base);
+    }
+    Ok(())
+}
+
+fn reflog(matches: &ArgMatches) -> Result<(), GitError> {
+    let repo = GitRepository::find()?;
+    // Like git, a full ref name is needed to find the reflog; HEAD is the default.
+    let full_name = |name: &str| -> Result<String, GitError> {
+        match repo.dwim_ref(name)? {
+            Some((full_name, _)) => Ok(full_name),
+            None if repo.has_reflog(name) => Ok(name.to_owned()),
+            None => Err(GitError::GenericError(format!(
+                "ambiguous argument '{}': unknown revision",
+                name
+            ))),
+        }
+    };
+    match matches.subcommand() {
+        Some(("expire", sub_matches)) => {
+            let now = chrono::Utc::now().timestamp();
+            let setting = |arg: &str, key: &str, default: &str| {
+                let value = match sub_matches.value_of(arg) {
+                    Some(value) => value.to_owned(),
+                    None => repo
+                        .config
+                        .get("gc", None, key)
+                        .unwrap_or_else(|| default.to_owned()),
+                };
+                parse_expiry(&value, now)
+            };
+            let expire = setting("expire", "reflogexpire", "90.days.ago")?;
+            let unreachable = setting(
+                "expire-unreachable",
+                "reflogexpireunreachable",
+                "30.days.ago",
+            )?;
+            let names = if sub_matches.is_present("all") {
+                repo.reflog_refs()?
+            } else {
+                sub_matches
+                    .values_of("refs")
+                    .unwrap_or_default()
+                    .map(full_name)
+                    .collect::<Result<Vec<String>, GitError>>()?
+            };
+            if names.is_empty() {
+                return Err(GitError::GenericError(
+                    "no reflog specified to expire; pass refs or --all".to_owned(),
+                ));
+            }
+            let dry_run = sub_matches.is_present("dry-run");
+            let action = if dry_run { "would prune" } else { "prune" };
+            for name in names {
+                let pruned = repo.expire_reflog(&name, expire, unreachable, dry_run)?;
+                if sub_matches.is_present("verbose") {
+                    for entry in pruned {
+                        println!("{} {}: {:.7} {}", action, name, entry.new, entry.message);
+                    }
+                }
+            }
+            Ok(())
+        }
+        other => {
+            let name = other
+                .and_then(|(_, sub_matches)| sub_matches.value_of("ref"))
+                .unwrap_or("HEAD");
+            let entries = repo.read_reflog(&full_name(name)?)?;
+            for (i, entry) in entries.iter().rev().enumerate() {
+                println!("{:.7} {}@{{{}}}: {}", entry.new, name, i, entry.message);
+            }
+            Ok(())
+        }
+    }
+}
+
+fn tag(matches: &ArgMatches) -> Result<(), GitError> {
+    let repo = GitRepository::find()?;
+    let args: Vec<&str> = matches.values_of("args").unwrap_or_default().collect();
+    if matches.is_present("delete") {
+        for name in &args {
+            let oid = repo.delete_tag(name)?;
+            println!("Deleted tag '{}' (was {:.7})", name, oid);
+        }
+        return Ok(());
+    }
+    if args.is_empty() || matches.is_present("list") {
+        for (name, _) in repo.list_tags(&args)? {
+            println!("{}", name);
+        }
+        return Ok(());
+    }
+    if args.len() > 2 {
+        return Err(GitError::GenericError(
+            "tag takes a name and at most one commit".to_owned(),
+        ));
+    }
+    let target = repo.rev_parse(args.get(1).unwrap_or(&"HEAD"))?;
+    let message = match matches.value_of("message") {
+        Some(message) => Some(message),
+        None if matches.is_present("annotate") => {
+            return Err(GitError::GenericError(
+                "An annotated tag needs a message; pass it with -m".to_owned(),
+            ))
+        }
+        None => None,
+    };
+    let tagger = match message {
+        Some(_) => Some(repo.ident(Role::Committer)?),
+        None => None,
+    };
+    let annotation = tagger.as_ref().zip(message);
+    repo.create_tag(args[0], &target, annotation, matches.is_present("force"))?;
+    Ok(())
+}
+
+fn describe(matches: &ArgMatches) -> Result<(), GitError> {
+    let repo = GitRepository::find()?;
+    let abbrev: usize = matches
+        .value_of("abbrev")
         .unwrap()
-        .to_string()
-        .split_whitespace()
-        .map(str::to_string)
+        .parse()
+        .map_err(|_| GitError::GenericError("--abbrev takes a number".to_owned()))?;
+    let names: Vec<&str> = matches.values_of("commits").unwrap_or_default().collect();
+    let names = if names.is_empty() {
+        vec!["HEAD"]
+    } else {
+        names
+    };
+    // Like git, only a description of HEAD says whether the worktree is dirty.
+    let dirty = matches.is_present("dirty") && matches.values_of("commits").is_none() && {
+        let status = repo.status(None)?;
+        status.has_staged() || status.has_unstaged()
+    };
+    for name in names {
+        let commit = repo.find_object(name, &ObjType::Commit)?;
+        let mut description = match repo.describe(&commit, matches.is_present("tags"))? {
+            Some((tag, 0)) if !matches.is_present("long") => tag,
+            Some((tag, depth)) => format!("{}-{}-g{:.*}", tag, depth, abbrev, commit),
+            None if matches.is_present("always") => format!("{:.*}", abbrev, commit),
+            None => {
+                return Err(GitError::GenericError(format!(
+                    "No names found, cannot describe '{}'",
+                    name
+                )))
+            }
+        };
+        if dirty {
+            description.push_str("-dirty");
+        }
+        println!("{}", description);
+    }
+    Ok(())
+}
+
+fn config(matches: &ArgMatches) -> Result<(), GitError> {
+    // Unlike other commands, config also works outside a repository.
+    let gitdir = GitRepository::discover()?.map(|repo| repo.gitdir);
+    let gitdir = gitdir.as_deref();
+    let scoped = config_files(matches, gitdir)?;
+    let config = match &scoped {
+        Some((paths, scope, _)) => GitConfig::load_files(paths, *scope, gitdir)?,
+        None => GitConfig::load(gitdir)?,
+    };
+    let show_origin = matches.is_present("show-origin");
+    let kind = matches.value_of("type");
+    if matches.is_present("list") {
+        for entry in &config.entries {
+            if show_origin {
+                print!("{}\t", config_origin(entry));
+            }
+            match &entry.value {
+                Some(value) => println!("{}={}", entry.name(), value),
+                None => println!("{}", entry.name()),
+            }
+        }
+        return Ok(());
+    }
+
+    let name = matches
+        .value_of("name")
+        .ok_or_else(|| GitError::GenericError("missing key name".to_owned()))?;
+    let (section, subsection, key) = parse_name(name)?;
+    let subsection = subsection.as_deref();
+    let value = matches.value_of("value");
+    let get_all = matches.is_present("get-all");
+    let unset = matches.is_present("unset");
+    let unset_all = matches.is_present("unset-all");
+    let add = matches.is_present("add");
+    if matches.is_present("get") || get_all || (value.is_none() && !unset && !unset_all && !add) {
+        let entries = config.get_all(&section, subsection, &key);
+        let shown = match get_all {
+            true => &entries[..],
+            false => &entries[entries.len().saturating_sub(1)..],
+        };
+        if shown.is_empty() {
+            std::process::exit(1);
+        }
+        for entry in shown {
+            if show_origin {
+                print!("{}\t", config_origin(entry));
+            }
+            println!(
+                "{}",
+                typed_config_value(name, entry.value.as_deref(), kind)?
+            );
+        }
+        return Ok(());
+    }
+
+    let path = match scoped {
+        Some((_, _, path)) => path,
+        None => config.local_path()?,
+    };
+    let existing = GitConfig::load_files(std::slice::from_ref(&path), ConfigScope::Local, None)?
+        .get_all(&section, subsection, &key)
+        .len();
+    if unset || unset_all {
+        if existing == 0 {
+            std::process::exit(5);
+        }
+        if existing > 1 && !unset_all {
+            eprintln!("warning: {} has multiple values", name);
+            std::process::exit(5);
+        }
+        let edit = match unset_all {
+            true => ConfigEdit::UnsetAll,
+            false => ConfigEdit::Unset,
+        };
+        edit_file(&path, &section, subsection, &key, edit)?;
+        return Ok(());
+    }
+
+    let value = value.ok_or_else(|| GitError::GenericError("missing value".to_owned()))?;
+    let value = match kind {
+        Some(_) => typed_config_value(name, Some(value), kind)?,
+        None => value.to_owned(),
+    };
+    if add {
+        edit_file(&path, &section, subsection, &key, ConfigEdit::Add(&value))?;
+        return Ok(());
+    }
+    if existing > 1 {
+        eprintln!("warning: {} has multiple values", name);
+        eprintln!("error: cannot overwrite multiple values with a single value");
+        eprintln!("       Use --add or --unset-all to change {}.", name);
+        std::process::exit(5);
+    }
+    edit_file(&path, &section, subsection, &key, ConfigEdit::Set(&value))?;
+    Ok(())
+}
+
+// The files that --system, --global, --local, --worktree or --file limit `config` to, with
+// the one it writes. None means every file is read and the repository's is written.
+fn config_files(
+    matches: &ArgMatches,
+    gitdir: Option<&Path>,
+) -> Result<Option<(Vec<PathBuf>, ConfigScope, PathBuf)>, GitError> {
+    let in_repo = |option: &str| {
+        gitdir.ok_or_else(|| {
+            GitError::GenericError(format!(
+                "--{} can only be used inside a git repository",
+                option
+            ))
+        })
+    };
+    let scoped = if let Some(file) = matches.value_of("file") {
+        (
+            vec![PathBuf::from(file)],
+            ConfigScope::Command,
+            PathBuf::from(file),
+        )
+    } else if matches.is_present("system") {
+        let path = system_path().unwrap_or_else(|| PathBuf::from("/etc/gitconfig"));
+        (vec![path.clone()], ConfigScope::System, path)
+    } else if matches.is_present("global") {
+        (global_paths(), ConfigScope::Global, global_write_path()?)
+    } else if matches.is_present("local") {
+        let path = in_repo("local")?.join("config");
+        (vec![path.clone()], ConfigScope::Local, path)
+    } else if matches.is_present("worktree") {
+        let gitdir = in_repo("worktree")?;
+        let path =
+            match GitConfig::load(Some(gitdir))?.get_bool("extensions", None, "worktreeconfig") {
+                Some(true) => gitdir.join("config.worktree"),
+                _ => gitdir.join("config"),
+            };
+        (vec![path.clone()], ConfigScope::Worktree, path)
+    } else {
+        return Ok(None);
+    };
+    Ok(Some(scoped))
+}
+
+// Where a value came from, as --show-origin prints it.
+fn config_origin(entry: &ConfigEntry) -> String {
+    let path = match &entry.origin {
+        Some(path) => path,
+        None => return "command line:".to_owned(),
+    };
+    let cwd = std::env::current_dir().unwrap_or_default();
+    format!("file:{}", path.strip_prefix(&cwd).unwrap_or(path).display())
+}
+
+// A value as --type shows it: booleans as true or false and integers without their suffix.
+fn typed_config_value(
+    name: &str,
+    value: Option<&str>,
+    kind: Option<&str>,
+) -> Result<String, GitError> {
+    match kind {
+        Some("bool") => match parse_bool(value) {
+            Some(value) => Ok(value.to_string()),
+            None => Err(GitError::GenericError(format!(
+                "bad boolean config value '{}' for '{}'",
+                value.unwrap_or_default(),
+                name
+            ))),
+        },
+        Some("int") => match parse_int(value.unwrap_or_default()) {
+            Some(value) => Ok(value.to_string()),
+            None => Err(GitError::GenericError(format!(
+                "bad numeric config value '{}' for '{}': invalid unit",
+                value.unwrap_or_default(),
+                name
+            ))),
+        },
+        _ => Ok(value.unwrap_or_default().to_owned()),
+    }
+}
+
+fn commit(matches: &ArgMatches) -> Result<(), GitError> {
+    let repo = GitRepository::find()?;
+    repo.require_worktree()?;
+    // Concluding a merge, the message prepared by `merge` is the default.
+    let message = match matches.value_of("message") {
+        Some(message) => message.to_owned(),
+        None if !repo.merge_heads()?.is_empty() => merge_message(&repo)?,
+        None => {
+            return Err(GitError::GenericError(
+                "Aborting commit due to empty commit message.".to_owned(),
+            ))
+        }
+    };
+    let author = commit_author(&repo, matches.value_of("author"), matches.value_of("date"))?;
+    let committer = repo.ident(Role::Committer)?;
+    let oid = repo.commit(message, &author, &committer)?;
+    print_committed(&repo, &oid)
+}
+
+fn print_committed(repo: &GitRepository, oid: &ObjectId) -> Result<(), GitError> {
+    let (branch, _) = repo.follow_ref("HEAD")?;
+    let target = branch
+        .strip_prefix("refs/heads/")
+        .unwrap_or("detached HEAD");
+    println!("Committed to {}: {}", target, oid);
+    Ok(())
+}
+
+// MERGE_MSG without its comment lines or trailing newline.
+fn merge_message(repo: &GitRepository) -> Result<String, GitError> {
+    let path = repo.repo_path(Path::new("MERGE_MSG"));
+    let message = std::fs::read_to_string(&path)
+        .map_err(|_| GitError::PathError("Could not read MERGE_MSG".to_owned(), path))?;
+    let lines: Vec<&str> = message
+        .lines()
+        .filter(|line| !line.starts_with('#'))
         .collect();
+    Ok(lines.join("\n").trim_end().to_owned())
+}
+
+// The author of a new commit, with the name and email of `--author` and the date of `--date`
+// replacing those from the environment and config.
+fn commit_author(
+    repo: &GitRepository,
+    author: Option<&str>,
+    date: Option<&str>,
+) -> Result<Ident, GitError> {
+    let (name, email) = match author {
+        Some(author) => match Ident::parse(author) {
+            Ok(ident) if !strip_crud(&ident.name).is_empty() => {
+                (strip_crud(&ident.name), strip_crud(&ident.email))
+            }
+            _ => {
+                return Err(GitError::GenericError(format!(
+                    "--author '{}' is not 'Name <email>'",
+                    author
+                )))
+            }
+        },
+        None => repo.ident_name(Role::Author)?,
+    };
+    let (time, offset) = match date {
+        Some(date) => parse_ident_date(date)?,
+        None => ident_date(Role::Author)?,
+    };
+    Ok(Ident {
+        name,
+        email,
+        time,
+        offset,
+    })
+}
 
-    GitRepository::add_git(&paths)
+fn git_add(sub_matches: &ArgMatches) -> Result<(), GitError> {
+    let pathspecs: Vec<String> = sub_matches
+        .values_of("pathspec")
+        .map(|values| values.map(str::to_owned).collect())
+        .unwrap_or_default();
+    let repo = GitRepository::find()?;
+    repo.require_worktree()?;
+    if sub_matches.is_present("patch") {
+        let stdin = std::io::stdin();
+        return repo.add_patch(&pathspecs, &mut stdin.lock(), &mut std::io::stdout());
+    }
+    // Without a pathspec only -A and -u have something to work on: the whole tree.
+    if pathspecs.is_empty() && !sub_matches.is_present("all") && !sub_matches.is_present("update") {
+        eprintln!("Nothing specified, nothing added.");
+        eprintln!("hint: Maybe you wanted to say 'git add .'?");
+        return Ok(());
+    }
+
+    let options = AddOptions {
+        force: sub_matches.is_present("force"),
+        update: sub_matches.is_present("update"),
+        dry_run: sub_matches.is_present("dry-run"),
+    };
+    let result = repo.add_git(&pathspecs, &options)?;
+    if options.dry_run || sub_matches.is_present("verbose") {
+        for change in &result.changes {
+            match change {
+                AddChange::Add(path) => println!("add '{}'", path),
+                AddChange::Remove(path) => println!("remove '{}'", path),
+            }
+        }
+    }
+    if !result.ignored.is_empty() {
+        return Err(GitError::GenericError(format!(
+            "The following paths are ignored by one of your .gitignore files:\n{}\nhint: Use -f if you really want to add them.",
+            result.ignored.join("\n")
+        )));
+    }
+    Ok(())
 }
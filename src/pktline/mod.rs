@@ -0,0 +1,115 @@
+use crate::error::GitError;
+use std::io::{self, Read, Write};
+
+// The most a packet may carry: 65520 bytes less the four of its length.
+pub const MAX_DATA: usize = 65516;
+
+// Side-band channels multiplexed into the data of packets.
+pub const BAND_DATA: u8 = 1;
+pub const BAND_PROGRESS: u8 = 2;
+pub const BAND_ERROR: u8 = 3;
+
+// A pkt-line: data prefixed with its length as four hex digits, or one of the special packets
+// that have no data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet {
+    Data(Vec<u8>),
+    // 0000: the end of a message.
+    Flush,
+    // 0001: separates the sections of a protocol v2 message.
+    Delim,
+    // 0002: the end of a response for stateless connections.
+    ResponseEnd,
+}
+
+impl Packet {
+    // The data as a line of text, without its trailing newline.
+    pub fn text(&self) -> Option<String> {
+        match self {
+            Packet::Data(data) => {
+                let data = data.strip_suffix(b"\n").unwrap_or(data);
+                Some(String::from_utf8_lossy(data).into_owned())
+            }
+            _ => None,
+        }
+    }
+}
+
+// Reads the next packet, or None at the end of the stream.
+pub fn read_packet<R: Read>(input: &mut R) -> io::Result<Option<Packet>> {
+    let mut length = [0u8; 4];
+    match input.read_exact(&mut length) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let length = std::str::from_utf8(&length)
+        .ok()
+        .and_then(|hex| usize::from_str_radix(hex, 16).ok())
+        .ok_or_else(|| invalid("Invalid packet length"))?;
+    match length {
+        0 => Ok(Some(Packet::Flush)),
+        1 => Ok(Some(Packet::Delim)),
+        2 => Ok(Some(Packet::ResponseEnd)),
+        3 => Err(invalid("Invalid packet length")),
+        _ => {
+            let mut data = vec![0; length - 4];
+            input.read_exact(&mut data)?;
+            Ok(Some(Packet::Data(data)))
+        }
+    }
+}
+
+// Reads the packets up to the next flush or delimiter, which is returned last. The end of the
+// stream counts as a flush.
+pub fn read_section<R: Read>(input: &mut R) -> io::Result<(Vec<Packet>, Packet)> {
+    let mut packets = Vec::new();
+    loop {
+        match read_packet(input)? {
+            Some(Packet::Data(data)) => packets.push(Packet::Data(data)),
+            Some(end) => return Ok((packets, end)),
+            None => return Ok((packets, Packet::Flush)),
+        }
+    }
+}
+
+pub fn write_packet<W: Write>(output: &mut W, data: &[u8]) -> io::Result<()> {
+    if data.len() > MAX_DATA {
+        return Err(invalid("Packet too long"));
+    }
+    write!(output, "{:04x}", data.len() + 4)?;
+    output.write_all(data)
+}
+
+// Writes a line of text as a packet, adding the newline.
+pub fn write_line<W: Write>(output: &mut W, line: &str) -> io::Result<()> {
+    write_packet(output, format!("{}\n", line).as_bytes())
+}
+
+pub fn write_flush<W: Write>(output: &mut W) -> io::Result<()> {
+    output.write_all(b"0000")
+}
+
+pub fn write_delim<W: Write>(output: &mut W) -> io::Result<()> {
+    output.write_all(b"0001")
+}
+
+// Sends data on a side-band channel, split into as many packets as it takes.
+pub fn write_band<W: Write>(output: &mut W, band: u8, data: &[u8]) -> io::Result<()> {
+    for chunk in data.chunks(MAX_DATA - 1) {
+        let mut packet = Vec::with_capacity(chunk.len() + 1);
+        packet.push(band);
+        packet.extend(chunk);
+        write_packet(output, &packet)?;
+    }
+    Ok(())
+}
+
+// Wraps a failure to read or write the connection.
+pub fn error(e: io::Error) -> GitError {
+    GitError::GenericError(format!("Protocol error: {}", e))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
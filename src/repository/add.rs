@@ -0,0 +1,425 @@
+use super::diff::FileDiff;
+use super::object::{GitObject, ObjType};
+use super::{GitIndex, GitRepository};
+use crate::diff::{self, Edit, Hunk};
+use crate::error::GitError;
+use std::collections::{BTreeSet, HashSet};
+use std::fs;
+use std::io::{BufRead, Write};
+
+// How `add` picks the files it stages.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AddOptions {
+    // Stage untracked files that are ignored as well.
+    pub force: bool,
+    // -u: only stage files the index already tracks.
+    pub update: bool,
+    // Work out what would be staged without touching the index or the object store.
+    pub dry_run: bool,
+}
+
+// What `add` did, or would do, to a path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddChange {
+    Add(String),
+    Remove(String),
+}
+
+// The outcome of `add`: the paths it staged and the pathspecs it refused because they name
+// ignored files.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AddResult {
+    pub changes: Vec<AddChange>,
+    pub ignored: Vec<String>,
+}
+
+const PATCH_HELP: [(char, &str); 10] = [
+    ('y', "stage this hunk"),
+    ('n', "do not stage this hunk"),
+    (
+        'q',
+        "quit; do not stage this hunk or any of the remaining ones",
+    ),
+    ('a', "stage this hunk and all later hunks in the file"),
+    (
+        'd',
+        "do not stage this hunk or any of the later hunks in the file",
+    ),
+    ('j', "leave this hunk undecided, see next undecided hunk"),
+    ('J', "leave this hunk undecided, see next hunk"),
+    (
+        'k',
+        "leave this hunk undecided, see previous undecided hunk",
+    ),
+    ('K', "leave this hunk undecided, see previous hunk"),
+    ('s', "split the current hunk into smaller hunks"),
+];
+
+impl GitRepository {
+    // Stages the files the pathspecs match: new and modified files are added and tracked files
+    // that are gone leave the index. Untracked files that are ignored are only added with
+    // `force`; naming one explicitly puts it in the result's `ignored`.
+    pub fn add_git(&self, args: &[String], options: &AddOptions) -> Result<AddResult, GitError> {
+        let pathspec = self.pathspec(args)?;
+        let index = self.read_index()?;
+        let tracked: BTreeSet<String> = index.iter().map(|entry| entry.path.clone()).collect();
+        let mut ignores = self.ignores()?;
+        let untracked = match (options.update, options.force) {
+            (true, _) => Vec::new(),
+            (false, true) => self.untracked_files(&tracked, false, None),
+            (false, false) => self.untracked_files(&tracked, false, Some(&mut ignores)),
+        };
+        let selected: BTreeSet<String> = tracked
+            .iter()
+            .chain(&untracked)
+            .filter(|path| pathspec.matches(path))
+            .cloned()
+            .collect();
+
+        // A pathspec naming an ignored file or directory is refused rather than left unmatched.
+        let mut result = AddResult::default();
+        for item in &pathspec.items {
+            let prefix = format!("{}/", item.path);
+            let has_tracked = tracked.contains(&item.path)
+                || tracked
+                    .range(prefix.clone()..)
+                    .next()
+                    .is_some_and(|first| first.starts_with(&prefix));
+            if options.force
+                || options.update
+                || item.exclude
+                || item.has_wildcard()
+                || item.path.is_empty()
+                || has_tracked
+            {
+                continue;
+            }
+            if let Ok(stat) = fs::symlink_metadata(self.worktree.join(&item.path)) {
+                if ignores.is_ignored(&item.path, stat.is_dir()) {
+                    result.ignored.push(item.original.clone());
+                }
+            }
+        }
+        let matched: Vec<String> = selected.iter().cloned().collect();
+        for item in pathspec.unmatched(&matched) {
+            if item.has_wildcard() || !self.worktree_path_exists(&item.path) {
+                return Err(GitError::GenericError(format!(
+                    "pathspec '{}' did not match any files",
+                    item.original
+                )));
+            }
+        }
+
+        let mut entries = Vec::new();
+        let mut staged = BTreeSet::new();
+        for entry in index {
+            // Unchanged entries stay as they are; conflicts are resolved by the new entry.
+            if !selected.contains(&entry.path)
+                || (entry.stage() == 0 && self.worktree_matches_index(&entry)?)
+            {
+                entries.push(entry);
+            } else if staged.insert(entry.path.clone()) {
+                result
+                    .changes
+                    .push(match self.worktree_path_exists(&entry.path) {
+                        true => AddChange::Add(entry.path),
+                        false => AddChange::Remove(entry.path),
+                    });
+            }
+        }
+        for path in untracked.into_iter().filter(|path| selected.contains(path)) {
+            result.changes.push(AddChange::Add(path));
+        }
+        if options.dry_run {
+            return Ok(result);
+        }
+
+        for change in &result.changes {
+            if let AddChange::Add(path) = change {
+                let full_path = self.worktree.join(path);
+                let stat = fs::symlink_metadata(&full_path).map_err(|_| {
+                    GitError::PathError("Could not stat file".to_owned(), full_path.clone())
+                })?;
+                let obj = GitObject::new(ObjType::Blob, &self.read_worktree_file(path)?);
+                let oid = GitRepository::write_object(self, &obj)?;
+                entries.push(GitIndex::from_metadata(path, oid, &stat));
+            }
+        }
+        self.write_index(&entries)?;
+        Ok(result)
+    }
+
+    // Stages parts of the unstaged changes to the files the pathspecs match. Each hunk is shown
+    // on `output` and the answer read from `input` decides whether it goes into the index.
+    pub fn add_patch<R: BufRead, W: Write>(
+        &self,
+        args: &[String],
+        input: &mut R,
+        output: &mut W,
+    ) -> Result<(), GitError> {
+        let pathspec = self.pathspec(args)?;
+        let diffs: Vec<FileDiff> = self
+            .diff_index_worktree(&[])?
+            .into_iter()
+            .filter(|diff| pathspec.matches(&diff.path))
+            // Mode changes alone have no hunks to pick from.
+            .filter(|diff| {
+                diff.old.as_ref().map(|side| side.oid) != diff.new.as_ref().map(|side| side.oid)
+            })
+            .filter(|diff| {
+                [&diff.old, &diff.new]
+                    .iter()
+                    .flat_map(|side| side.as_ref())
+                    .all(|side| !diff::is_binary(&side.data))
+            })
+            .collect();
+        if diffs.is_empty() {
+            write_output(output, b"No changes.\n")?;
+            return Ok(());
+        }
+
+        let mut index = self.read_index()?;
+        let mut changed = false;
+        for diff in &diffs {
+            let old_data = diff.old.as_ref().map_or(&[][..], |side| &side.data);
+            let new_data = diff.new.as_ref().map_or(&[][..], |side| &side.data);
+            let (a, b) = (diff::split_lines(old_data), diff::split_lines(new_data));
+            let edits = diff::myers(&a, &b);
+            let hunks = diff::hunks(&edits, 3);
+            // The patch header is everything up to the first hunk.
+            let patch = diff.to_patch(3);
+            let header_end = patch
+                .windows(4)
+                .position(|window| window == b"\n@@ ")
+                .map_or(patch.len(), |i| i + 1);
+            // A deletion is staged whole, so the rest of its header goes with its one hunk.
+            let deletion = diff.new.is_none();
+            let split = match deletion {
+                true => patch.iter().position(|&c| c == b'\n').map_or(0, |i| i + 1),
+                false => header_end,
+            };
+            write_output(output, &patch[..split])?;
+
+            let prompt = Prompt {
+                a: &a,
+                b: &b,
+                head: &patch[split..header_end],
+                deletion,
+            };
+            let (chosen, quit) = choose_hunks(hunks, &prompt, input, output)?;
+            if !chosen.is_empty() {
+                changed = true;
+                match diff.new {
+                    None => index.retain(|entry| entry.path != diff.path),
+                    Some(_) => {
+                        let data = apply_hunks(&edits, &chosen, &a, &b);
+                        let oid = GitRepository::write_object(
+                            self,
+                            &GitObject::new(ObjType::Blob, &data),
+                        )?;
+                        // The entry's stat data no longer describes the worktree file.
+                        if let Some(entry) = index
+                            .iter_mut()
+                            .find(|entry| entry.path == diff.path && entry.stage() == 0)
+                        {
+                            entry.oid = oid;
+                            entry.size = data.len() as u32;
+                            entry.mtime_s = 0;
+                            entry.mtime_n = 0;
+                        }
+                    }
+                }
+            }
+            if quit {
+                break;
+            }
+        }
+        if changed {
+            self.write_index(&index)?;
+        }
+        Ok(())
+    }
+}
+
+// The file whose hunks `choose_hunks` asks about: its old and new lines, the header lines
+// shown with each hunk and whether the whole file is being deleted.
+struct Prompt<'a> {
+    a: &'a [&'a [u8]],
+    b: &'a [&'a [u8]],
+    head: &'a [u8],
+    deletion: bool,
+}
+
+// Asks about each hunk of a file until all are decided, returning the accepted ones and
+// whether the user quit.
+fn choose_hunks<R: BufRead, W: Write>(
+    mut hunks: Vec<Hunk>,
+    prompt: &Prompt,
+    input: &mut R,
+    output: &mut W,
+) -> Result<(Vec<Hunk>, bool), GitError> {
+    let mut decisions: Vec<Option<bool>> = vec![None; hunks.len()];
+    let mut quit = false;
+    let mut i = 0;
+    loop {
+        if i >= hunks.len() {
+            i = 0;
+        }
+        let previous = (0..i).rev().find(|&j| decisions[j].is_none());
+        let next = (i + 1..hunks.len()).find(|&j| decisions[j].is_none());
+        if previous.is_none() && next.is_none() && decisions[i].is_some() {
+            break;
+        }
+        let splittable = !prompt.deletion && hunks[i].split().len() > 1;
+        let mut keys = vec!['y', 'n', 'q', 'a', 'd'];
+        if !prompt.deletion {
+            let available = [
+                ('k', previous.is_some()),
+                ('K', i > 0),
+                ('j', next.is_some()),
+                ('J', i + 1 < hunks.len()),
+                ('s', splittable),
+            ];
+            keys.extend(available.iter().filter(|(_, ok)| *ok).map(|(key, _)| key));
+        }
+
+        let mut out = prompt.head.to_vec();
+        diff::write_hunk(&hunks[i], prompt.a, prompt.b, &mut out);
+        let keys_list: Vec<String> = keys
+            .iter()
+            .chain(&['?'])
+            .map(|key| key.to_string())
+            .collect();
+        out.extend(
+            format!(
+                "({}/{}) {} [{}]? ",
+                i + 1,
+                hunks.len(),
+                if prompt.deletion {
+                    "Stage deletion"
+                } else {
+                    "Stage this hunk"
+                },
+                keys_list.join(",")
+            )
+            .as_bytes(),
+        );
+        write_output(output, &out)?;
+
+        let mut answer = String::new();
+        let read = input
+            .read_line(&mut answer)
+            .map_err(|e| GitError::GenericError(format!("Could not read answer: {}", e)))?;
+        let key = match answer.trim().chars().next() {
+            Some(key) => key,
+            None if read == 0 => 'q',
+            None => continue,
+        };
+        let message = match key {
+            'y' | 'n' => {
+                decisions[i] = Some(key == 'y');
+                i = next.unwrap_or(hunks.len());
+                None
+            }
+            'a' | 'd' => {
+                for decision in decisions[i..].iter_mut().filter(|d| d.is_none()) {
+                    *decision = Some(key == 'a');
+                }
+                i = hunks.len();
+                None
+            }
+            'q' => {
+                for decision in decisions.iter_mut().filter(|d| d.is_none()) {
+                    *decision = Some(false);
+                }
+                quit = true;
+                break;
+            }
+            'j' => match next {
+                Some(j) => {
+                    i = j;
+                    None
+                }
+                None => Some("No next hunk\n".to_owned()),
+            },
+            'J' => match i + 1 < hunks.len() {
+                true => {
+                    i += 1;
+                    None
+                }
+                false => Some("No next hunk\n".to_owned()),
+            },
+            'k' => match previous {
+                Some(j) => {
+                    i = j;
+                    None
+                }
+                None => Some("No previous hunk\n".to_owned()),
+            },
+            'K' => match i > 0 {
+                true => {
+                    i -= 1;
+                    None
+                }
+                false => Some("No previous hunk\n".to_owned()),
+            },
+            's' => match splittable {
+                true => {
+                    let parts = hunks[i].split();
+                    let count = parts.len();
+                    hunks.splice(i..=i, parts);
+                    decisions.splice(i..=i, vec![None; count]);
+                    Some(format!("Split into {} hunks.\n", count))
+                }
+                false => Some("Sorry, cannot split this hunk\n".to_owned()),
+            },
+            // "?" and anything unknown print the help.
+            _ => {
+                let help: Vec<String> = PATCH_HELP
+                    .iter()
+                    .filter(|(key, _)| keys.contains(key))
+                    .map(|(key, text)| format!("{} - {}\n", key, text))
+                    .chain(std::iter::once("? - print help\n".to_owned()))
+                    .collect();
+                Some(help.concat())
+            }
+        };
+        if let Some(message) = message {
+            write_output(output, message.as_bytes())?;
+        }
+    }
+    write_output(output, b"\n")?;
+    let chosen = hunks
+        .into_iter()
+        .zip(decisions)
+        .filter(|(_, decision)| *decision == Some(true))
+        .map(|(hunk, _)| hunk)
+        .collect();
+    Ok((chosen, quit))
+}
+
+// Rebuilds `b` from `a`, keeping only the changes of the chosen hunks.
+fn apply_hunks(edits: &[Edit], chosen: &[Hunk], a: &[&[u8]], b: &[&[u8]]) -> Vec<u8> {
+    let accepted: HashSet<Edit> = chosen
+        .iter()
+        .flat_map(|hunk| hunk.edits.iter().copied())
+        .filter(|edit| !matches!(edit, Edit::Equal(..)))
+        .collect();
+    let mut data = Vec::new();
+    for edit in edits {
+        match *edit {
+            Edit::Equal(i, _) => data.extend(a[i]),
+            Edit::Delete(i) if !accepted.contains(edit) => data.extend(a[i]),
+            Edit::Insert(j) if accepted.contains(edit) => data.extend(b[j]),
+            _ => {}
+        }
+    }
+    data
+}
+
+fn write_output<W: Write>(output: &mut W, data: &[u8]) -> Result<(), GitError> {
+    output
+        .write_all(data)
+        .and_then(|_| output.flush())
+        .map_err(|e| GitError::GenericError(format!("Could not write output: {}", e)))
+}
@@ -0,0 +1,129 @@
+use super::object::ObjType;
+use super::oid::ObjectId;
+use super::refs::check_ref_name;
+use super::GitRepository;
+use crate::error::GitError;
+
+impl GitRepository {
+    // Lists local branches by short name.
+    pub fn list_branches(&self) -> Result<Vec<(String, ObjectId)>, GitError> {
+        Ok(self
+            .list_refs("refs/heads/")?
+            .into_iter()
+            .map(|(name, oid)| (name["refs/heads/".len()..].to_owned(), oid))
+            .collect())
+    }
+
+    // Creates a branch pointing at the commit `start` resolves to. An existing branch is only
+    // overwritten with `force`, and never if it is checked out.
+    pub fn create_branch(
+        &self,
+        name: &str,
+        start: &str,
+        force: bool,
+    ) -> Result<ObjectId, GitError> {
+        let full_name = branch_ref(name)?;
+        let oid = self.find_object(start, &ObjType::Commit)?;
+        let exists = self.resolve_ref(&full_name)?.is_some();
+        if exists {
+            if !force {
+                self.check_new_branch(name)?;
+            }
+            if self.current_branch().as_deref() == Some(name) {
+                return Err(GitError::GenericError(format!(
+                    "cannot force update the current branch '{}'",
+                    name
+                )));
+            }
+        }
+        let message = match exists {
+            true => format!("branch: Reset to {}", start),
+            false => format!("branch: Created from {}", start),
+        };
+        self.update_ref(&full_name, &oid, None, false, &message)?;
+        Ok(oid)
+    }
+
+    // Checks that `name` is a valid branch name that is not taken yet.
+    pub fn check_new_branch(&self, name: &str) -> Result<(), GitError> {
+        if self.resolve_ref(&branch_ref(name)?)?.is_some() {
+            return Err(GitError::GenericError(format!(
+                "a branch named '{}' already exists",
+                name
+            )));
+        }
+        Ok(())
+    }
+
+    // Renames a branch along with its reflog and config section, moving HEAD if it pointed to it.
+    pub fn rename_branch(&mut self, old: &str, new: &str, force: bool) -> Result<(), GitError> {
+        let old_ref = branch_ref(old)?;
+        let new_ref = branch_ref(new)?;
+        let oid = self
+            .resolve_ref(&old_ref)?
+            .ok_or_else(|| GitError::GenericError(format!("branch '{}' not found", old)))?;
+        if old_ref != new_ref && self.resolve_ref(&new_ref)?.is_some() && !force {
+            return Err(GitError::GenericError(format!(
+                "a branch named '{}' already exists",
+                new
+            )));
+        }
+
+        // The reflog moves first, so deleting the old ref does not take it along.
+        let message = format!("Branch: renamed {} to {}", old_ref, new_ref);
+        if old_ref != new_ref {
+            self.delete_reflog(&new_ref)?;
+            self.rename_reflog(&old_ref, &new_ref)?;
+        }
+        self.update_ref(&new_ref, &oid, None, false, &message)?;
+        if old_ref != new_ref {
+            self.delete_ref(&old_ref, None, false)?;
+        }
+        if self.symbolic_ref("HEAD")?.as_deref() == Some(old_ref.as_str()) {
+            self.set_symbolic_ref("HEAD", &new_ref, Some(&message))?;
+        }
+        self.config.rename_section("branch", Some(old), Some(new))
+    }
+
+    // Deletes a branch. Unless forced, the branch must be merged into HEAD.
+    pub fn delete_branch(&mut self, name: &str, force: bool) -> Result<ObjectId, GitError> {
+        let full_name = branch_ref(name)?;
+        let oid = self
+            .resolve_ref(&full_name)?
+            .ok_or_else(|| GitError::GenericError(format!("branch '{}' not found", name)))?;
+        if self.current_branch().as_deref() == Some(name) {
+            return Err(GitError::GenericError(format!(
+                "Cannot delete branch '{}' checked out at '{}'",
+                name,
+                self.worktree.display()
+            )));
+        }
+        if !force {
+            let merged = match self.resolve_ref("HEAD")? {
+                Some(head) => self.is_ancestor(&oid, &head)?,
+                None => false,
+            };
+            if !merged {
+                return Err(GitError::GenericError(format!(
+                    "The branch '{}' is not fully merged.\nIf you are sure you want to delete it, run 'branch -D {}'",
+                    name, name
+                )));
+            }
+        }
+        self.delete_ref(&full_name, Some(&oid), false)?;
+        self.config.rename_section("branch", Some(name), None)?;
+        Ok(oid)
+    }
+}
+
+fn branch_ref(name: &str) -> Result<String, GitError> {
+    let full_name = format!("refs/heads/{}", name);
+    if name.starts_with('-') || name == "HEAD" {
+        return Err(GitError::GenericError(format!(
+            "'{}' is not a valid branch name",
+            name
+        )));
+    }
+    check_ref_name(&full_name)?;
+    Ok(full_name)
+}
@@ -0,0 +1,164 @@
+use super::object::ObjType;
+use super::oid::ObjectId;
+use super::{GitIndex, GitRepository};
+use crate::error::GitError;
+use std::collections::{BTreeMap, BTreeSet};
+
+impl GitRepository {
+    // Moves the worktree and index from HEAD's tree to the tree of `commit`. Paths that are the
+    // same in both trees keep any local changes; unless forced, it refuses to overwrite changes
+    // to paths that differ.
+    pub fn checkout_commit(&self, commit: &ObjectId, force: bool) -> Result<(), GitError> {
+        let old = self.commit_tree_entries(None)?;
+        let new = self.commit_tree_entries(Some(commit))?;
+        let index = self.read_index()?;
+        if !force && index.iter().any(|entry| entry.stage() != 0) {
+            return Err(GitError::GenericError(
+                "you need to resolve your current index first".to_owned(),
+            ));
+        }
+        let mut index: BTreeMap<String, GitIndex> = index
+            .into_iter()
+            .filter(|entry| entry.stage() == 0)
+            .map(|entry| (entry.path.clone(), entry))
+            .collect();
+
+        let changed: BTreeSet<&String> = old
+            .keys()
+            .chain(new.keys())
+            .filter(|path| old.get(*path) != new.get(*path))
+            .collect();
+
+        if force {
+            let stale: Vec<String> = index
+                .keys()
+                .chain(old.keys())
+                .filter(|path| !new.contains_key(*path))
+                .cloned()
+                .collect();
+            for path in stale {
+                if old.contains_key(&path) {
+                    self.remove_worktree_file(&path)?;
+                }
+                index.remove(&path);
+            }
+            for (path, (mode, oid)) in &new {
+                index.insert(path.clone(), self.checkout_entry(path, *mode, oid)?);
+            }
+        } else {
+            // Tracked files the switch deletes may stand where the new tree needs a directory,
+            // or fill a directory where it needs a file.
+            let removed: BTreeSet<String> = old
+                .keys()
+                .filter(|path| !new.contains_key(*path) && index.contains_key(*path))
+                .cloned()
+                .collect();
+            let mut conflicts = Vec::new();
+            let mut untracked = BTreeSet::new();
+            for path in &changed {
+                let staged = index
+                    .get(*path)
+                    .map(|entry| (GitRepository::tree_mode(entry.mode), entry.oid));
+                let clean = match index.get(*path) {
+                    Some(entry) => {
+                        (staged.as_ref() == old.get(*path) || staged.as_ref() == new.get(*path))
+                            && self.worktree_matches_index(entry)?
+                    }
+                    None => true,
+                };
+                if !clean {
+                    conflicts.push(path.to_string());
+                }
+                // Untracked files must not be overwritten by ones from the new tree.
+                if new.contains_key(*path) {
+                    if !index.contains_key(*path) && self.worktree_mode(path).is_some() {
+                        untracked.insert(path.to_string());
+                    }
+                    untracked.extend(self.untracked_in_the_way(path, &removed));
+                }
+            }
+            if !conflicts.is_empty() {
+                return Err(GitError::GenericError(format!(
+                    "Your local changes to the following files would be overwritten by checkout:\n\t{}\nPlease commit your changes before you switch branches.",
+                    conflicts.join("\n\t")
+                )));
+            }
+            if !untracked.is_empty() {
+                return Err(GitError::GenericError(format!(
+                    "The following untracked working tree files would be overwritten by checkout:\n\t{}\nPlease move or remove them before you switch branches.",
+                    untracked.into_iter().collect::<Vec<_>>().join("\n\t")
+                )));
+            }
+            for path in changed {
+                match new.get(path) {
+                    Some((mode, oid)) => {
+                        index.insert(path.clone(), self.checkout_entry(path, *mode, oid)?);
+                    }
+                    None => {
+                        self.remove_worktree_file(path)?;
+                        index.remove(path);
+                    }
+                }
+            }
+        }
+
+        let entries: Vec<GitIndex> = index.into_values().collect();
+        self.write_index(&entries)?;
+        Ok(())
+    }
+
+    // Checks out a branch and attaches HEAD to it, or with `detach` (or when `target` is not a
+    // branch) checks out a commit and detaches HEAD there. Returns a message for the user.
+    pub fn switch_to(&self, target: &str, detach: bool, force: bool) -> Result<String, GitError> {
+        let branch_ref = format!("refs/heads/{}", target);
+        if !detach {
+            if let Some(oid) = self.resolve_ref(&branch_ref)? {
+                if self.symbolic_ref("HEAD")?.as_deref() == Some(branch_ref.as_str()) {
+                    return Ok(format!("Already on '{}'", target));
+                }
+                let message = self.checkout_message(target)?;
+                self.checkout_commit(&oid, force)?;
+                self.set_symbolic_ref("HEAD", &branch_ref, Some(&message))?;
+                return Ok(format!("Switched to branch '{}'", target));
+            }
+        }
+        let oid = self.find_object(target, &ObjType::Commit)?;
+        let message = self.checkout_message(target)?;
+        self.checkout_commit(&oid, force)?;
+        self.update_ref("HEAD", &oid, None, false, &message)?;
+        Ok(format!(
+            "HEAD is now at {:.7} {}",
+            oid,
+            self.read_commit(&oid)?.subject()
+        ))
+    }
+
+    // Creates a branch at `start`, checks it out and attaches HEAD to it, as `checkout -b`
+    // does. The branch is checked before the worktree moves, so a bad name changes nothing.
+    pub fn checkout_new_branch(
+        &self,
+        name: &str,
+        start: &str,
+        force: bool,
+    ) -> Result<String, GitError> {
+        self.check_new_branch(name)?;
+        let oid = self.find_object(start, &ObjType::Commit)?;
+        let message = self.checkout_message(name)?;
+        self.checkout_commit(&oid, force)?;
+        self.create_branch(name, start, false)?;
+        self.set_symbolic_ref("HEAD", &format!("refs/heads/{}", name), Some(&message))?;
+        Ok(format!("Switched to a new branch '{}'", name))
+    }
+
+    // The reflog message for HEAD moving from where it is now to `target`.
+    pub fn checkout_message(&self, target: &str) -> Result<String, GitError> {
+        let from = match self.current_branch() {
+            Some(branch) => branch,
+            None => self
+                .resolve_ref("HEAD")?
+                .map(|oid| oid.to_hex())
+                .unwrap_or_default(),
+        };
+        Ok(format!("checkout: moving from {} to {}", from, target))
+    }
+}
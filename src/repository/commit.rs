@@ -0,0 +1,187 @@
+use super::ident::Ident;
+use super::object::{ObjType, Serializable, Typed};
+use super::oid::ObjectId;
+use crate::error::GitError;
+
+// Headers of a commit or tag object, in the order they appear, followed by the message.
+pub type Kvlm = (Vec<(String, Vec<u8>)>, Vec<u8>);
+
+// Parses the "key value" header lines of a commit or tag, followed by a blank line and the message.
+// Continuation lines begin with a space, which is dropped from the value.
+pub fn kvlm_parse(raw: &[u8]) -> Result<Kvlm, GitError> {
+    let mut headers = Vec::new();
+    let mut current: usize = 0;
+
+    while current < raw.len() {
+        let rest = &raw[current..];
+        let space = rest.iter().position(|b| b == &b' ');
+        let nl = rest.iter().position(|b| b == &b'\n');
+
+        // A blank line (or a line without a key) separates the headers from the message.
+        if space.is_none() || (nl.is_some() && nl.unwrap() < space.unwrap()) {
+            if nl != Some(0) {
+                return Err(GitError::ObjectError(
+                    "Invalid object: malformed header".to_owned(),
+                ));
+            }
+            return Ok((headers, rest[1..].to_vec()));
+        }
+
+        let space_pos = space.unwrap();
+        let key = String::from_utf8(rest[..space_pos].to_vec())
+            .map_err(|_| GitError::ObjectError("Invalid object: header key".to_owned()))?;
+
+        // Find the end of the value.  Continuation lines begin with a
+        // space, so we loop until we find a "\n" not followed by a space.
+        let mut it = space_pos + 1;
+        let end_pos = loop {
+            match rest[it..].iter().position(|b| b == &b'\n') {
+                Some(newline) if rest.get(it + newline + 1) == Some(&b' ') => {
+                    it += newline + 1;
+                }
+                Some(newline) => break it + newline,
+                None => break rest.len(),
+            }
+        };
+        headers.push((
+            key,
+            remove_spaces_after_newline(&rest[space_pos + 1..end_pos]),
+        ));
+
+        current += end_pos + 1;
+    }
+
+    Ok((headers, Vec::new()))
+}
+
+pub fn kvlm_serialize(headers: &[(String, Vec<u8>)], message: &[u8]) -> Vec<u8> {
+    let mut result = Vec::new();
+    for (key, value) in headers {
+        result.extend(key.as_bytes());
+        result.push(b' ');
+        for b in value {
+            result.push(*b);
+            if *b == b'\n' {
+                result.push(b' ');
+            }
+        }
+        result.push(b'\n');
+    }
+    result.push(b'\n');
+    result.extend(message);
+    result
+}
+
+fn remove_spaces_after_newline(input: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(input.len());
+    let mut idx = 0;
+    while idx < input.len() {
+        result.push(input[idx]);
+        if input[idx] == b'\n' && input.get(idx + 1) == Some(&b' ') {
+            idx += 2;
+        } else {
+            idx += 1;
+        }
+    }
+    result
+}
+
+pub(crate) fn header_string(key: &str, value: Vec<u8>) -> Result<String, GitError> {
+    String::from_utf8(value)
+        .map_err(|_| GitError::ObjectError(format!("Invalid object: bad {} header", key)))
+}
+
+pub(crate) fn header_oid(key: &str, value: Vec<u8>) -> Result<ObjectId, GitError> {
+    ObjectId::from_hex(&header_string(key, value)?)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Commit {
+    pub tree: ObjectId,
+    pub parents: Vec<ObjectId>,
+    // Kept as bytes: with an encoding header they need not be UTF-8.
+    pub author: Vec<u8>,
+    pub committer: Vec<u8>,
+    // Any other headers, such as encoding, mergetag or gpgsig, in their original order. Each
+    // comes with the number of tree, parent, author and committer headers before it, so that
+    // it is written back in place and the commit keeps its id.
+    pub extra_headers: Vec<(usize, String, Vec<u8>)>,
+    pub message: Vec<u8>,
+}
+
+impl Commit {
+    // The first line of the commit message.
+    pub fn subject(&self) -> String {
+        String::from_utf8_lossy(&self.message)
+            .lines()
+            .next()
+            .unwrap_or("")
+            .to_owned()
+    }
+
+    pub fn author_ident(&self) -> Result<Ident, GitError> {
+        Ident::parse(&String::from_utf8_lossy(&self.author))
+    }
+
+    pub fn committer_ident(&self) -> Result<Ident, GitError> {
+        Ident::parse(&String::from_utf8_lossy(&self.committer))
+    }
+}
+
+impl Serializable for Commit {
+    fn serialize(&self) -> Vec<u8> {
+        let mut standard = vec![("tree".to_owned(), self.tree.to_hex().into_bytes())];
+        for parent in &self.parents {
+            standard.push(("parent".to_owned(), parent.to_hex().into_bytes()));
+        }
+        standard.push(("author".to_owned(), self.author.clone()));
+        standard.push(("committer".to_owned(), self.committer.clone()));
+
+        let mut headers = Vec::new();
+        let mut extra = self.extra_headers.iter().peekable();
+        for (i, header) in standard.into_iter().enumerate() {
+            while let Some((_, key, value)) = extra.next_if(|(before, ..)| *before <= i) {
+                headers.push((key.clone(), value.clone()));
+            }
+            headers.push(header);
+        }
+        headers.extend(extra.map(|(_, key, value)| (key.clone(), value.clone())));
+        kvlm_serialize(&headers, &self.message)
+    }
+
+    fn deserialize(input: &[u8]) -> Result<Self, GitError> {
+        let (headers, message) = kvlm_parse(input)?;
+        let mut tree = None;
+        let mut parents = Vec::new();
+        let mut author = None;
+        let mut committer = None;
+        let mut extra_headers = Vec::new();
+        let mut standard = 0;
+        for (key, value) in headers {
+            match key.as_str() {
+                "tree" => tree = Some(header_oid(&key, value)?),
+                "parent" => parents.push(header_oid(&key, value)?),
+                "author" => author = Some(value),
+                "committer" => committer = Some(value),
+                _ => {
+                    extra_headers.push((standard, key, value));
+                    continue;
+                }
+            }
+            standard += 1;
+        }
+        let missing = |key: &str| GitError::ObjectError(format!("Invalid commit: missing {}", key));
+        Ok(Commit {
+            tree: tree.ok_or_else(|| missing("tree"))?,
+            parents,
+            author: author.ok_or_else(|| missing("author"))?,
+            committer: committer.ok_or_else(|| missing("committer"))?,
+            extra_headers,
+            message,
+        })
+    }
+}
+
+impl Typed for Commit {
+    const OBJ_TYPE: ObjType = ObjType::Commit;
+}
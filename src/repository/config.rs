@@ -1,28 +1,859 @@
-use configparser::ini::Ini;
-use std::fmt::Debug;
-use std::path::Path;
+use crate::error::GitError;
+use crate::files::wildmatch;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
 
-#[derive(Debug)]
+// Git stops following include.path after this many nested files.
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+// Where a value was set, from lowest to highest precedence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ConfigScope {
+    System,
+    Global,
+    Local,
+    Worktree,
+    Command,
+}
+
+impl ConfigScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConfigScope::System => "system",
+            ConfigScope::Global => "global",
+            ConfigScope::Local => "local",
+            ConfigScope::Worktree => "worktree",
+            ConfigScope::Command => "command",
+        }
+    }
+}
+
+// One "key = value" line. Section and key names are case-insensitive and kept lowercased;
+// subsections are case-sensitive. A key without "=" has no value, which means true.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigEntry {
+    pub section: String,
+    pub subsection: Option<String>,
+    pub key: String,
+    pub value: Option<String>,
+    // The file the entry was read from, or None for GIT_CONFIG_KEY_<n> variables.
+    pub origin: Option<PathBuf>,
+    pub scope: ConfigScope,
+}
+
+impl ConfigEntry {
+    fn matches(&self, section: &str, subsection: Option<&str>, key: &str) -> bool {
+        self.section.eq_ignore_ascii_case(section)
+            && self.subsection.as_deref() == subsection
+            && self.key.eq_ignore_ascii_case(key)
+    }
+
+    // The full name, e.g. "remote.origin.url".
+    pub fn name(&self) -> String {
+        match &self.subsection {
+            Some(subsection) => format!("{}.{}.{}", self.section, subsection, self.key),
+            None => format!("{}.{}", self.section, self.key),
+        }
+    }
+}
+
+// The configuration of a repository: the system, global, repository and worktree files read
+// in that order, so later entries override earlier ones.
+#[derive(Debug, Default)]
 pub struct GitConfig {
-    pub conf: Ini,
+    pub entries: Vec<ConfigEntry>,
+    gitdir: Option<PathBuf>,
 }
 
 impl GitConfig {
-    pub fn new(conf: Ini) -> GitConfig {
-        GitConfig { conf }
+    // Reads every config file that applies to the repository at `gitdir`, or only the system
+    // and global ones outside a repository.
+    pub fn load(gitdir: Option<&Path>) -> Result<GitConfig, GitError> {
+        let mut entries = Vec::new();
+        let gitdir = gitdir.map(|gitdir| gitdir.to_path_buf());
+        let context = gitdir.as_deref();
+        // Tests must not depend on the configuration of whoever runs them.
+        if !cfg!(test) {
+            if let Some(path) = system_path() {
+                read_file(&path, ConfigScope::System, context, Some(0), &mut entries)?;
+            }
+            for path in global_paths() {
+                read_file(&path, ConfigScope::Global, context, Some(0), &mut entries)?;
+            }
+        }
+        if let Some(gitdir) = context {
+            read_file(
+                &gitdir.join("config"),
+                ConfigScope::Local,
+                context,
+                Some(0),
+                &mut entries,
+            )?;
+            let worktree_config = entries
+                .iter()
+                .rev()
+                .find(|entry| entry.matches("extensions", None, "worktreeconfig"))
+                .and_then(|entry| parse_bool(entry.value.as_deref()));
+            if worktree_config == Some(true) {
+                read_file(
+                    &gitdir.join("config.worktree"),
+                    ConfigScope::Worktree,
+                    context,
+                    Some(0),
+                    &mut entries,
+                )?;
+            }
+        }
+        entries.extend(command_entries()?);
+        Ok(GitConfig { entries, gitdir })
+    }
+
+    // Reads only the given files, as `config --file` and friends do; includes are not followed.
+    pub fn load_files(
+        paths: &[PathBuf],
+        scope: ConfigScope,
+        gitdir: Option<&Path>,
+    ) -> Result<GitConfig, GitError> {
+        let mut entries = Vec::new();
+        for path in paths {
+            read_file(path, scope, None, None, &mut entries)?;
+        }
+        Ok(GitConfig {
+            entries,
+            gitdir: gitdir.map(|gitdir| gitdir.to_path_buf()),
+        })
+    }
+
+    // The value that applies for a key: the last one set. An entry without a value gives "".
+    pub fn get(&self, section: &str, subsection: Option<&str>, key: &str) -> Option<String> {
+        self.get_entry(section, subsection, key)
+            .map(|entry| entry.value.clone().unwrap_or_default())
+    }
+
+    pub fn get_entry(
+        &self,
+        section: &str,
+        subsection: Option<&str>,
+        key: &str,
+    ) -> Option<&ConfigEntry> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|entry| entry.matches(section, subsection, key))
+    }
+
+    // Every value of a multi-valued key, such as remote.<name>.fetch, in order.
+    pub fn get_all(&self, section: &str, subsection: Option<&str>, key: &str) -> Vec<&ConfigEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.matches(section, subsection, key))
+            .collect()
+    }
+
+    // A boolean value; values that are not booleans count as unset.
+    pub fn get_bool(&self, section: &str, subsection: Option<&str>, key: &str) -> Option<bool> {
+        self.get_entry(section, subsection, key)
+            .and_then(|entry| parse_bool(entry.value.as_deref()))
+    }
+
+    pub fn get_int(&self, section: &str, subsection: Option<&str>, key: &str) -> Option<i64> {
+        self.get(section, subsection, key)
+            .and_then(|value| parse_int(&value))
+    }
+
+    // The repository's own config file, which `set` and friends change.
+    pub fn local_path(&self) -> Result<PathBuf, GitError> {
+        self.gitdir
+            .as_ref()
+            .map(|gitdir| gitdir.join("config"))
+            .ok_or_else(|| GitError::GenericError("not in a git directory".to_owned()))
+    }
+
+    // Sets a key in the repository's config file, replacing its value if it has one.
+    pub fn set(
+        &mut self,
+        section: &str,
+        subsection: Option<&str>,
+        key: &str,
+        value: &str,
+    ) -> Result<(), GitError> {
+        edit_file(
+            &self.local_path()?,
+            section,
+            subsection,
+            key,
+            ConfigEdit::Set(value),
+        )?;
+        self.reload()
+    }
+
+    // Renames a section of the repository's config file, e.g. `[branch "a"]` to `[branch "b"]`,
+    // or removes it with everything in it if `new_subsection` is None.
+    pub fn rename_section(
+        &mut self,
+        section: &str,
+        subsection: Option<&str>,
+        new_subsection: Option<&str>,
+    ) -> Result<(), GitError> {
+        rename_section_in_file(&self.local_path()?, section, subsection, new_subsection)?;
+        self.reload()
+    }
+
+    pub fn reload(&mut self) -> Result<(), GitError> {
+        *self = GitConfig::load(self.gitdir.as_deref())?;
+        Ok(())
+    }
+}
+
+// How `edit_file` changes a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigEdit<'a> {
+    // Replaces the single value of the key, or adds it.
+    Set(&'a str),
+    // Adds another value, keeping any existing ones.
+    Add(&'a str),
+    // Removes the single value of the key.
+    Unset,
+    // Removes every value of the key.
+    UnsetAll,
+}
+
+// Changes a key in one config file, leaving the rest of the file as it was. Returns how many
+// values were replaced or removed.
+pub fn edit_file(
+    path: &Path,
+    section: &str,
+    subsection: Option<&str>,
+    key: &str,
+    edit: ConfigEdit,
+) -> Result<usize, GitError> {
+    let text = fs::read_to_string(path).unwrap_or_default();
+    let items = parse(&text, path)?;
+    let mut lines: Vec<String> = text.split_inclusive('\n').map(str::to_owned).collect();
+    if lines.last().is_some_and(|line| !line.ends_with('\n')) {
+        lines.last_mut().unwrap().push('\n');
+    }
+    let found: Vec<(usize, usize)> = items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Entry { entry, first, last } if entry.matches(section, subsection, key) => {
+                Some((*first, *last))
+            }
+            _ => None,
+        })
+        .collect();
+    let name = match subsection {
+        Some(subsection) => format!("{}.{}.{}", section, subsection, key),
+        None => format!("{}.{}", section, key),
+    };
+    let multiple = || {
+        GitError::GenericError(format!(
+            "{} has multiple values; use --add or --unset-all to change it",
+            name
+        ))
+    };
+
+    match edit {
+        ConfigEdit::Set(value) if !found.is_empty() => {
+            if found.len() > 1 {
+                return Err(multiple());
+            }
+            let (first, last) = found[0];
+            lines.splice(first..=last, [format_entry(key, value)]);
+        }
+        ConfigEdit::Set(value) | ConfigEdit::Add(value) => {
+            // The new value goes after the last line of the last matching section.
+            let mut insert_at = None;
+            let mut in_section = false;
+            for item in &items {
+                match item {
+                    Item::Section {
+                        section: name,
+                        subsection: sub,
+                        line,
+                    } => {
+                        in_section =
+                            name.eq_ignore_ascii_case(section) && sub.as_deref() == subsection;
+                        if in_section {
+                            insert_at = Some(line + 1);
+                        }
+                    }
+                    Item::Entry { last, .. } if in_section => insert_at = Some(last + 1),
+                    _ => {}
+                }
+            }
+            match insert_at {
+                Some(at) => lines.insert(at, format_entry(key, value)),
+                None => {
+                    lines.push(format_header(section, subsection));
+                    lines.push(format_entry(key, value));
+                }
+            }
+        }
+        ConfigEdit::Unset | ConfigEdit::UnsetAll => {
+            if found.len() > 1 && edit == ConfigEdit::Unset {
+                return Err(multiple());
+            }
+            for (first, last) in found.iter().rev() {
+                lines.drain(*first..=*last);
+            }
+        }
+    }
+    write_file(path, &lines.concat())?;
+    Ok(found.len())
+}
+
+// Renames every matching section header, or removes the sections when `new_subsection` is None.
+pub fn rename_section_in_file(
+    path: &Path,
+    section: &str,
+    subsection: Option<&str>,
+    new_subsection: Option<&str>,
+) -> Result<(), GitError> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(_) => return Ok(()),
+    };
+    let items = parse(&text, path)?;
+    let mut lines: Vec<Option<String>> = text
+        .split_inclusive('\n')
+        .map(|line| Some(line.to_owned()))
+        .collect();
+    let mut removing = false;
+    for item in &items {
+        if let Item::Section {
+            section: name,
+            subsection: sub,
+            line,
+        } = item
+        {
+            let matching = name.eq_ignore_ascii_case(section) && sub.as_deref() == subsection;
+            removing = matching && new_subsection.is_none();
+            if let (true, Some(new_subsection)) = (matching, new_subsection) {
+                lines[*line] = Some(format_header(section, Some(new_subsection)));
+            }
+        }
+        if removing {
+            let (first, last) = match item {
+                Item::Section { line, .. } => (*line, *line),
+                Item::Entry { first, last, .. } => (*first, *last),
+            };
+            for line in &mut lines[first..=last] {
+                *line = None;
+            }
+        }
+    }
+    write_file(path, &lines.into_iter().flatten().collect::<String>())
+}
+
+// Splits "section.key" or "section.sub.section.key" into its parts.
+pub fn parse_name(name: &str) -> Result<(String, Option<String>, String), GitError> {
+    let invalid = || GitError::GenericError(format!("invalid key: {}", name));
+    let (section, rest) = name.split_once('.').ok_or_else(invalid)?;
+    let (subsection, key) = match rest.rsplit_once('.') {
+        Some((subsection, key)) => (Some(subsection.to_owned()), key),
+        None => (None, rest),
+    };
+    if !is_section_name(section) || !is_key_name(key) {
+        return Err(invalid());
+    }
+    Ok((section.to_ascii_lowercase(), subsection, key.to_owned()))
+}
+
+// Booleans as git spells them; a key without a value is true.
+pub fn parse_bool(value: Option<&str>) -> Option<bool> {
+    let value = match value {
+        Some(value) => value.to_ascii_lowercase(),
+        None => return Some(true),
+    };
+    match value.as_str() {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" | "" => Some(false),
+        _ => None,
     }
-    pub fn save(&self, path: &Path) -> Result<(), String> {
-        self.conf.write(path).map_err(|e| e.to_string())
+}
+
+// An integer with an optional k, m or g suffix, for kibi-, mebi- or gibi-.
+pub fn parse_int(value: &str) -> Option<i64> {
+    let value = value.trim();
+    let (digits, factor) = match value.chars().last()?.to_ascii_lowercase() {
+        'k' => (&value[..value.len() - 1], 1 << 10),
+        'm' => (&value[..value.len() - 1], 1 << 20),
+        'g' => (&value[..value.len() - 1], 1 << 30),
+        _ => (value, 1),
+    };
+    digits.parse::<i64>().ok()?.checked_mul(factor)
+}
+
+// The file `config --global` writes: $GIT_CONFIG_GLOBAL, or ~/.gitconfig unless only the XDG
+// file exists.
+pub fn global_write_path() -> Result<PathBuf, GitError> {
+    if let Some(path) = env::var_os("GIT_CONFIG_GLOBAL") {
+        return Ok(PathBuf::from(path));
+    }
+    let home = home_dir().ok_or_else(|| GitError::GenericError("$HOME not set".to_owned()))?;
+    let dotfile = home.join(".gitconfig");
+    match xdg_path() {
+        Some(xdg) if !dotfile.exists() && xdg.exists() => Ok(xdg),
+        _ => Ok(dotfile),
     }
 }
 
-impl Default for GitConfig {
-    fn default() -> Self {
-        let mut conf = Ini::new();
-        conf.setstr("core", "repositoryformatversion", Some("0"));
-        conf.setstr("core", "filemode", Some("false"));
-        conf.setstr("core", "bare", Some("false"));
+pub fn system_path() -> Option<PathBuf> {
+    let disabled = env::var("GIT_CONFIG_NOSYSTEM")
+        .ok()
+        .and_then(|value| parse_bool(Some(&value)))
+        .unwrap_or(false);
+    if disabled {
+        return None;
+    }
+    Some(
+        env::var_os("GIT_CONFIG_SYSTEM")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("/etc/gitconfig")),
+    )
+}
 
-        GitConfig { conf }
+// The global files, lowest precedence first.
+pub fn global_paths() -> Vec<PathBuf> {
+    if let Some(path) = env::var_os("GIT_CONFIG_GLOBAL") {
+        return vec![PathBuf::from(path)];
     }
-}
\ No newline at end of file
+    xdg_path()
+        .into_iter()
+        .chain(home_dir().map(|home| home.join(".gitconfig")))
+        .collect()
+}
+
+fn xdg_path() -> Option<PathBuf> {
+    match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir).join("git").join("config")),
+        _ => home_dir().map(|home| home.join(".config").join("git").join("config")),
+    }
+}
+
+fn home_dir() -> Option<PathBuf> {
+    env::var_os("HOME")
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+}
+
+// Values given through GIT_CONFIG_COUNT, GIT_CONFIG_KEY_<n> and GIT_CONFIG_VALUE_<n>.
+fn command_entries() -> Result<Vec<ConfigEntry>, GitError> {
+    let count = match env::var("GIT_CONFIG_COUNT") {
+        Ok(count) => count
+            .parse::<usize>()
+            .map_err(|_| GitError::GenericError("bogus count in GIT_CONFIG_COUNT".to_owned()))?,
+        Err(_) => return Ok(Vec::new()),
+    };
+    (0..count)
+        .map(|i| {
+            let name = env::var(format!("GIT_CONFIG_KEY_{}", i)).map_err(|_| {
+                GitError::GenericError(format!("missing config key GIT_CONFIG_KEY_{}", i))
+            })?;
+            let (section, subsection, key) = parse_name(&name)?;
+            Ok(ConfigEntry {
+                section,
+                subsection,
+                key: key.to_ascii_lowercase(),
+                value: env::var(format!("GIT_CONFIG_VALUE_{}", i)).ok(),
+                origin: None,
+                scope: ConfigScope::Command,
+            })
+        })
+        .collect()
+}
+
+// Reads a config file into `entries`, following include.path and includeIf unless `depth` is
+// None. A missing file is not an error.
+fn read_file(
+    path: &Path,
+    scope: ConfigScope,
+    gitdir: Option<&Path>,
+    depth: Option<usize>,
+    entries: &mut Vec<ConfigEntry>,
+) -> Result<(), GitError> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(_) => return Ok(()),
+    };
+    for item in parse(&text, path)? {
+        let mut entry = match item {
+            Item::Entry { entry, .. } => entry,
+            Item::Section { .. } => continue,
+        };
+        entry.origin = Some(path.to_path_buf());
+        entry.scope = scope;
+        let include = entry.key == "path"
+            && match (entry.section.as_str(), &entry.subsection) {
+                ("include", None) => true,
+                ("includeif", Some(condition)) => include_applies(condition, path, gitdir),
+                _ => false,
+            };
+        let included = match (include, &entry.value, depth) {
+            (true, Some(value), Some(depth)) => Some((resolve_include(value, path), depth)),
+            _ => None,
+        };
+        entries.push(entry);
+        if let Some((included, depth)) = included {
+            if depth >= MAX_INCLUDE_DEPTH {
+                return Err(GitError::GenericError(format!(
+                    "exceeded maximum include depth ({}) while including {}",
+                    MAX_INCLUDE_DEPTH,
+                    included.display()
+                )));
+            }
+            read_file(&included, scope, gitdir, Some(depth + 1), entries)?;
+        }
+    }
+    Ok(())
+}
+
+// Include paths may start with "~/" for the home directory; relative ones are relative to the
+// including file.
+fn resolve_include(value: &str, including: &Path) -> PathBuf {
+    if let (Some(rest), Some(home)) = (value.strip_prefix("~/"), home_dir()) {
+        return home.join(rest);
+    }
+    let base = including.parent().unwrap_or(Path::new(""));
+    base.join(value)
+}
+
+// Evaluates an includeIf condition. Only "gitdir:" and "gitdir/i:" are understood; as in git,
+// unknown conditions are false.
+fn include_applies(condition: &str, including: &Path, gitdir: Option<&Path>) -> bool {
+    let (pattern, fold_case) = match condition.strip_prefix("gitdir:") {
+        Some(pattern) => (pattern, false),
+        None => match condition.strip_prefix("gitdir/i:") {
+            Some(pattern) => (pattern, true),
+            None => return false,
+        },
+    };
+    let gitdir = match gitdir {
+        Some(gitdir) => fs::canonicalize(gitdir).unwrap_or_else(|_| gitdir.to_path_buf()),
+        None => return false,
+    };
+    let mut pattern = if let (Some(rest), Some(home)) = (pattern.strip_prefix("~/"), home_dir()) {
+        format!("{}/{}", home.display(), rest)
+    } else if let Some(rest) = pattern.strip_prefix("./") {
+        let base = including.parent().unwrap_or(Path::new(""));
+        format!("{}/{}", base.display(), rest)
+    } else if !pattern.starts_with('/') {
+        format!("**/{}", pattern)
+    } else {
+        pattern.to_owned()
+    };
+    if pattern.ends_with('/') {
+        pattern.push_str("**");
+    }
+    let text = gitdir.display().to_string();
+    match fold_case {
+        true => wildmatch(&pattern.to_lowercase(), &text.to_lowercase(), true),
+        false => wildmatch(&pattern, &text, true),
+    }
+}
+
+fn write_file(path: &Path, contents: &str) -> Result<(), GitError> {
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent).map_err(|_| {
+            GitError::PathError(
+                "Could not create directories".to_owned(),
+                parent.to_path_buf(),
+            )
+        })?;
+    }
+    let mut lock = path.as_os_str().to_owned();
+    lock.push(".lock");
+    let lock = PathBuf::from(lock);
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&lock)
+        .map_err(|_| GitError::PathError("Unable to create lock file".to_owned(), lock.clone()))?;
+    fs::write(&lock, contents)
+        .and_then(|_| fs::rename(&lock, path))
+        .map_err(|_| {
+            let _ = fs::remove_file(&lock);
+            GitError::PathError("Could not write config".to_owned(), path.to_path_buf())
+        })
+}
+
+fn format_header(section: &str, subsection: Option<&str>) -> String {
+    match subsection {
+        Some(subsection) => format!(
+            "[{} \"{}\"]\n",
+            section,
+            subsection.replace('\\', "\\\\").replace('"', "\\\"")
+        ),
+        None => format!("[{}]\n", section),
+    }
+}
+
+// A "key = value" line, quoting the value when it would not read back as it is.
+fn format_entry(key: &str, value: &str) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            c => escaped.push(c),
+        }
+    }
+    let quote = value.starts_with(char::is_whitespace)
+        || value.ends_with(char::is_whitespace)
+        || value.contains(['#', ';']);
+    match quote {
+        true => format!("\t{} = \"{}\"\n", key, escaped),
+        false => format!("\t{} = {}\n", key, escaped),
+    }
+}
+
+fn is_section_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+}
+
+fn is_key_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+// A parsed piece of a config file, with the (0-based) lines it spans so it can be edited.
+enum Item {
+    Section {
+        section: String,
+        subsection: Option<String>,
+        line: usize,
+    },
+    Entry {
+        entry: ConfigEntry,
+        first: usize,
+        last: usize,
+    },
+}
+
+struct ParseError;
+
+fn parse(text: &str, path: &Path) -> Result<Vec<Item>, GitError> {
+    let mut parser = Parser {
+        chars: text.chars().collect(),
+        pos: 0,
+        line: 0,
+        item_line: 0,
+    };
+    parser.items().map_err(|_| {
+        GitError::GenericError(format!(
+            "bad config line {} in file {}",
+            parser.item_line + 1,
+            path.display()
+        ))
+    })
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+    // Where the section header or entry being read started, for error messages.
+    item_line: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn skip_blanks(&mut self) {
+        while matches!(self.peek(), Some(' ') | Some('\t') | Some('\r')) {
+            self.next();
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        while !matches!(self.peek(), None | Some('\n')) {
+            self.next();
+        }
+    }
+
+    fn items(&mut self) -> Result<Vec<Item>, ParseError> {
+        let mut items = Vec::new();
+        let mut section: Option<(String, Option<String>)> = None;
+        loop {
+            self.item_line = self.line;
+            match self.peek() {
+                None => return Ok(items),
+                Some(c) if c.is_whitespace() => {
+                    self.next();
+                }
+                Some('#') | Some(';') => self.skip_comment(),
+                Some('[') => {
+                    let line = self.line;
+                    let (name, subsection) = self.header()?;
+                    items.push(Item::Section {
+                        section: name.clone(),
+                        subsection: subsection.clone(),
+                        line,
+                    });
+                    section = Some((name, subsection));
+                }
+                Some(c) if c.is_ascii_alphabetic() => {
+                    let (name, subsection) = section.clone().ok_or(ParseError)?;
+                    let first = self.line;
+                    let (key, value) = self.entry()?;
+                    items.push(Item::Entry {
+                        entry: ConfigEntry {
+                            section: name,
+                            subsection,
+                            key,
+                            value,
+                            origin: None,
+                            scope: ConfigScope::Local,
+                        },
+                        first,
+                        last: self.line,
+                    });
+                }
+                Some(_) => return Err(ParseError),
+            }
+        }
+    }
+
+    // `[section]`, `[section "subsection"]` or the old `[section.subsection]`.
+    fn header(&mut self) -> Result<(String, Option<String>), ParseError> {
+        self.next();
+        let mut name = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                name.push(c);
+                self.next();
+            } else {
+                break;
+            }
+        }
+        self.skip_blanks();
+        let subsection = match self.next() {
+            Some(']') => match name.split_once('.') {
+                Some((section, subsection)) => {
+                    let subsection = subsection.to_ascii_lowercase();
+                    name = section.to_owned();
+                    Some(subsection)
+                }
+                None => None,
+            },
+            Some('"') if !name.contains('.') => {
+                let mut subsection = String::new();
+                loop {
+                    match self.next().ok_or(ParseError)? {
+                        '"' => break,
+                        '\n' => return Err(ParseError),
+                        '\\' => subsection.push(self.next().ok_or(ParseError)?),
+                        c => subsection.push(c),
+                    }
+                }
+                if self.next() != Some(']') {
+                    return Err(ParseError);
+                }
+                Some(subsection)
+            }
+            _ => return Err(ParseError),
+        };
+        if name.is_empty() {
+            return Err(ParseError);
+        }
+        Ok((name.to_ascii_lowercase(), subsection))
+    }
+
+    fn entry(&mut self) -> Result<(String, Option<String>), ParseError> {
+        let mut key = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                key.push(c.to_ascii_lowercase());
+                self.next();
+            } else {
+                break;
+            }
+        }
+        self.skip_blanks();
+        match self.peek() {
+            None | Some('\n') => return Ok((key, None)),
+            Some('#') | Some(';') => {
+                self.skip_comment();
+                return Ok((key, None));
+            }
+            Some('=') => {
+                self.next();
+            }
+            Some(_) => return Err(ParseError),
+        }
+        self.skip_blanks();
+
+        // Whitespace outside quotes is kept inside the value but trimmed from its end.
+        let mut value = String::new();
+        let mut quoted = false;
+        let mut trailing = 0;
+        loop {
+            let c = match self.peek() {
+                None if quoted => return Err(ParseError),
+                None => break,
+                Some(c) => c,
+            };
+            match c {
+                '\n' if quoted => return Err(ParseError),
+                '\n' => break,
+                '#' | ';' if !quoted => {
+                    self.skip_comment();
+                    break;
+                }
+                '"' => {
+                    self.next();
+                    quoted = !quoted;
+                    trailing = 0;
+                    continue;
+                }
+                '\\' => {
+                    self.next();
+                    match self.next().ok_or(ParseError)? {
+                        '\n' => {}
+                        'n' => value.push('\n'),
+                        't' => value.push('\t'),
+                        'b' => value.push('\u{8}'),
+                        '\\' => value.push('\\'),
+                        '"' => value.push('"'),
+                        _ => return Err(ParseError),
+                    }
+                    trailing = 0;
+                    continue;
+                }
+                c if c.is_whitespace() && !quoted => {
+                    // Unquoted tabs and the like read as spaces.
+                    value.push(' ');
+                    trailing += 1;
+                    self.next();
+                    continue;
+                }
+                _ => trailing = 0,
+            }
+            value.push(c);
+            self.next();
+        }
+        value.truncate(value.len() - trailing);
+        Ok((key, Some(value)))
+    }
+}
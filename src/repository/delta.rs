@@ -0,0 +1,182 @@
+use crate::error::GitError;
+use std::collections::HashMap;
+
+// Matches between a target and its base are looked for in blocks of this many bytes.
+const BLOCK: usize = 16;
+// Git's readers expect a single copy instruction to cover at most this many bytes.
+const MAX_COPY: usize = 0x10000;
+// Candidate offsets remembered for one block, so repetitive data stays fast to index.
+const MAX_CANDIDATES: usize = 64;
+
+fn read_varint(data: &[u8], i: &mut usize) -> Result<usize, GitError> {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *data
+            .get(*i)
+            .ok_or_else(|| GitError::ObjectError("Truncated delta".to_owned()))?;
+        *i += 1;
+        // Sizes past 64 bits cannot be real.
+        value |= ((byte & 0x7f) as usize)
+            .checked_shl(shift)
+            .ok_or_else(|| GitError::ObjectError("Corrupt delta".to_owned()))?;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+// Rebuilds an object from its base and a delta: the sizes of both, then instructions that
+// either copy a range of the base or insert literal bytes.
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, GitError> {
+    let corrupt = || GitError::ObjectError("Corrupt delta".to_owned());
+    let mut i = 0;
+    if read_varint(delta, &mut i)? != base.len() {
+        return Err(GitError::ObjectError(
+            "Delta does not apply to its base".to_owned(),
+        ));
+    }
+    // The size comes from the delta itself, so the result only grows as instructions fill it.
+    let size = read_varint(delta, &mut i)?;
+    let mut result = Vec::new();
+    while i < delta.len() {
+        let op = delta[i];
+        i += 1;
+        if op & 0x80 != 0 {
+            let mut offset = 0usize;
+            let mut length = 0usize;
+            for bit in 0..4 {
+                if op & (1 << bit) != 0 {
+                    offset |= (*delta.get(i).ok_or_else(corrupt)? as usize) << (bit * 8);
+                    i += 1;
+                }
+            }
+            for bit in 0..3 {
+                if op & (0x10 << bit) != 0 {
+                    length |= (*delta.get(i).ok_or_else(corrupt)? as usize) << (bit * 8);
+                    i += 1;
+                }
+            }
+            if length == 0 {
+                length = 0x10000;
+            }
+            let copy = base.get(offset..offset + length).ok_or_else(corrupt)?;
+            result.extend_from_slice(copy);
+        } else if op != 0 {
+            let insert = delta.get(i..i + op as usize).ok_or_else(corrupt)?;
+            result.extend_from_slice(insert);
+            i += op as usize;
+        } else {
+            return Err(corrupt());
+        }
+        if result.len() > size {
+            return Err(corrupt());
+        }
+    }
+    if result.len() != size {
+        return Err(corrupt());
+    }
+    Ok(result)
+}
+
+// Encodes `target` as a delta against `base`: runs of at least BLOCK bytes found in the base
+// become copy instructions and everything else is inserted literally.
+pub fn create_delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    write_varint(&mut delta, base.len());
+    write_varint(&mut delta, target.len());
+
+    let mut index: HashMap<&[u8], Vec<usize>> = HashMap::new();
+    for offset in (0..base.len().saturating_sub(BLOCK - 1)).step_by(BLOCK) {
+        let candidates = index.entry(&base[offset..offset + BLOCK]).or_default();
+        if candidates.len() < MAX_CANDIDATES {
+            candidates.push(offset);
+        }
+    }
+
+    let mut pending = 0;
+    let mut i = 0;
+    while i + BLOCK <= target.len() {
+        let candidates = match index.get(&target[i..i + BLOCK]) {
+            Some(candidates) => candidates,
+            None => {
+                i += 1;
+                continue;
+            }
+        };
+        let (mut offset, mut length) = (0, 0);
+        for &candidate in candidates {
+            let matched = base[candidate..]
+                .iter()
+                .zip(&target[i..])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if matched > length {
+                offset = candidate;
+                length = matched;
+            }
+        }
+        // Grow the match backwards over bytes that would otherwise be inserted.
+        let mut start = i;
+        while offset > 0 && start > pending && base[offset - 1] == target[start - 1] {
+            offset -= 1;
+            start -= 1;
+            length += 1;
+        }
+        write_insert(&mut delta, &target[pending..start]);
+        write_copy(&mut delta, offset, length);
+        i = start + length;
+        pending = i;
+    }
+    write_insert(&mut delta, &target[pending..]);
+    delta
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn write_insert(out: &mut Vec<u8>, data: &[u8]) {
+    for chunk in data.chunks(0x7f) {
+        out.push(chunk.len() as u8);
+        out.extend_from_slice(chunk);
+    }
+}
+
+// A copy instruction stores only the non-zero bytes of its offset and size, flagged in the
+// opcode; a size of 0x10000 is written as no size bytes at all.
+fn write_copy(out: &mut Vec<u8>, mut offset: usize, mut length: usize) {
+    while length > 0 {
+        let size = length.min(MAX_COPY);
+        let mut op = 0x80u8;
+        let mut args = Vec::new();
+        for byte in 0..4 {
+            let value = ((offset >> (byte * 8)) & 0xff) as u8;
+            if value != 0 {
+                op |= 1 << byte;
+                args.push(value);
+            }
+        }
+        let encoded = if size == MAX_COPY { 0 } else { size };
+        for byte in 0..3 {
+            let value = ((encoded >> (byte * 8)) & 0xff) as u8;
+            if value != 0 {
+                op |= 0x10 << byte;
+                args.push(value);
+            }
+        }
+        out.push(op);
+        out.extend(args);
+        offset += size;
+        length -= size;
+    }
+}
@@ -0,0 +1,195 @@
+use super::object::{GitObject, ObjType};
+use super::oid::ObjectId;
+use super::worktree::TreeEntries;
+use super::GitRepository;
+use crate::diff;
+use crate::error::GitError;
+use std::collections::BTreeSet;
+
+// One version of a file in a diff.
+#[derive(Debug, Clone)]
+pub struct DiffSide {
+    pub mode: u32,
+    pub oid: ObjectId,
+    pub data: Vec<u8>,
+}
+
+// A changed path; `old` is None for an added file and `new` is None for a deleted one.
+#[derive(Debug, Clone)]
+pub struct FileDiff {
+    pub path: String,
+    pub old: Option<DiffSide>,
+    pub new: Option<DiffSide>,
+}
+
+impl FileDiff {
+    // Renders the change as a git style patch, starting with its "diff --git" header.
+    pub fn to_patch(&self, context: usize) -> Vec<u8> {
+        let mut out = format!("diff --git a/{} b/{}\n", self.path, self.path).into_bytes();
+        let zero = ObjectId::default();
+        let (old_oid, new_oid) = (
+            self.old.as_ref().map(|s| s.oid).unwrap_or(zero),
+            self.new.as_ref().map(|s| s.oid).unwrap_or(zero),
+        );
+        match (&self.old, &self.new) {
+            (None, Some(new)) => {
+                out.extend(format!("new file mode {:06o}\n", new.mode).as_bytes());
+                out.extend(format!("index {:.7}..{:.7}\n", old_oid, new_oid).as_bytes());
+            }
+            (Some(old), None) => {
+                out.extend(format!("deleted file mode {:06o}\n", old.mode).as_bytes());
+                out.extend(format!("index {:.7}..{:.7}\n", old_oid, new_oid).as_bytes());
+            }
+            (Some(old), Some(new)) if old.mode != new.mode => {
+                out.extend(
+                    format!("old mode {:06o}\nnew mode {:06o}\n", old.mode, new.mode).as_bytes(),
+                );
+                if old_oid != new_oid {
+                    out.extend(format!("index {:.7}..{:.7}\n", old_oid, new_oid).as_bytes());
+                }
+            }
+            (Some(old), Some(_)) => {
+                out.extend(
+                    format!("index {:.7}..{:.7} {:06o}\n", old_oid, new_oid, old.mode).as_bytes(),
+                );
+            }
+            (None, None) => {}
+        }
+        if old_oid == new_oid {
+            return out;
+        }
+
+        let old_data = self.old.as_ref().map(|s| s.data.as_slice()).unwrap_or(&[]);
+        let new_data = self.new.as_ref().map(|s| s.data.as_slice()).unwrap_or(&[]);
+        let old_name = match self.old {
+            Some(_) => format!("a/{}", self.path),
+            None => "/dev/null".to_owned(),
+        };
+        let new_name = match self.new {
+            Some(_) => format!("b/{}", self.path),
+            None => "/dev/null".to_owned(),
+        };
+        if diff::is_binary(old_data) || diff::is_binary(new_data) {
+            out.extend(format!("Binary files {} and {} differ\n", old_name, new_name).as_bytes());
+            return out;
+        }
+        out.extend(format!("--- {}\n+++ {}\n", old_name, new_name).as_bytes());
+        out.extend(diff::unified(old_data, new_data, context));
+        out
+    }
+}
+
+impl GitRepository {
+    // Changes in the worktree that are not staged: the index compared with the worktree.
+    pub fn diff_index_worktree(&self, paths: &[String]) -> Result<Vec<FileDiff>, GitError> {
+        let mut diffs = Vec::new();
+        for entry in self.read_index()? {
+            if entry.stage() != 0 || !matches_paths(&entry.path, paths) {
+                continue;
+            }
+            if self.worktree_matches_index(&entry)? {
+                continue;
+            }
+            let old = Some(self.blob_side(GitRepository::tree_mode(entry.mode), &entry.oid)?);
+            let new = match self.worktree_mode(&entry.path) {
+                Some(mode) => {
+                    let data = self.read_worktree_file(&entry.path)?;
+                    Some(DiffSide {
+                        mode,
+                        oid: GitObject::new(ObjType::Blob, &data).id(),
+                        data,
+                    })
+                }
+                None => None,
+            };
+            diffs.push(FileDiff {
+                path: entry.path.clone(),
+                old,
+                new,
+            });
+        }
+        Ok(diffs)
+    }
+
+    // Staged changes: the tree of `commit` (HEAD when None) compared with the index.
+    pub fn diff_tree_index(
+        &self,
+        commit: Option<&ObjectId>,
+        paths: &[String],
+    ) -> Result<Vec<FileDiff>, GitError> {
+        let tree = self.commit_tree_entries(commit)?;
+        let index: TreeEntries = self
+            .read_index()?
+            .into_iter()
+            .filter(|entry| entry.stage() == 0)
+            .map(|entry| {
+                (
+                    entry.path,
+                    (GitRepository::tree_mode(entry.mode), entry.oid),
+                )
+            })
+            .collect();
+        self.diff_entries(&tree, &index, paths)
+    }
+
+    // Changes between the trees of two commits.
+    pub fn diff_commits(
+        &self,
+        old: &ObjectId,
+        new: &ObjectId,
+        paths: &[String],
+    ) -> Result<Vec<FileDiff>, GitError> {
+        let old = self.commit_tree_entries(Some(old))?;
+        let new = self.commit_tree_entries(Some(new))?;
+        self.diff_entries(&old, &new, paths)
+    }
+
+    pub fn diff_entries(
+        &self,
+        old: &TreeEntries,
+        new: &TreeEntries,
+        paths: &[String],
+    ) -> Result<Vec<FileDiff>, GitError> {
+        let all: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+        let mut diffs = Vec::new();
+        for path in all {
+            let (before, after) = (old.get(path), new.get(path));
+            if before == after || !matches_paths(path, paths) {
+                continue;
+            }
+            diffs.push(FileDiff {
+                path: path.clone(),
+                old: before
+                    .map(|(mode, oid)| self.blob_side(*mode, oid))
+                    .transpose()?,
+                new: after
+                    .map(|(mode, oid)| self.blob_side(*mode, oid))
+                    .transpose()?,
+            });
+        }
+        Ok(diffs)
+    }
+
+    fn blob_side(&self, mode: u32, oid: &ObjectId) -> Result<DiffSide, GitError> {
+        // Submodule entries point at commits in another repository.
+        let data = if mode == 0o160000 {
+            format!("Subproject commit {}\n", oid).into_bytes()
+        } else {
+            self.read_object(oid)?.data
+        };
+        Ok(DiffSide {
+            mode,
+            oid: *oid,
+            data,
+        })
+    }
+}
+
+// Whether a path is one of the given paths or inside one of them. No paths matches everything.
+pub fn matches_paths(path: &str, paths: &[String]) -> bool {
+    paths.is_empty()
+        || paths.iter().any(|p| {
+            let p = p.trim_end_matches('/');
+            p.is_empty() || p == "." || path == p || path.starts_with(&format!("{}/", p))
+        })
+}
@@ -0,0 +1,122 @@
+use super::commit::Commit;
+use super::oid::ObjectId;
+use super::GitRepository;
+use crate::error::GitError;
+use std::collections::HashSet;
+
+impl GitRepository {
+    pub fn read_commit(&self, oid: &ObjectId) -> Result<Commit, GitError> {
+        self.read_object(oid)?.parse::<Commit>()
+    }
+
+    // Whether `ancestor` can be reached by following parents from `descendant`.
+    pub fn is_ancestor(
+        &self,
+        ancestor: &ObjectId,
+        descendant: &ObjectId,
+    ) -> Result<bool, GitError> {
+        let mut seen = HashSet::new();
+        let mut pending = vec![*descendant];
+        while let Some(oid) = pending.pop() {
+            if oid == *ancestor {
+                return Ok(true);
+            }
+            if seen.insert(oid) {
+                pending.extend(self.read_commit(&oid)?.parents);
+            }
+        }
+        Ok(false)
+    }
+
+    // Every commit reachable from `tips`, the tips included.
+    pub fn ancestors(&self, tips: &[ObjectId]) -> Result<HashSet<ObjectId>, GitError> {
+        let mut seen = HashSet::new();
+        let mut pending = tips.to_vec();
+        while let Some(oid) = pending.pop() {
+            if seen.insert(oid) {
+                pending.extend(self.read_commit(&oid)?.parents);
+            }
+        }
+        Ok(seen)
+    }
+
+    // The best common ancestors of two commits: those no other common ancestor descends from,
+    // newest first. Criss-cross histories have more than one.
+    pub fn merge_bases(&self, a: &ObjectId, b: &ObjectId) -> Result<Vec<ObjectId>, GitError> {
+        self.merge_bases_many(a, &[*b])
+    }
+
+    // The best common ancestors of `a` and a merge of all the `others`, as `merge-base A B C`
+    // computes them.
+    pub fn merge_bases_many(
+        &self,
+        a: &ObjectId,
+        others: &[ObjectId],
+    ) -> Result<Vec<ObjectId>, GitError> {
+        let from_a = self.ancestors(&[*a])?;
+        // A best common ancestor is reached from the others before any other common ancestor.
+        let mut candidates = Vec::new();
+        let mut seen = HashSet::new();
+        let mut pending = others.to_vec();
+        while let Some(oid) = pending.pop() {
+            if !seen.insert(oid) {
+                continue;
+            }
+            if from_a.contains(&oid) {
+                candidates.push(oid);
+            } else {
+                pending.extend(self.read_commit(&oid)?.parents);
+            }
+        }
+        self.independent(&candidates)
+    }
+
+    // The best common ancestors of all the commits, as an octopus merge of them would use.
+    pub fn octopus_bases(&self, commits: &[ObjectId]) -> Result<Vec<ObjectId>, GitError> {
+        let (first, rest) = match commits.split_first() {
+            Some(split) => split,
+            None => return Ok(Vec::new()),
+        };
+        let mut bases = vec![*first];
+        for commit in rest {
+            let mut next = Vec::new();
+            for base in &bases {
+                next.extend(self.merge_bases(base, commit)?);
+            }
+            bases = self.independent(&next)?;
+        }
+        Ok(bases)
+    }
+
+    // How many commits `a` has that `b` does not, and how many `b` has that `a` does not.
+    pub fn ahead_behind(&self, a: &ObjectId, b: &ObjectId) -> Result<(usize, usize), GitError> {
+        let (from_a, from_b) = (self.ancestors(&[*a])?, self.ancestors(&[*b])?);
+        Ok((
+            from_a.difference(&from_b).count(),
+            from_b.difference(&from_a).count(),
+        ))
+    }
+
+    // The commits none of the others can reach, newest first.
+    fn independent(&self, commits: &[ObjectId]) -> Result<Vec<ObjectId>, GitError> {
+        let mut dated = Vec::new();
+        for (i, commit) in commits.iter().enumerate() {
+            if commits[..i].contains(commit) {
+                continue;
+            }
+            let mut redundant = false;
+            for other in commits {
+                if other != commit && self.is_ancestor(commit, other)? {
+                    redundant = true;
+                    break;
+                }
+            }
+            if !redundant {
+                let time = self.read_commit(commit)?.committer_ident()?.time;
+                dated.push((std::cmp::Reverse(time), *commit));
+            }
+        }
+        dated.sort();
+        Ok(dated.into_iter().map(|(_, oid)| oid).collect())
+    }
+}
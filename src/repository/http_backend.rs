@@ -0,0 +1,125 @@
+use super::GitRepository;
+use crate::error::GitError;
+use crate::http::{self, Request, Response};
+use crate::pktline;
+use std::io::BufReader;
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::thread;
+
+// Serves the repositories under `root` over smart HTTP, like git's http-backend: a repository
+// at root/<path> is at http://<address>/<path>. Each connection gets its own thread.
+pub fn serve_http(listener: TcpListener, root: PathBuf) -> Result<(), GitError> {
+    for stream in listener.incoming() {
+        let stream = stream
+            .map_err(|e| GitError::GenericError(format!("Could not accept a connection: {}", e)))?;
+        let root = root.clone();
+        thread::spawn(move || {
+            // The client sees a dropped connection; there is no one else to tell.
+            let _ = handle_connection(stream, &root);
+        });
+    }
+    Ok(())
+}
+
+fn handle_connection(mut stream: TcpStream, root: &Path) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    if let Some(request) = http::read_request(&mut reader)? {
+        http::write_response(&mut stream, &respond(root, &request))?;
+    }
+    Ok(())
+}
+
+fn respond(root: &Path, request: &Request) -> Response {
+    let path = request.path();
+    let (repo_path, service, advertise) = if let Some(repo) = path.strip_suffix("/info/refs") {
+        match request.query("service") {
+            Some(service) => (repo, service, true),
+            // Dumb HTTP would need the repository's files served as they are.
+            None => return text(403, "Only the smart HTTP protocol is supported"),
+        }
+    } else if let Some((repo, service)) = path.rsplit_once('/') {
+        (repo, service, false)
+    } else {
+        return text(404, "Not found");
+    };
+    if !matches!(service, "git-upload-pack" | "git-receive-pack") {
+        return text(403, &format!("Unsupported service '{}'", service));
+    }
+    match (advertise, request.method.as_str()) {
+        (true, "GET") | (false, "POST") => {}
+        _ => return text(405, "Method not allowed"),
+    }
+
+    let repo = match open_repository(root, repo_path) {
+        Some(repo) => repo,
+        None => return text(404, "Repository not found"),
+    };
+    // Pushing has to be turned on for each repository, as git's http-backend requires for
+    // anonymous users.
+    if service == "git-receive-pack"
+        && repo.config.get_bool("http", None, "receivepack") != Some(true)
+    {
+        return text(403, "Pushing is not enabled for this repository");
+    }
+
+    let mut body = Vec::new();
+    let result = match (service, advertise) {
+        ("git-upload-pack", true) => {
+            let version2 = request
+                .header("Git-Protocol")
+                .is_some_and(|protocol| protocol.split(':').any(|p| p == "version=2"));
+            // Clients that did not ask for protocol v2 expect the service header of v0, and
+            // then recognize the v2 capabilities that follow.
+            (match version2 {
+                true => Ok(()),
+                false => service_header(&mut body, service),
+            })
+            .and_then(|_| repo.advertise_upload_pack(&mut body))
+        }
+        ("git-upload-pack", false) => {
+            repo.serve_upload_pack(&mut &request.body[..], &mut body, true)
+        }
+        (_, true) => service_header(&mut body, service)
+            .and_then(|_| repo.advertise_receive_pack(&mut body, 0)),
+        (_, false) => repo.serve_receive_pack(&mut &request.body[..], &mut body),
+    };
+    // Failures are reported inside the protocol when it allows; anything else is a server error.
+    if let (Err(e), true) = (result, body.is_empty()) {
+        return text(500, &e.to_string());
+    }
+    let content_type = match advertise {
+        true => format!("application/x-{}-advertisement", service),
+        false => format!("application/x-{}-result", service),
+    };
+    let mut response = Response::new(200, &content_type, body);
+    response
+        .headers
+        .push(("Cache-Control".to_owned(), "no-cache".to_owned()));
+    response
+}
+
+// Finds the repository for a URL path, also trying it with ".git" added. Paths may not leave
+// the root.
+fn open_repository(root: &Path, path: &str) -> Option<GitRepository> {
+    let relative = Path::new(path.trim_start_matches('/'));
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return None;
+    }
+    let path = root.join(relative);
+    GitRepository::open(&path)
+        .or_else(|_| GitRepository::open(Path::new(&format!("{}.git", path.display()))))
+        .ok()
+}
+
+fn service_header(body: &mut Vec<u8>, service: &str) -> Result<(), GitError> {
+    pktline::write_line(body, &format!("# service={}", service)).map_err(pktline::error)?;
+    pktline::write_flush(body).map_err(pktline::error)
+}
+
+fn text(status: u16, message: &str) -> Response {
+    Response::new(status, "text/plain", format!("{}\n", message).into_bytes())
+}
@@ -0,0 +1,346 @@
+use super::GitRepository;
+use crate::error::GitError;
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use std::env;
+use std::fmt;
+
+// Whose identity is being looked up; each has its own environment variables and config keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Author,
+    Committer,
+}
+
+impl Role {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Role::Author => "author",
+            Role::Committer => "committer",
+        }
+    }
+
+    fn env_prefix(&self) -> &'static str {
+        match self {
+            Role::Author => "GIT_AUTHOR",
+            Role::Committer => "GIT_COMMITTER",
+        }
+    }
+}
+
+// The "Name <email> timestamp +zzzz" value of author, committer and tagger headers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ident {
+    pub name: String,
+    pub email: String,
+    pub time: i64,
+    // Offset from UTC in minutes, as written in the "+hhmm" suffix.
+    pub offset: i32,
+}
+
+impl Ident {
+    pub fn parse(value: &str) -> Result<Ident, GitError> {
+        let invalid = || GitError::GenericError(format!("Invalid identity: {}", value));
+        let open = value.find('<').ok_or_else(invalid)?;
+        // The email ends at the first '>' after it, the date starts after the last one.
+        let close = open + value[open..].find('>').ok_or_else(invalid)?;
+        let date_start = value.rfind('>').unwrap_or(close) + 1;
+        let mut date = value[date_start..].split_whitespace();
+        let time = date.next().and_then(|t| t.parse().ok()).unwrap_or(0);
+        let offset = date.next().and_then(parse_offset).unwrap_or(0);
+        Ok(Ident {
+            name: value[..open].trim().to_owned(),
+            email: value[open + 1..close].to_owned(),
+            time,
+            offset,
+        })
+    }
+
+    // An identity dated now, in the local timezone.
+    pub fn now(name: &str, email: &str) -> Ident {
+        let now = Local::now();
+        Ident {
+            name: name.to_owned(),
+            email: email.to_owned(),
+            time: now.timestamp(),
+            offset: now.offset().local_minus_utc() / 60,
+        }
+    }
+
+    pub fn date(&self) -> DateTime<FixedOffset> {
+        let zone = FixedOffset::east_opt(self.offset * 60)
+            .unwrap_or_else(|| FixedOffset::east_opt(0).unwrap());
+        zone.timestamp_opt(self.time, 0)
+            .single()
+            .unwrap_or_else(|| zone.timestamp_opt(0, 0).unwrap())
+    }
+
+    // "+hhmm" or "-hhmm"; a zero offset is written as "+0000".
+    pub fn offset_string(&self) -> String {
+        let sign = if self.offset < 0 { '-' } else { '+' };
+        let minutes = self.offset.abs();
+        format!("{}{:02}{:02}", sign, minutes / 60, minutes % 60)
+    }
+
+    // The date in git's default format, e.g. "Thu Apr 7 15:13:13 2005 -0700".
+    pub fn default_date(&self) -> String {
+        format!(
+            "{} {}",
+            self.date().format("%a %b %-d %H:%M:%S %Y"),
+            self.offset_string()
+        )
+    }
+
+    // The date as "2005-04-07 15:13:13 -0700".
+    pub fn iso_date(&self) -> String {
+        format!(
+            "{} {}",
+            self.date().format("%Y-%m-%d %H:%M:%S"),
+            self.offset_string()
+        )
+    }
+
+    pub fn strict_iso_date(&self) -> String {
+        self.date().format("%Y-%m-%dT%H:%M:%S%:z").to_string()
+    }
+
+    // How long ago the date was, e.g. "3 days ago".
+    pub fn relative_date(&self) -> String {
+        relative_date(Utc::now().timestamp() - self.time)
+    }
+}
+
+impl GitRepository {
+    // The identity recorded as author or committer: its name, email and date, each resolved
+    // as described by ident_name and ident_date.
+    pub fn ident(&self, role: Role) -> Result<Ident, GitError> {
+        let (name, email) = self.ident_name(role)?;
+        let (time, offset) = ident_date(role)?;
+        Ok(Ident {
+            name,
+            email,
+            time,
+            offset,
+        })
+    }
+
+    // The name and email from GIT_<ROLE>_NAME and GIT_<ROLE>_EMAIL, then <role>.name and
+    // <role>.email, then user.name and user.email (and $EMAIL), like git.
+    pub fn ident_name(&self, role: Role) -> Result<(String, String), GitError> {
+        let variable = |key: &str| {
+            env::var(format!(
+                "{}_{}",
+                role.env_prefix(),
+                key.to_ascii_uppercase()
+            ))
+            .ok()
+            .or_else(|| self.config.get(role.as_str(), None, key))
+            .or_else(|| self.config.get("user", None, key))
+        };
+        let name = variable("name").map(|name| strip_crud(&name));
+        let email = variable("email")
+            .or_else(|| env::var("EMAIL").ok())
+            .map(|email| strip_crud(&email));
+        match (name, email) {
+            (Some(name), Some(email)) if !name.is_empty() => Ok((name, email)),
+            (Some(_), Some(_)) => Err(GitError::GenericError(format!(
+                "empty ident name not allowed for the {}",
+                role.as_str()
+            ))),
+            _ => Err(GitError::GenericError(format!(
+                "{} identity unknown; set user.name and user.email, or {}_NAME and {}_EMAIL",
+                role.as_str(),
+                role.env_prefix(),
+                role.env_prefix()
+            ))),
+        }
+    }
+}
+
+// A name or email as git records it: without leading or trailing whitespace and punctuation,
+// and without any '<', '>' or newline that would break the header it goes into.
+pub fn strip_crud(value: &str) -> String {
+    let crud = |c: char| c <= ' ' || ".,:;<>\"\\'".contains(c);
+    value
+        .trim_matches(crud)
+        .chars()
+        .filter(|c| !"<>\n".contains(*c))
+        .collect()
+}
+
+// The date from GIT_<ROLE>_DATE, or now.
+pub fn ident_date(role: Role) -> Result<(i64, i32), GitError> {
+    match env::var(format!("{}_DATE", role.env_prefix())) {
+        Ok(date) => parse_ident_date(&date),
+        Err(_) => {
+            let now = Ident::now("", "");
+            Ok((now.time, now.offset))
+        }
+    }
+}
+
+// A date given for a commit, like --date or GIT_AUTHOR_DATE: any absolute format parse_date
+// knows, or a relative one such as "yesterday", taken in the local timezone.
+pub fn parse_ident_date(value: &str) -> Result<(i64, i32), GitError> {
+    if let Some(date) = parse_date(value) {
+        return Ok(date);
+    }
+    let now = Local::now();
+    let time = approxidate(value, now.timestamp())
+        .ok_or_else(|| GitError::GenericError(format!("invalid date format: {}", value)))?;
+    let offset = Local
+        .timestamp_opt(time, 0)
+        .single()
+        .map_or(now.offset().local_minus_utc(), |date| {
+            date.offset().local_minus_utc()
+        });
+    Ok((time, offset / 60))
+}
+
+impl fmt::Display for Ident {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} <{}> {} {}",
+            self.name,
+            self.email,
+            self.time,
+            self.offset_string()
+        )
+    }
+}
+
+// Parses the absolute dates git accepts: "@<seconds>" or "<seconds>" with an optional "+hhmm",
+// RFC 2822, and ISO 8601 (in local time when no zone is given). Returns the time and offset.
+pub fn parse_date(value: &str) -> Option<(i64, i32)> {
+    let value = value.trim();
+    let mut words = value.split_whitespace();
+    let first = words.next()?;
+    let seconds = first.strip_prefix('@').unwrap_or(first);
+    if !seconds.is_empty() && seconds.bytes().all(|b| b.is_ascii_digit()) {
+        let offset = match words.next() {
+            Some(offset) => parse_offset(offset)?,
+            None => 0,
+        };
+        return match words.next() {
+            Some(_) => None,
+            None => Some((seconds.parse().ok()?, offset)),
+        };
+    }
+    if let Ok(date) = DateTime::parse_from_rfc2822(value) {
+        return Some((date.timestamp(), date.offset().local_minus_utc() / 60));
+    }
+
+    let iso = value.replacen('T', " ", 1).replace('Z', " +0000");
+    for format in [
+        "%Y-%m-%d %H:%M:%S %z",
+        "%Y-%m-%d %H:%M:%S%z",
+        "%Y-%m-%d %H:%M:%S%:z",
+    ] {
+        if let Ok(date) = DateTime::parse_from_str(&iso, format) {
+            return Some((date.timestamp(), date.offset().local_minus_utc() / 60));
+        }
+    }
+    let naive = NaiveDateTime::parse_from_str(&iso, "%Y-%m-%d %H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(&iso, "%Y-%m-%d %H:%M"))
+        .or_else(|_| {
+            NaiveDate::parse_from_str(&iso, "%Y-%m-%d").map(|d| d.and_hms_opt(0, 0, 0).unwrap())
+        })
+        .ok()?;
+    let date = Local.from_local_datetime(&naive).earliest()?;
+    Some((date.timestamp(), date.offset().local_minus_utc() / 60))
+}
+
+// Parses a date that may also be relative to `now`, such as "yesterday" or "2.weeks.ago", the
+// way git's approxidate does for reflog lookups and expiry.
+pub fn approxidate(value: &str, now: i64) -> Option<i64> {
+    if let Some((time, _)) = parse_date(value) {
+        return Some(time);
+    }
+    let value = value.trim().to_ascii_lowercase().replace(['.', '_'], " ");
+    let mut words = value.split_whitespace();
+    let mut time = now;
+    let mut relative = false;
+    while let Some(word) = words.next() {
+        let seconds = match word {
+            "now" => 0,
+            "yesterday" => 86400,
+            "ago" => continue,
+            count => {
+                let count: i64 = count.parse().ok()?;
+                let unit = words.next()?;
+                let unit = unit.strip_suffix('s').unwrap_or(unit);
+                count.checked_mul(match unit {
+                    "second" | "sec" => 1,
+                    "minute" | "min" => 60,
+                    "hour" => 3600,
+                    "day" => 86400,
+                    "week" => 7 * 86400,
+                    "month" => 30 * 86400,
+                    "year" => 365 * 86400,
+                    _ => return None,
+                })?
+            }
+        };
+        // Spans too large to represent are not dates.
+        time = time.checked_sub(seconds)?;
+        relative = true;
+    }
+    match relative {
+        true => Some(time),
+        false => None,
+    }
+}
+
+fn parse_offset(value: &str) -> Option<i32> {
+    let (sign, digits) = match value.as_bytes().first()? {
+        b'+' => (1, &value[1..]),
+        b'-' => (-1, &value[1..]),
+        _ => return None,
+    };
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let hours: i32 = digits[..2].parse().ok()?;
+    let minutes: i32 = digits[2..].parse().ok()?;
+    Some(sign * (hours * 60 + minutes))
+}
+
+// Follows the thresholds git's show_date_relative uses.
+fn relative_date(seconds: i64) -> String {
+    if seconds < 0 {
+        return "in the future".to_owned();
+    }
+    let plural =
+        |n: i64, unit: &str| format!("{} {}{} ago", n, unit, if n == 1 { "" } else { "s" });
+    if seconds < 90 {
+        return plural(seconds, "second");
+    }
+    let minutes = (seconds + 30) / 60;
+    if minutes < 90 {
+        return plural(minutes, "minute");
+    }
+    let hours = (minutes + 30) / 60;
+    if hours < 36 {
+        return plural(hours, "hour");
+    }
+    let days = (hours + 12) / 24;
+    if days < 14 {
+        return plural(days, "day");
+    }
+    if days < 70 {
+        return plural((days + 3) / 7, "week");
+    }
+    if days < 365 {
+        return plural((days + 15) / 30, "month");
+    }
+    let years = days / 365;
+    let months = (days % 365 * 12 + 182) / 365;
+    if years < 5 && months > 0 {
+        return format!(
+            "{}, {}",
+            plural(years, "year").trim_end_matches(" ago"),
+            plural(months, "month")
+        );
+    }
+    plural((days + 183) / 365, "year")
+}
@@ -0,0 +1,196 @@
+use super::GitRepository;
+use crate::error::GitError;
+use crate::files::wildmatch;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// One pattern of a .gitignore or exclude file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IgnorePattern {
+    // The glob without its "!", leading "/" or trailing "/".
+    pub pattern: String,
+    // "!pattern" re-includes what an earlier pattern excluded.
+    pub negated: bool,
+    // "pattern/" only matches directories.
+    pub dir_only: bool,
+    // A pattern with a "/" other than a trailing one matches the path below `base`; any other
+    // matches the last path component at any depth.
+    pub anchored: bool,
+    // The directory of the .gitignore file, as "" or "dir/".
+    pub base: String,
+    // Where the pattern was read, with its line number and text, for `check-ignore -v`.
+    pub source: String,
+    pub line: usize,
+    pub text: String,
+}
+
+impl IgnorePattern {
+    // Parses a line, or returns None for blank lines and comments.
+    pub fn parse(line: &str, base: &str, source: &str, number: usize) -> Option<IgnorePattern> {
+        let text = line.trim_end_matches('\r');
+        if text.is_empty() || text.starts_with('#') {
+            return None;
+        }
+        // Trailing spaces are dropped unless escaped with a backslash.
+        let mut pattern = text;
+        while pattern.ends_with(' ') && !pattern.ends_with("\\ ") {
+            pattern = &pattern[..pattern.len() - 1];
+        }
+        let negated = pattern.starts_with('!');
+        if negated || pattern.starts_with("\\!") || pattern.starts_with("\\#") {
+            pattern = &pattern[1..];
+        }
+        let dir_only = pattern.ends_with('/');
+        let pattern = pattern.trim_end_matches('/');
+        let anchored = pattern.contains('/');
+        let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+        if pattern.is_empty() {
+            return None;
+        }
+        Some(IgnorePattern {
+            pattern: pattern.to_owned(),
+            negated,
+            dir_only,
+            anchored,
+            base: base.to_owned(),
+            source: source.to_owned(),
+            line: number,
+            text: text.to_owned(),
+        })
+    }
+
+    // Whether the pattern matches a worktree path, which is a directory if `is_dir`.
+    pub fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let relative = match path.strip_prefix(&self.base) {
+            Some(relative) => relative,
+            None => return false,
+        };
+        match self.anchored {
+            true => wildmatch(&self.pattern, relative, true),
+            false => wildmatch(
+                &self.pattern,
+                relative.rsplit('/').next().unwrap_or(relative),
+                true,
+            ),
+        }
+    }
+}
+
+// The exclude rules of a worktree. From lowest to highest precedence they are core.excludesFile,
+// info/exclude and the .gitignore files, where a deeper one overrides those above it and the
+// last matching line of a file wins.
+#[derive(Debug)]
+pub struct Ignores {
+    worktree: PathBuf,
+    global: Vec<IgnorePattern>,
+    // The patterns of each directory's .gitignore, read when first needed.
+    dirs: BTreeMap<String, Vec<IgnorePattern>>,
+}
+
+impl GitRepository {
+    // The standard exclude rules of the worktree, as `--exclude-standard` uses.
+    pub fn ignores(&self) -> Result<Ignores, GitError> {
+        let mut global = Vec::new();
+        let excludes_file = match self.config.get("core", None, "excludesfile") {
+            Some(path) => Some(expand_home(&path)),
+            None => match env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+                Some(dir) => Some(PathBuf::from(dir).join("git/ignore")),
+                None => {
+                    env::var_os("HOME").map(|home| PathBuf::from(home).join(".config/git/ignore"))
+                }
+            },
+        };
+        if let Some(path) = excludes_file {
+            read_patterns(&path, "", &path.display().to_string(), &mut global);
+        }
+        let exclude = self.repo_path(Path::new("info/exclude"));
+        let source = exclude
+            .strip_prefix(&self.worktree)
+            .unwrap_or(&exclude)
+            .display()
+            .to_string();
+        read_patterns(&exclude, "", &source, &mut global);
+        Ok(Ignores {
+            worktree: self.worktree.clone(),
+            global,
+            dirs: BTreeMap::new(),
+        })
+    }
+}
+
+impl Ignores {
+    // The pattern that decides whether a worktree path is ignored, which may be a negated one
+    // that re-includes it. Nothing inside an excluded directory can be re-included, so an
+    // excluded parent directory decides for everything in it.
+    pub fn matched(&mut self, path: &str, is_dir: bool) -> Option<&IgnorePattern> {
+        let parents: Vec<usize> = path.match_indices('/').map(|(i, _)| i).collect();
+        self.load(&parents, path);
+        let excluded_parent = parents.iter().find(|&&end| {
+            self.find(&path[..end], true)
+                .is_some_and(|pattern| !pattern.negated)
+        });
+        match excluded_parent {
+            Some(&end) => self.find(&path[..end], true),
+            None => self.find(path, is_dir),
+        }
+    }
+
+    pub fn is_ignored(&mut self, path: &str, is_dir: bool) -> bool {
+        self.matched(path, is_dir)
+            .is_some_and(|pattern| !pattern.negated)
+    }
+
+    // Reads the .gitignore files of the worktree root and of the directories ending at `parents`.
+    fn load(&mut self, parents: &[usize], path: &str) {
+        let bases = std::iter::once(String::new())
+            .chain(parents.iter().map(|&end| format!("{}/", &path[..end])));
+        for base in bases {
+            if self.dirs.contains_key(&base) {
+                continue;
+            }
+            let source = format!("{}.gitignore", base);
+            let mut patterns = Vec::new();
+            read_patterns(&self.worktree.join(&source), &base, &source, &mut patterns);
+            self.dirs.insert(base, patterns);
+        }
+    }
+
+    fn find(&self, path: &str, is_dir: bool) -> Option<&IgnorePattern> {
+        let bases = std::iter::once("")
+            .chain(path.match_indices('/').map(|(i, _)| &path[..=i]))
+            .collect::<Vec<_>>();
+        bases
+            .iter()
+            .rev()
+            .filter_map(|base| self.dirs.get(*base))
+            .chain(std::iter::once(&self.global))
+            .find_map(|patterns| {
+                patterns
+                    .iter()
+                    .rev()
+                    .find(|pattern| pattern.matches(path, is_dir))
+            })
+    }
+}
+
+fn read_patterns(path: &Path, base: &str, source: &str, out: &mut Vec<IgnorePattern>) {
+    if let Ok(text) = fs::read_to_string(path) {
+        out.extend(
+            text.lines()
+                .enumerate()
+                .filter_map(|(i, line)| IgnorePattern::parse(line, base, source, i + 1)),
+        );
+    }
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
@@ -0,0 +1,171 @@
+use super::config::{edit_file, ConfigEdit, GitConfig};
+use super::refs::check_ref_name;
+use super::GitRepository;
+use crate::error::GitError;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const DEFAULT_DESCRIPTION: &str =
+    "Unnamed repository; edit this file 'description' to name the repository.";
+
+// How `init` lays out a repository. The defaults give a repository with a worktree and a .git
+// directory, as `write_to_path` makes.
+#[derive(Debug, Clone, Default)]
+pub struct InitOptions {
+    pub bare: bool,
+    // The branch HEAD points to; init.defaultBranch or "master" when None.
+    pub initial_branch: Option<String>,
+    // Files to copy into the new gitdir; $GIT_TEMPLATE_DIR or init.templateDir when None.
+    pub template: Option<PathBuf>,
+    // Where to put the gitdir instead of <path>/.git, which becomes a "gitdir:" file.
+    pub separate_git_dir: Option<PathBuf>,
+}
+
+impl GitRepository {
+    // Creates a repository at `path`, or reinitializes the one already there. Reinitializing
+    // only adds what is missing: objects, refs, HEAD and the config are left as they are.
+    // Returns the repository and whether it already existed.
+    pub fn init(path: &Path, options: &InitOptions) -> Result<(GitRepository, bool), GitError> {
+        let global = GitConfig::load(None)?;
+        let branch = options
+            .initial_branch
+            .clone()
+            .or_else(|| global.get("init", None, "defaultbranch"))
+            .unwrap_or_else(|| "master".to_owned());
+        if check_ref_name(&format!("refs/heads/{}", branch)).is_err() {
+            return Err(GitError::GenericError(format!(
+                "invalid initial branch name: '{}'",
+                branch
+            )));
+        }
+        fs::create_dir_all(path).map_err(|_| {
+            GitError::PathError("Could not create directory".to_owned(), path.to_path_buf())
+        })?;
+        let worktree = fs::canonicalize(path).map_err(|_| {
+            GitError::PathError("Could not create directory".to_owned(), path.to_path_buf())
+        })?;
+        let dotgit = worktree.join(".git");
+        let gitdir = match (&options.separate_git_dir, options.bare) {
+            (Some(_), true) => {
+                return Err(GitError::GenericError(
+                    "--separate-git-dir and --bare are mutually exclusive".to_owned(),
+                ))
+            }
+            (Some(separate), false) => {
+                let separate = env::current_dir().unwrap_or_default().join(separate);
+                // An existing .git directory moves to the new place.
+                if dotgit.is_dir() && !separate.exists() {
+                    fs::rename(&dotgit, &separate).map_err(|_| {
+                        GitError::PathError("unable to move .git to".to_owned(), separate.clone())
+                    })?;
+                }
+                separate
+            }
+            (None, true) => worktree.clone(),
+            (None, false) if dotgit.is_file() => super::read_gitfile(&dotgit)?,
+            (None, false) => dotgit.clone(),
+        };
+        let reinit = super::is_gitdir(&gitdir);
+
+        for dir in [
+            "branches",
+            "info",
+            "objects/info",
+            "objects/pack",
+            "refs/heads",
+            "refs/tags",
+        ] {
+            fs::create_dir_all(gitdir.join(dir)).map_err(|_| {
+                GitError::PathError("Could not create directories".to_owned(), gitdir.join(dir))
+            })?;
+        }
+        let gitdir = fs::canonicalize(&gitdir)
+            .map_err(|_| GitError::PathError("Could not create directory".to_owned(), gitdir))?;
+        if options.separate_git_dir.is_some() {
+            fs::write(&dotgit, format!("gitdir: {}\n", gitdir.display())).map_err(|_| {
+                GitError::PathError("Could not write file".to_owned(), dotgit.clone())
+            })?;
+        }
+
+        let template = options
+            .template
+            .clone()
+            .or_else(|| env::var_os("GIT_TEMPLATE_DIR").map(PathBuf::from))
+            .or_else(|| global.get("init", None, "templatedir").map(PathBuf::from));
+        if let Some(template) = template.filter(|template| template.is_dir()) {
+            copy_template(&template, &gitdir)?;
+        }
+        let description = gitdir.join("description");
+        if !description.exists() {
+            write_new_file(&description, &format!("{}\n", DEFAULT_DESCRIPTION))?;
+        }
+
+        if reinit {
+            if options.initial_branch.is_some() {
+                eprintln!(
+                    "warning: re-init: ignored --initial-branch={}",
+                    options.initial_branch.as_deref().unwrap_or_default()
+                );
+            }
+        } else {
+            write_new_file(
+                &gitdir.join("HEAD"),
+                &format!("ref: refs/heads/{}\n", branch),
+            )?;
+
+            // A config from the template keeps its settings, but core ones are always ours.
+            let config = gitdir.join("config");
+            let bare = options.bare.to_string();
+            let mut core = vec![
+                ("repositoryformatversion", "0"),
+                ("filemode", "false"),
+                ("bare", bare.as_str()),
+            ];
+            if !options.bare {
+                core.push(("logallrefupdates", "true"));
+            }
+            for (key, value) in core {
+                edit_file(&config, "core", None, key, ConfigEdit::Set(value))?;
+            }
+        }
+
+        let worktree = match options.bare {
+            true => gitdir.clone(),
+            false => worktree,
+        };
+        let repo = GitRepository {
+            config: GitConfig::load(Some(&gitdir))?,
+            worktree,
+            gitdir,
+            pack_store: Default::default(),
+        };
+        Ok((repo, reinit))
+    }
+}
+
+// Copies a template directory into a gitdir, without replacing files that already exist.
+fn copy_template(template: &Path, gitdir: &Path) -> Result<(), GitError> {
+    let entries = fs::read_dir(template).map_err(|_| {
+        GitError::PathError("Could not read template".to_owned(), template.to_path_buf())
+    })?;
+    for entry in entries.flatten() {
+        let source = entry.path();
+        let target = gitdir.join(entry.file_name());
+        if source.is_dir() {
+            fs::create_dir_all(&target).map_err(|_| {
+                GitError::PathError("Could not create directories".to_owned(), target.clone())
+            })?;
+            copy_template(&source, &target)?;
+        } else if !target.exists() {
+            fs::copy(&source, &target)
+                .map_err(|_| GitError::PathError("Could not copy template".to_owned(), source))?;
+        }
+    }
+    Ok(())
+}
+
+fn write_new_file(path: &Path, contents: &str) -> Result<(), GitError> {
+    fs::write(path, contents)
+        .map_err(|_| GitError::PathError("Could not write file".to_owned(), path.to_path_buf()))
+}
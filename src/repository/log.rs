@@ -0,0 +1,338 @@
+use super::commit::Commit;
+use super::oid::ObjectId;
+use super::tree::TreeEntry;
+use super::GitRepository;
+use crate::error::GitError;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogOrder {
+    // Newest commit date first, never showing a parent before its children.
+    Date,
+    // Like Date, but keeps each line of history together instead of interleaving branches.
+    Topo,
+}
+
+#[derive(Debug, Clone)]
+pub struct LogEntry {
+    pub oid: ObjectId,
+    pub commit: Commit,
+    // The parents as they appear in the log: when paths are given, each parent is replaced
+    // by its closest ancestors that touch them.
+    pub parents: Vec<ObjectId>,
+}
+
+impl GitRepository {
+    // Lists the commits reachable from `include` but not from `exclude`. With `paths`, only
+    // commits that changed one of them are kept, and at a merge that matches one of its parents
+    // for those paths only that parent is followed, as git's default history simplification does.
+    pub fn log(
+        &self,
+        include: &[ObjectId],
+        exclude: &[ObjectId],
+        paths: &[String],
+        order: LogOrder,
+    ) -> Result<Vec<LogEntry>, GitError> {
+        let mut hidden = HashSet::new();
+        let mut pending = exclude.to_vec();
+        while let Some(oid) = pending.pop() {
+            if hidden.insert(oid) {
+                pending.extend(self.read_commit(&oid)?.parents);
+            }
+        }
+
+        // Walk everything reachable, remembering which parents are followed and which
+        // commits are shown.
+        let mut commits: HashMap<ObjectId, (Commit, Vec<ObjectId>, bool)> = HashMap::new();
+        let mut pending: Vec<ObjectId> = include.iter().rev().copied().collect();
+        while let Some(oid) = pending.pop() {
+            if hidden.contains(&oid) || commits.contains_key(&oid) {
+                continue;
+            }
+            let commit = self.read_commit(&oid)?;
+            let (followed, shown) = self.simplify(&commit, paths)?;
+            let followed: Vec<ObjectId> = followed
+                .into_iter()
+                .filter(|parent| !hidden.contains(parent))
+                .collect();
+            pending.extend(followed.iter().rev());
+            commits.insert(oid, (commit, followed, shown));
+        }
+
+        let ordered = topological_order(include, &commits, order);
+
+        // Rewrite parents so the graph connects shown commits, oldest commits first.
+        let mut rewritten: HashMap<ObjectId, Vec<ObjectId>> = HashMap::new();
+        for oid in ordered.iter().rev() {
+            let (_, followed, shown) = &commits[oid];
+            let mut parents = Vec::new();
+            for parent in followed {
+                for ancestor in &rewritten[parent] {
+                    if !parents.contains(ancestor) {
+                        parents.push(*ancestor);
+                    }
+                }
+            }
+            rewritten.insert(*oid, if *shown { vec![*oid] } else { parents });
+        }
+
+        let mut entries = Vec::new();
+        for oid in ordered {
+            let (commit, followed, shown) = commits.remove(&oid).unwrap();
+            if !shown {
+                continue;
+            }
+            let mut parents = Vec::new();
+            for parent in &followed {
+                for ancestor in &rewritten[parent] {
+                    if !parents.contains(ancestor) {
+                        parents.push(*ancestor);
+                    }
+                }
+            }
+            entries.push(LogEntry {
+                oid,
+                commit,
+                parents,
+            });
+        }
+        Ok(entries)
+    }
+
+    // Decides which parents of a commit to follow and whether it is shown for the given paths.
+    fn simplify(
+        &self,
+        commit: &Commit,
+        paths: &[String],
+    ) -> Result<(Vec<ObjectId>, bool), GitError> {
+        if paths.is_empty() {
+            return Ok((commit.parents.clone(), true));
+        }
+        let own = self.path_entries(&commit.tree, paths)?;
+        if commit.parents.is_empty() {
+            return Ok((Vec::new(), own.iter().any(Option::is_some)));
+        }
+        for parent in &commit.parents {
+            let tree = self.read_commit(parent)?.tree;
+            if self.path_entries(&tree, paths)? == own {
+                return Ok((vec![*parent], false));
+            }
+        }
+        Ok((commit.parents.clone(), true))
+    }
+
+    fn path_entries(
+        &self,
+        tree: &ObjectId,
+        paths: &[String],
+    ) -> Result<Vec<Option<TreeEntry>>, GitError> {
+        paths
+            .iter()
+            .map(|path| {
+                let path = path.trim_end_matches('/');
+                if path.is_empty() || path == "." {
+                    Ok(Some(TreeEntry {
+                        mode: 0o40000,
+                        name: String::new(),
+                        oid: *tree,
+                    }))
+                } else {
+                    self.lookup_path(tree, path)
+                }
+            })
+            .collect()
+    }
+}
+
+// Orders the walked commits so that children always come before their parents.
+fn topological_order(
+    include: &[ObjectId],
+    commits: &HashMap<ObjectId, (Commit, Vec<ObjectId>, bool)>,
+    order: LogOrder,
+) -> Vec<ObjectId> {
+    let mut children: HashMap<ObjectId, usize> = HashMap::new();
+    for (_, followed, _) in commits.values() {
+        for parent in followed {
+            *children.entry(*parent).or_default() += 1;
+        }
+    }
+    let date = |oid: &ObjectId| {
+        commits[oid]
+            .0
+            .committer_ident()
+            .map(|ident| ident.time)
+            .unwrap_or(0)
+    };
+
+    let mut result = Vec::with_capacity(commits.len());
+    let mut seen = HashSet::new();
+    let starts: Vec<ObjectId> = include
+        .iter()
+        .filter(|oid| commits.contains_key(oid) && !children.contains_key(oid))
+        .filter(|oid| seen.insert(**oid))
+        .copied()
+        .collect();
+    match order {
+        LogOrder::Date => {
+            // Ties on the date keep the order commits became ready in.
+            let mut sequence = 0usize;
+            let mut ready: BinaryHeap<(i64, std::cmp::Reverse<usize>, ObjectId)> = starts
+                .iter()
+                .map(|oid| {
+                    sequence += 1;
+                    (date(oid), std::cmp::Reverse(sequence), *oid)
+                })
+                .collect();
+            while let Some((_, _, oid)) = ready.pop() {
+                result.push(oid);
+                for parent in &commits[&oid].1 {
+                    let count = children.get_mut(parent).unwrap();
+                    *count -= 1;
+                    if *count == 0 {
+                        sequence += 1;
+                        ready.push((date(parent), std::cmp::Reverse(sequence), *parent));
+                    }
+                }
+            }
+        }
+        LogOrder::Topo => {
+            let mut ready: Vec<ObjectId> = starts.into_iter().rev().collect();
+            while let Some(oid) = ready.pop() {
+                result.push(oid);
+                // As in git, the last parent is pushed last and so its line is followed first.
+                for parent in &commits[&oid].1 {
+                    let count = children.get_mut(parent).unwrap();
+                    *count -= 1;
+                    if *count == 0 {
+                        ready.push(*parent);
+                    }
+                }
+            }
+        }
+    }
+    result
+}
+
+// Draws the ASCII history graph of `log --graph`, one commit at a time. Each column holds the
+// commit the line drawn in it leads to.
+#[derive(Debug, Default)]
+pub struct GraphRenderer {
+    columns: Vec<ObjectId>,
+    last_row: String,
+}
+
+// The graph drawn for one commit, each row padded to the same width: the row with its '*', the
+// rows that move lines from the old columns to the new ones, and the plain lines before and
+// after the commit, which prefix separators and any remaining lines of its text.
+#[derive(Debug, Default)]
+pub struct GraphRows {
+    pub commit: String,
+    pub transitions: Vec<String>,
+    pub before: String,
+    pub after: String,
+}
+
+impl GraphRenderer {
+    pub fn next(&mut self, oid: &ObjectId, parents: &[ObjectId]) -> GraphRows {
+        let index = match self.columns.iter().position(|c| c == oid) {
+            Some(index) => index,
+            None => {
+                self.columns.push(*oid);
+                self.columns.len() - 1
+            }
+        };
+        let old = std::mem::take(&mut self.columns);
+
+        // The commit's column is replaced by those of its parents that no other line leads to.
+        let mut new: Vec<ObjectId> = old[..index].to_vec();
+        for parent in parents {
+            let elsewhere = old
+                .iter()
+                .enumerate()
+                .any(|(i, c)| i != index && c == parent);
+            if !elsewhere && !new.contains(parent) {
+                new.push(*parent);
+            }
+        }
+        for column in &old[index + 1..] {
+            if column != oid && !new.contains(column) {
+                new.push(*column);
+            }
+        }
+
+        let mut edges = Vec::new();
+        for (i, column) in old.iter().enumerate() {
+            if i == index {
+                for parent in parents {
+                    if let Some(to) = new.iter().position(|c| c == parent) {
+                        edges.push((i, to));
+                    }
+                }
+            } else if let Some(to) = new.iter().position(|c| c == column) {
+                edges.push((i, to));
+            }
+        }
+
+        let width = old.len().max(new.len()) * 2;
+        let pad = |row: String| format!("{:width$}", row, width = width);
+        let mut commit = pipes(old.len());
+        commit.replace_range(index * 2..index * 2 + 1, "*");
+        // Lines to the right of a merge that were still leaning from the previous row keep
+        // leaning towards the columns they move to.
+        if parents.len() > 1 {
+            for &(from, to) in &edges {
+                if from > index
+                    && to > from
+                    && self.last_row.as_bytes().get(from * 2 - 1) == Some(&b'\\')
+                {
+                    commit.replace_range(from * 2..from * 2 + 1, "\\");
+                }
+            }
+        }
+        let rows = GraphRows {
+            commit: pad(commit),
+            transitions: transitions(edges).into_iter().map(pad).collect(),
+            before: pad(pipes(old.len())),
+            after: pad(pipes(new.len())),
+        };
+        self.columns = new;
+        self.last_row = rows.transitions.last().unwrap_or(&rows.commit).clone();
+        rows
+    }
+
+    // Records that the commit's text went on past its transitions, so no line is still leaning.
+    // Padding rows drawn for separators do not count, as in git.
+    pub fn straighten(&mut self) {
+        self.last_row.clear();
+    }
+}
+
+fn pipes(count: usize) -> String {
+    vec!["|"; count].join(" ") + " "
+}
+
+// Moves every line at most one column per row until each reaches its new column.
+fn transitions(mut edges: Vec<(usize, usize)>) -> Vec<String> {
+    let mut rows = Vec::new();
+    while edges.iter().any(|(from, to)| from != to) {
+        let width = edges
+            .iter()
+            .map(|(from, to)| from.max(to) + 1)
+            .max()
+            .unwrap_or(0);
+        let mut row = vec![b' '; width * 2];
+        for (from, to) in edges.iter_mut() {
+            if *to > *from {
+                row[*from * 2 + 1] = b'\\';
+                *from += 1;
+            } else if *to < *from {
+                row[*from * 2 - 1] = b'/';
+                *from -= 1;
+            } else if row[*from * 2] == b' ' {
+                row[*from * 2] = b'|';
+            }
+        }
+        rows.push(String::from_utf8(row).unwrap());
+    }
+    rows
+}
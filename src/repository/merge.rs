@@ -0,0 +1,453 @@
+use super::object::{GitObject, ObjType};
+use super::oid::ObjectId;
+use super::tree::verify_path;
+use super::worktree::TreeEntries;
+use super::{GitIndex, GitRepository};
+use crate::diff::{self, ConflictStyle, MergeLabels};
+use crate::error::GitError;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+
+// Whether `merge` may just move the branch forward when HEAD is behind the merged commit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FastForward {
+    Allow,
+    Never,
+    Only,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeResult {
+    UpToDate,
+    FastForward(Option<ObjectId>, ObjectId),
+    // The merged tree is in the index and worktree, ready to commit unless there are conflicts.
+    // `messages` are the "Auto-merging" and "CONFLICT" lines git prints.
+    Merged {
+        messages: Vec<String>,
+        conflicts: Vec<String>,
+    },
+}
+
+// The merged version of one path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergedEntry {
+    Clean(u32, ObjectId),
+    // The base, ours and theirs versions for stages 1 to 3, and what to leave in the worktree.
+    Conflict {
+        stages: [Option<(u32, ObjectId)>; 3],
+        worktree: Option<(u32, Vec<u8>)>,
+    },
+}
+
+impl GitRepository {
+    // Merges `theirs`, named `name` on the command line, into HEAD. A merged tree is left in the
+    // index and worktree with MERGE_HEAD and MERGE_MSG written for `commit` to finish.
+    pub fn merge(
+        &self,
+        theirs: &ObjectId,
+        name: &str,
+        fast_forward: FastForward,
+        style: ConflictStyle,
+    ) -> Result<MergeResult, GitError> {
+        if self.repo_path(&path!("MERGE_HEAD")).exists() {
+            return Err(GitError::GenericError(
+                "You have not concluded your merge (MERGE_HEAD exists).".to_owned(),
+            ));
+        }
+        let head = self.resolve_ref("HEAD")?;
+        let head = match head {
+            Some(head) if head == *theirs || self.is_ancestor(theirs, &head)? => {
+                return Ok(MergeResult::UpToDate)
+            }
+            Some(head) => head,
+            None => {
+                self.checkout_commit(theirs, false)?;
+                let message = format!("merge {}: Fast-forward", name);
+                self.update_ref("HEAD", theirs, None, true, &message)?;
+                return Ok(MergeResult::FastForward(None, *theirs));
+            }
+        };
+        if fast_forward != FastForward::Never && self.is_ancestor(&head, theirs)? {
+            self.checkout_commit(theirs, false)?;
+            let message = format!("merge {}: Fast-forward", name);
+            self.update_ref("HEAD", theirs, Some(&head), true, &message)?;
+            return Ok(MergeResult::FastForward(Some(head), *theirs));
+        }
+        if fast_forward == FastForward::Only {
+            return Err(GitError::GenericError(
+                "Not possible to fast-forward, aborting.".to_owned(),
+            ));
+        }
+
+        // With several bases, as after criss-cross merges, the newest one is used.
+        let base = self.merge_bases(&head, theirs)?.first().copied();
+        let base_label = base.map(|oid| format!("{:.7}", oid)).unwrap_or_default();
+        let labels = MergeLabels {
+            ours: "HEAD",
+            base: &base_label,
+            theirs: name,
+        };
+        let ours_entries = self.commit_tree_entries(Some(&head))?;
+        let (merged, messages) = self.merge_trees(
+            &base
+                .map(|base| self.commit_tree_entries(Some(&base)))
+                .transpose()?
+                .unwrap_or_default(),
+            &ours_entries,
+            &self.commit_tree_entries(Some(theirs))?,
+            &labels,
+            style,
+        )?;
+        let conflicts = self.apply_merge(&ours_entries, &merged)?;
+
+        let mut message = self.merge_message(name)?;
+        if !conflicts.is_empty() {
+            message.push_str("\n# Conflicts:\n");
+            for path in &conflicts {
+                message.push_str(&format!("#\t{}\n", path));
+            }
+        }
+        self.write_merge_file("ORIG_HEAD", &format!("{}\n", head))?;
+        self.write_merge_file("MERGE_HEAD", &format!("{}\n", theirs))?;
+        self.write_merge_file("MERGE_MSG", &message)?;
+        Ok(MergeResult::Merged {
+            messages,
+            conflicts,
+        })
+    }
+
+    // Merges flattened trees path by path. A path changed on one side only takes that side;
+    // files changed on both sides are merged line by line.
+    pub fn merge_trees(
+        &self,
+        base: &TreeEntries,
+        ours: &TreeEntries,
+        theirs: &TreeEntries,
+        labels: &MergeLabels,
+        style: ConflictStyle,
+    ) -> Result<(BTreeMap<String, MergedEntry>, Vec<String>), GitError> {
+        let paths: BTreeSet<&String> = base
+            .keys()
+            .chain(ours.keys())
+            .chain(theirs.keys())
+            .collect();
+        let mut merged = BTreeMap::new();
+        let mut messages = Vec::new();
+        for path in paths {
+            let (b, o, t) = (base.get(path), ours.get(path), theirs.get(path));
+            let result = if o == t || b == t {
+                o.map(|(mode, oid)| MergedEntry::Clean(*mode, *oid))
+            } else if b == o {
+                t.map(|(mode, oid)| MergedEntry::Clean(*mode, *oid))
+            } else {
+                match (o, t) {
+                    (Some(o), Some(t)) => {
+                        messages.push(format!("Auto-merging {}", path));
+                        let entry = self.merge_file(b, o, t, labels, style)?;
+                        if let MergedEntry::Conflict { .. } = entry {
+                            let kind = if b.is_some() { "content" } else { "add/add" };
+                            messages
+                                .push(format!("CONFLICT ({}): Merge conflict in {}", kind, path));
+                        }
+                        Some(entry)
+                    }
+                    // Deleted on one side and modified on the other: the modified version stays
+                    // in the worktree.
+                    (kept, _) => {
+                        let (side, deleted_in, modified_in) = match kept {
+                            Some(_) => (o, labels.theirs, "HEAD"),
+                            None => (t, "HEAD", labels.theirs),
+                        };
+                        let (mode, oid) = side.unwrap();
+                        messages.push(format!(
+                            "CONFLICT (modify/delete): {} deleted in {} and modified in {}.  Version {} of {} left in tree.",
+                            path, deleted_in, modified_in, modified_in, path
+                        ));
+                        Some(MergedEntry::Conflict {
+                            stages: [b.copied(), o.copied(), t.copied()],
+                            worktree: Some((*mode, self.read_object(oid)?.data)),
+                        })
+                    }
+                }
+            };
+            if let Some(result) = result {
+                merged.insert(path.clone(), result);
+            }
+        }
+
+        // A file on one side where the other side has a directory cannot stay at its path. As
+        // git does, it moves to "<path>~<side>" as a conflict and the directory takes its place.
+        let in_the_way: Vec<String> = merged
+            .keys()
+            .filter(|path| {
+                let prefix = format!("{}/", path);
+                merged
+                    .range(prefix.clone()..)
+                    .next()
+                    .is_some_and(|(next, _)| next.starts_with(&prefix))
+            })
+            .cloned()
+            .collect();
+        for path in in_the_way {
+            let prefix = format!("{}/", path);
+            let (side, stage) = match ours.keys().any(|other| other.starts_with(&prefix)) {
+                true => (labels.theirs, 2),
+                false => (labels.ours, 1),
+            };
+            let (stages, worktree) = match merged.remove(&path) {
+                // Only the side with the file has a stage for it.
+                Some(MergedEntry::Clean(mode, oid)) => {
+                    let mut stages = [None; 3];
+                    stages[stage] = Some((mode, oid));
+                    (stages, Some((mode, self.read_object(&oid)?.data)))
+                }
+                Some(MergedEntry::Conflict { stages, worktree }) => (stages, worktree),
+                None => continue,
+            };
+            let moved = format!("{}~{}", path, side);
+            messages.push(format!(
+                "CONFLICT (file/directory): directory in the way of {} from {}; moving it to {} instead.",
+                path, side, moved
+            ));
+            merged.insert(moved, MergedEntry::Conflict { stages, worktree });
+        }
+        Ok((merged, messages))
+    }
+
+    // Merges two versions of a file against their base, which is empty for files both sides
+    // added. Only regular files with the same mode change on at most one side merge cleanly.
+    fn merge_file(
+        &self,
+        base: Option<&(u32, ObjectId)>,
+        ours: &(u32, ObjectId),
+        theirs: &(u32, ObjectId),
+        labels: &MergeLabels,
+        style: ConflictStyle,
+    ) -> Result<MergedEntry, GitError> {
+        let stages = [base.copied(), Some(*ours), Some(*theirs)];
+        let mode = match base {
+            Some((mode, _)) if *mode == ours.0 => theirs.0,
+            _ if ours.0 == theirs.0 => ours.0,
+            Some((mode, _)) if *mode == theirs.0 => ours.0,
+            _ => {
+                let data = self.read_object(&ours.1)?.data;
+                return Ok(MergedEntry::Conflict {
+                    stages,
+                    worktree: Some((ours.0, data)),
+                });
+            }
+        };
+        let base_data = match base {
+            Some((_, oid)) => self.read_object(oid)?.data,
+            None => Vec::new(),
+        };
+        let ours_data = self.read_object(&ours.1)?.data;
+        let theirs_data = self.read_object(&theirs.1)?.data;
+        let regular = |mode: u32| mode == 0o100644 || mode == 0o100755;
+        if !regular(ours.0)
+            || !regular(theirs.0)
+            || [&base_data, &ours_data, &theirs_data]
+                .iter()
+                .any(|data| diff::is_binary(data))
+        {
+            return Ok(MergedEntry::Conflict {
+                stages,
+                worktree: Some((ours.0, ours_data)),
+            });
+        }
+        let (data, conflicts) = diff::merge(&base_data, &ours_data, &theirs_data, labels, style);
+        if conflicts > 0 {
+            return Ok(MergedEntry::Conflict {
+                stages,
+                worktree: Some((mode, data)),
+            });
+        }
+        let oid = GitRepository::write_object(self, &GitObject::new(ObjType::Blob, &data))?;
+        Ok(MergedEntry::Clean(mode, oid))
+    }
+
+    // Updates the index and worktree from HEAD's tree to the merged one, refusing if that would
+    // lose local changes. Returns the conflicted paths.
+    fn apply_merge(
+        &self,
+        ours: &TreeEntries,
+        merged: &BTreeMap<String, MergedEntry>,
+    ) -> Result<Vec<String>, GitError> {
+        let mut index: BTreeMap<String, GitIndex> = self
+            .read_index()?
+            .into_iter()
+            .map(|entry| (entry.path.clone(), entry))
+            .collect();
+        let staged: Vec<&String> = index
+            .keys()
+            .chain(ours.keys())
+            .filter(|path| {
+                let entry = index
+                    .get(*path)
+                    .map(|entry| (GitRepository::tree_mode(entry.mode), entry.oid));
+                entry.as_ref() != ours.get(*path)
+            })
+            .collect();
+        if !staged.is_empty() {
+            return Err(GitError::GenericError(format!(
+                "Your local changes to the following files would be overwritten by merge:\n\t{}\nPlease commit your changes or stash them before you merge.",
+                staged.iter().map(|path| path.as_str()).collect::<Vec<_>>().join("\n\t")
+            )));
+        }
+        let changed: Vec<&String> = ours
+            .keys()
+            .chain(merged.keys())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .filter(|path| match merged.get(*path) {
+                Some(MergedEntry::Clean(mode, oid)) => ours.get(*path) != Some(&(*mode, *oid)),
+                _ => true,
+            })
+            .collect();
+        let removed: BTreeSet<String> = ours
+            .keys()
+            .filter(|path| !merged.contains_key(*path))
+            .cloned()
+            .collect();
+        let mut dirty = Vec::new();
+        let mut untracked = BTreeSet::new();
+        for path in &changed {
+            match index.get(*path) {
+                Some(entry) if !self.worktree_matches_index(entry)? => dirty.push(path.as_str()),
+                Some(_) => {}
+                None if self.worktree_mode(path).is_some() => {
+                    untracked.insert(path.to_string());
+                }
+                None => {}
+            }
+            if merged.contains_key(*path) {
+                untracked.extend(self.untracked_in_the_way(path, &removed));
+            }
+        }
+        if !dirty.is_empty() {
+            return Err(GitError::GenericError(format!(
+                "Your local changes to the following files would be overwritten by merge:\n\t{}\nPlease commit your changes or stash them before you merge.",
+                dirty.join("\n\t")
+            )));
+        }
+        if !untracked.is_empty() {
+            return Err(GitError::GenericError(format!(
+                "The following untracked working tree files would be overwritten by merge:\n\t{}\nPlease move or remove them before you merge.",
+                untracked.into_iter().collect::<Vec<_>>().join("\n\t")
+            )));
+        }
+
+        let mut conflicts = Vec::new();
+        let mut entries: Vec<GitIndex> = Vec::new();
+        for path in changed {
+            index.remove(path);
+            match merged.get(path) {
+                Some(MergedEntry::Clean(mode, oid)) => {
+                    index.insert(path.clone(), self.checkout_entry(path, *mode, oid)?);
+                }
+                Some(MergedEntry::Conflict { stages, worktree }) => {
+                    if let Some((mode, data)) = worktree {
+                        self.write_conflicted_file(path, *mode, data)?;
+                    }
+                    for (stage, version) in stages.iter().enumerate() {
+                        if let Some((mode, oid)) = version {
+                            entries.push(GitIndex::unmerged(path, stage as u16 + 1, *mode, *oid));
+                        }
+                    }
+                    conflicts.push(path.clone());
+                }
+                None => self.remove_worktree_file(path)?,
+            }
+        }
+        entries.extend(index.into_values());
+        self.write_index(&entries)?;
+        Ok(conflicts)
+    }
+
+    fn write_conflicted_file(&self, path: &str, mode: u32, data: &[u8]) -> Result<(), GitError> {
+        let full_path = self.worktree.join(path);
+        let io_error = |message: &str| GitError::PathError(message.to_owned(), full_path.clone());
+        if !verify_path(path) {
+            return Err(io_error("invalid path"));
+        }
+        if mode == 0o120000 || mode == 0o160000 {
+            // Links and submodules keep our version, already in the worktree.
+            return Ok(());
+        }
+        if let Some(parent) = full_path.parent() {
+            fs::create_dir_all(parent).map_err(|_| io_error("Could not create directory"))?;
+        }
+        fs::write(&full_path, data).map_err(|_| io_error("Could not write file"))?;
+        let permissions = if mode == 0o100755 { 0o755 } else { 0o644 };
+        fs::set_permissions(&full_path, fs::Permissions::from_mode(permissions))
+            .map_err(|_| io_error("Could not set permissions"))
+    }
+
+    // "Merge branch 'topic'", with " into <branch>" unless merging into main or master.
+    fn merge_message(&self, name: &str) -> Result<String, GitError> {
+        let mut message = match self.dwim_ref(name)? {
+            Some((full, _)) if full.starts_with("refs/heads/") => {
+                format!("Merge branch '{}'", name)
+            }
+            Some((full, _)) if full.starts_with("refs/tags/") => format!("Merge tag '{}'", name),
+            Some((full, _)) if full.starts_with("refs/remotes/") => {
+                format!("Merge remote-tracking branch '{}'", name)
+            }
+            _ => format!("Merge commit '{}'", name),
+        };
+        match self.current_branch() {
+            Some(branch) if branch != "main" && branch != "master" => {
+                message.push_str(&format!(" into {}", branch))
+            }
+            _ => {}
+        }
+        message.push('\n');
+        Ok(message)
+    }
+
+    // The commits being merged, read from MERGE_HEAD; empty when no merge is in progress.
+    pub fn merge_heads(&self) -> Result<Vec<ObjectId>, GitError> {
+        match fs::read_to_string(self.repo_path(&path!("MERGE_HEAD"))) {
+            Ok(heads) => heads.lines().map(ObjectId::from_hex).collect(),
+            Err(_) => Ok(Vec::new()),
+        }
+    }
+
+    // Forgets the merge in progress, once committed or aborted.
+    pub fn clear_merge_state(&self) -> Result<(), GitError> {
+        for name in ["MERGE_HEAD", "MERGE_MSG"] {
+            let path = self.repo_path(&path!(name));
+            if path.exists() {
+                fs::remove_file(&path)
+                    .map_err(|_| GitError::PathError(format!("Could not remove {}", name), path))?;
+            }
+        }
+        Ok(())
+    }
+
+    // Throws away a conflicted merge: the index and worktree go back to HEAD.
+    pub fn abort_merge(&self) -> Result<(), GitError> {
+        if self.merge_heads()?.is_empty() {
+            return Err(GitError::GenericError(
+                "There is no merge to abort (MERGE_HEAD missing).".to_owned(),
+            ));
+        }
+        let head = self.commit_tree_entries(None)?;
+        for entry in self.read_index()? {
+            if !head.contains_key(&entry.path) {
+                self.remove_worktree_file(&entry.path)?;
+            }
+        }
+        if let Some(oid) = self.resolve_ref("HEAD")? {
+            self.checkout_commit(&oid, true)?;
+        }
+        self.clear_merge_state()
+    }
+
+    fn write_merge_file(&self, name: &str, contents: &str) -> Result<(), GitError> {
+        let path = self.repo_path(&path!(name));
+        fs::write(&path, contents)
+            .map_err(|_| GitError::PathError(format!("Could not write {}", name), path))
+    }
+}
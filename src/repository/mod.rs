@@ -1,24 +1,56 @@
+pub mod add;
+pub mod branch;
+pub mod checkout;
+pub mod commit;
 pub mod config;
+pub mod delta;
+pub mod diff;
+pub mod graph;
+pub mod http_backend;
+pub mod ident;
+pub mod ignore;
+pub mod init;
+pub mod log;
+pub mod merge;
 pub mod object;
+pub mod oid;
+pub mod pack;
+pub mod pathspec;
+pub mod pretty;
+pub mod receive_pack;
+pub mod reflog;
+pub mod refs;
+pub mod remote;
+pub mod repack;
+pub mod revision;
+pub mod status;
+pub mod tag;
+pub mod transport;
+pub mod tree;
+pub mod upload_pack;
+pub mod worktree;
+use self::commit::Commit;
 use self::config::GitConfig;
-use self::object::{GitObject, ObjType, Serializable};
-use crate::files::is_dir_empty;
+use self::ident::Ident;
+use self::init::InitOptions;
+use self::object::{GitObject, Serializable, Typed};
+use self::oid::ObjectId;
+use self::pack::PackStore;
+use self::tree::{Tree, TreeEntry};
 use crate::{error::GitError, files};
 use byteorder::{BigEndian, ByteOrder};
-use chrono::{offset::Offset, offset::TimeZone, Local};
-use configparser::ini::Ini;
 use flate2::read::ZlibDecoder;
 use flate2::write::ZlibEncoder;
 use flate2::Compression;
 use sha1::Sha1;
 use std::collections::BTreeMap;
+use std::env;
 use std::fs;
 use std::io::{Read, Write};
 use std::os::unix::prelude::MetadataExt;
-use std::time::SystemTime;
 use std::{
     fs::{create_dir_all, File},
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
 };
 
 #[derive(Debug)]
@@ -26,6 +58,7 @@ pub struct GitRepository {
     pub worktree: PathBuf,
     pub gitdir: PathBuf,
     pub config: GitConfig,
+    pub pack_store: PackStore,
 }
 
 #[derive(Debug)]
@@ -40,11 +73,57 @@ pub struct GitIndex {
     pub uid: u32,
     pub gid: u32,
     pub size: u32,
-    pub sha1: String,
-    pub sha1_vec: Vec<u8>,
+    pub oid: ObjectId,
     pub flags: u16,
     pub path: String,
 }
+
+impl GitIndex {
+    // Builds an index entry for a worktree file from its stat data.
+    pub fn from_metadata(path: &str, oid: ObjectId, stat: &fs::Metadata) -> GitIndex {
+        GitIndex {
+            ctime_s: stat.ctime() as u32,
+            ctime_n: stat.ctime_nsec() as u32,
+            mtime_s: stat.mtime() as u32,
+            mtime_n: stat.mtime_nsec() as u32,
+            dev: stat.dev() as u32,
+            ino: stat.ino() as u32,
+            mode: GitRepository::tree_mode(stat.mode()),
+            uid: stat.uid(),
+            gid: stat.gid(),
+            size: stat.size() as u32,
+            oid,
+            // The low 12 bits hold the path length, saturating for long paths.
+            flags: path.len().min(0xfff) as u16,
+            path: path.to_owned(),
+        }
+    }
+
+    // An entry for one side of a conflict; it has no stat data as no worktree file matches it.
+    pub fn unmerged(path: &str, stage: u16, mode: u32, oid: ObjectId) -> GitIndex {
+        GitIndex {
+            ctime_s: 0,
+            ctime_n: 0,
+            mtime_s: 0,
+            mtime_n: 0,
+            dev: 0,
+            ino: 0,
+            mode,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            oid,
+            flags: (stage << 12) | path.len().min(0xfff) as u16,
+            path: path.to_owned(),
+        }
+    }
+
+    // The merge stage: 0 for a normal entry, 1-3 for the base, ours and theirs of a conflict.
+    pub fn stage(&self) -> u16 {
+        (self.flags >> 12) & 0x3
+    }
+}
+
 impl GitRepository {
     // Computes a path under the repo's gitdir
     pub fn repo_path(&self, path: &Path) -> PathBuf {
@@ -79,167 +158,194 @@ impl GitRepository {
     // Create a new git repository .
     pub fn new(path: &Path) -> GitRepository {
         let gitdir = path.join(&path!(".git"));
-        let conf = Ini::new();
 
         GitRepository {
             worktree: path.to_path_buf(),
             gitdir,
-            config: GitConfig::new(conf),
+            config: GitConfig::default(),
+            pack_store: PackStore::default(),
         }
     }
 
-    // Write a new git repository to the path.
-
+    // Creates a repository with a worktree at `path`, or reinitializes the one there.
     pub fn write_to_path(path: &Path) -> Result<GitRepository, GitError> {
-        let repo = GitRepository::new(path);
-        if path != path!(".") {
-            GitRepository::create_repo_dir(&repo, path)?;
-        }
-        repo.repo_dir(&path!("branches"), true);
-        repo.repo_dir(&path!("objects"), true);
-        repo.repo_dir(&path!("refs/tags"), true);
-        repo.repo_dir(&path!("refs/heads"), true);
-        GitRepository::create_repo_file(
-            &repo,
-            &path!("description"),
-            "Unnamed repository; edit this file 'description' to name the repository.",
-        )?;
-        GitRepository::create_repo_file(&repo, &path!("HEAD"), "ref: refs/heads/master")?;
-        let default_config: GitConfig = Default::default();
-        let config = repo.repo_file(&path!("config"))?;
-        default_config.save(&config).unwrap();
+        GitRepository::init(path, &InitOptions::default()).map(|(repo, _)| repo)
+    }
 
-        Ok(repo)
+    // Loads an existing git repository
+    pub fn load(path: &Path) -> Result<GitRepository, GitError> {
+        GitRepository::load_gitdir(path, &path.join(path!(".git")))
     }
 
-    // This function creates the repository 's base working directory.
-    // It will throw an error if the directory already exists and it is not empty, or if the given path is not a directory.
-    pub fn create_repo_dir(repo: &GitRepository, path: &Path) -> Result<(), GitError> {
-        if repo.worktree.exists() {
-            if !repo.worktree.is_dir() {
-                return Err(GitError::PathError(
-                    "Specified directory is not empty".to_owned(),
-                    repo.worktree.clone(),
-                ));
-            } else if !is_dir_empty(path) {
-                return Err(GitError::PathError(
-                    "Could not create directory".to_owned(),
-                    repo.worktree.clone(),
-                ));
-            }
-        } else {
-            create_dir_all(&repo.worktree).map_err(|_| {
-                GitError::PathError(
-                    "Could not create directory".to_owned(),
-                    repo.worktree.clone(),
-                )
-            })?;
+    fn load_gitdir(worktree: &Path, gitdir: &Path) -> Result<GitRepository, GitError> {
+        let conf_path = gitdir.join(&path!("config"));
+        if !conf_path.is_file() {
+            return Err(GitError::PathError(
+                "Unable to load git config for repo".to_owned(),
+                conf_path,
+            ));
         }
-        Ok(())
+        Ok(GitRepository {
+            worktree: worktree.to_path_buf(),
+            gitdir: gitdir.to_path_buf(),
+            config: GitConfig::load(Some(gitdir))?,
+            pack_store: PackStore::default(),
+        })
     }
 
-    fn create_repo_file(repo: &GitRepository, path: &Path, contents: &str) -> Result<(), GitError> {
-        let file = repo.repo_file(path)?;
-        files::create_write_file(&file, contents)
+    // Opens the repository at `path`, which is either a worktree containing .git or the gitdir
+    // of a bare repository.
+    pub fn open(path: &Path) -> Result<GitRepository, GitError> {
+        let dotgit = path.join(path!(".git"));
+        if dotgit.is_file() {
+            return GitRepository::load_gitdir(path, &read_gitfile(&dotgit)?);
+        }
+        if dotgit.exists() {
+            return GitRepository::load(path);
+        }
+        if !is_gitdir(path) {
+            return Err(GitError::PathError(
+                "Not a git repository".to_owned(),
+                path.to_path_buf(),
+            ));
+        }
+        GitRepository::load_gitdir(path, path)
     }
 
-    // Loads an existing git repository
-    pub fn load(path: &Path) -> Result<GitRepository, GitError> {
-        let gitdir = path.join(path!(".git"));
-        let conf_path = gitdir.join(&path!("config"));
-        let mut conf = Ini::new();
-        conf.load(&conf_path).map_err(|e| {
-            GitError::GenericError(format!(
-                "Unable to load git config for repo: {}, {}",
-                conf_path.to_str().unwrap(),
-                e
-            ))
-        })?;
-        Ok(GitRepository {
-            worktree: path.to_path_buf(),
-            gitdir,
-            config: GitConfig::new(conf),
-        })
+    // Whether the repository has no worktree, as in core.bare.
+    pub fn is_bare(&self) -> bool {
+        self.gitdir == self.worktree
     }
 
-    // This function tries to find a git repository from the current working directory.
+    // Commands that change files fail in bare repositories.
+    pub fn require_worktree(&self) -> Result<(), GitError> {
+        match self.is_bare() {
+            true => Err(GitError::GenericError(
+                "this operation must be run in a work tree".to_owned(),
+            )),
+            false => Ok(()),
+        }
+    }
+
+    // Finds the repository for the current directory, as git does.
     pub fn find() -> Result<GitRepository, GitError> {
-        let cwd = files::cwd()?;
-        let mut some = Some(cwd);
+        GitRepository::discover()?.ok_or_else(|| {
+            GitError::GenericError(
+                "not a git repository (or any of the parent directories): .git".to_owned(),
+            )
+        })
+    }
 
-        while some.is_some() {
-            let current = some.as_ref().unwrap();
-            let path = current.join(path!(".git"));
+    // The repository named by $GIT_DIR, or else the nearest one from the current directory
+    // upwards: a directory with a .git directory or "gitdir:" file, or a bare repository. The
+    // search does not go up into $GIT_CEILING_DIRECTORIES. None when there is no repository.
+    pub fn discover() -> Result<Option<GitRepository>, GitError> {
+        let cwd = files::cwd()?;
+        if let Some(gitdir) = env::var_os("GIT_DIR").filter(|gitdir| !gitdir.is_empty()) {
+            let gitdir = cwd.join(gitdir);
+            if !is_gitdir(&gitdir) {
+                return Err(GitError::PathError(
+                    "not a git repository".to_owned(),
+                    gitdir,
+                ));
+            }
+            return GitRepository::load_discovered(&gitdir, Some(&cwd)).map(Some);
+        }
 
-            if path.exists() {
-                return GitRepository::load(current);
+        let ceiling = ceiling_directory(&cwd);
+        for dir in cwd.ancestors() {
+            if ceiling.as_deref() == Some(dir) {
+                break;
+            }
+            let dotgit = dir.join(path!(".git"));
+            if dotgit.is_file() {
+                let gitdir = read_gitfile(&dotgit)?;
+                return GitRepository::load_discovered(&gitdir, Some(dir)).map(Some);
+            }
+            if is_gitdir(&dotgit) {
+                return GitRepository::load_discovered(&dotgit, Some(dir)).map(Some);
+            }
+            if is_gitdir(dir) {
+                return GitRepository::load_discovered(dir, None).map(Some);
             }
-            some = some.unwrap().parent().map(|p| p.to_path_buf());
         }
-        Err(GitError::GenericError(
-            "Git repository could not be found.".to_owned(),
-        ))
+        Ok(None)
     }
 
-    pub fn find_object(&self, name: &str, _format: &ObjType) -> String {
-        name.to_owned()
+    // Loads a discovered gitdir. Its worktree is $GIT_WORK_TREE, core.worktree or `worktree`,
+    // in that order, unless core.bare says there is none.
+    fn load_discovered(gitdir: &Path, worktree: Option<&Path>) -> Result<GitRepository, GitError> {
+        let gitdir = fs::canonicalize(gitdir).map_err(|_| {
+            GitError::PathError("not a git repository".to_owned(), gitdir.to_path_buf())
+        })?;
+        let config = GitConfig::load(Some(&gitdir))?;
+        let worktree =
+            if let Some(worktree) = env::var_os("GIT_WORK_TREE").filter(|dir| !dir.is_empty()) {
+                Some(files::cwd()?.join(worktree))
+            } else if let Some(worktree) = config.get("core", None, "worktree") {
+                Some(gitdir.join(worktree))
+            } else if config.get_bool("core", None, "bare") == Some(true) {
+                None
+            } else {
+                worktree.map(Path::to_path_buf)
+            };
+        let worktree = match worktree {
+            Some(worktree) => fs::canonicalize(&worktree).map_err(|_| {
+                GitError::PathError("Could not open the worktree".to_owned(), worktree)
+            })?,
+            None => gitdir.clone(),
+        };
+        Ok(GitRepository {
+            worktree,
+            gitdir,
+            config,
+            pack_store: PackStore::default(),
+        })
     }
 
-    pub fn read_object(&self, sha: &str) -> Result<GitObject, GitError> {
-        let dir = &sha[0..2];
-        let rest = &sha[2..];
+    // Computes the path of a loose object under objects/.
+    fn object_path(oid: &ObjectId) -> PathBuf {
+        let hex = oid.to_hex();
+        path!("objects", &hex[0..2], &hex[2..])
+    }
 
-        let object = self.repo_file(&path!("objects", dir, rest))?;
+    pub fn read_object(&self, oid: &ObjectId) -> Result<GitObject, GitError> {
+        let object = self.repo_path(&GitRepository::object_path(oid));
 
-        let file = File::open(&object).map_err(|_| {
-            GitError::PathError("Could not open file".to_owned(), object.to_path_buf())
-        })?;
+        let file = match File::open(&object) {
+            Ok(file) => file,
+            Err(_) => {
+                return self
+                    .read_packed_object(oid)?
+                    .ok_or_else(|| GitError::ObjectError(format!("Object not found: {}", oid)))
+            }
+        };
 
         let mut buf = Vec::new();
         ZlibDecoder::new(file)
             .read_to_end(&mut buf)
             .map_err(|e| GitError::GenericError(format!("Could not read object data: {}", e)))?;
-        let space = buf.iter().position(|b| b == &b' ').unwrap();
-        let null = buf.iter().position(|b| b == &b'\x00').unwrap();
-        let bytes: &[u8] = buf.as_ref();
-
-        let format = &bytes[0..space];
-        let data = &bytes[null + 1..];
-
-        let size: usize = String::from_utf8(bytes[space..null].to_vec())
-            .unwrap()
-            .trim()
-            .parse()
-            .unwrap();
-
-        if size != buf.len() - null - 1 {
-            return Err(GitError::ObjectError(format!(
-                "Invalid object {0}: bad length",
-                sha
-            )));
-        }
-        let object_type = ObjType::deserialize(format);
-        Ok(GitObject::new(object_type, data))
+        GitObject::deserialize(&buf).map_err(|e| match e {
+            GitError::ObjectError(message) => {
+                GitError::ObjectError(format!("{} ({})", message, oid))
+            }
+            e => e,
+        })
     }
 
-    pub fn write_object(repo: &GitRepository, obj: &GitObject) -> Result<String, GitError> {
-        let mut data = obj.serialize().to_vec();
-        let mut result = Vec::new();
-        result.append(&mut obj.obj_type.serialize().to_vec());
-        result.push(b' ');
-        result.append(
-            &mut data
-                .len()
-                .to_string()
-                .to_ascii_uppercase()
-                .as_bytes()
-                .to_vec(),
-        );
-        result.push(0_u8);
-        result.append(&mut data);
+    // Whether the object is stored, loose or in a pack, without reading it.
+    pub fn has_object(&self, oid: &ObjectId) -> bool {
+        self.repo_path(&GitRepository::object_path(oid)).is_file()
+            || self
+                .packs()
+                .is_ok_and(|packs| packs.iter().any(|pack| pack.index.find(oid).is_some()))
+    }
 
-        let sha = Sha1::from(&result).hexdigest();
-        let path = repo.repo_file(&path!("objects", &sha[0..2], &sha[2..]))?;
+    pub fn write_object(repo: &GitRepository, obj: &GitObject) -> Result<ObjectId, GitError> {
+        let result = obj.serialize();
+
+        let oid = ObjectId::hash(&result);
+        let path = repo.repo_file(&GitRepository::object_path(&oid))?;
 
         let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(1));
 
@@ -251,91 +357,24 @@ impl GitRepository {
                 Ok((compressed, file))
             })
             .and_then(|(compressed, mut file)| file.write_all(&compressed))
-            .and(Ok(sha.to_owned()))
+            .and(Ok(oid))
             .map_err(|e| {
                 GitError::GenericError(format!(
                     "Unable to compress and save object data: {} - {}",
-                    sha, e
+                    oid, e
                 ))
             })
     }
-    // It parses commit puts it into an ordered hash map.
-    pub fn commit_parse(raw: &[u8]) -> BTreeMap<String, Vec<u8>> {
-        let buf = raw.to_vec();
-        let mut result = BTreeMap::new();
-
-        let mut current: usize = 0;
-
-        while current < buf.len() {
-            let space = buf.iter().position(|b| b == &b' ');
-            let nl = buf.iter().position(|b| b == &b'\n');
-            if space.is_none() || (nl.is_some() && space.unwrap() < nl.unwrap()) {
-                result.insert("data".to_owned(), buf[current + 1..].to_vec());
-                return result;
-            }
-
-            let space_pos = space.unwrap();
-            let key = String::from_utf8(buf[current..space_pos].to_vec()).unwrap();
-
-            // Find the end of the value.  Continuation lines begin with a
-            // space, so we loop until we find a "\n" not followed by a space.
-
-            let mut it = space_pos + 1;
-            let end_pos = loop {
-                let newline = buf[it..].iter().position(|b| b == &b'\n');
-                if let Some(newline_pos) = newline {
-                    if buf[newline_pos + 1] != b' ' {
-                        break newline_pos;
-                    }
-                    it = newline_pos + 1;
-                } else {
-                    break buf.len();
-                }
-            };
-            let mut value =
-                GitRepository::remove_spaces_after_newline(&buf[space_pos + 1..end_pos]);
-
-            // Don't overwrite existing value, but append to it
-            if result.contains_key(&key) {
-                let mut previous = result.get(&key).unwrap().to_vec();
-                previous.append(&mut value);
-                result.insert(key, previous);
-            } else {
-                result.insert(key, value);
-            }
-
-            current = end_pos + 1;
-        }
-
-        result
-    }
-    fn remove_spaces_after_newline(input: &[u8]) -> Vec<u8> {
-        if input.len() <= 1 {
-            return Vec::from(input);
-        }
-
-        let mut result = Vec::new();
-        let mut idx = 1;
-        while idx < input.len() + 1 {
-            result.push(input[idx - 1]);
-            if idx < input.len() && input[idx - 1] == b'\n' && input[idx] == b' ' {
-                idx += 2;
-            } else {
-                idx += 1;
-            }
+    pub fn read_index(&self) -> Result<Vec<GitIndex>, GitError> {
+        // A repository without an index file has nothing staged.
+        let index = self.repo_path(&path!("index"));
+        if !index.exists() {
+            return Ok(Vec::new());
         }
-
-        result
-    }
-
-    pub fn read_index() -> Result<Vec<GitIndex>, GitError> {
-        let data = files::read_data(&path!(".git", "index"));
+        let data = files::read_data(&index);
 
         if data.is_err() {
-            return Err(GitError::PathError(
-                String::from("reading file"),
-                path!(".git", "index"),
-            ));
+            return Err(GitError::PathError(String::from("reading file"), index));
         }
         let data = data.unwrap();
         if data.is_empty() {
@@ -379,19 +418,18 @@ impl GitRepository {
             let uid = BigEndian::read_u32(&entry_data[i + 28..i + 32]);
             let gid = BigEndian::read_u32(&entry_data[i + 32..i + 36]);
             let size = BigEndian::read_u32(&entry_data[i + 36..i + 40]);
-            let mut w: Vec<u8> = Vec::new();
-            for b in &entry_data[i + 40..i + 60] {
-                write!(w, "{:02x}", b).unwrap();
-            }
-            let sha1 = String::from_utf8(w).unwrap();
-            let sha1_vec = entry_data[i + 40..i + 60].to_vec();
+            let oid = ObjectId::from_bytes(&entry_data[i + 40..i + 60])?;
             let flags = BigEndian::read_u16(&entry_data[i + 60..i + 62]);
             let mut path_end = fields_end;
             while entry_data[path_end] != 0 {
                 path_end += 1;
             }
-            // let path_end = memchr(b'0', &entry_data[fields_end..]).unwrap();
-            let path = std::str::from_utf8(&entry_data[fields_end..path_end]).unwrap();
+            let path = std::str::from_utf8(&entry_data[fields_end..path_end])
+                .ok()
+                .filter(|path| tree::verify_path(path))
+                .ok_or_else(|| {
+                    GitError::PathError("invalid path in index".to_owned(), index.clone())
+                })?;
             entries.push(GitIndex {
                 ctime_s,
                 ctime_n,
@@ -403,8 +441,7 @@ impl GitRepository {
                 uid,
                 gid,
                 size,
-                sha1,
-                sha1_vec,
+                oid,
                 flags,
                 path: path.to_string(),
             });
@@ -413,93 +450,113 @@ impl GitRepository {
         }
         Ok(entries)
     }
-    pub fn write_tree() -> Result<String, GitError> {
-        let mut tree_entries: Vec<Vec<u8>> = Vec::new();
-        let entries = GitRepository::read_index().unwrap();
+    pub fn write_tree(&self) -> Result<ObjectId, GitError> {
+        // Writes the index as a hierarchy of tree objects, one per directory, and returns the root tree's hash.
+        let mut entries = self.read_index()?;
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        let entries: Vec<&GitIndex> = entries.iter().collect();
+        GitRepository::write_tree_level(self, &entries, 0)
+    }
+
+    // Writes the tree for the directory formed by the entries' paths minus their first `depth` components.
+    fn write_tree_level(
+        repo: &GitRepository,
+        entries: &[&GitIndex],
+        depth: usize,
+    ) -> Result<ObjectId, GitError> {
+        let mut tree = Tree::default();
+        let mut subdirs: BTreeMap<String, Vec<&GitIndex>> = BTreeMap::new();
         for entry in entries {
-            if entry.path.contains('/') {
-                panic!("currently only supports a single, top-level directory");
-                //TODO: provide support for multiple level of  directories
+            let components: Vec<&str> = entry.path.split('/').collect();
+            let name = components[depth].to_owned();
+            if components.len() == depth + 1 {
+                tree.entries.push(TreeEntry {
+                    mode: GitRepository::tree_mode(entry.mode),
+                    name,
+                    oid: entry.oid,
+                });
+            } else {
+                subdirs.entry(name).or_default().push(entry);
             }
-            let mode_path: Vec<u8> = format!("{:o} {}", entry.mode, entry.path)
-                .as_bytes()
-                .to_vec();
-            let mut tree_entry = mode_path;
-            tree_entry.push(b'\x00');
-            let mut sha1_vec = entry.sha1_vec;
-            tree_entry.append(&mut sha1_vec);
-            tree_entries.push(tree_entry);
         }
-        let mut data: Vec<u8> = Vec::new();
-        for entry in tree_entries {
-            data.extend(entry.iter());
+        for (name, children) in subdirs {
+            let oid = GitRepository::write_tree_level(repo, &children, depth + 1)?;
+            tree.entries.push(TreeEntry {
+                mode: 0o40000,
+                name,
+                oid,
+            });
         }
-
-        let repo = GitRepository::find().unwrap();
-        let tree_object = GitObject {
-            obj_type: ObjType::Tree,
-            data,
-        };
-        GitRepository::write_object(&repo, &tree_object)
+        tree.sort();
+        GitRepository::write_object(repo, &tree.to_object())
     }
-    pub fn get_local_master_hash() -> Option<String> {
-        let master_path = path!(".git", "refs", "heads", "master");
-        let file_data = fs::read_to_string(master_path);
-        if let Ok(data) = file_data {
-            Some(data)
-        } else {
-            None
+
+    // Normalizes a stat mode to one of the modes git allows in tree objects.
+    fn tree_mode(mode: u32) -> u32 {
+        match mode & 0o170000 {
+            0o120000 => 0o120000,
+            0o160000 => 0o160000,
+            _ if mode & 0o111 != 0 => 0o100755,
+            _ => 0o100644,
         }
     }
-    pub fn commit(message: String, author: String) {
-        // Commits the current state of the index to master with a given message.It returns hash of a commit object.
-        let tree = GitRepository::write_tree().unwrap();
-        let parent = GitRepository::get_local_master_hash();
-        let timestamp = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        let utc_offset = Local.timestamp(0, 0).offset().fix().local_minus_utc();
-        let author_time = format!(
-            "{} {}{:02}{:02}",
-            timestamp,
-            if utc_offset > 0 { '+' } else { '-' },
-            (utc_offset / 3600) as u32,
-            ((utc_offset / 60) as u32) % 60
-        );
-        let mut lines = vec![String::from("tree ") + &tree];
-
-        if parent != None {
-            let parent_string = parent.unwrap();
-            lines.push(String::from("parent ") + &parent_string[..parent_string.len() - 1]);
-        }
-        lines.push(format!("author {} {}", author, author_time));
-        lines.push(format!("commiter {} {}", author, author_time));
-        lines.push(" ".to_owned());
-        lines.push(message);
-        let mut data = String::new();
-        for x in lines {
-            data.push_str(&x);
-            data.push('\n');
+    // Commits the current state of the index to the branch HEAD points to (or to HEAD itself
+    // when detached), returning the new commit. During a merge the merged commits become
+    // further parents.
+    pub fn commit(
+        &self,
+        message: String,
+        author: &Ident,
+        committer: &Ident,
+    ) -> Result<ObjectId, GitError> {
+        if self.read_index()?.iter().any(|entry| entry.stage() != 0) {
+            return Err(GitError::GenericError(
+                "Committing is not possible because you have unmerged files.".to_owned(),
+            ));
         }
-        let data = data.as_bytes().to_vec();
-        let repo = GitRepository::find().unwrap();
-        let obj = GitObject {
-            obj_type: ObjType::Commit,
-            data,
+        let tree = self.write_tree()?;
+        let (_, parent) = self.follow_ref("HEAD")?;
+        let merge_heads = self.merge_heads()?;
+        let mut message = message.into_bytes();
+        message.push(b'\n');
+        let commit = Commit {
+            tree,
+            parents: parent.into_iter().chain(merge_heads).collect(),
+            author: author.to_string().into_bytes(),
+            committer: committer.to_string().into_bytes(),
+            extra_headers: Vec::new(),
+            message,
+        };
+        let kind = match (parent, commit.parents.len()) {
+            (None, _) => " (initial)",
+            (_, 1) => "",
+            _ => " (merge)",
         };
-        let sha1 = GitRepository::write_object(&repo, &obj).expect("Unable to open the git object");
-        let master_path = path!(".git", "refs", "heads", "master");
-        fs::write(master_path, sha1.as_bytes())
-            .expect("Cannot open the file to write commit info into");
-        println!("Commited to master: {:7}", sha1);
+        let reflog_message = format!("commit{}: {}", kind, commit.subject());
+        let oid = GitRepository::write_object(self, &commit.to_object())?;
+        self.update_ref(
+            "HEAD",
+            &oid,
+            Some(&parent.unwrap_or_default()),
+            true,
+            &reflog_message,
+        )?;
+        self.clear_merge_state()?;
+        Ok(oid)
     }
 
-    pub fn write_index(entries: &[GitIndex]) {
-        // Write list of GitIndex entries to the git index file.
+    pub fn write_index(&self, entries: &[GitIndex]) -> Result<(), GitError> {
+        // Write list of GitIndex entries to the git index file, sorted by path and stage as git requires.
         let mut packed_entries: Vec<Vec<u8>> = Vec::new();
+        let mut entries: Vec<&GitIndex> = entries.iter().collect();
+        entries.sort_by(|a, b| {
+            a.path
+                .as_bytes()
+                .cmp(b.path.as_bytes())
+                .then(a.stage().cmp(&b.stage()))
+        });
 
-        for entry in entries {
+        for entry in &entries {
             let mut packed_entry: Vec<u8> = vec![0; 40];
             BigEndian::write_u32(&mut packed_entry[0..4], entry.ctime_s);
             BigEndian::write_u32(&mut packed_entry[4..8], entry.ctime_n);
@@ -511,11 +568,11 @@ impl GitRepository {
             BigEndian::write_u32(&mut packed_entry[28..32], entry.uid);
             BigEndian::write_u32(&mut packed_entry[32..36], entry.gid);
             BigEndian::write_u32(&mut packed_entry[36..40], entry.size);
-            packed_entry.append(&mut hex::decode(entry.sha1.clone()).expect("Decoding failed"));
+            packed_entry.extend(entry.oid.as_bytes());
             packed_entry.append(&mut vec![0, 0]);
             BigEndian::write_u16(&mut packed_entry[60..62], entry.flags);
             packed_entry.append(&mut entry.path.as_bytes().to_vec());
-            let path = entry.path.as_bytes().len();
+            let path = entry.path.len();
             let mut length: usize = (62 + path + 8) / 8;
             length *= 8;
             packed_entry.append(&mut vec![b'\x00'; length - 62 - path]);
@@ -533,58 +590,63 @@ impl GitRepository {
             .collect();
         let mut digest = Sha1::from(&packed_data).digest().bytes().to_vec();
         packed_data.append(&mut digest);
-        let path = path!(".git", "index");
-        let mut file = fs::OpenOptions::new().write(true).open(path.clone());
-        if file.is_err() {
-            File::create(path).unwrap();
-            file = fs::OpenOptions::new()
-                .write(true)
-                .open(path!(".git", "index"));
-        }
-        let mut file = file.expect("Cannot open the file to write index info into.");
-        file.write_all(&packed_data)
-            .expect("Not able to write into the index file");
+        let path = self.repo_path(&path!("index"));
+        File::create(&path)
+            .and_then(|mut file| file.write_all(&packed_data))
+            .map_err(|_| GitError::PathError("Could not write the index".to_owned(), path))
     }
-    pub fn add_git(paths: &[String]) {
-        let mut all_entries = GitRepository::read_index();
-        if all_entries.is_err() {
-            all_entries = Ok(Vec::new())
-        }
-        let all_entries = all_entries.unwrap();
-        let mut entries: Vec<GitIndex> = Vec::new();
-        for e in all_entries {
-            if !paths.contains(&e.path) {
-                entries.push(e);
+    // A path given relative to the current directory, as a path relative to the worktree.
+    pub fn worktree_relative(&self, path: &Path) -> Result<String, GitError> {
+        let mut full_path = PathBuf::new();
+        for component in files::cwd()?.join(path).components() {
+            match component {
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    full_path.pop();
+                }
+                component => full_path.push(component),
             }
         }
-        for path in paths {
-            let obj = GitObject {
-                obj_type: ObjType::Blob,
-                data: files::read_data(&path!(path)).unwrap(),
-            };
-            let repo = GitRepository::find().unwrap();
-            let sha1 = GitRepository::write_object(&repo, &obj).unwrap();
-            let flags = path.as_bytes().len();
-            assert!(flags < (1 << 12));
-            let stat = fs::metadata(path).unwrap();
-            let entry = GitIndex {
-                ctime_s: u32::try_from(stat.ctime()).ok().unwrap(),
-                ctime_n: 0,
-                mtime_s: u32::try_from(stat.mtime()).ok().unwrap(),
-                mtime_n: 0,
-                dev: u32::try_from(stat.dev()).ok().unwrap(),
-                ino: u32::try_from(stat.ino()).ok().unwrap(),
-                mode: u32::try_from(stat.mode()).ok().unwrap(),
-                uid: stat.uid(),
-                gid: stat.gid(),
-                size: u32::try_from(stat.size()).ok().unwrap(),
-                sha1: sha1.clone(),
-                sha1_vec: sha1.as_bytes().to_vec(),
-                flags: u16::try_from(flags).ok().unwrap(),
-                path: path.to_string(),
-            };
-            entries.push(entry);
+        match full_path.strip_prefix(&self.worktree) {
+            Ok(relative) if !self.is_bare() => Ok(relative.to_string_lossy().into_owned()),
+            _ => Err(GitError::PathError(
+                format!("'{}' is outside repository at", path.display()),
+                self.worktree.clone(),
+            )),
         }
-        GitRepository::write_index(&entries);
     }
 }
+
+// Whether `path` looks like a gitdir: it has HEAD, objects and refs.
+fn is_gitdir(path: &Path) -> bool {
+    path.join("HEAD").is_file() && path.join("objects").is_dir() && path.join("refs").is_dir()
+}
+
+// The gitdir a ".git" file points to with its "gitdir: <path>" line, relative to the file.
+fn read_gitfile(path: &Path) -> Result<PathBuf, GitError> {
+    let invalid = || GitError::PathError("invalid gitfile format".to_owned(), path.to_path_buf());
+    let contents = fs::read_to_string(path).map_err(|_| invalid())?;
+    let target = contents
+        .strip_prefix("gitdir: ")
+        .map(str::trim_end)
+        .filter(|target| !target.is_empty())
+        .ok_or_else(invalid)?;
+    let gitdir = path.parent().unwrap_or(Path::new("")).join(target);
+    if !is_gitdir(&gitdir) {
+        return Err(GitError::PathError(
+            "not a git repository".to_owned(),
+            gitdir,
+        ));
+    }
+    Ok(gitdir)
+}
+
+// The deepest of $GIT_CEILING_DIRECTORIES above `cwd`. Discovery stops below it.
+fn ceiling_directory(cwd: &Path) -> Option<PathBuf> {
+    let ceilings = env::var_os("GIT_CEILING_DIRECTORIES")?;
+    env::split_paths(&ceilings)
+        .filter(|dir| dir.is_absolute())
+        .map(|dir| fs::canonicalize(&dir).unwrap_or(dir))
+        .filter(|dir| cwd.starts_with(dir) && dir != cwd)
+        .max_by_key(|dir| dir.components().count())
+}
@@ -1,4 +1,7 @@
-#[derive(Copy, Clone, Debug)]
+use super::oid::ObjectId;
+use crate::error::GitError;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum ObjType {
     Commit,
     Tree,
@@ -11,43 +14,82 @@ pub struct GitObject {
     pub data: Vec<u8>,
 }
 
-pub trait Serializable {
-    fn serialize(&self) -> &[u8];
-    fn deserialize(input: &[u8]) -> Self;
+pub trait Serializable: Sized {
+    fn serialize(&self) -> Vec<u8>;
+    fn deserialize(input: &[u8]) -> Result<Self, GitError>;
+}
+
+// Implemented by the parsed object types so they can be converted to and from a GitObject.
+pub trait Typed: Serializable {
+    const OBJ_TYPE: ObjType;
+
+    fn to_object(&self) -> GitObject {
+        GitObject::new(Self::OBJ_TYPE, &self.serialize())
+    }
 }
 
+// A GitObject serializes to the loose object format, "<type> <size>\0<data>", which is what gets hashed.
 impl Serializable for GitObject {
-    fn serialize(&self) -> &[u8] {
-        match self.obj_type {
-            ObjType::Commit => &self.data,
-            ObjType::Tree => &self.data,
-            ObjType::Tag => &self.data,
-            ObjType::Blob => &self.data,
-        }
+    fn serialize(&self) -> Vec<u8> {
+        let mut result = self.obj_type.serialize();
+        result.push(b' ');
+        result.extend(self.data.len().to_string().as_bytes());
+        result.push(0_u8);
+        result.extend(&self.data);
+        result
     }
 
-    fn deserialize(_input: &[u8]) -> Self {
-        unimplemented!()
+    fn deserialize(input: &[u8]) -> Result<Self, GitError> {
+        let space = input.iter().position(|b| b == &b' ');
+        let null = input.iter().position(|b| b == &b'\x00');
+        let (space, null) = match (space, null) {
+            (Some(space), Some(null)) if space < null => (space, null),
+            _ => {
+                return Err(GitError::ObjectError(
+                    "Invalid object: malformed header".to_owned(),
+                ))
+            }
+        };
+        let obj_type = ObjType::deserialize(&input[0..space])?;
+        let size: usize = std::str::from_utf8(&input[space + 1..null])
+            .ok()
+            .and_then(|size| size.parse().ok())
+            .ok_or_else(|| GitError::ObjectError("Invalid object: bad length".to_owned()))?;
+        if size != input.len() - null - 1 {
+            return Err(GitError::ObjectError(
+                "Invalid object: bad length".to_owned(),
+            ));
+        }
+        Ok(GitObject::new(obj_type, &input[null + 1..]))
     }
 }
 
 impl Serializable for ObjType {
-    fn serialize(&self) -> &[u8] {
-        match *self {
-            ObjType::Commit => b"commit",
-            ObjType::Tree => b"tree",
-            ObjType::Tag => b"tag",
-            ObjType::Blob => b"blob",
-        }
+    fn serialize(&self) -> Vec<u8> {
+        self.as_str().as_bytes().to_vec()
     }
 
-    fn deserialize(input: &[u8]) -> ObjType {
+    fn deserialize(input: &[u8]) -> Result<ObjType, GitError> {
         match input {
-            b"commit" => ObjType::Commit,
-            b"tree" => ObjType::Tree,
-            b"tag" => ObjType::Tag,
-            b"blob" => ObjType::Blob,
-            _ => ObjType::Commit,
+            b"commit" => Ok(ObjType::Commit),
+            b"tree" => Ok(ObjType::Tree),
+            b"tag" => Ok(ObjType::Tag),
+            b"blob" => Ok(ObjType::Blob),
+            _ => Err(GitError::ObjectError(format!(
+                "Unknown object type: {}",
+                String::from_utf8_lossy(input)
+            ))),
+        }
+    }
+}
+
+impl ObjType {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            ObjType::Commit => "commit",
+            ObjType::Tree => "tree",
+            ObjType::Tag => "tag",
+            ObjType::Blob => "blob",
         }
     }
 }
@@ -59,4 +101,41 @@ impl GitObject {
             data: data.to_vec(),
         }
     }
+
+    pub fn id(&self) -> ObjectId {
+        ObjectId::hash(&self.serialize())
+    }
+
+    // Parses the object's data as the given type, failing if the object is of a different type.
+    pub fn parse<T: Typed>(&self) -> Result<T, GitError> {
+        if self.obj_type != T::OBJ_TYPE {
+            return Err(GitError::ObjectError(format!(
+                "Expected a {} object, found a {}",
+                T::OBJ_TYPE.as_str(),
+                self.obj_type.as_str()
+            )));
+        }
+        T::deserialize(&self.data)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Blob {
+    pub data: Vec<u8>,
+}
+
+impl Serializable for Blob {
+    fn serialize(&self) -> Vec<u8> {
+        self.data.clone()
+    }
+
+    fn deserialize(input: &[u8]) -> Result<Self, GitError> {
+        Ok(Blob {
+            data: input.to_vec(),
+        })
+    }
+}
+
+impl Typed for Blob {
+    const OBJ_TYPE: ObjType = ObjType::Blob;
 }
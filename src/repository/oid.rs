@@ -0,0 +1,72 @@
+use crate::error::GitError;
+use sha1::Sha1;
+use std::fmt::{Debug, Display, Formatter};
+use std::str::FromStr;
+
+// The 20 byte SHA-1 name of an object.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct ObjectId([u8; 20]);
+
+impl ObjectId {
+    pub const LEN: usize = 20;
+    pub const HEX_LEN: usize = 40;
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<ObjectId, GitError> {
+        let raw: [u8; 20] = bytes.try_into().map_err(|_| {
+            GitError::ObjectError(format!("Invalid object id: {} bytes", bytes.len()))
+        })?;
+        Ok(ObjectId(raw))
+    }
+
+    pub fn from_array(raw: [u8; 20]) -> ObjectId {
+        ObjectId(raw)
+    }
+
+    pub fn from_hex(hex: &str) -> Result<ObjectId, GitError> {
+        if hex.len() != ObjectId::HEX_LEN {
+            return Err(GitError::ObjectError(format!("Invalid object id: {}", hex)));
+        }
+        let mut raw = [0; 20];
+        hex::decode_to_slice(hex, &mut raw)
+            .map_err(|_| GitError::ObjectError(format!("Invalid object id: {}", hex)))?;
+        Ok(ObjectId(raw))
+    }
+
+    // Hashes the given bytes, which should be an object in its loose "<type> <size>\0<data>" form.
+    pub fn hash(data: &[u8]) -> ObjectId {
+        ObjectId(Sha1::from(data).digest().bytes())
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 20] {
+        &self.0
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.0)
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.0 == [0; 20]
+    }
+}
+
+// Honors precision so `{:.7}` prints an abbreviated id.
+impl Display for ObjectId {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.pad(&self.to_hex())
+    }
+}
+
+impl Debug for ObjectId {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "ObjectId({})", self.to_hex())
+    }
+}
+
+impl FromStr for ObjectId {
+    type Err = GitError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ObjectId::from_hex(s)
+    }
+}
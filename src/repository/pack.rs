@@ -0,0 +1,804 @@
+use super::delta::apply_delta;
+use super::object::{GitObject, ObjType};
+use super::oid::ObjectId;
+use super::GitRepository;
+use crate::error::GitError;
+use byteorder::{BigEndian, ByteOrder};
+use flate2::bufread;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::{Compression, Crc};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, BufRead, Read, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+const IDX_MAGIC: &[u8] = b"\xfftOc";
+const OBJ_OFS_DELTA: u8 = 6;
+const OBJ_REF_DELTA: u8 = 7;
+// Resolved delta bases are kept up to this many bytes in total.
+const DELTA_CACHE_LIMIT: usize = 32 * 1024 * 1024;
+
+// A version 2 pack index: a fanout table, the sorted object ids, their CRC32s and their offsets
+// in the pack, with offsets past 2GiB moved to a separate table of 64 bit values.
+#[derive(Debug)]
+pub struct PackIndex {
+    data: Vec<u8>,
+    count: usize,
+}
+
+impl PackIndex {
+    pub fn parse(data: Vec<u8>) -> Result<PackIndex, GitError> {
+        let invalid =
+            |reason: &str| GitError::ObjectError(format!("Invalid pack index: {}", reason));
+        if data.len() < 8 + 256 * 4 || &data[0..4] != IDX_MAGIC {
+            return Err(invalid("only version 2 indexes are supported"));
+        }
+        if BigEndian::read_u32(&data[4..8]) != 2 {
+            return Err(invalid("only version 2 indexes are supported"));
+        }
+        let fanout = |byte: usize| BigEndian::read_u32(&data[8 + byte * 4..12 + byte * 4]);
+        if (1..256).any(|byte| fanout(byte - 1) > fanout(byte)) {
+            return Err(invalid("fanout table is not sorted"));
+        }
+        let count = fanout(255) as usize;
+        let large = count
+            .checked_mul(ObjectId::LEN + 8)
+            .and_then(|tables| tables.checked_add(8 + 256 * 4))
+            .filter(|&large| large + 2 * ObjectId::LEN <= data.len())
+            .ok_or_else(|| invalid("truncated"))?;
+        // Every offset with the top bit set must point into the table of 64 bit offsets, which
+        // fills what is left before the trailing pack and index checksums.
+        let large_count = (data.len() - large - 2 * ObjectId::LEN) / 8;
+        let offsets = &data[large - count * 4..large];
+        if offsets.chunks(4).any(|offset| {
+            let offset = BigEndian::read_u32(offset);
+            offset & 0x8000_0000 != 0 && (offset & 0x7fff_ffff) as usize >= large_count
+        }) {
+            return Err(invalid("large offset out of range"));
+        }
+        Ok(PackIndex { data, count })
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    fn fanout(&self, byte: usize) -> usize {
+        BigEndian::read_u32(&self.data[8 + byte * 4..12 + byte * 4]) as usize
+    }
+
+    pub fn oid(&self, i: usize) -> ObjectId {
+        let start = 8 + 256 * 4 + i * ObjectId::LEN;
+        let mut raw = [0; ObjectId::LEN];
+        raw.copy_from_slice(&self.data[start..start + ObjectId::LEN]);
+        ObjectId::from_array(raw)
+    }
+
+    pub fn crc32(&self, i: usize) -> u32 {
+        let start = 8 + 256 * 4 + self.count * ObjectId::LEN + i * 4;
+        BigEndian::read_u32(&self.data[start..start + 4])
+    }
+
+    pub fn offset(&self, i: usize) -> u64 {
+        let table = 8 + 256 * 4 + self.count * (ObjectId::LEN + 4);
+        let offset = BigEndian::read_u32(&self.data[table + i * 4..table + i * 4 + 4]);
+        if offset & 0x8000_0000 == 0 {
+            return offset as u64;
+        }
+        let large = table + self.count * 4 + (offset & 0x7fff_ffff) as usize * 8;
+        BigEndian::read_u64(&self.data[large..large + 8])
+    }
+
+    // The position of an object in the index, found by binary search within its fanout bucket.
+    pub fn position(&self, oid: &ObjectId) -> Option<usize> {
+        let first = oid.as_bytes()[0] as usize;
+        let start = if first == 0 {
+            0
+        } else {
+            self.fanout(first - 1)
+        };
+        let end = self.fanout(first);
+        let (mut low, mut high) = (start, end);
+        while low < high {
+            let middle = (low + high) / 2;
+            match self.oid(middle).cmp(oid) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => return Some(middle),
+            }
+        }
+        None
+    }
+
+    pub fn find(&self, oid: &ObjectId) -> Option<u64> {
+        self.position(oid).map(|i| self.offset(i))
+    }
+
+    pub fn oids(&self) -> impl Iterator<Item = ObjectId> + '_ {
+        (0..self.count).map(move |i| self.oid(i))
+    }
+}
+
+// How the data of a pack entry is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EntryKind {
+    Base(ObjType),
+    OfsDelta(u64),
+    RefDelta(ObjectId),
+}
+
+#[derive(Debug)]
+struct EntryHeader {
+    kind: EntryKind,
+    size: usize,
+    // Where the zlib compressed data starts.
+    data_offset: u64,
+}
+
+#[derive(Debug)]
+pub struct Pack {
+    pub path: PathBuf,
+    pub index: PackIndex,
+    file: File,
+}
+
+impl Pack {
+    // Opens "pack-<hash>.pack" and its "pack-<hash>.idx".
+    pub fn open(path: &Path) -> Result<Pack, GitError> {
+        let idx_path = path.with_extension("idx");
+        let index = fs::read(&idx_path)
+            .map_err(|_| GitError::PathError("Could not read pack index".to_owned(), idx_path))
+            .and_then(PackIndex::parse)?;
+        let file = File::open(path)
+            .map_err(|_| GitError::PathError("Could not open pack".to_owned(), path.into()))?;
+        let mut header = [0; 12];
+        file.read_exact_at(&mut header, 0)
+            .map_err(|_| GitError::PathError("Could not read pack".to_owned(), path.into()))?;
+        let version = BigEndian::read_u32(&header[4..8]);
+        if &header[0..4] != b"PACK" || !(version == 2 || version == 3) {
+            return Err(GitError::PathError(
+                "Not a version 2 pack".to_owned(),
+                path.into(),
+            ));
+        }
+        Ok(Pack {
+            path: path.to_path_buf(),
+            index,
+            file,
+        })
+    }
+
+    fn read_header(&self, offset: u64) -> Result<EntryHeader, GitError> {
+        // A header is at most 10 bytes of type and size plus a 20 byte base id or a 10 byte offset.
+        let mut buf = [0u8; 32];
+        let read = self
+            .file
+            .read_at(&mut buf, offset)
+            .map_err(|e| self.error(e))?;
+        parse_entry_header(&buf[..read], offset)
+    }
+
+    fn inflate(&self, header: &EntryHeader) -> Result<Vec<u8>, GitError> {
+        let reader = FileReader {
+            file: &self.file,
+            position: header.data_offset,
+        };
+        // The size is read from the pack, which may be corrupt, so it is not preallocated.
+        let mut data = Vec::new();
+        ZlibDecoder::new(reader)
+            .take(header.size as u64)
+            .read_to_end(&mut data)
+            .map_err(|e| self.error(e))?;
+        if data.len() != header.size {
+            return Err(GitError::ObjectError(format!(
+                "Truncated pack entry at offset {} in {}",
+                header.data_offset,
+                self.path.display()
+            )));
+        }
+        Ok(data)
+    }
+
+    fn error(&self, e: io::Error) -> GitError {
+        GitError::PathError(format!("Could not read pack: {}", e), self.path.clone())
+    }
+}
+
+// Parses the type, size and delta base of the entry at `offset` from the bytes starting there.
+fn parse_entry_header(buf: &[u8], offset: u64) -> Result<EntryHeader, GitError> {
+    let corrupt = || GitError::ObjectError(format!("Corrupt pack entry at offset {}", offset));
+
+    let mut i = 0;
+    let mut byte = *buf.get(i).ok_or_else(corrupt)?;
+    let kind = (byte >> 4) & 0x7;
+    let mut size = (byte & 0x0f) as usize;
+    let mut shift = 4;
+    while byte & 0x80 != 0 {
+        i += 1;
+        byte = *buf.get(i).ok_or_else(corrupt)?;
+        // Sizes past 64 bits cannot be real.
+        size |= ((byte & 0x7f) as usize)
+            .checked_shl(shift)
+            .ok_or_else(corrupt)?;
+        shift += 7;
+    }
+    i += 1;
+
+    let kind = match kind {
+        1 => EntryKind::Base(ObjType::Commit),
+        2 => EntryKind::Base(ObjType::Tree),
+        3 => EntryKind::Base(ObjType::Blob),
+        4 => EntryKind::Base(ObjType::Tag),
+        OBJ_OFS_DELTA => {
+            // The distance back to the base, in a big endian varint where each continuation
+            // adds one so that there is only one encoding for each value.
+            let mut byte = *buf.get(i).ok_or_else(corrupt)?;
+            let mut distance = (byte & 0x7f) as u64;
+            while byte & 0x80 != 0 {
+                i += 1;
+                byte = *buf.get(i).ok_or_else(corrupt)?;
+                distance = distance
+                    .checked_add(1)
+                    .and_then(|distance| distance.checked_mul(1 << 7))
+                    .ok_or_else(corrupt)?
+                    | (byte & 0x7f) as u64;
+            }
+            i += 1;
+            EntryKind::OfsDelta(offset.checked_sub(distance).ok_or_else(corrupt)?)
+        }
+        OBJ_REF_DELTA => {
+            let base = buf.get(i..i + ObjectId::LEN).ok_or_else(corrupt)?;
+            i += ObjectId::LEN;
+            EntryKind::RefDelta(ObjectId::from_bytes(base)?)
+        }
+        _ => return Err(corrupt()),
+    };
+    Ok(EntryHeader {
+        kind,
+        size,
+        data_offset: offset + i as u64,
+    })
+}
+
+// Reads a file sequentially from a position without moving a shared cursor.
+struct FileReader<'a> {
+    file: &'a File,
+    position: u64,
+}
+
+impl<'a> Read for FileReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.file.read_at(buf, self.position)?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+// An object read from a pack, shared between the cache and its readers.
+type Resolved = (ObjType, Rc<Vec<u8>>);
+
+// Recently resolved objects that delta chains start from, keyed by pack and offset, evicted
+// oldest first once they take more than DELTA_CACHE_LIMIT bytes.
+#[derive(Debug, Default)]
+struct DeltaBaseCache {
+    entries: HashMap<(usize, u64), Resolved>,
+    order: VecDeque<(usize, u64)>,
+    size: usize,
+}
+
+impl DeltaBaseCache {
+    fn get(&self, key: &(usize, u64)) -> Option<Resolved> {
+        self.entries.get(key).cloned()
+    }
+
+    fn insert(&mut self, key: (usize, u64), obj_type: ObjType, data: Rc<Vec<u8>>) {
+        if data.len() > DELTA_CACHE_LIMIT || self.entries.contains_key(&key) {
+            return;
+        }
+        self.size += data.len();
+        self.entries.insert(key, (obj_type, data));
+        self.order.push_back(key);
+        while self.size > DELTA_CACHE_LIMIT {
+            let oldest = match self.order.pop_front() {
+                Some(oldest) => oldest,
+                None => break,
+            };
+            if let Some((_, data)) = self.entries.remove(&oldest) {
+                self.size -= data.len();
+            }
+        }
+    }
+}
+
+// The packs of a repository, opened the first time an object is not found loose.
+#[derive(Debug, Default)]
+pub struct PackStore {
+    packs: RefCell<Option<Rc<Vec<Pack>>>>,
+    cache: RefCell<DeltaBaseCache>,
+}
+
+impl GitRepository {
+    pub fn packs(&self) -> Result<Rc<Vec<Pack>>, GitError> {
+        if let Some(packs) = self.pack_store.packs.borrow().as_ref() {
+            return Ok(packs.clone());
+        }
+        let mut packs = Vec::new();
+        let dir = self.repo_path(&path!("objects", "pack"));
+        if let Ok(entries) = fs::read_dir(&dir) {
+            let mut paths: Vec<PathBuf> = entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| {
+                    path.extension().is_some_and(|ext| ext == "pack")
+                        && path.with_extension("idx").is_file()
+                })
+                .collect();
+            paths.sort();
+            for path in paths {
+                packs.push(Pack::open(&path)?);
+            }
+        }
+        let packs = Rc::new(packs);
+        *self.pack_store.packs.borrow_mut() = Some(packs.clone());
+        Ok(packs)
+    }
+
+    // Forgets the opened packs, so packs written or removed since are seen.
+    pub fn reload_packs(&self) {
+        *self.pack_store.packs.borrow_mut() = None;
+        *self.pack_store.cache.borrow_mut() = DeltaBaseCache::default();
+    }
+
+    pub fn read_packed_object(&self, oid: &ObjectId) -> Result<Option<GitObject>, GitError> {
+        let packs = self.packs()?;
+        for (number, pack) in packs.iter().enumerate() {
+            if let Some(offset) = pack.index.find(oid) {
+                let (obj_type, data) = self.read_pack_entry(&packs, number, offset)?;
+                return Ok(Some(GitObject {
+                    obj_type,
+                    data: Rc::try_unwrap(data).unwrap_or_else(|data| (*data).clone()),
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    // Every object id stored in a pack.
+    pub fn packed_object_ids(&self) -> Result<Vec<ObjectId>, GitError> {
+        Ok(self
+            .packs()?
+            .iter()
+            .flat_map(|pack| pack.index.oids())
+            .collect())
+    }
+
+    // Resolves the entry at `offset`, following its chain of deltas back to a full object and
+    // then applying them in turn.
+    fn read_pack_entry(
+        &self,
+        packs: &[Pack],
+        number: usize,
+        offset: u64,
+    ) -> Result<Resolved, GitError> {
+        let pack = &packs[number];
+        let mut deltas = Vec::new();
+        let mut offset = offset;
+        // The base is cached unless it is a loose object a REF_DELTA points to.
+        let (obj_type, mut data, cache_base) = loop {
+            if let Some((obj_type, data)) = self.pack_store.cache.borrow().get(&(number, offset)) {
+                break (obj_type, data, false);
+            }
+            let header = pack.read_header(offset)?;
+            match header.kind {
+                EntryKind::Base(obj_type) => {
+                    break (obj_type, Rc::new(pack.inflate(&header)?), true)
+                }
+                EntryKind::OfsDelta(base) => {
+                    deltas.push((offset, pack.inflate(&header)?));
+                    offset = base;
+                }
+                EntryKind::RefDelta(base) => {
+                    deltas.push((offset, pack.inflate(&header)?));
+                    match pack.index.find(&base) {
+                        Some(base) => offset = base,
+                        None => {
+                            let base = self.read_object(&base)?;
+                            break (base.obj_type, Rc::new(base.data), false);
+                        }
+                    }
+                }
+            }
+        };
+        if cache_base && !deltas.is_empty() {
+            self.pack_store
+                .cache
+                .borrow_mut()
+                .insert((number, offset), obj_type, data.clone());
+        }
+        while let Some((offset, delta)) = deltas.pop() {
+            data = Rc::new(apply_delta(&data, &delta)?);
+            if !deltas.is_empty() {
+                self.pack_store
+                    .cache
+                    .borrow_mut()
+                    .insert((number, offset), obj_type, data.clone());
+            }
+        }
+        Ok((obj_type, data))
+    }
+}
+
+// An object to write to a pack, either whole or as a delta against an earlier entry.
+#[derive(Debug, Clone)]
+pub struct PackEntry {
+    pub oid: ObjectId,
+    pub obj_type: ObjType,
+    pub data: Vec<u8>,
+    // The position of the base in the list of entries, and the delta against it.
+    pub delta: Option<(usize, Vec<u8>)>,
+}
+
+// Where an object ended up in a written pack, as recorded in its index.
+#[derive(Debug, Clone, Copy)]
+pub struct PackedObject {
+    pub oid: ObjectId,
+    pub offset: u64,
+    pub crc32: u32,
+}
+
+fn type_code(obj_type: ObjType) -> u8 {
+    match obj_type {
+        ObjType::Commit => 1,
+        ObjType::Tree => 2,
+        ObjType::Blob => 3,
+        ObjType::Tag => 4,
+    }
+}
+
+// Encodes one pack entry: its type and size, the distance back to its base for an OFS_DELTA, and
+// the compressed data.
+fn encode_record(code: u8, data: &[u8], distance: Option<u64>) -> Result<Vec<u8>, GitError> {
+    let mut header = Vec::new();
+    let mut size = data.len();
+    let mut byte = (code << 4) | (size & 0x0f) as u8;
+    size >>= 4;
+    while size > 0 {
+        header.push(byte | 0x80);
+        byte = (size & 0x7f) as u8;
+        size >>= 7;
+    }
+    header.push(byte);
+    if let Some(mut distance) = distance {
+        let mut encoded = vec![(distance & 0x7f) as u8];
+        distance >>= 7;
+        while distance > 0 {
+            distance -= 1;
+            encoded.push(0x80 | (distance & 0x7f) as u8);
+            distance >>= 7;
+        }
+        encoded.reverse();
+        header.extend(encoded);
+    }
+
+    let mut encoder = ZlibEncoder::new(header, Compression::default());
+    encoder
+        .write_all(data)
+        .and_then(|_| encoder.finish())
+        .map_err(|e| GitError::GenericError(format!("Unable to compress object: {}", e)))
+}
+
+// Builds a version 2 pack from the entries in order, deltas as OFS_DELTA, followed by the
+// SHA-1 of its contents.
+pub fn encode_pack(entries: &[PackEntry]) -> Result<(Vec<u8>, Vec<PackedObject>), GitError> {
+    let mut pack = b"PACK".to_vec();
+    pack.extend(2u32.to_be_bytes());
+    pack.extend((entries.len() as u32).to_be_bytes());
+    let mut objects: Vec<PackedObject> = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let offset = pack.len() as u64;
+        let (code, data) = match &entry.delta {
+            Some((_, delta)) => (OBJ_OFS_DELTA, delta),
+            None => (type_code(entry.obj_type), &entry.data),
+        };
+        let distance = entry
+            .delta
+            .as_ref()
+            .map(|(base, _)| offset - objects[*base].offset);
+        let record = encode_record(code, data, distance)?;
+        let mut crc = Crc::new();
+        crc.update(&record);
+        pack.extend(&record);
+        objects.push(PackedObject {
+            oid: entry.oid,
+            offset,
+            crc32: crc.sum(),
+        });
+    }
+    let checksum = ObjectId::hash(&pack);
+    pack.extend(checksum.as_bytes());
+    Ok((pack, objects))
+}
+
+// Builds the version 2 index of a pack whose trailing checksum is `checksum`.
+pub fn encode_index(objects: &[PackedObject], checksum: &ObjectId) -> Vec<u8> {
+    let mut objects = objects.to_vec();
+    objects.sort_by_key(|object| object.oid);
+    let mut index = IDX_MAGIC.to_vec();
+    index.extend(2u32.to_be_bytes());
+    let mut count = 0u32;
+    for byte in 0..=255u8 {
+        count += objects
+            .iter()
+            .filter(|object| object.oid.as_bytes()[0] == byte)
+            .count() as u32;
+        index.extend(count.to_be_bytes());
+    }
+    for object in &objects {
+        index.extend(object.oid.as_bytes());
+    }
+    for object in &objects {
+        index.extend(object.crc32.to_be_bytes());
+    }
+    let mut large = Vec::new();
+    for object in &objects {
+        if object.offset < 0x8000_0000 {
+            index.extend((object.offset as u32).to_be_bytes());
+        } else {
+            index.extend((0x8000_0000 | (large.len() / 8) as u32).to_be_bytes());
+            large.extend(object.offset.to_be_bytes());
+        }
+    }
+    index.extend(large);
+    index.extend(checksum.as_bytes());
+    let own = ObjectId::hash(&index);
+    index.extend(own.as_bytes());
+    index
+}
+
+impl GitRepository {
+    // Stores a pack and its index as objects/pack/pack-<checksum>.{pack,idx}, writing the index
+    // last so the pack is never seen without it.
+    pub fn install_pack(&self, pack: &[u8], index: &[u8]) -> Result<PathBuf, GitError> {
+        let checksum = ObjectId::from_bytes(&pack[pack.len() - ObjectId::LEN..])?;
+        let name = format!("pack-{}", checksum);
+        let pack_path = self.repo_file(&path!("objects", "pack", &format!("{}.pack", name)))?;
+        let index_path = pack_path.with_extension("idx");
+        for (path, data) in [(&pack_path, pack), (&index_path, index)] {
+            let mut temporary = path.clone().into_os_string();
+            temporary.push(".tmp");
+            fs::write(&temporary, data)
+                .and_then(|_| fs::rename(&temporary, path))
+                .map_err(|_| {
+                    GitError::PathError("Could not write pack".to_owned(), path.clone())
+                })?;
+        }
+        self.reload_packs();
+        Ok(pack_path)
+    }
+}
+
+impl GitRepository {
+    // Builds the index of a pack received whole, as index-pack does: every entry is inflated,
+    // deltas are resolved against bases in the pack or, for a thin pack, in the repository, and
+    // each object is hashed to learn its id. A thin pack gets the bases it lacks appended, so
+    // the pack returned with the index stands on its own.
+    pub fn index_pack(&self, mut pack: Vec<u8>) -> Result<(Vec<u8>, Vec<u8>), GitError> {
+        let corrupt = |message: &str| GitError::ObjectError(format!("Invalid pack: {}", message));
+        if pack.len() < 12 + ObjectId::LEN || &pack[0..4] != b"PACK" {
+            return Err(corrupt("bad header"));
+        }
+        let version = BigEndian::read_u32(&pack[4..8]);
+        if !(version == 2 || version == 3) {
+            return Err(corrupt("unsupported version"));
+        }
+        let count = BigEndian::read_u32(&pack[8..12]) as usize;
+        let body = pack.len() - ObjectId::LEN;
+        let checksum = ObjectId::from_bytes(&pack[body..])?;
+        if ObjectId::hash(&pack[..body]) != checksum {
+            return Err(corrupt("checksum mismatch"));
+        }
+
+        // Inflate every entry, remembering where it starts and the CRC32 of its raw bytes. The
+        // counts and sizes come from whoever sent the pack, so nothing is allocated up front;
+        // inflating one byte past the size is enough to notice an entry that is too long.
+        let mut entries: Vec<(u64, EntryKind, Vec<u8>, u32)> = Vec::new();
+        let mut offset = 12;
+        for _ in 0..count {
+            let header = parse_entry_header(&pack[offset..body], offset as u64)?;
+            let start = header.data_offset as usize;
+            let mut decoder = ZlibDecoder::new(&pack[start..body]);
+            let mut data = Vec::new();
+            (&mut decoder)
+                .take((header.size as u64).saturating_add(1))
+                .read_to_end(&mut data)
+                .map_err(|e| GitError::ObjectError(format!("Invalid pack: {}", e)))?;
+            if data.len() != header.size {
+                return Err(corrupt("truncated entry"));
+            }
+            let end = start + decoder.total_in() as usize;
+            let mut crc = Crc::new();
+            crc.update(&pack[offset..end]);
+            entries.push((offset as u64, header.kind, data, crc.sum()));
+            offset = end;
+        }
+        if offset != body {
+            return Err(corrupt("trailing data"));
+        }
+
+        // Resolve deltas whose bases are known, repeating until no more can be resolved. Only
+        // then are REF_DELTA bases looked up in the repository, as the pack is thin.
+        let position: HashMap<u64, usize> = entries
+            .iter()
+            .enumerate()
+            .map(|(i, (offset, ..))| (*offset, i))
+            .collect();
+        let mut resolved: Vec<Option<Resolved>> = vec![None; count];
+        let mut ids: Vec<ObjectId> = vec![ObjectId::default(); count];
+        let mut by_id: HashMap<ObjectId, usize> = HashMap::new();
+        let mut remaining = count;
+        let mut thin = false;
+        let mut external = Vec::new();
+        while remaining > 0 {
+            let before = remaining;
+            for i in 0..count {
+                if resolved[i].is_some() {
+                    continue;
+                }
+                let (_, kind, data, _) = &entries[i];
+                let delta = |base: Option<&Resolved>| {
+                    base.map(|(obj_type, base)| Ok((*obj_type, Rc::new(apply_delta(base, data)?))))
+                };
+                let object = match kind {
+                    EntryKind::Base(obj_type) => Some(Ok((*obj_type, Rc::new(data.clone())))),
+                    EntryKind::OfsDelta(base) => {
+                        let base = position
+                            .get(base)
+                            .ok_or_else(|| corrupt("delta base outside the pack"))?;
+                        delta(resolved[*base].as_ref())
+                    }
+                    EntryKind::RefDelta(base) => match by_id.get(base) {
+                        Some(base) => delta(resolved[*base].as_ref()),
+                        None if thin && self.has_object(base) => {
+                            let base = self.read_object(base)?;
+                            if !external.contains(&base.id()) {
+                                external.push(base.id());
+                            }
+                            delta(Some(&(base.obj_type, Rc::new(base.data))))
+                        }
+                        None => None,
+                    },
+                };
+                let (obj_type, data) = match object {
+                    Some(object) => object?,
+                    None => continue,
+                };
+                let oid = GitObject::new(obj_type, &data).id();
+                by_id.insert(oid, i);
+                ids[i] = oid;
+                resolved[i] = Some((obj_type, data));
+                remaining -= 1;
+            }
+            if remaining == before {
+                if thin {
+                    return Err(corrupt("unresolved deltas"));
+                }
+                thin = true;
+            }
+        }
+
+        let mut objects: Vec<PackedObject> = entries
+            .iter()
+            .zip(ids)
+            .map(|((offset, _, _, crc32), oid)| PackedObject {
+                oid,
+                offset: *offset,
+                crc32: *crc32,
+            })
+            .collect();
+        if external.is_empty() {
+            let index = encode_index(&objects, &checksum);
+            return Ok((pack, index));
+        }
+
+        pack.truncate(body);
+        for oid in &external {
+            let object = self.read_object(oid)?;
+            let record = encode_record(type_code(object.obj_type), &object.data, None)?;
+            let mut crc = Crc::new();
+            crc.update(&record);
+            objects.push(PackedObject {
+                oid: *oid,
+                offset: pack.len() as u64,
+                crc32: crc.sum(),
+            });
+            pack.extend(record);
+        }
+        BigEndian::write_u32(&mut pack[8..12], objects.len() as u32);
+        let checksum = ObjectId::hash(&pack);
+        pack.extend(checksum.as_bytes());
+        let index = encode_index(&objects, &checksum);
+        Ok((pack, index))
+    }
+}
+
+// Reads one pack from a stream that may go on past it, as receive-pack must: each entry is
+// inflated just far enough to find where it ends.
+pub fn read_pack<R: BufRead>(input: &mut R) -> Result<Vec<u8>, GitError> {
+    let error = |e: io::Error| GitError::ObjectError(format!("Could not read pack: {}", e));
+    let mut pack = vec![0; 12];
+    input.read_exact(&mut pack).map_err(error)?;
+    if &pack[0..4] != b"PACK" {
+        return Err(GitError::ObjectError("Invalid pack: bad header".to_owned()));
+    }
+    let count = BigEndian::read_u32(&pack[8..12]);
+    for _ in 0..count {
+        // The type and size bytes, then an OFS_DELTA offset or a REF_DELTA base id.
+        let mut byte = [0u8];
+        input.read_exact(&mut byte).map_err(error)?;
+        pack.push(byte[0]);
+        let kind = (byte[0] >> 4) & 0x7;
+        while byte[0] & 0x80 != 0 {
+            input.read_exact(&mut byte).map_err(error)?;
+            pack.push(byte[0]);
+        }
+        if kind == OBJ_OFS_DELTA {
+            loop {
+                input.read_exact(&mut byte).map_err(error)?;
+                pack.push(byte[0]);
+                if byte[0] & 0x80 == 0 {
+                    break;
+                }
+            }
+        } else if kind == OBJ_REF_DELTA {
+            let mut base = [0u8; ObjectId::LEN];
+            input.read_exact(&mut base).map_err(error)?;
+            pack.extend(base);
+        }
+        let recorder = Recorder {
+            inner: &mut *input,
+            record: &mut pack,
+        };
+        io::copy(&mut bufread::ZlibDecoder::new(recorder), &mut io::sink()).map_err(error)?;
+    }
+    let mut checksum = [0u8; ObjectId::LEN];
+    input.read_exact(&mut checksum).map_err(error)?;
+    pack.extend(checksum);
+    Ok(pack)
+}
+
+// Passes a buffered stream through, keeping a copy of every byte consumed from it.
+struct Recorder<'a, R: BufRead> {
+    inner: &'a mut R,
+    record: &'a mut Vec<u8>,
+}
+
+impl<'a, R: BufRead> Read for Recorder<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let read = available.len().min(buf.len());
+        buf[..read].copy_from_slice(&available[..read]);
+        self.consume(read);
+        Ok(read)
+    }
+}
+
+impl<'a, R: BufRead> BufRead for Recorder<'a, R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        if let Ok(available) = self.inner.fill_buf() {
+            self.record.extend(&available[..amount]);
+        }
+        self.inner.consume(amount);
+    }
+}
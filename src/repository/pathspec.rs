@@ -0,0 +1,133 @@
+use super::GitRepository;
+use crate::error::GitError;
+use crate::files::wildmatch;
+use std::path::Path;
+
+// One pathspec argument. Magic comes first, in the long ":(exclude,icase)path" form or the
+// short ":!path", ":^path" and ":/path" forms.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathspecItem {
+    // The argument as given, for error messages.
+    pub original: String,
+    // The path relative to the worktree root; "" is the whole worktree.
+    pub path: String,
+    pub exclude: bool,
+    // ":(literal)" takes wildcards as plain characters.
+    pub literal: bool,
+    // ":(glob)" keeps '*' from matching '/', as in .gitignore patterns.
+    pub glob: bool,
+    pub icase: bool,
+}
+
+impl PathspecItem {
+    // Whether the item names paths by wildcard rather than a file or a directory.
+    pub fn has_wildcard(&self) -> bool {
+        !self.literal && self.path.contains(['*', '?', '[', '\\'])
+    }
+
+    // Whether the item names `path` itself, a directory holding it or, as a glob, matches it.
+    pub fn matches(&self, path: &str) -> bool {
+        let (pattern, path) = match self.icase {
+            true => (self.path.to_lowercase(), path.to_lowercase()),
+            false => (self.path.clone(), path.to_owned()),
+        };
+        pattern.is_empty()
+            || path == pattern
+            || path.starts_with(&format!("{}/", pattern))
+            || (self.has_wildcard() && wildmatch(&pattern, &path, self.glob))
+    }
+}
+
+// A list of pathspecs. A path matches when some item that is not an exclusion matches it and
+// no exclusion does; with only exclusions, everything that is not excluded matches.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Pathspec {
+    pub items: Vec<PathspecItem>,
+}
+
+impl Pathspec {
+    pub fn matches(&self, path: &str) -> bool {
+        let mut included = self.items.iter().filter(|item| !item.exclude).peekable();
+        (included.peek().is_none() || included.any(|item| item.matches(path)))
+            && !self
+                .items
+                .iter()
+                .any(|item| item.exclude && item.matches(path))
+    }
+
+    // The items that are not exclusions and match none of `paths`, which `add` reports as
+    // pathspecs that "did not match any files".
+    pub fn unmatched<'a>(&'a self, paths: &[String]) -> Vec<&'a PathspecItem> {
+        self.items
+            .iter()
+            .filter(|item| !item.exclude && !paths.iter().any(|path| item.matches(path)))
+            .collect()
+    }
+}
+
+impl GitRepository {
+    // Parses pathspec arguments given relative to the current directory.
+    pub fn pathspec(&self, args: &[String]) -> Result<Pathspec, GitError> {
+        let mut items = Vec::new();
+        for arg in args {
+            let mut item = PathspecItem {
+                original: arg.clone(),
+                path: String::new(),
+                exclude: false,
+                literal: false,
+                glob: false,
+                icase: false,
+            };
+            let mut top = false;
+            let mut rest = arg.as_str();
+            if let Some(long) = rest.strip_prefix(":(") {
+                let (magic, path) = long.split_once(')').ok_or_else(|| {
+                    GitError::GenericError(format!(
+                        "Missing ')' at the end of pathspec magic in '{}'",
+                        arg
+                    ))
+                })?;
+                for word in magic.split(',').filter(|word| !word.is_empty()) {
+                    match word {
+                        "exclude" => item.exclude = true,
+                        "literal" => item.literal = true,
+                        "glob" => item.glob = true,
+                        "icase" => item.icase = true,
+                        "top" => top = true,
+                        _ => {
+                            return Err(GitError::GenericError(format!(
+                                "Invalid pathspec magic '{}' in '{}'",
+                                word, arg
+                            )))
+                        }
+                    }
+                }
+                rest = path;
+            } else if let Some(short) = rest.strip_prefix(':') {
+                // Short magic runs up to the next ':' or the first character that is not magic.
+                let end = short
+                    .find(|c| !matches!(c, '!' | '^' | '/'))
+                    .unwrap_or(short.len());
+                for c in short[..end].chars() {
+                    match c {
+                        '/' => top = true,
+                        _ => item.exclude = true,
+                    }
+                }
+                rest = short[end..].strip_prefix(':').unwrap_or(&short[end..]);
+            }
+            if item.literal && item.glob {
+                return Err(GitError::GenericError(format!(
+                    "'literal' and 'glob' are incompatible in '{}'",
+                    arg
+                )));
+            }
+            item.path = match top {
+                true => rest.trim_matches('/').to_owned(),
+                false => self.worktree_relative(Path::new(rest))?,
+            };
+            items.push(item);
+        }
+        Ok(Pathspec { items })
+    }
+}
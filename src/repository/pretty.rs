@@ -0,0 +1,279 @@
+use super::commit::Commit;
+use super::ident::Ident;
+use super::oid::ObjectId;
+use super::GitRepository;
+use crate::error::GitError;
+use std::collections::HashMap;
+
+// The layouts of `log --format`: the built in ones and a user template, which is either
+// terminated by a newline ("tformat:" or a bare template) or only separated by one ("format:").
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PrettyFormat {
+    Oneline,
+    Short,
+    Medium,
+    Full,
+    Fuller,
+    Raw,
+    Template { template: String, terminator: bool },
+}
+
+impl PrettyFormat {
+    pub fn parse(spec: &str) -> Result<PrettyFormat, GitError> {
+        Ok(match spec {
+            "oneline" => PrettyFormat::Oneline,
+            "short" => PrettyFormat::Short,
+            "medium" => PrettyFormat::Medium,
+            "full" => PrettyFormat::Full,
+            "fuller" => PrettyFormat::Fuller,
+            "raw" => PrettyFormat::Raw,
+            _ => match spec.split_once(':') {
+                Some(("format", template)) => PrettyFormat::Template {
+                    template: template.to_owned(),
+                    terminator: false,
+                },
+                Some(("tformat", template)) => PrettyFormat::Template {
+                    template: template.to_owned(),
+                    terminator: true,
+                },
+                _ if spec.contains('%') => PrettyFormat::Template {
+                    template: spec.to_owned(),
+                    terminator: true,
+                },
+                _ => {
+                    return Err(GitError::GenericError(format!(
+                        "Invalid pretty format: {}",
+                        spec
+                    )))
+                }
+            },
+        })
+    }
+
+    // Multi-line formats are separated from each other by a blank line.
+    pub fn separator(&self) -> &'static str {
+        match self {
+            PrettyFormat::Oneline => "",
+            PrettyFormat::Template {
+                terminator: true, ..
+            } => "",
+            _ => "\n",
+        }
+    }
+}
+
+impl GitRepository {
+    // The names pointing at each commit, as shown by "%d": "HEAD -> master", "tag: v1.0", ...
+    pub fn decorations(&self) -> Result<HashMap<ObjectId, Vec<String>>, GitError> {
+        let mut decorations: HashMap<ObjectId, Vec<String>> = HashMap::new();
+        let head_branch = self.current_branch();
+        if let Some(head) = self.resolve_ref("HEAD")? {
+            let name = match &head_branch {
+                Some(branch) => format!("HEAD -> {}", branch),
+                None => "HEAD".to_owned(),
+            };
+            decorations.entry(head).or_default().push(name);
+        }
+        for (name, oid) in self.list_refs("refs/")? {
+            let short = if let Some(branch) = name.strip_prefix("refs/heads/") {
+                if head_branch.as_deref() == Some(branch) {
+                    continue;
+                }
+                branch.to_owned()
+            } else if let Some(tag) = name.strip_prefix("refs/tags/") {
+                format!("tag: {}", tag)
+            } else if let Some(remote) = name.strip_prefix("refs/remotes/") {
+                remote.to_owned()
+            } else {
+                continue;
+            };
+            let target = self.peel(&oid, None).unwrap_or(oid);
+            decorations.entry(target).or_default().push(short);
+        }
+        Ok(decorations)
+    }
+}
+
+// Formats one commit. `abbrev` shortens the commit ids in the built in formats, as
+// `--abbrev-commit` and `--oneline` do.
+pub fn format_commit(
+    oid: &ObjectId,
+    commit: &Commit,
+    format: &PrettyFormat,
+    abbrev: bool,
+    decorations: &[String],
+) -> String {
+    let id = if abbrev {
+        format!("{:.7}", oid)
+    } else {
+        oid.to_hex()
+    };
+    let decoration = if decorations.is_empty() {
+        String::new()
+    } else {
+        format!(" ({})", decorations.join(", "))
+    };
+    let author = commit.author_ident().ok();
+    let committer = commit.committer_ident().ok();
+    let person = |ident: &Option<Ident>| {
+        ident
+            .as_ref()
+            .map(|i| format!("{} <{}>", i.name, i.email))
+            .unwrap_or_default()
+    };
+    let date = |ident: &Option<Ident>| ident.as_ref().map(Ident::default_date).unwrap_or_default();
+
+    let mut out = String::new();
+    let template = match format {
+        PrettyFormat::Oneline => {
+            return format!("{}{} {}\n", id, decoration, commit.subject());
+        }
+        PrettyFormat::Raw => {
+            out.push_str(&format!("commit {}{}\n", id, decoration));
+            out.push_str(&format!("tree {}\n", commit.tree));
+            for parent in &commit.parents {
+                out.push_str(&format!("parent {}\n", parent));
+            }
+            out.push_str(&format!(
+                "author {}\n",
+                String::from_utf8_lossy(&commit.author)
+            ));
+            out.push_str(&format!(
+                "committer {}\n",
+                String::from_utf8_lossy(&commit.committer)
+            ));
+            out.push('\n');
+            out.push_str(&indented_message(commit));
+            return out;
+        }
+        PrettyFormat::Template { template, .. } => template,
+        _ => {
+            out.push_str(&format!("commit {}{}\n", id, decoration));
+            if commit.parents.len() > 1 {
+                let parents: Vec<String> =
+                    commit.parents.iter().map(|p| format!("{:.7}", p)).collect();
+                out.push_str(&format!("Merge: {}\n", parents.join(" ")));
+            }
+            match format {
+                PrettyFormat::Short => {
+                    out.push_str(&format!("Author: {}\n\n", person(&author)));
+                    out.push_str(&format!("    {}\n", commit.subject()));
+                    return out;
+                }
+                PrettyFormat::Medium => {
+                    out.push_str(&format!("Author: {}\n", person(&author)));
+                    out.push_str(&format!("Date:   {}\n", date(&author)));
+                }
+                PrettyFormat::Full => {
+                    out.push_str(&format!("Author: {}\n", person(&author)));
+                    out.push_str(&format!("Commit: {}\n", person(&committer)));
+                }
+                _ => {
+                    out.push_str(&format!("Author:     {}\n", person(&author)));
+                    out.push_str(&format!("AuthorDate: {}\n", date(&author)));
+                    out.push_str(&format!("Commit:     {}\n", person(&committer)));
+                    out.push_str(&format!("CommitDate: {}\n", date(&committer)));
+                }
+            }
+            out.push('\n');
+            out.push_str(&indented_message(commit));
+            return out;
+        }
+    };
+
+    let message = String::from_utf8_lossy(&commit.message).into_owned();
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        let mut placeholder = String::new();
+        if let Some(next) = chars.next() {
+            placeholder.push(next);
+            if matches!(next, 'a' | 'c') {
+                if let Some(detail) = chars.next() {
+                    placeholder.push(detail);
+                }
+            }
+        }
+        let value = match placeholder.as_str() {
+            "H" => oid.to_hex(),
+            "h" => format!("{:.7}", oid),
+            "T" => commit.tree.to_hex(),
+            "t" => format!("{:.7}", commit.tree),
+            "P" => join_ids(&commit.parents, false),
+            "p" => join_ids(&commit.parents, true),
+            "s" => commit.subject(),
+            "b" => body(&message),
+            "B" => message.clone(),
+            "d" => decoration.clone(),
+            "D" => decorations.join(", "),
+            "n" => "\n".to_owned(),
+            "%" => "%".to_owned(),
+            p if p.len() == 2 && matches!(p.as_bytes()[0], b'a' | b'c') => {
+                let ident = if p.starts_with('a') {
+                    &author
+                } else {
+                    &committer
+                };
+                match (ident, &p[1..]) {
+                    (Some(i), "n") => i.name.clone(),
+                    (Some(i), "e") => i.email.clone(),
+                    (Some(i), "d") => i.default_date(),
+                    (Some(i), "t") => i.time.to_string(),
+                    (Some(i), "i") => i.iso_date(),
+                    (Some(i), "I") => i.strict_iso_date(),
+                    (Some(i), "r") => i.relative_date(),
+                    (Some(i), "s") => i.date().format("%Y-%m-%d").to_string(),
+                    (None, _) => String::new(),
+                    _ => format!("%{}", p),
+                }
+            }
+            // Unknown placeholders are printed as they are.
+            p => format!("%{}", p),
+        };
+        out.push_str(&value);
+    }
+    if let PrettyFormat::Template {
+        terminator: true, ..
+    } = format
+    {
+        out.push('\n');
+    }
+    out
+}
+
+fn join_ids(ids: &[ObjectId], abbrev: bool) -> String {
+    let ids: Vec<String> = ids
+        .iter()
+        .map(|id| {
+            if abbrev {
+                format!("{:.7}", id)
+            } else {
+                id.to_hex()
+            }
+        })
+        .collect();
+    ids.join(" ")
+}
+
+// Everything after the subject line and the blank lines that follow it.
+fn body(message: &str) -> String {
+    match message.split_once('\n') {
+        Some((_, rest)) => rest.trim_start_matches('\n').to_owned(),
+        None => String::new(),
+    }
+}
+
+// The message indented by four spaces, without trailing blank lines.
+fn indented_message(commit: &Commit) -> String {
+    let message = String::from_utf8_lossy(&commit.message);
+    let mut out = String::new();
+    for line in message.trim_end().lines() {
+        out.push_str("    ");
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
@@ -0,0 +1,178 @@
+use super::oid::ObjectId;
+use super::pack::read_pack;
+use super::refs::is_valid_ref_name;
+use super::upload_pack::AGENT;
+use super::GitRepository;
+use crate::error::GitError;
+use crate::pktline::{self, Packet};
+use std::io::{BufRead, Write};
+
+impl GitRepository {
+    // Serves a protocol v0 or v1 push: the refs are advertised, then the client's ref updates
+    // are applied after unpacking the pack sent with them.
+    pub fn receive_pack<R: BufRead, W: Write>(
+        &self,
+        input: &mut R,
+        output: &mut W,
+        version: u8,
+    ) -> Result<(), GitError> {
+        self.advertise_receive_pack(output, version)?;
+        self.serve_receive_pack(input, output)
+    }
+
+    // Lists every ref, the first line also carrying the capabilities. A repository without refs
+    // advertises the capabilities on a placeholder line.
+    pub fn advertise_receive_pack<W: Write>(
+        &self,
+        output: &mut W,
+        version: u8,
+    ) -> Result<(), GitError> {
+        if version == 1 {
+            pktline::write_line(output, "version 1").map_err(pktline::error)?;
+        }
+        let capabilities = format!(
+            "report-status delete-refs ofs-delta quiet object-format=sha1 agent={}",
+            AGENT
+        );
+        let mut refs: Vec<(String, ObjectId)> = self.list_refs("refs/")?.into_iter().collect();
+        if refs.is_empty() {
+            refs.push(("capabilities^{}".to_owned(), ObjectId::default()));
+        }
+        for (i, (name, oid)) in refs.iter().enumerate() {
+            let line = match i {
+                0 => format!("{} {}\0{}", oid, name, capabilities),
+                _ => format!("{} {}", oid, name),
+            };
+            pktline::write_line(output, &line).map_err(pktline::error)?;
+        }
+        pktline::write_flush(output).map_err(pktline::error)?;
+        output.flush().map_err(pktline::error)
+    }
+
+    // Reads the "<old> <new> <ref>" commands and the pack that follows them, then updates each
+    // ref whose old value still matches, reporting the outcome if the client asked for it.
+    pub fn serve_receive_pack<R: BufRead, W: Write>(
+        &self,
+        input: &mut R,
+        output: &mut W,
+    ) -> Result<(), GitError> {
+        let (request, _) = pktline::read_section(input).map_err(pktline::error)?;
+        // A client with nothing to push just hangs up.
+        if request.is_empty() {
+            return Ok(());
+        }
+        let mut report = false;
+        let mut commands = Vec::new();
+        for (i, line) in request.iter().filter_map(Packet::text).enumerate() {
+            let line = match (i, line.split_once('\0')) {
+                (0, Some((line, capabilities))) => {
+                    report = capabilities.split(' ').any(|c| c == "report-status");
+                    line.to_owned()
+                }
+                _ => line,
+            };
+            let fields: Vec<&str> = line.split(' ').collect();
+            if fields.len() != 3 {
+                return Err(GitError::GenericError(format!(
+                    "Invalid push command: {}",
+                    line
+                )));
+            }
+            commands.push((
+                ObjectId::from_hex(fields[0])?,
+                ObjectId::from_hex(fields[1])?,
+                fields[2].to_owned(),
+            ));
+        }
+
+        // Deletions alone come without a pack.
+        let unpacked = if commands.iter().any(|(_, new, _)| !new.is_null()) {
+            self.unpack(input)
+        } else {
+            Ok(())
+        };
+
+        let results = match &unpacked {
+            Ok(()) => self.apply_push(&commands)?,
+            Err(_) => vec![Some("unpacker error".to_owned()); commands.len()],
+        };
+        let statuses = commands
+            .iter()
+            .zip(results)
+            .map(|((_, _, name), result)| match result {
+                None => format!("ok {}", name),
+                Some(reason) => format!("ng {} {}", name, reason),
+            });
+
+        if report {
+            let unpack = match &unpacked {
+                Ok(()) => "unpack ok".to_owned(),
+                Err(e) => format!("unpack {}", e),
+            };
+            pktline::write_line(output, &unpack).map_err(pktline::error)?;
+            for status in statuses {
+                pktline::write_line(output, &status).map_err(pktline::error)?;
+            }
+            pktline::write_flush(output).map_err(pktline::error)?;
+            output.flush().map_err(pktline::error)?;
+        }
+        unpacked
+    }
+
+    fn unpack<R: BufRead>(&self, input: &mut R) -> Result<(), GitError> {
+        let pack = read_pack(input)?;
+        let (pack, index) = self.index_pack(pack)?;
+        // An empty pack is sent when the client knows the remote has every object already.
+        if pack.len() > 12 + ObjectId::LEN {
+            self.install_pack(&pack, &index)?;
+        }
+        Ok(())
+    }
+
+    // Applies pushed ref updates whose objects are all present, returning for each None if the
+    // ref was updated or the reason it was refused.
+    pub fn apply_push(
+        &self,
+        commands: &[(ObjectId, ObjectId, String)],
+    ) -> Result<Vec<Option<String>>, GitError> {
+        let checked_out = match self.is_bare() {
+            true => None,
+            false => self.symbolic_ref("HEAD")?,
+        };
+        let tips: Vec<ObjectId> = self.list_refs("refs/")?.into_values().collect();
+        Ok(commands
+            .iter()
+            .map(|(old, new, name)| {
+                self.apply_command(old, new, name, checked_out.as_deref(), &tips)
+                    .err()
+            })
+            .collect())
+    }
+
+    // Applies one ref update, or says why it was refused. The new value must be connected:
+    // everything it reaches that the existing `tips` do not has to be present.
+    fn apply_command(
+        &self,
+        old: &ObjectId,
+        new: &ObjectId,
+        name: &str,
+        checked_out: Option<&str>,
+        tips: &[ObjectId],
+    ) -> Result<(), String> {
+        if !name.starts_with("refs/") || !is_valid_ref_name(name) {
+            return Err("funny refname".to_owned());
+        }
+        if checked_out == Some(name) {
+            return Err("branch is currently checked out".to_owned());
+        }
+        if !new.is_null() && (!self.has_object(new) || self.list_objects(&[*new], tips).is_err()) {
+            return Err("missing necessary objects".to_owned());
+        }
+        let result = if new.is_null() {
+            self.delete_ref(name, Some(old), false)
+        } else {
+            self.update_ref(name, new, Some(old), false, "push")
+        };
+        result.map_err(|_| "failed to update ref".to_owned())
+    }
+}
@@ -0,0 +1,241 @@
+use super::ident::{approxidate, strip_crud, Ident, Role};
+use super::oid::ObjectId;
+use super::GitRepository;
+use crate::error::GitError;
+use std::env;
+use std::fs;
+use std::io::Write;
+
+// One line of a reflog: "<old> <new> <identity>\t<message>".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReflogEntry {
+    pub old: ObjectId,
+    pub new: ObjectId,
+    pub identity: Ident,
+    pub message: String,
+}
+
+impl ReflogEntry {
+    pub fn parse(line: &str) -> Result<ReflogEntry, GitError> {
+        let invalid = || GitError::GenericError(format!("Invalid reflog entry: {}", line));
+        let (head, message) = line.split_once('\t').unwrap_or((line, ""));
+        let mut fields = head.splitn(3, ' ');
+        let mut oid = || {
+            fields
+                .next()
+                .and_then(|hex| ObjectId::from_hex(hex).ok())
+                .ok_or_else(invalid)
+        };
+        let old = oid()?;
+        let new = oid()?;
+        let identity = Ident::parse(fields.next().ok_or_else(invalid)?)?;
+        Ok(ReflogEntry {
+            old,
+            new,
+            identity,
+            message: message.to_owned(),
+        })
+    }
+
+    pub fn to_line(&self) -> String {
+        format!(
+            "{} {} {}\t{}\n",
+            self.old.to_hex(),
+            self.new.to_hex(),
+            self.identity,
+            self.message
+        )
+    }
+}
+
+impl GitRepository {
+    // The entries of a ref's reflog, oldest first; empty if it has none.
+    pub fn read_reflog(&self, name: &str) -> Result<Vec<ReflogEntry>, GitError> {
+        let path = self.repo_path(&path!("logs", name));
+        let data = match fs::read_to_string(&path) {
+            Ok(data) => data,
+            Err(_) => return Ok(Vec::new()),
+        };
+        data.lines()
+            .filter(|line| !line.is_empty())
+            .map(ReflogEntry::parse)
+            .collect()
+    }
+
+    pub fn has_reflog(&self, name: &str) -> bool {
+        self.repo_path(&path!("logs", name)).is_file()
+    }
+
+    // Records a ref moving from `old` to `new` (None meaning it did not exist), if the ref is
+    // one that gets a reflog.
+    pub fn append_reflog(
+        &self,
+        name: &str,
+        old: Option<&ObjectId>,
+        new: Option<&ObjectId>,
+        message: &str,
+    ) -> Result<(), GitError> {
+        if !self.logs_ref(name) {
+            return Ok(());
+        }
+        let entry = ReflogEntry {
+            old: old.copied().unwrap_or_default(),
+            new: new.copied().unwrap_or_default(),
+            identity: self.reflog_identity(),
+            // A reflog message is a single line.
+            message: message.split_whitespace().collect::<Vec<_>>().join(" "),
+        };
+        let path = self.repo_file(&path!("logs", name))?;
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .and_then(|mut file| file.write_all(entry.to_line().as_bytes()))
+            .map_err(|_| GitError::PathError("Could not write reflog".to_owned(), path))
+    }
+
+    pub fn delete_reflog(&self, name: &str) -> Result<(), GitError> {
+        let path = self.repo_path(&path!("logs", name));
+        if path.is_file() {
+            fs::remove_file(&path)
+                .map_err(|_| GitError::PathError("Could not delete reflog".to_owned(), path))?;
+        }
+        Ok(())
+    }
+
+    pub fn rename_reflog(&self, old: &str, new: &str) -> Result<(), GitError> {
+        let old_log = self.repo_path(&path!("logs", old));
+        if old_log.is_file() {
+            let new_log = self.repo_file(&path!("logs", new))?;
+            fs::rename(&old_log, &new_log)
+                .map_err(|_| GitError::PathError("Could not move reflog".to_owned(), old_log))?;
+        }
+        Ok(())
+    }
+
+    // The value a ref had `n` updates ago, 0 being its latest.
+    pub fn reflog_nth(&self, name: &str, n: usize) -> Result<ObjectId, GitError> {
+        let entries = self.read_reflog(name)?;
+        if entries.is_empty() {
+            return Err(GitError::GenericError(format!(
+                "reflog for '{}' is empty",
+                name
+            )));
+        }
+        entries
+            .iter()
+            .rev()
+            .nth(n)
+            .map(|entry| entry.new)
+            .ok_or_else(|| {
+                GitError::GenericError(format!(
+                    "log for '{}' only has {} entries",
+                    name,
+                    entries.len()
+                ))
+            })
+    }
+
+    // The value a ref had at `time`. Before the oldest entry, that is the value it started from.
+    pub fn reflog_at(&self, name: &str, time: i64) -> Result<ObjectId, GitError> {
+        let entries = self.read_reflog(name)?;
+        if let Some(entry) = entries.iter().rev().find(|e| e.identity.time <= time) {
+            return Ok(entry.new);
+        }
+        let first = entries
+            .first()
+            .ok_or_else(|| GitError::GenericError(format!("reflog for '{}' is empty", name)))?;
+        eprintln!(
+            "warning: log for '{}' only goes back to {}",
+            name,
+            first.identity.default_date()
+        );
+        Ok(match first.old.is_null() {
+            true => first.new,
+            false => first.old,
+        })
+    }
+
+    // Drops entries older than `expire`, and those older than `expire_unreachable` whose commit
+    // is no longer reachable from the ref. Returns the entries that were (or with `dry_run`,
+    // would be) dropped.
+    pub fn expire_reflog(
+        &self,
+        name: &str,
+        expire: i64,
+        expire_unreachable: i64,
+        dry_run: bool,
+    ) -> Result<Vec<ReflogEntry>, GitError> {
+        let entries = self.read_reflog(name)?;
+        let reachable = match self.resolve_ref(name)? {
+            Some(tip) if entries.iter().any(|e| e.identity.time < expire_unreachable) => {
+                // A ref that is not a commit has no history to reach the entries through.
+                self.ancestors(&[tip]).unwrap_or_default()
+            }
+            _ => Default::default(),
+        };
+        let (kept, dropped): (Vec<ReflogEntry>, Vec<ReflogEntry>) =
+            entries.into_iter().partition(|entry| {
+                let time = entry.identity.time;
+                time >= expire && (time >= expire_unreachable || reachable.contains(&entry.new))
+            });
+        if !dry_run && !dropped.is_empty() {
+            let data: String = kept.iter().map(ReflogEntry::to_line).collect();
+            self.write_locked(&path!("logs", name), &data)?;
+        }
+        Ok(dropped)
+    }
+
+    // Every ref with a reflog, HEAD first.
+    pub fn reflog_refs(&self) -> Result<Vec<String>, GitError> {
+        let mut names: Vec<String> = self.list_refs("refs/")?.into_keys().collect();
+        names.insert(0, "HEAD".to_owned());
+        Ok(names
+            .into_iter()
+            .filter(|name| self.has_reflog(name))
+            .collect())
+    }
+
+    // Follows core.logAllRefUpdates: by default branches, remote-tracking refs, notes and HEAD
+    // are logged in repositories with a worktree, and any ref that already has a reflog.
+    fn logs_ref(&self, name: &str) -> bool {
+        if self.has_reflog(name) {
+            return true;
+        }
+        let setting = self
+            .config
+            .get("core", None, "logallrefupdates")
+            .map(|value| value.to_ascii_lowercase());
+        let enabled = match setting.as_deref() {
+            Some("always") => return true,
+            Some("false") | Some("no") | Some("off") | Some("0") => false,
+            Some(_) => true,
+            None => self.config.get_bool("core", None, "bare") != Some(true),
+        };
+        enabled
+            && (name == "HEAD"
+                || ["refs/heads/", "refs/remotes/", "refs/notes/"]
+                    .iter()
+                    .any(|prefix| name.starts_with(prefix)))
+    }
+
+    // The committer recorded for ref updates. Unlike commits, ref updates go ahead without a
+    // configured identity.
+    fn reflog_identity(&self) -> Ident {
+        self.ident(Role::Committer).unwrap_or_else(|_| {
+            let user = env::var("USER").unwrap_or_else(|_| "unknown".to_owned());
+            Ident::now(&strip_crud(&user), "")
+        })
+    }
+}
+
+// Parses an expiry time for `reflog expire`: "never" keeps everything, "all" or "now" drops
+// everything, and anything else is a date, possibly relative.
+pub fn parse_expiry(value: &str, now: i64) -> Result<i64, GitError> {
+    match value {
+        "never" | "false" => Ok(i64::MIN),
+        "all" | "now" => Ok(i64::MAX),
+        value => approxidate(value, now)
+            .ok_or_else(|| GitError::GenericError(format!("Invalid expiry date: {}", value))),
+    }
+}
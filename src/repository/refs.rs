@@ -0,0 +1,359 @@
+use super::oid::ObjectId;
+use super::GitRepository;
+use crate::error::GitError;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Git gives up following symbolic refs after this many hops.
+const SYMREF_MAXDEPTH: usize = 5;
+
+// What a single ref file contains: either another ref's name or an object id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RefTarget {
+    Symbolic(String),
+    Direct(ObjectId),
+}
+
+impl GitRepository {
+    // Reads a ref without following it, looking at the loose ref file and then packed-refs.
+    pub fn read_ref(&self, name: &str) -> Result<Option<RefTarget>, GitError> {
+        let path = self.repo_path(&path!(name));
+        if path.is_file() {
+            let data = fs::read_to_string(&path)
+                .map_err(|_| GitError::PathError("Could not read ref".to_owned(), path.clone()))?;
+            let data = data.trim();
+            return match data.strip_prefix("ref: ") {
+                Some(target) => Ok(Some(RefTarget::Symbolic(target.trim().to_owned()))),
+                None => ObjectId::from_hex(data)
+                    .map(|oid| Some(RefTarget::Direct(oid)))
+                    .map_err(|_| GitError::GenericError(format!("Invalid ref {}: {}", name, data))),
+            };
+        }
+        Ok(self.packed_refs()?.remove(name).map(RefTarget::Direct))
+    }
+
+    // Follows symbolic refs starting at `name`, returning the name of the last ref in the chain
+    // and the object it points to. The object is None for an unborn branch.
+    pub fn follow_ref(&self, name: &str) -> Result<(String, Option<ObjectId>), GitError> {
+        let mut visited: Vec<String> = Vec::new();
+        let mut name = name.to_owned();
+        loop {
+            if visited.contains(&name) {
+                return Err(GitError::GenericError(format!(
+                    "Symbolic ref loop detected at {}",
+                    name
+                )));
+            }
+            if visited.len() > SYMREF_MAXDEPTH {
+                return Err(GitError::GenericError(format!(
+                    "Too many levels of symbolic refs: {}",
+                    name
+                )));
+            }
+            match self.read_ref(&name)? {
+                Some(RefTarget::Symbolic(target)) => {
+                    visited.push(name);
+                    name = target;
+                }
+                Some(RefTarget::Direct(oid)) => return Ok((name, Some(oid))),
+                None => return Ok((name, None)),
+            }
+        }
+    }
+
+    // Resolves a full ref name such as "HEAD" or "refs/heads/master" to the object it points to,
+    // following symbolic refs. Returns None if the ref does not exist.
+    pub fn resolve_ref(&self, name: &str) -> Result<Option<ObjectId>, GitError> {
+        self.follow_ref(name).map(|(_, oid)| oid)
+    }
+
+    // The ref a symbolic ref points to, or None if `name` is not symbolic (e.g. a detached HEAD).
+    pub fn symbolic_ref(&self, name: &str) -> Result<Option<String>, GitError> {
+        match self.read_ref(name)? {
+            Some(RefTarget::Symbolic(target)) => Ok(Some(target)),
+            _ => Ok(None),
+        }
+    }
+
+    // The short name of the branch HEAD points to, or None if HEAD is detached.
+    pub fn current_branch(&self) -> Option<String> {
+        self.symbolic_ref("HEAD")
+            .ok()
+            .flatten()?
+            .strip_prefix("refs/heads/")
+            .map(str::to_owned)
+    }
+
+    // Points a symbolic ref at another ref. With a message, the move is recorded in the reflog.
+    pub fn set_symbolic_ref(
+        &self,
+        name: &str,
+        target: &str,
+        message: Option<&str>,
+    ) -> Result<(), GitError> {
+        check_ref_name(name)?;
+        if !target.starts_with("refs/") {
+            return Err(GitError::GenericError(format!(
+                "Refusing to point {} outside of refs/: {}",
+                name, target
+            )));
+        }
+        check_ref_name(target)?;
+        let old = match message {
+            Some(_) => self.resolve_ref(name)?,
+            None => None,
+        };
+        self.write_ref_file(name, &format!("ref: {}", target))?;
+        match message {
+            Some(message) => {
+                let new = self.resolve_ref(target)?;
+                self.append_reflog(name, old.as_ref(), new.as_ref(), message)
+            }
+            None => Ok(()),
+        }
+    }
+
+    // Points a ref at a new object. If `old` is given, the ref must currently point to it
+    // (the null id meaning the ref must not exist). With `deref`, symbolic refs are followed
+    // and the ref they end at is updated instead. The update is logged with `message` in the
+    // reflog of the ref, and in HEAD's when HEAD points to it.
+    pub fn update_ref(
+        &self,
+        name: &str,
+        new: &ObjectId,
+        old: Option<&ObjectId>,
+        deref: bool,
+        message: &str,
+    ) -> Result<(), GitError> {
+        let name = match deref {
+            true => self.follow_ref(name)?.0,
+            false => name.to_owned(),
+        };
+        check_ref_name(&name)?;
+        // The old value is compared under the lock, so a concurrent update cannot slip in
+        // between the check and the write.
+        let lock = self.lock_file(&path!(&name))?;
+        check_old_value(&name, self.direct_value(&name)?.as_ref(), old)?;
+        // A symbolic ref being overwritten, like HEAD detaching, is logged as moving from
+        // where it pointed.
+        let previous = self.resolve_ref(&name)?;
+        lock.commit(&format!("{}\n", new.to_hex()))?;
+        self.append_reflog(&name, previous.as_ref(), Some(new), message)?;
+        if name != "HEAD" && self.symbolic_ref("HEAD")?.as_deref() == Some(name.as_str()) {
+            self.append_reflog("HEAD", previous.as_ref(), Some(new), message)?;
+        }
+        Ok(())
+    }
+
+    pub fn delete_ref(
+        &self,
+        name: &str,
+        old: Option<&ObjectId>,
+        deref: bool,
+    ) -> Result<(), GitError> {
+        let name = match deref {
+            true => self.follow_ref(name)?.0,
+            false => name.to_owned(),
+        };
+        let _lock = self.lock_file(&path!(&name))?;
+        let current = self.direct_value(&name)?;
+        if current.is_none() && self.read_ref(&name)?.is_none() {
+            return Err(GitError::GenericError(format!("No such ref: {}", name)));
+        }
+        check_old_value(&name, current.as_ref(), old)?;
+
+        let path = self.repo_path(&path!(&name));
+        if path.is_file() {
+            fs::remove_file(&path)
+                .map_err(|_| GitError::PathError("Could not delete ref".to_owned(), path))?;
+        }
+        let mut packed = self.packed_refs()?;
+        if packed.remove(&name).is_some() {
+            self.write_packed_refs(&packed)?;
+        }
+        self.delete_reflog(&name)
+    }
+
+    // Lists every ref under the given prefix (e.g. "refs/heads/"), with symbolic refs resolved.
+    pub fn list_refs(&self, prefix: &str) -> Result<BTreeMap<String, ObjectId>, GitError> {
+        let mut refs: BTreeMap<String, ObjectId> = self
+            .packed_refs()?
+            .into_iter()
+            .filter(|(name, _)| name.starts_with(prefix))
+            .collect();
+        let mut loose = Vec::new();
+        collect_loose_refs(&self.repo_path(&path!("refs")), "refs", &mut loose);
+        for name in loose {
+            if !name.starts_with(prefix) {
+                continue;
+            }
+            if let Some(oid) = self.resolve_ref(&name)? {
+                refs.insert(name, oid);
+            }
+        }
+        Ok(refs)
+    }
+
+    // Parses the packed-refs file, skipping the "^<oid>" lines that record peeled tags.
+    pub fn packed_refs(&self) -> Result<BTreeMap<String, ObjectId>, GitError> {
+        let mut refs = BTreeMap::new();
+        let data = match fs::read_to_string(self.repo_path(&path!("packed-refs"))) {
+            Ok(data) => data,
+            Err(_) => return Ok(refs),
+        };
+        for line in data.lines() {
+            if line.starts_with('#') || line.starts_with('^') || line.is_empty() {
+                continue;
+            }
+            if let Some((oid, name)) = line.split_once(' ') {
+                refs.insert(name.to_owned(), ObjectId::from_hex(oid)?);
+            }
+        }
+        Ok(refs)
+    }
+
+    fn write_packed_refs(&self, refs: &BTreeMap<String, ObjectId>) -> Result<(), GitError> {
+        let mut data = String::from("# pack-refs with: sorted \n");
+        for (name, oid) in refs {
+            data.push_str(&format!("{} {}\n", oid, name));
+        }
+        self.write_locked(&path!("packed-refs"), &data)
+    }
+
+    fn direct_value(&self, name: &str) -> Result<Option<ObjectId>, GitError> {
+        match self.read_ref(name)? {
+            Some(RefTarget::Direct(oid)) => Ok(Some(oid)),
+            _ => Ok(None),
+        }
+    }
+
+    fn write_ref_file(&self, name: &str, contents: &str) -> Result<(), GitError> {
+        self.write_locked(&path!(name), &format!("{}\n", contents))
+    }
+
+    // Writes a file under the gitdir through "<file>.lock", so readers never see a partial write.
+    pub(crate) fn write_locked(&self, path: &Path, contents: &str) -> Result<(), GitError> {
+        self.lock_file(path)?.commit(contents)
+    }
+
+    // Takes "<file>.lock" for a file under the gitdir. Whoever holds it is the only writer of
+    // the file until the lock is committed or dropped.
+    pub(crate) fn lock_file(&self, path: &Path) -> Result<LockFile, GitError> {
+        let target = self.repo_file(path)?;
+        let mut lock = target.clone().into_os_string();
+        lock.push(".lock");
+        let lock = PathBuf::from(lock);
+        fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock)
+            .map_err(|_| {
+                GitError::PathError("Unable to create lock file".to_owned(), lock.clone())
+            })?;
+        Ok(LockFile {
+            lock,
+            target,
+            committed: false,
+        })
+    }
+}
+
+// A held "<file>.lock". Committing renames it over the file; dropping it releases the lock
+// and leaves the file as it was.
+pub(crate) struct LockFile {
+    lock: PathBuf,
+    target: PathBuf,
+    committed: bool,
+}
+
+impl LockFile {
+    pub(crate) fn commit(mut self, contents: &str) -> Result<(), GitError> {
+        fs::write(&self.lock, contents)
+            .and_then(|_| fs::rename(&self.lock, &self.target))
+            .map_err(|_| {
+                GitError::PathError("Could not write file".to_owned(), self.target.clone())
+            })?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.lock);
+        }
+    }
+}
+
+fn check_old_value(
+    name: &str,
+    current: Option<&ObjectId>,
+    old: Option<&ObjectId>,
+) -> Result<(), GitError> {
+    let old = match old {
+        Some(old) => old,
+        None => return Ok(()),
+    };
+    let matches = match current {
+        Some(current) => current == old,
+        None => old.is_null(),
+    };
+    if !matches {
+        return Err(GitError::GenericError(format!(
+            "Cannot update ref {}: expected {} but found {}",
+            name,
+            old,
+            current
+                .map(|c| c.to_hex())
+                .unwrap_or_else(|| "nothing".to_owned())
+        )));
+    }
+    Ok(())
+}
+
+fn collect_loose_refs(dir: &Path, prefix: &str, refs: &mut Vec<String>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let name = format!("{}/{}", prefix, entry.file_name().to_string_lossy());
+        let path = entry.path();
+        if path.is_dir() {
+            collect_loose_refs(&path, &name, refs);
+        } else if !name.ends_with(".lock") {
+            refs.push(name);
+        }
+    }
+}
+
+// Applies the rules of git-check-ref-format(1).
+pub fn is_valid_ref_name(name: &str) -> bool {
+    if name.is_empty() || name == "@" || name.ends_with('/') || name.ends_with('.') {
+        return false;
+    }
+    if name.contains("..") || name.contains("//") || name.contains("@{") {
+        return false;
+    }
+    if name
+        .chars()
+        .any(|c| c.is_ascii_control() || " ~^:?*[\\".contains(c))
+    {
+        return false;
+    }
+    name.split('/')
+        .all(|component| !component.starts_with('.') && !component.ends_with(".lock"))
+}
+
+pub fn check_ref_name(name: &str) -> Result<(), GitError> {
+    if is_valid_ref_name(name) {
+        Ok(())
+    } else {
+        Err(GitError::GenericError(format!(
+            "'{}' is not a valid ref name",
+            name
+        )))
+    }
+}
@@ -0,0 +1,593 @@
+use super::oid::ObjectId;
+use super::refs::check_ref_name;
+use super::transport::Connection;
+use super::GitRepository;
+use crate::error::GitError;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+// Which refs to copy and where to, e.g. "+refs/heads/*:refs/remotes/origin/*". A leading '+'
+// allows updates that are not fast-forwards; '*' matches the same part of both names.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Refspec {
+    pub force: bool,
+    pub src: String,
+    // Empty when no destination was given.
+    pub dst: String,
+}
+
+impl Refspec {
+    pub fn parse(spec: &str) -> Result<Refspec, GitError> {
+        let (force, rest) = match spec.strip_prefix('+') {
+            Some(rest) => (true, rest),
+            None => (false, spec),
+        };
+        let (src, dst) = rest.split_once(':').unwrap_or((rest, ""));
+        let globs = src.matches('*').count();
+        if globs > 1 || (!dst.is_empty() && dst.matches('*').count() != globs) {
+            return Err(GitError::GenericError(format!(
+                "Invalid refspec '{}'",
+                spec
+            )));
+        }
+        Ok(Refspec {
+            force,
+            src: src.to_owned(),
+            dst: dst.to_owned(),
+        })
+    }
+
+    pub fn is_glob(&self) -> bool {
+        self.src.contains('*')
+    }
+
+    // The destination for a ref matching the source, e.g. refs/heads/main -> refs/remotes/origin/main
+    // for the default fetch refspec. Plain sources also match their short names.
+    pub fn map(&self, name: &str) -> Option<String> {
+        match self.src.split_once('*') {
+            Some((prefix, suffix)) => {
+                if name.len() < prefix.len() + suffix.len()
+                    || !name.starts_with(prefix)
+                    || !name.ends_with(suffix)
+                {
+                    return None;
+                }
+                let middle = &name[prefix.len()..name.len() - suffix.len()];
+                Some(self.dst.replacen('*', middle, 1))
+            }
+            None => {
+                let matches = name == self.src
+                    || (!self.src.starts_with("refs/")
+                        && ["refs/", "refs/tags/", "refs/heads/"]
+                            .iter()
+                            .any(|prefix| name.strip_prefix(prefix) == Some(self.src.as_str())));
+                matches.then(|| self.dst.clone())
+            }
+        }
+    }
+}
+
+impl fmt::Display for Refspec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let force = if self.force { "+" } else { "" };
+        if self.dst.is_empty() {
+            write!(f, "{}{}", force, self.src)
+        } else {
+            write!(f, "{}{}:{}", force, self.src, self.dst)
+        }
+    }
+}
+
+// A `[remote "<name>"]` section: where the repository is and what fetch copies from it.
+#[derive(Debug, Clone)]
+pub struct Remote {
+    pub name: String,
+    pub url: String,
+    pub fetch: Vec<Refspec>,
+}
+
+impl Remote {
+    // The ref that tracks the remote's `name` locally, if the fetch refspecs map it anywhere.
+    pub fn tracking_ref(&self, name: &str) -> Option<String> {
+        self.fetch
+            .iter()
+            .filter_map(|spec| spec.map(name))
+            .find(|dst| !dst.is_empty())
+    }
+}
+
+// How a ref was changed by fetch or push.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UpdateStatus {
+    UpToDate,
+    New,
+    FastForward,
+    Forced,
+    Deleted,
+    Rejected(String),
+    // Refused by the other repository after the objects were sent.
+    RemoteRejected(String),
+}
+
+// One ref fetched or pushed, reported the way git does.
+#[derive(Debug, Clone)]
+pub struct RefUpdate {
+    pub src: String,
+    pub dst: String,
+    pub old: Option<ObjectId>,
+    pub new: Option<ObjectId>,
+    pub status: UpdateStatus,
+}
+
+impl RefUpdate {
+    pub fn is_rejected(&self) -> bool {
+        matches!(
+            self.status,
+            UpdateStatus::Rejected(_) | UpdateStatus::RemoteRejected(_)
+        )
+    }
+
+    // A line like "   1a2b3c4..5d6e7f8  main -> origin/main", with `src` padded to `width`.
+    pub fn summary(&self, width: usize) -> String {
+        let old = self
+            .old
+            .map(|oid| format!("{:.7}", oid))
+            .unwrap_or_default();
+        let new = self
+            .new
+            .map(|oid| format!("{:.7}", oid))
+            .unwrap_or_default();
+        let kind = if self.dst.starts_with("refs/tags/") {
+            "[new tag]"
+        } else if self.dst.starts_with("refs/heads/") || self.dst.starts_with("refs/remotes/") {
+            "[new branch]"
+        } else {
+            "[new ref]"
+        };
+        let (flag, summary, reason) = match &self.status {
+            UpdateStatus::UpToDate => ('=', "[up to date]".to_owned(), String::new()),
+            UpdateStatus::New => ('*', kind.to_owned(), String::new()),
+            UpdateStatus::FastForward => (' ', format!("{}..{}", old, new), String::new()),
+            UpdateStatus::Forced => (
+                '+',
+                format!("{}...{}", old, new),
+                "forced update".to_owned(),
+            ),
+            UpdateStatus::Deleted => ('-', "[deleted]".to_owned(), String::new()),
+            UpdateStatus::Rejected(reason) => ('!', "[rejected]".to_owned(), reason.clone()),
+            UpdateStatus::RemoteRejected(reason) => {
+                ('!', "[remote rejected]".to_owned(), reason.clone())
+            }
+        };
+        let src = short_ref_name(&self.src);
+        let mut line = if self.dst.is_empty() {
+            format!(" {} {:<17} {}", flag, summary, src)
+        } else if self.status == UpdateStatus::Deleted {
+            format!(" {} {:<17} {}", flag, summary, short_ref_name(&self.dst))
+        } else {
+            format!(
+                " {} {:<17} {:<width$} -> {}",
+                flag,
+                summary,
+                src,
+                short_ref_name(&self.dst),
+                width = width
+            )
+        };
+        if !reason.is_empty() {
+            line.push_str(&format!(" ({})", reason));
+        }
+        line
+    }
+}
+
+// "main" for refs/heads/main, "origin/main" for refs/remotes/origin/main, and so on.
+pub fn short_ref_name(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
+
+impl GitRepository {
+    // Looks up a configured remote. A path or URL of a repository works as an unnamed remote
+    // without fetch refspecs, as in `fetch ../other`.
+    pub fn remote(&self, name: &str) -> Result<Remote, GitError> {
+        if let Some(url) = self.config.get("remote", Some(name), "url") {
+            let fetch = self
+                .config
+                .get_all("remote", Some(name), "fetch")
+                .iter()
+                .map(|entry| Refspec::parse(entry.value.as_deref().unwrap_or_default()))
+                .collect::<Result<_, _>>()?;
+            return Ok(Remote {
+                name: name.to_owned(),
+                url,
+                fetch,
+            });
+        }
+        if Path::new(name).is_dir() || name.contains("://") {
+            return Ok(Remote {
+                name: name.to_owned(),
+                url: name.to_owned(),
+                fetch: Vec::new(),
+            });
+        }
+        Err(GitError::GenericError(format!(
+            "'{}' does not appear to be a git repository",
+            name
+        )))
+    }
+
+    // Adds `[remote "<name>"]` with the url and the default refspec, which fetches every branch
+    // to refs/remotes/<name>/.
+    pub fn add_remote(&mut self, name: &str, url: &str) -> Result<Remote, GitError> {
+        if self.config.get("remote", Some(name), "url").is_some() {
+            return Err(GitError::GenericError(format!(
+                "remote {} already exists.",
+                name
+            )));
+        }
+        check_ref_name(&format!("refs/remotes/{}/HEAD", name))?;
+        let fetch = Refspec::parse(&format!("+refs/heads/*:refs/remotes/{}/*", name))?;
+        self.config.set("remote", Some(name), "url", url)?;
+        self.config
+            .set("remote", Some(name), "fetch", &fetch.to_string())?;
+        Ok(Remote {
+            name: name.to_owned(),
+            url: url.to_owned(),
+            fetch: vec![fetch],
+        })
+    }
+
+    // The remote to use when none is named: the current branch's, or "origin".
+    pub fn default_remote(&self) -> String {
+        self.current_branch()
+            .and_then(|branch| self.config.get("branch", Some(&branch), "remote"))
+            .unwrap_or_else(|| "origin".to_owned())
+    }
+
+    // Copies the refs matched by `refspecs`, or by the remote's configured ones, together with
+    // the objects they need. Existing refs are only moved forward unless the refspec or `force`
+    // allows it; every fetched ref is also recorded in FETCH_HEAD.
+    pub fn fetch(
+        &self,
+        remote: &Remote,
+        refspecs: &[Refspec],
+        force: bool,
+    ) -> Result<Vec<RefUpdate>, GitError> {
+        let connection = Connection::open(&remote.url)?;
+        let default = [Refspec::parse("HEAD")?];
+        let specs = match (refspecs.is_empty(), remote.fetch.is_empty()) {
+            (false, _) => refspecs,
+            (true, false) => &remote.fetch[..],
+            (true, true) => &default[..],
+        };
+
+        let remote_refs = connection.list_refs()?;
+        let advertised: BTreeMap<String, ObjectId> = remote_refs
+            .iter()
+            .filter_map(|r| Some((r.name.clone(), r.oid?)))
+            .collect();
+        // With the configured refspecs, the branch the current one merges from is the one marked
+        // for merging in FETCH_HEAD.
+        let upstream = match self.current_branch() {
+            Some(branch)
+                if refspecs.is_empty()
+                    && self.config.get("branch", Some(&branch), "remote").as_ref()
+                        == Some(&remote.name) =>
+            {
+                self.config.get("branch", Some(&branch), "merge")
+            }
+            _ => None,
+        };
+        let mut fetched: Vec<(String, ObjectId, String, bool, bool)> = Vec::new();
+        for spec in specs {
+            let mut matched = false;
+            for (name, oid) in &advertised {
+                if let Some(dst) = spec.map(name) {
+                    // Short names go to the first ref they match, as elsewhere.
+                    if !spec.is_glob() && matched {
+                        break;
+                    }
+                    matched = true;
+                    let dst = expand_destination(&dst, name);
+                    let merge = !spec.is_glob() || Some(name) == upstream.as_ref();
+                    fetched.push((name.clone(), *oid, dst, spec.force, merge));
+                }
+            }
+            if !spec.is_glob() && !matched {
+                return Err(GitError::GenericError(format!(
+                    "couldn't find remote ref {}",
+                    spec.src
+                )));
+            }
+        }
+
+        let wants: Vec<ObjectId> = fetched.iter().map(|(_, oid, ..)| *oid).collect();
+        let haves: Vec<ObjectId> = self.list_refs("refs/")?.into_values().collect();
+        connection.fetch(self, &wants, &haves)?;
+
+        // Tags pointing into the history just fetched come along, as git's tag following does.
+        if refspecs.is_empty() && !remote.fetch.is_empty() {
+            let mut tags = Vec::new();
+            for remote_ref in &remote_refs {
+                let (name, oid) = match (&remote_ref.name, remote_ref.oid) {
+                    (name, Some(oid)) if name.starts_with("refs/tags/") => (name, oid),
+                    _ => continue,
+                };
+                if fetched.iter().any(|(fetched, ..)| fetched == name)
+                    || self.resolve_ref(name)?.is_some()
+                {
+                    continue;
+                }
+                if self.has_object(&remote_ref.peeled.unwrap_or(oid)) {
+                    tags.push((name.clone(), oid, name.clone(), false, false));
+                }
+            }
+            // What was just fetched is had now too.
+            let haves: Vec<ObjectId> = haves.into_iter().chain(wants).collect();
+            let wants: Vec<ObjectId> = tags.iter().map(|(_, oid, ..)| *oid).collect();
+            connection.fetch(self, &wants, &haves)?;
+            fetched.extend(tags);
+        }
+
+        let current = self.symbolic_ref("HEAD")?;
+        let mut updates = Vec::new();
+        let mut fetch_head = Vec::new();
+        for (name, oid, dst, forced, merge) in fetched {
+            let merge = if merge { "" } else { "not-for-merge" };
+            let kind = match name.strip_prefix("refs/heads/") {
+                Some(branch) => format!("branch '{}' of ", branch),
+                None => match name.strip_prefix("refs/tags/") {
+                    Some(tag) => format!("tag '{}' of ", tag),
+                    None if name == "HEAD" => String::new(),
+                    None => format!("'{}' of ", name),
+                },
+            };
+            fetch_head.push((
+                merge,
+                format!("{}\t{}\t{}{}\n", oid, merge, kind, remote.url),
+            ));
+            if dst.is_empty() {
+                continue;
+            }
+            let old = self.resolve_ref(&dst)?;
+            let status = if old == Some(oid) {
+                UpdateStatus::UpToDate
+            } else if current.as_deref() == Some(dst.as_str()) && !self.is_bare() {
+                UpdateStatus::Rejected("refusing to fetch into current branch".to_owned())
+            } else {
+                self.update_status(old.as_ref(), &oid, &dst, force || forced)
+            };
+            if matches!(
+                status,
+                UpdateStatus::New | UpdateStatus::FastForward | UpdateStatus::Forced
+            ) {
+                let reason = match status {
+                    UpdateStatus::New if dst.starts_with("refs/tags/") => "storing tag",
+                    UpdateStatus::New => "storing head",
+                    UpdateStatus::FastForward => "fast-forward",
+                    _ => "forced-update",
+                };
+                let message = format!("fetch {}: {}", remote.name, reason);
+                self.update_ref(&dst, &oid, Some(&old.unwrap_or_default()), false, &message)?;
+            }
+            updates.push(RefUpdate {
+                src: name,
+                dst,
+                old,
+                new: Some(oid),
+                status,
+            });
+        }
+        // Refs to merge come first, as `pull` merges from the top of the file.
+        fetch_head.sort_by_key(|(merge, _)| !merge.is_empty());
+        let fetch_head: String = fetch_head.into_iter().map(|(_, line)| line).collect();
+        fs::write(self.repo_path(&path!("FETCH_HEAD")), fetch_head).map_err(|_| {
+            GitError::PathError(
+                "Could not write FETCH_HEAD".to_owned(),
+                self.repo_path(&path!("FETCH_HEAD")),
+            )
+        })?;
+        Ok(updates)
+    }
+
+    // Updates the remote's refs as `refspecs` say, after sending the objects they need. Only
+    // fast-forwards are allowed unless forced, and the remote may still refuse an update, such
+    // as one to its checked-out branch. Refs that were pushed are also updated in refs/remotes/.
+    pub fn push(
+        &self,
+        remote: &Remote,
+        refspecs: &[Refspec],
+        force: bool,
+    ) -> Result<Vec<RefUpdate>, GitError> {
+        let connection = Connection::open(&remote.url)?;
+        let remote_refs: BTreeMap<String, ObjectId> = connection
+            .push_refs()?
+            .into_iter()
+            .filter_map(|r| Some((r.name, r.oid?)))
+            .collect();
+
+        let mut pushed: Vec<(String, Option<ObjectId>, String, bool)> = Vec::new();
+        for spec in refspecs {
+            if spec.is_glob() {
+                for (name, oid) in self.list_refs("refs/")? {
+                    if let Some(dst) = spec.map(&name) {
+                        pushed.push((name, Some(oid), dst, spec.force));
+                    }
+                }
+            } else if spec.src.is_empty() {
+                let dst = expand_destination(&spec.dst, "refs/heads/");
+                pushed.push((String::new(), None, dst, spec.force));
+            } else {
+                let (name, oid) = self.dwim_ref(&spec.src)?.ok_or_else(|| {
+                    GitError::GenericError(format!("src refspec {} does not match any", spec.src))
+                })?;
+                let dst = match spec.dst.as_str() {
+                    "" if name == "HEAD" => self.symbolic_ref("HEAD")?.ok_or_else(|| {
+                        GitError::GenericError("HEAD is detached; name the destination".to_owned())
+                    })?,
+                    "" => name.clone(),
+                    dst => expand_destination(dst, &name),
+                };
+                pushed.push((name, Some(oid), dst, spec.force));
+            }
+        }
+
+        let mut updates = Vec::new();
+        for (src, new, dst, forced) in pushed {
+            check_ref_name(&dst)?;
+            let old = remote_refs.get(&dst).copied();
+            let status = match new {
+                None if old.is_none() => {
+                    UpdateStatus::Rejected("remote ref does not exist".to_owned())
+                }
+                None => UpdateStatus::Deleted,
+                Some(new) if old == Some(new) => UpdateStatus::UpToDate,
+                Some(new) => match old {
+                    Some(old) if !(self.has_object(&old) || force || forced) => {
+                        UpdateStatus::Rejected("fetch first".to_owned())
+                    }
+                    _ => self.update_status(old.as_ref(), &new, &dst, force || forced),
+                },
+            };
+            updates.push(RefUpdate {
+                src,
+                dst,
+                old,
+                new,
+                status,
+            });
+        }
+
+        let sent: Vec<usize> = (0..updates.len())
+            .filter(|&i| {
+                matches!(
+                    updates[i].status,
+                    UpdateStatus::New
+                        | UpdateStatus::FastForward
+                        | UpdateStatus::Forced
+                        | UpdateStatus::Deleted
+                )
+            })
+            .collect();
+        if sent.is_empty() {
+            return Ok(updates);
+        }
+        let commands: Vec<(ObjectId, ObjectId, String)> = sent
+            .iter()
+            .map(|&i| {
+                let update = &updates[i];
+                let old = update.old.unwrap_or_default();
+                (old, update.new.unwrap_or_default(), update.dst.clone())
+            })
+            .collect();
+        let results = connection.push(self, &commands)?;
+
+        for (i, result) in sent.into_iter().zip(results) {
+            let update = &mut updates[i];
+            if let Some(reason) = result {
+                update.status = UpdateStatus::RemoteRejected(reason);
+                continue;
+            }
+            if let Some(tracking) = remote.tracking_ref(&update.dst) {
+                match update.new {
+                    Some(new) => self.update_ref(&tracking, &new, None, false, "update by push")?,
+                    None if self.resolve_ref(&tracking)?.is_some() => {
+                        self.delete_ref(&tracking, None, false)?
+                    }
+                    None => {}
+                }
+            }
+        }
+        Ok(updates)
+    }
+
+    // Sets up a new repository as a clone of `url`: adds it as "origin", fetches every branch
+    // and creates a local branch for the one the remote's HEAD is on, tracking it. Returns that
+    // branch and its commit, which is None for an empty remote.
+    pub fn clone_remote(&mut self, url: &str) -> Result<(String, Option<ObjectId>), GitError> {
+        let remote = self.add_remote("origin", url)?;
+        let connection = Connection::open(url)?;
+        let head = connection
+            .list_refs()?
+            .into_iter()
+            .find(|r| r.name == "HEAD")
+            .and_then(|r| r.symref)
+            .unwrap_or_else(|| "refs/heads/master".to_owned());
+        self.fetch(&remote, &[], false)?;
+
+        let branch = head.strip_prefix("refs/heads/").unwrap_or(&head).to_owned();
+        self.set_symbolic_ref("HEAD", &format!("refs/heads/{}", branch), None)?;
+
+        let tracking = match remote.tracking_ref(&head) {
+            Some(tracking) => tracking,
+            None => return Ok((branch, None)),
+        };
+        let oid = match self.resolve_ref(&tracking)? {
+            Some(oid) => oid,
+            None => return Ok((branch, None)),
+        };
+        self.set_symbolic_ref(
+            &format!("refs/remotes/{}/HEAD", remote.name),
+            &tracking,
+            None,
+        )?;
+        let message = format!("clone: from {}", url);
+        self.update_ref(
+            &format!("refs/heads/{}", branch),
+            &oid,
+            None,
+            false,
+            &message,
+        )?;
+        self.config
+            .set("branch", Some(&branch), "remote", &remote.name)?;
+        self.config.set("branch", Some(&branch), "merge", &head)?;
+        Ok((branch, Some(oid)))
+    }
+
+    // Whether moving `name` from `old` to `new` is allowed: new refs always are, existing tags
+    // and non-fast-forwards only when forced.
+    fn update_status(
+        &self,
+        old: Option<&ObjectId>,
+        new: &ObjectId,
+        name: &str,
+        force: bool,
+    ) -> UpdateStatus {
+        let old = match old {
+            Some(old) => old,
+            None => return UpdateStatus::New,
+        };
+        if name.starts_with("refs/tags/") {
+            return match force {
+                true => UpdateStatus::Forced,
+                false => UpdateStatus::Rejected("already exists".to_owned()),
+            };
+        }
+        // Anything that is not a commit cannot be fast-forwarded.
+        if self.is_ancestor(old, new).unwrap_or(false) {
+            UpdateStatus::FastForward
+        } else if force {
+            UpdateStatus::Forced
+        } else {
+            UpdateStatus::Rejected("non-fast-forward".to_owned())
+        }
+    }
+}
+
+// Completes a destination written without "refs/": it goes next to the source ref, so a tag
+// stays a tag and anything else becomes a branch.
+fn expand_destination(dst: &str, src: &str) -> String {
+    if dst.is_empty() || dst.starts_with("refs/") {
+        dst.to_owned()
+    } else if src.starts_with("refs/tags/") {
+        format!("refs/tags/{}", dst)
+    } else {
+        format!("refs/heads/{}", dst)
+    }
+}
@@ -0,0 +1,265 @@
+use super::commit::Commit;
+use super::delta::create_delta;
+use super::object::{GitObject, ObjType};
+use super::oid::ObjectId;
+use super::pack::{encode_index, encode_pack, PackEntry, PackedObject};
+use super::tag::Tag;
+use super::tree::Tree;
+use super::GitRepository;
+use crate::error::GitError;
+use std::collections::{HashSet, VecDeque};
+use std::fs;
+use std::path::PathBuf;
+
+// How many preceding objects each object is tried as a delta against.
+const DELTA_WINDOW: usize = 10;
+// The longest chain of deltas an object may sit at the end of.
+const DELTA_DEPTH: usize = 50;
+// Objects smaller than this are stored whole; a delta would hardly save anything.
+const MIN_DELTA_SIZE: usize = 64;
+
+// What a repack did, for reporting.
+#[derive(Debug, Default)]
+pub struct RepackSummary {
+    pub pack: Option<PathBuf>,
+    pub objects: usize,
+    pub deltas: usize,
+    pub removed_packs: usize,
+    pub removed_loose: usize,
+}
+
+impl GitRepository {
+    // Lists every object reachable from `roots` but not from `exclude`, each with the path it
+    // was first found at, which packing uses to find good delta bases.
+    pub fn list_objects(
+        &self,
+        roots: &[ObjectId],
+        exclude: &[ObjectId],
+    ) -> Result<Vec<(ObjectId, String)>, GitError> {
+        let mut seen = HashSet::new();
+        if !exclude.is_empty() {
+            for (oid, _) in self.list_objects(exclude, &[])? {
+                seen.insert(oid);
+            }
+        }
+        let mut objects = Vec::new();
+        let mut pending: Vec<(ObjectId, String)> = roots
+            .iter()
+            .rev()
+            .map(|oid| (*oid, String::new()))
+            .collect();
+        while let Some((oid, name)) = pending.pop() {
+            if !seen.insert(oid) {
+                continue;
+            }
+            let object = self.read_object(&oid)?;
+            match object.obj_type {
+                ObjType::Commit => {
+                    let commit = object.parse::<Commit>()?;
+                    pending.extend(commit.parents.iter().map(|p| (*p, String::new())));
+                    pending.push((commit.tree, String::new()));
+                }
+                ObjType::Tree => {
+                    for entry in object.parse::<Tree>()?.entries.into_iter().rev() {
+                        // Submodules point at commits of another repository.
+                        if entry.mode == 0o160000 {
+                            continue;
+                        }
+                        let path = if name.is_empty() {
+                            entry.name
+                        } else {
+                            format!("{}/{}", name, entry.name)
+                        };
+                        pending.push((entry.oid, path));
+                    }
+                }
+                ObjType::Tag => pending.push((object.parse::<Tag>()?.object, String::new())),
+                ObjType::Blob => {}
+            }
+            objects.push((oid, name));
+        }
+        Ok(objects)
+    }
+
+    // The objects that must be kept: everything reachable from refs, HEAD and the index.
+    pub fn reachable_objects(&self) -> Result<Vec<(ObjectId, String)>, GitError> {
+        let mut roots: Vec<ObjectId> = self.list_refs("refs/")?.into_values().collect();
+        roots.extend(self.resolve_ref("HEAD")?);
+        roots.extend(
+            self.read_index()?
+                .iter()
+                .filter(|entry| entry.mode & 0o170000 != 0o160000)
+                .map(|entry| entry.oid),
+        );
+        self.list_objects(&roots, &[])
+    }
+
+    // Encodes the objects as a pack, storing each object that is similar enough to one of the
+    // DELTA_WINDOW objects before it as a delta. Objects are sorted by type, then by a hash of
+    // their file name, then by size, so versions of the same file end up next to each other.
+    pub fn build_pack(
+        &self,
+        objects: &[(ObjectId, String)],
+    ) -> Result<(Vec<u8>, Vec<PackedObject>, usize), GitError> {
+        let mut loaded: Vec<(ObjectId, GitObject, u32)> = Vec::with_capacity(objects.len());
+        for (oid, name) in objects {
+            loaded.push((*oid, self.read_object(oid)?, name_hash(name)));
+        }
+        loaded.sort_by(|(_, a, a_hash), (_, b, b_hash)| {
+            type_rank(a.obj_type)
+                .cmp(&type_rank(b.obj_type))
+                .then(a_hash.cmp(b_hash))
+                .then(b.data.len().cmp(&a.data.len()))
+        });
+
+        let mut entries: Vec<PackEntry> = Vec::with_capacity(loaded.len());
+        let mut depths: Vec<usize> = Vec::with_capacity(loaded.len());
+        let mut window: VecDeque<usize> = VecDeque::with_capacity(DELTA_WINDOW);
+        let mut deltas = 0;
+        for (oid, object, _) in loaded {
+            let mut best: Option<(usize, Vec<u8>)> = None;
+            if object.data.len() >= MIN_DELTA_SIZE {
+                for &base in &window {
+                    let candidate = &entries[base];
+                    if candidate.obj_type != object.obj_type
+                        || depths[base] >= DELTA_DEPTH
+                        || candidate.data.len() < object.data.len() / 32
+                    {
+                        continue;
+                    }
+                    let delta = create_delta(&candidate.data, &object.data);
+                    let limit = best
+                        .as_ref()
+                        .map_or(object.data.len() / 2, |(_, best)| best.len());
+                    if delta.len() < limit {
+                        best = Some((base, delta));
+                    }
+                }
+            }
+            depths.push(best.as_ref().map_or(0, |(base, _)| depths[*base] + 1));
+            deltas += usize::from(best.is_some());
+            if window.len() == DELTA_WINDOW {
+                window.pop_front();
+            }
+            window.push_back(entries.len());
+            entries.push(PackEntry {
+                oid,
+                obj_type: object.obj_type,
+                data: object.data,
+                delta: best,
+            });
+        }
+        let (pack, packed) = encode_pack(&entries)?;
+        Ok((pack, packed, deltas))
+    }
+
+    // Packs the reachable objects that are still loose, or with `all` every reachable object
+    // into a single new pack. With `delete`, packs made redundant by `all` and loose objects
+    // that are now packed are removed.
+    pub fn repack(&self, all: bool, delete: bool) -> Result<RepackSummary, GitError> {
+        let mut objects = self.reachable_objects()?;
+        if !all {
+            objects.retain(|(oid, _)| self.is_loose_object(oid));
+        }
+        let old_packs: Vec<PathBuf> = self.packs()?.iter().map(|p| p.path.clone()).collect();
+
+        let mut summary = RepackSummary::default();
+        if !objects.is_empty() {
+            let (pack, packed, deltas) = self.build_pack(&objects)?;
+            let checksum = ObjectId::from_bytes(&pack[pack.len() - ObjectId::LEN..])?;
+            let index = encode_index(&packed, &checksum);
+            summary.pack = Some(self.install_pack(&pack, &index)?);
+            summary.objects = packed.len();
+            summary.deltas = deltas;
+        }
+
+        if delete {
+            if all {
+                for path in old_packs {
+                    if Some(&path) == summary.pack.as_ref() {
+                        continue;
+                    }
+                    for extension in ["idx", "pack"] {
+                        let file = path.with_extension(extension);
+                        fs::remove_file(&file).map_err(|_| {
+                            GitError::PathError("Could not remove pack".to_owned(), file)
+                        })?;
+                    }
+                    summary.removed_packs += 1;
+                }
+                self.reload_packs();
+            }
+            summary.removed_loose = self.prune_packed()?;
+        }
+        Ok(summary)
+    }
+
+    // Removes loose objects that are also stored in a pack, returning how many were removed.
+    pub fn prune_packed(&self) -> Result<usize, GitError> {
+        let packs = self.packs()?;
+        let mut removed = 0;
+        for oid in self.loose_object_ids()? {
+            if packs.iter().any(|pack| pack.index.find(&oid).is_some()) {
+                let path = self.repo_path(&GitRepository::object_path(&oid));
+                fs::remove_file(&path)
+                    .map_err(|_| GitError::PathError("Could not remove object".to_owned(), path))?;
+                removed += 1;
+            }
+        }
+        // Remove the fan-out directories that are now empty.
+        if let Ok(entries) = fs::read_dir(self.repo_path(&path!("objects"))) {
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if name.len() == 2 && name.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    let _ = fs::remove_dir(entry.path());
+                }
+            }
+        }
+        Ok(removed)
+    }
+
+    pub fn is_loose_object(&self, oid: &ObjectId) -> bool {
+        self.repo_path(&GitRepository::object_path(oid)).is_file()
+    }
+
+    pub fn loose_object_ids(&self) -> Result<Vec<ObjectId>, GitError> {
+        let mut oids = Vec::new();
+        let entries = match fs::read_dir(self.repo_path(&path!("objects"))) {
+            Ok(entries) => entries,
+            Err(_) => return Ok(oids),
+        };
+        for dir in entries.flatten() {
+            let prefix = dir.file_name().to_string_lossy().to_string();
+            if prefix.len() != 2 {
+                continue;
+            }
+            for file in fs::read_dir(dir.path()).into_iter().flatten().flatten() {
+                let rest = file.file_name().to_string_lossy().to_string();
+                if let Ok(oid) = ObjectId::from_hex(&format!("{}{}", prefix, rest)) {
+                    oids.push(oid);
+                }
+            }
+        }
+        oids.sort();
+        Ok(oids)
+    }
+}
+
+fn type_rank(obj_type: ObjType) -> u8 {
+    match obj_type {
+        ObjType::Commit => 0,
+        ObjType::Tree => 1,
+        ObjType::Blob => 2,
+        ObjType::Tag => 3,
+    }
+}
+
+// Git's pack_name_hash: the last sixteen non-space characters of the path, weighted towards
+// the end, so files with the same name or extension sort together.
+fn name_hash(name: &str) -> u32 {
+    let mut hash = 0u32;
+    for c in name.bytes().filter(|c| !c.is_ascii_whitespace()) {
+        hash = (hash >> 2).wrapping_add((c as u32) << 24);
+    }
+    hash
+}
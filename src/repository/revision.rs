@@ -0,0 +1,346 @@
+use super::commit::Commit;
+use super::ident::approxidate;
+use super::object::ObjType;
+use super::oid::ObjectId;
+use super::tag::Tag;
+use super::tree::{Tree, TreeEntry};
+use super::GitRepository;
+use crate::error::GitError;
+use chrono::Utc;
+use std::fs;
+
+// The places a short ref name is looked for, in order, as described in gitrevisions(7).
+const REF_RULES: [&str; 6] = [
+    "{}",
+    "refs/{}",
+    "refs/tags/{}",
+    "refs/heads/{}",
+    "refs/remotes/{}",
+    "refs/remotes/{}/HEAD",
+];
+
+// Abbreviated object names shorter than this are never treated as hex.
+const MIN_ABBREV: usize = 4;
+
+impl GitRepository {
+    // Resolves a revision and peels it to an object of the requested type.
+    pub fn find_object(&self, name: &str, format: &ObjType) -> Result<ObjectId, GitError> {
+        let oid = self.rev_parse(name)?;
+        self.peel(&oid, Some(*format))
+    }
+
+    // Resolves git revision syntax (see gitrevisions(7)) to an object id.
+    pub fn rev_parse(&self, rev: &str) -> Result<ObjectId, GitError> {
+        if let Some(colon) = find_outside_braces(rev, ':') {
+            return self.rev_parse_path(&rev[..colon], &rev[colon + 1..]);
+        }
+
+        let base_end = rev.find(['~', '^']).unwrap_or(rev.len());
+        let mut oid = self.resolve_base(rev, &rev[..base_end])?;
+
+        let mut rest = &rev[base_end..];
+        while !rest.is_empty() {
+            let op = rest.as_bytes()[0];
+            rest = &rest[1..];
+            if op == b'^' && rest.starts_with('{') {
+                let close = rest.find('}').ok_or_else(|| bad_revision(rev))?;
+                oid = self.peel_spec(rev, &oid, &rest[1..close])?;
+                rest = &rest[close + 1..];
+                continue;
+            }
+            let digits = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            let n: usize = if digits == 0 {
+                1
+            } else {
+                rest[..digits].parse().map_err(|_| bad_revision(rev))?
+            };
+            rest = &rest[digits..];
+            if op == b'^' {
+                oid = self.nth_parent(rev, &oid, n)?;
+            } else {
+                for _ in 0..n {
+                    oid = self.nth_parent(rev, &oid, 1)?;
+                }
+            }
+        }
+        Ok(oid)
+    }
+
+    // Follows tags, and commits to their trees, until reaching an object of the given type.
+    // With no type, only tags are peeled.
+    pub fn peel(&self, oid: &ObjectId, target: Option<ObjType>) -> Result<ObjectId, GitError> {
+        let mut oid = *oid;
+        loop {
+            let object = self.read_object(&oid)?;
+            if Some(object.obj_type) == target {
+                return Ok(oid);
+            }
+            oid = match (object.obj_type, target) {
+                (ObjType::Tag, _) => object.parse::<Tag>()?.object,
+                (_, None) => return Ok(oid),
+                (ObjType::Commit, Some(ObjType::Tree)) => object.parse::<Commit>()?.tree,
+                (found, Some(target)) => {
+                    return Err(GitError::ObjectError(format!(
+                        "{} is a {}, not a {}",
+                        oid,
+                        found.as_str(),
+                        target.as_str()
+                    )))
+                }
+            };
+        }
+    }
+
+    // Expands a ref name the way git does, returning the full name of the first ref that exists.
+    pub fn dwim_ref(&self, name: &str) -> Result<Option<(String, ObjectId)>, GitError> {
+        if name.is_empty() || name.contains("..") || name.starts_with('/') {
+            return Ok(None);
+        }
+        for rule in REF_RULES {
+            let full_name = rule.replace("{}", name);
+            // Only pseudo refs like HEAD or ORIG_HEAD live directly under the git directory.
+            if rule == "{}" && !is_pseudo_ref(&full_name) && !full_name.starts_with("refs/") {
+                continue;
+            }
+            if let Some(oid) = self.resolve_ref(&full_name)? {
+                return Ok(Some((full_name, oid)));
+            }
+        }
+        Ok(None)
+    }
+
+    // Lists the objects whose hex name starts with the given prefix.
+    pub fn find_objects_by_prefix(&self, prefix: &str) -> Result<Vec<ObjectId>, GitError> {
+        let prefix = prefix.to_ascii_lowercase();
+        let mut found = Vec::new();
+        let dir = self.repo_path(&path!("objects", &prefix[..2]));
+        if let Ok(entries) = fs::read_dir(&dir) {
+            for entry in entries.flatten() {
+                let rest = entry.file_name().to_string_lossy().to_string();
+                if rest.starts_with(&prefix[2..]) {
+                    if let Ok(oid) = ObjectId::from_hex(&format!("{}{}", &prefix[..2], rest)) {
+                        found.push(oid);
+                    }
+                }
+            }
+        }
+        for pack in self.packs()?.iter() {
+            found.extend(
+                pack.index
+                    .oids()
+                    .filter(|oid| oid.to_hex().starts_with(&prefix)),
+            );
+        }
+        found.sort();
+        found.dedup();
+        Ok(found)
+    }
+
+    // Looks up a slash separated path in a tree, returning its entry.
+    pub fn lookup_path(&self, tree: &ObjectId, path: &str) -> Result<Option<TreeEntry>, GitError> {
+        let mut tree = *tree;
+        let mut found = None;
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            if let Some(TreeEntry { mode, .. }) = &found {
+                if *mode != 0o40000 {
+                    return Ok(None);
+                }
+            }
+            let entries = self.read_object(&tree)?.parse::<Tree>()?;
+            match entries.get(component) {
+                Some(entry) => {
+                    tree = entry.oid;
+                    found = Some(entry.clone());
+                }
+                None => return Ok(None),
+            }
+        }
+        Ok(found)
+    }
+
+    // Resolves the part of a revision before any ~ or ^ operators.
+    fn resolve_base(&self, rev: &str, base: &str) -> Result<ObjectId, GitError> {
+        if let Some(at) = base.find("@{") {
+            if !base.ends_with('}') {
+                return Err(bad_revision(rev));
+            }
+            let spec = &base[at + 2..base.len() - 1];
+            return self.resolve_at_suffix(rev, &base[..at], spec);
+        }
+
+        let base = if base == "@" { "HEAD" } else { base };
+        if base.len() == ObjectId::HEX_LEN {
+            if let Ok(oid) = ObjectId::from_hex(base) {
+                return Ok(oid);
+            }
+        }
+        if let Some((_, oid)) = self.dwim_ref(base)? {
+            return Ok(oid);
+        }
+        if base.len() >= MIN_ABBREV && base.chars().all(|c| c.is_ascii_hexdigit()) {
+            let candidates = self.find_objects_by_prefix(base)?;
+            match candidates.len() {
+                0 => {}
+                1 => return Ok(candidates[0]),
+                _ => {
+                    return Err(GitError::ObjectError(format!(
+                        "short object ID {} is ambiguous",
+                        base
+                    )))
+                }
+            }
+        }
+        Err(bad_revision(rev))
+    }
+
+    // Handles `<branch>@{upstream}` and `<branch>@{push}`, and otherwise looks the ref up in its
+    // reflog: `<ref>@{n}` is its value n updates ago, `<ref>@{date}` its value at that date.
+    fn resolve_at_suffix(&self, rev: &str, name: &str, spec: &str) -> Result<ObjectId, GitError> {
+        let spec = spec.to_ascii_lowercase();
+        if spec != "upstream" && spec != "u" && spec != "push" {
+            return self.resolve_reflog_suffix(rev, name, &spec);
+        }
+        let branch = match name {
+            "" | "@" | "HEAD" => self.current_branch().ok_or_else(|| {
+                GitError::GenericError("HEAD does not point to a branch".to_owned())
+            })?,
+            name => name.strip_prefix("refs/heads/").unwrap_or(name).to_owned(),
+        };
+        let no_upstream =
+            || GitError::GenericError(format!("no upstream configured for branch '{}'", branch));
+        let remote = if spec == "push" {
+            self.config
+                .get("remote", None, "pushdefault")
+                .or_else(|| self.config.get("branch", Some(&branch), "pushremote"))
+                .or_else(|| self.config.get("branch", Some(&branch), "remote"))
+                .ok_or_else(no_upstream)?
+        } else {
+            self.config
+                .get("branch", Some(&branch), "remote")
+                .ok_or_else(no_upstream)?
+        };
+        let merge = if spec == "push" {
+            format!("refs/heads/{}", branch)
+        } else {
+            self.config
+                .get("branch", Some(&branch), "merge")
+                .ok_or_else(no_upstream)?
+        };
+        let merge = merge.strip_prefix("refs/heads/").unwrap_or(&merge);
+        // A remote of "." means the upstream is a local branch.
+        let tracking = if remote == "." {
+            format!("refs/heads/{}", merge)
+        } else {
+            format!("refs/remotes/{}/{}", remote, merge)
+        };
+        self.resolve_ref(&tracking)?.ok_or_else(no_upstream)
+    }
+
+    fn resolve_reflog_suffix(
+        &self,
+        rev: &str,
+        name: &str,
+        spec: &str,
+    ) -> Result<ObjectId, GitError> {
+        // A bare `@{n}` is about the current branch, or HEAD when it is detached.
+        let full_name = match name {
+            "" => self
+                .symbolic_ref("HEAD")?
+                .unwrap_or_else(|| "HEAD".to_owned()),
+            "@" => "HEAD".to_owned(),
+            name => match self.dwim_ref(name)? {
+                Some((full_name, _)) => full_name,
+                None => return Err(bad_revision(rev)),
+            },
+        };
+        // As in git, numbers this large are timestamps rather than counts.
+        match spec.parse::<usize>() {
+            Ok(n) if n < 100000000 => return self.reflog_nth(&full_name, n),
+            _ => {}
+        }
+        match approxidate(spec, Utc::now().timestamp()) {
+            Some(time) => self.reflog_at(&full_name, time),
+            None => Err(GitError::GenericError(format!(
+                "Unsupported revision suffix in {}",
+                rev
+            ))),
+        }
+    }
+
+    fn peel_spec(&self, rev: &str, oid: &ObjectId, spec: &str) -> Result<ObjectId, GitError> {
+        match spec {
+            "" => self.peel(oid, None),
+            "object" => Ok(*oid),
+            "commit" => self.peel(oid, Some(ObjType::Commit)),
+            "tree" => self.peel(oid, Some(ObjType::Tree)),
+            "blob" => self.peel(oid, Some(ObjType::Blob)),
+            "tag" => self.peel(oid, Some(ObjType::Tag)),
+            _ => Err(bad_revision(rev)),
+        }
+    }
+
+    // `rev^0` is the commit itself, `rev^1` its first parent and so on.
+    fn nth_parent(&self, rev: &str, oid: &ObjectId, n: usize) -> Result<ObjectId, GitError> {
+        let commit_id = self.peel(oid, Some(ObjType::Commit))?;
+        if n == 0 {
+            return Ok(commit_id);
+        }
+        let commit = self.read_object(&commit_id)?.parse::<Commit>()?;
+        commit
+            .parents
+            .get(n - 1)
+            .copied()
+            .ok_or_else(|| bad_revision(rev))
+    }
+
+    // Handles `rev:path`, and `:path` or `:<stage>:path` which name an entry in the index.
+    fn rev_parse_path(&self, rev: &str, path: &str) -> Result<ObjectId, GitError> {
+        let not_found =
+            || GitError::ObjectError(format!("path '{}' does not exist in '{}'", path, rev));
+        if rev.is_empty() {
+            let (stage, path) = match path.split_once(':') {
+                Some((stage, path)) if stage.len() == 1 => {
+                    (stage.parse::<u16>().map_err(|_| not_found())?, path)
+                }
+                _ => (0, path),
+            };
+            let entries = self.read_index()?;
+            return entries
+                .iter()
+                .find(|entry| entry.path == path && entry.stage() == stage)
+                .map(|entry| entry.oid)
+                .ok_or_else(not_found);
+        }
+        let tree = self.find_object(rev, &ObjType::Tree)?;
+        if path.trim_matches('/').is_empty() {
+            return Ok(tree);
+        }
+        self.lookup_path(&tree, path)?
+            .map(|entry| entry.oid)
+            .ok_or_else(not_found)
+    }
+}
+
+fn bad_revision(rev: &str) -> GitError {
+    GitError::ObjectError(format!("bad revision '{}'", rev))
+}
+
+// Pseudo refs are named in all capitals, like HEAD, FETCH_HEAD or MERGE_HEAD.
+fn is_pseudo_ref(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_uppercase() || c == '_')
+}
+
+fn find_outside_braces(s: &str, needle: char) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            c if c == needle && depth == 0 => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
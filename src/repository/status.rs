@@ -0,0 +1,266 @@
+use super::oid::ObjectId;
+use super::{GitIndex, GitRepository};
+use crate::error::GitError;
+use std::collections::{BTreeMap, BTreeSet};
+
+// A tracked path whose HEAD, index or worktree versions differ. `staged` and `unstaged` are the
+// X and Y letters of `git status --short`: ' ' unmodified, 'A' added, 'M' modified,
+// 'T' type changed, 'D' deleted.
+#[derive(Debug, Clone)]
+pub struct StatusEntry {
+    pub path: String,
+    pub staged: char,
+    pub unstaged: char,
+    pub head: Option<(u32, ObjectId)>,
+    pub index: Option<(u32, ObjectId)>,
+    pub worktree_mode: Option<u32>,
+}
+
+// A path with conflict stages in the index; `stages` holds stages 1 (base), 2 (ours), 3 (theirs).
+#[derive(Debug, Clone)]
+pub struct UnmergedEntry {
+    pub path: String,
+    pub stages: [Option<(u32, ObjectId)>; 3],
+    pub worktree_mode: Option<u32>,
+}
+
+impl UnmergedEntry {
+    // The two letter code git uses for each combination of conflict stages.
+    pub fn code(&self) -> &'static str {
+        match (
+            self.stages[0].is_some(),
+            self.stages[1].is_some(),
+            self.stages[2].is_some(),
+        ) {
+            (true, true, true) => "UU",
+            (false, true, true) => "AA",
+            (true, true, false) => "UD",
+            (true, false, true) => "DU",
+            (false, true, false) => "AU",
+            (false, false, true) => "UA",
+            _ => "DD",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self.code() {
+            "UU" => "both modified",
+            "AA" => "both added",
+            "UD" => "deleted by them",
+            "DU" => "deleted by us",
+            "AU" => "added by us",
+            "UA" => "added by them",
+            _ => "both deleted",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Status {
+    pub branch: Option<String>,
+    pub head: Option<ObjectId>,
+    pub entries: Vec<StatusEntry>,
+    pub unmerged: Vec<UnmergedEntry>,
+    pub untracked: Vec<String>,
+}
+
+impl Status {
+    pub fn has_staged(&self) -> bool {
+        self.entries.iter().any(|entry| entry.staged != ' ')
+    }
+
+    pub fn has_unstaged(&self) -> bool {
+        self.entries.iter().any(|entry| entry.unstaged != ' ')
+    }
+
+    // The `--short` and `--porcelain` (v1) format: two status letters and the path per line.
+    pub fn short_format(&self, show_branch: bool) -> String {
+        let mut out = String::new();
+        if show_branch {
+            match (&self.branch, self.head) {
+                (Some(branch), Some(_)) => out.push_str(&format!("## {}\n", branch)),
+                (Some(branch), None) => out.push_str(&format!("## No commits yet on {}\n", branch)),
+                (None, _) => out.push_str("## HEAD (no branch)\n"),
+            }
+        }
+        let mut lines: Vec<(String, String)> = self
+            .entries
+            .iter()
+            .map(|e| (e.path.clone(), format!("{}{}", e.staged, e.unstaged)))
+            .chain(
+                self.unmerged
+                    .iter()
+                    .map(|e| (e.path.clone(), e.code().to_owned())),
+            )
+            .collect();
+        lines.sort();
+        for (path, code) in lines {
+            out.push_str(&format!("{} {}\n", code, path));
+        }
+        for path in &self.untracked {
+            out.push_str(&format!("?? {}\n", path));
+        }
+        out
+    }
+
+    // The `--porcelain=v2` format, with modes and object ids for each changed path.
+    pub fn porcelain_v2(&self, show_branch: bool) -> String {
+        let mut out = String::new();
+        if show_branch {
+            match self.head {
+                Some(head) => out.push_str(&format!("# branch.oid {}\n", head)),
+                None => out.push_str("# branch.oid (initial)\n"),
+            }
+            out.push_str(&format!(
+                "# branch.head {}\n",
+                self.branch.as_deref().unwrap_or("(detached)")
+            ));
+        }
+        let zero = ObjectId::default();
+        let dot = |c: char| if c == ' ' { '.' } else { c };
+        for entry in &self.entries {
+            let (head_mode, head_oid) = entry.head.unwrap_or((0, zero));
+            let (index_mode, index_oid) = entry.index.unwrap_or((0, zero));
+            out.push_str(&format!(
+                "1 {}{} N... {:06o} {:06o} {:06o} {} {} {}\n",
+                dot(entry.staged),
+                dot(entry.unstaged),
+                head_mode,
+                index_mode,
+                entry.worktree_mode.unwrap_or(0),
+                head_oid,
+                index_oid,
+                entry.path
+            ));
+        }
+        for entry in &self.unmerged {
+            let stage = |n: usize| entry.stages[n].unwrap_or((0, zero));
+            out.push_str(&format!(
+                "u {} N... {:06o} {:06o} {:06o} {:06o} {} {} {} {}\n",
+                entry.code(),
+                stage(0).0,
+                stage(1).0,
+                stage(2).0,
+                entry.worktree_mode.unwrap_or(0),
+                stage(0).1,
+                stage(1).1,
+                stage(2).1,
+                entry.path
+            ));
+        }
+        for path in &self.untracked {
+            out.push_str(&format!("? {}\n", path));
+        }
+        out
+    }
+}
+
+impl GitRepository {
+    // Compares HEAD with the index (staged changes) and the index with the worktree (unstaged
+    // changes), and lists untracked files unless `untracked` is None. `Some(true)` lists every
+    // untracked file rather than collapsing untracked directories.
+    pub fn status(&self, untracked: Option<bool>) -> Result<Status, GitError> {
+        let head = self.resolve_ref("HEAD")?;
+        let head_entries = self.commit_tree_entries(head.as_ref())?;
+        let index = self.read_index()?;
+
+        let mut staged: BTreeMap<String, &GitIndex> = BTreeMap::new();
+        let mut conflicted: BTreeMap<String, Vec<&GitIndex>> = BTreeMap::new();
+        for entry in &index {
+            if entry.stage() == 0 {
+                staged.insert(entry.path.clone(), entry);
+            } else {
+                conflicted
+                    .entry(entry.path.clone())
+                    .or_default()
+                    .push(entry);
+            }
+        }
+
+        let mut entries = Vec::new();
+        let paths: BTreeSet<&String> = head_entries.keys().chain(staged.keys()).collect();
+        for path in paths {
+            if conflicted.contains_key(path) {
+                continue;
+            }
+            let head = head_entries.get(path).copied();
+            let index_entry = staged.get(path);
+            let index = index_entry.map(|e| (GitRepository::tree_mode(e.mode), e.oid));
+            let staged_code = change_code(head, index);
+
+            let worktree_mode = self.worktree_mode(path);
+            let unstaged_code = match index_entry {
+                None => ' ',
+                Some(_) if worktree_mode.is_none() => 'D',
+                Some(entry) => {
+                    let index_mode = GitRepository::tree_mode(entry.mode);
+                    if worktree_mode.map(|m| m & 0o170000) != Some(index_mode & 0o170000) {
+                        'T'
+                    } else if !self.worktree_matches_index(entry)? {
+                        'M'
+                    } else {
+                        ' '
+                    }
+                }
+            };
+            if staged_code != ' ' || unstaged_code != ' ' {
+                entries.push(StatusEntry {
+                    path: path.clone(),
+                    staged: staged_code,
+                    unstaged: unstaged_code,
+                    head,
+                    index,
+                    worktree_mode,
+                });
+            }
+        }
+
+        let unmerged = conflicted
+            .into_iter()
+            .map(|(path, stages)| {
+                let mut entry = UnmergedEntry {
+                    worktree_mode: self.worktree_mode(&path),
+                    path,
+                    stages: [None; 3],
+                };
+                for stage in stages {
+                    entry.stages[stage.stage() as usize - 1] =
+                        Some((GitRepository::tree_mode(stage.mode), stage.oid));
+                }
+                entry
+            })
+            .collect();
+
+        let untracked = match untracked {
+            Some(all) => {
+                let tracked: BTreeSet<String> = index.iter().map(|e| e.path.clone()).collect();
+                let mut ignores = self.ignores()?;
+                self.untracked_files(&tracked, !all, Some(&mut ignores))
+            }
+            None => Vec::new(),
+        };
+
+        Ok(Status {
+            branch: self.current_branch(),
+            head,
+            entries,
+            unmerged,
+            untracked,
+        })
+    }
+}
+
+fn change_code(from: Option<(u32, ObjectId)>, to: Option<(u32, ObjectId)>) -> char {
+    match (from, to) {
+        (None, None) => ' ',
+        (None, Some(_)) => 'A',
+        (Some(_), None) => 'D',
+        (Some((from_mode, _)), Some((to_mode, _)))
+            if from_mode & 0o170000 != to_mode & 0o170000 =>
+        {
+            'T'
+        }
+        (Some(from), Some(to)) if from != to => 'M',
+        _ => ' ',
+    }
+}
@@ -0,0 +1,196 @@
+use super::commit::{header_oid, header_string, kvlm_parse, kvlm_serialize};
+use super::ident::Ident;
+use super::object::{ObjType, Serializable, Typed};
+use super::oid::ObjectId;
+use super::refs::check_ref_name;
+use super::GitRepository;
+use crate::error::GitError;
+use crate::files::wildmatch;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+// `describe` weighs at most this many tags, the first ones found walking back from the commit.
+const MAX_CANDIDATES: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tag {
+    pub object: ObjectId,
+    pub target_type: ObjType,
+    pub tag: String,
+    // Very old tags were written without a tagger line.
+    pub tagger: Option<String>,
+    pub extra_headers: Vec<(String, Vec<u8>)>,
+    pub message: Vec<u8>,
+}
+
+impl Serializable for Tag {
+    fn serialize(&self) -> Vec<u8> {
+        let mut headers = vec![
+            ("object".to_owned(), self.object.to_hex().into_bytes()),
+            ("type".to_owned(), self.target_type.serialize()),
+            ("tag".to_owned(), self.tag.as_bytes().to_vec()),
+        ];
+        if let Some(tagger) = &self.tagger {
+            headers.push(("tagger".to_owned(), tagger.as_bytes().to_vec()));
+        }
+        headers.extend(self.extra_headers.iter().cloned());
+        kvlm_serialize(&headers, &self.message)
+    }
+
+    fn deserialize(input: &[u8]) -> Result<Self, GitError> {
+        let (headers, message) = kvlm_parse(input)?;
+        let mut object = None;
+        let mut target_type = None;
+        let mut tag = None;
+        let mut tagger = None;
+        let mut extra_headers = Vec::new();
+        for (key, value) in headers {
+            match key.as_str() {
+                "object" => object = Some(header_oid(&key, value)?),
+                "type" => target_type = Some(ObjType::deserialize(&value)?),
+                "tag" => tag = Some(header_string(&key, value)?),
+                "tagger" => tagger = Some(header_string(&key, value)?),
+                _ => extra_headers.push((key, value)),
+            }
+        }
+        let missing = |key: &str| GitError::ObjectError(format!("Invalid tag: missing {}", key));
+        Ok(Tag {
+            object: object.ok_or_else(|| missing("object"))?,
+            target_type: target_type.ok_or_else(|| missing("type"))?,
+            tag: tag.ok_or_else(|| missing("tag"))?,
+            tagger,
+            extra_headers,
+            message,
+        })
+    }
+}
+
+impl Typed for Tag {
+    const OBJ_TYPE: ObjType = ObjType::Tag;
+}
+
+impl GitRepository {
+    // Tags by short name, limited to those matching one of `patterns` if any are given.
+    pub fn list_tags(&self, patterns: &[&str]) -> Result<Vec<(String, ObjectId)>, GitError> {
+        Ok(self
+            .list_refs("refs/tags/")?
+            .into_iter()
+            .map(|(name, oid)| (name["refs/tags/".len()..].to_owned(), oid))
+            .filter(|(name, _)| {
+                patterns.is_empty() || patterns.iter().any(|p| wildmatch(p, name, false))
+            })
+            .collect())
+    }
+
+    // Tags `target`. With an annotation, a tag object carrying the tagger and message is
+    // written and the ref points to it; otherwise the ref points straight at `target`.
+    pub fn create_tag(
+        &self,
+        name: &str,
+        target: &ObjectId,
+        annotation: Option<(&Ident, &str)>,
+        force: bool,
+    ) -> Result<ObjectId, GitError> {
+        let full_name = format!("refs/tags/{}", name);
+        check_ref_name(&full_name)?;
+        if !force && self.resolve_ref(&full_name)?.is_some() {
+            return Err(GitError::GenericError(format!(
+                "tag '{}' already exists",
+                name
+            )));
+        }
+        let oid = match annotation {
+            Some((tagger, message)) => {
+                let mut message = message.trim_end().as_bytes().to_vec();
+                message.push(b'\n');
+                let tag = Tag {
+                    object: *target,
+                    target_type: self.read_object(target)?.obj_type,
+                    tag: name.to_owned(),
+                    tagger: Some(tagger.to_string()),
+                    extra_headers: Vec::new(),
+                    message,
+                };
+                GitRepository::write_object(self, &tag.to_object())?
+            }
+            None => *target,
+        };
+        self.update_ref(&full_name, &oid, None, false, "")?;
+        Ok(oid)
+    }
+
+    pub fn delete_tag(&self, name: &str) -> Result<ObjectId, GitError> {
+        let full_name = format!("refs/tags/{}", name);
+        let oid = self
+            .resolve_ref(&full_name)?
+            .ok_or_else(|| GitError::GenericError(format!("tag '{}' not found.", name)))?;
+        self.delete_ref(&full_name, Some(&oid), false)?;
+        Ok(oid)
+    }
+
+    // The tag nearest to `commit` and how many commits `commit` has that the tag does not.
+    // Only annotated tags count unless `all_tags`; None if no tag reaches the commit.
+    pub fn describe(
+        &self,
+        commit: &ObjectId,
+        all_tags: bool,
+    ) -> Result<Option<(String, usize)>, GitError> {
+        // For each tagged commit its best tag: annotated over lightweight, then the newest.
+        let mut names: HashMap<ObjectId, (bool, i64, String)> = HashMap::new();
+        for (name, oid) in self.list_tags(&[])? {
+            let object = self.read_object(&oid)?;
+            let (annotated, time) = match object.obj_type {
+                ObjType::Tag => {
+                    let tag = object.parse::<Tag>()?;
+                    let time = match &tag.tagger {
+                        Some(tagger) => Ident::parse(tagger)?.time,
+                        None => 0,
+                    };
+                    (true, time)
+                }
+                _ if all_tags => (false, 0),
+                _ => continue,
+            };
+            let target = match self.peel(&oid, Some(ObjType::Commit)) {
+                Ok(target) => target,
+                Err(_) => continue,
+            };
+            let best = names
+                .entry(target)
+                .or_insert((annotated, time, name.clone()));
+            if (annotated, time) > (best.0, best.1) {
+                *best = (annotated, time, name);
+            }
+        }
+        if let Some((_, _, name)) = names.get(commit) {
+            return Ok(Some((name.clone(), 0)));
+        }
+
+        // Walk back breadth first, taking the first tags found as candidates.
+        let mut candidates = Vec::new();
+        let mut seen = HashSet::new();
+        let mut pending = VecDeque::from([*commit]);
+        while let Some(oid) = pending.pop_front() {
+            if !seen.insert(oid) {
+                continue;
+            }
+            if let Some((_, _, name)) = names.get(&oid) {
+                candidates.push((name.clone(), oid));
+                if candidates.len() == MAX_CANDIDATES {
+                    break;
+                }
+                continue;
+            }
+            pending.extend(self.read_commit(&oid)?.parents);
+        }
+
+        let history = self.ancestors(&[*commit])?;
+        let mut best: Option<(String, usize)> = None;
+        for (name, oid) in candidates {
+            let depth = history.difference(&self.ancestors(&[oid])?).count();
+            if best.as_ref().is_none_or(|(_, best)| depth < *best) {
+                best = Some((name, depth));
+            }
+        }
+        Ok(best)
+    }
+}
@@ -0,0 +1,411 @@
+use super::oid::ObjectId;
+use super::refs::RefTarget;
+use super::upload_pack::AGENT;
+use super::GitRepository;
+use crate::error::GitError;
+use crate::http::{self, Request, Url};
+use crate::pktline::{self, Packet, BAND_DATA, BAND_ERROR, BAND_PROGRESS};
+use std::path::Path;
+
+// A ref as the other repository advertises it.
+#[derive(Debug, Clone)]
+pub struct RemoteRef {
+    pub name: String,
+    // None for an unborn HEAD.
+    pub oid: Option<ObjectId>,
+    pub symref: Option<String>,
+    // What an annotated tag points to.
+    pub peeled: Option<ObjectId>,
+}
+
+// A ref update sent by push: the old and new values and the ref, a null new value deleting it.
+pub type PushCommand = (ObjectId, ObjectId, String);
+
+// The other end of a fetch or push: a repository on this machine, or one served over smart HTTP.
+#[derive(Debug)]
+pub enum Connection {
+    Local(Box<GitRepository>),
+    Http(Url),
+}
+
+impl Connection {
+    pub fn open(url: &str) -> Result<Connection, GitError> {
+        if url.starts_with("http://") {
+            return Url::parse(url)
+                .map(Connection::Http)
+                .ok_or_else(|| GitError::GenericError(format!("Invalid URL: {}", url)));
+        }
+        if url.contains("://") && !url.starts_with("file://") {
+            return Err(GitError::GenericError(format!(
+                "Unsupported protocol in URL: {}",
+                url
+            )));
+        }
+        let path = url.strip_prefix("file://").unwrap_or(url);
+        GitRepository::open(Path::new(path)).map(|repo| Connection::Local(Box::new(repo)))
+    }
+
+    // The refs a fetch can copy, HEAD first.
+    pub fn list_refs(&self) -> Result<Vec<RemoteRef>, GitError> {
+        match self {
+            Connection::Local(repo) => local_refs(repo),
+            Connection::Http(url) => {
+                let advertisement = advertisement(url, "git-upload-pack")?;
+                if advertisement.first().and_then(Packet::text).as_deref() != Some("version 2") {
+                    return Err(GitError::GenericError(
+                        "The server does not speak protocol v2".to_owned(),
+                    ));
+                }
+                let response = rpc(
+                    url,
+                    "git-upload-pack",
+                    &["command=ls-refs", "object-format=sha1"],
+                    &["symrefs", "peel", "unborn"],
+                )?;
+                let mut input = &response[..];
+                let (lines, _) = pktline::read_section(&mut input).map_err(pktline::error)?;
+                lines
+                    .iter()
+                    .filter_map(Packet::text)
+                    .map(parse_ls_ref)
+                    .collect()
+            }
+        }
+    }
+
+    // The refs a push would update, as receive-pack advertises them.
+    pub fn push_refs(&self) -> Result<Vec<RemoteRef>, GitError> {
+        match self {
+            Connection::Local(repo) => local_refs(repo),
+            Connection::Http(url) => {
+                let mut refs = Vec::new();
+                for line in advertisement(url, "git-receive-pack")?
+                    .iter()
+                    .filter_map(Packet::text)
+                {
+                    if line == "version 1" {
+                        continue;
+                    }
+                    let line = line.split('\0').next().unwrap_or_default();
+                    let (oid, name) = line.split_once(' ').ok_or_else(|| {
+                        GitError::GenericError(format!("Invalid ref advertisement: {}", line))
+                    })?;
+                    if name != "capabilities^{}" {
+                        refs.push(RemoteRef {
+                            name: name.to_owned(),
+                            oid: Some(ObjectId::from_hex(oid)?),
+                            symref: None,
+                            peeled: None,
+                        });
+                    }
+                }
+                Ok(refs)
+            }
+        }
+    }
+
+    // Copies into `repo` what it lacks of the objects reachable from `wants`. `haves` are tips
+    // `repo` already has everything behind.
+    pub fn fetch(
+        &self,
+        repo: &GitRepository,
+        wants: &[ObjectId],
+        haves: &[ObjectId],
+    ) -> Result<(), GitError> {
+        let wants: Vec<ObjectId> = wants
+            .iter()
+            .filter(|oid| !repo.has_object(oid))
+            .copied()
+            .collect();
+        if wants.is_empty() {
+            return Ok(());
+        }
+        match self {
+            Connection::Local(source) => transfer(source, repo, &wants, haves),
+            Connection::Http(url) => {
+                let mut args: Vec<String> =
+                    wants.iter().map(|oid| format!("want {}", oid)).collect();
+                args.extend(haves.iter().map(|oid| format!("have {}", oid)));
+                args.extend(["ofs-delta".to_owned(), "done".to_owned()]);
+                let args: Vec<&str> = args.iter().map(String::as_str).collect();
+                let response = rpc(
+                    url,
+                    "git-upload-pack",
+                    &["command=fetch", "object-format=sha1"],
+                    &args,
+                )?;
+                let pack = read_packfile(&response)?;
+                let (pack, index) = repo.index_pack(pack)?;
+                repo.install_pack(&pack, &index)?;
+                Ok(())
+            }
+        }
+    }
+
+    // Sends the objects the commands need and asks the other side to apply them, returning
+    // for each command None if the ref was updated or the reason it was refused.
+    pub fn push(
+        &self,
+        repo: &GitRepository,
+        commands: &[PushCommand],
+    ) -> Result<Vec<Option<String>>, GitError> {
+        let wants: Vec<ObjectId> = commands
+            .iter()
+            .map(|(_, new, _)| *new)
+            .filter(|new| !new.is_null())
+            .collect();
+        let haves: Vec<ObjectId> = self
+            .push_refs()?
+            .into_iter()
+            .filter_map(|r| r.oid)
+            .filter(|oid| repo.has_object(oid))
+            .collect();
+        match self {
+            Connection::Local(target) => {
+                let wants: Vec<ObjectId> = wants
+                    .into_iter()
+                    .filter(|oid| !target.has_object(oid))
+                    .collect();
+                if !wants.is_empty() {
+                    transfer(repo, target, &wants, &haves)?;
+                }
+                target.apply_push(commands)
+            }
+            Connection::Http(url) => {
+                let mut body = Vec::new();
+                for (i, (old, new, name)) in commands.iter().enumerate() {
+                    let mut line = format!("{} {} {}", old, new, name);
+                    if i == 0 {
+                        line.push_str(&format!("\0report-status agent={}", AGENT));
+                    }
+                    pktline::write_line(&mut body, &line).map_err(pktline::error)?;
+                }
+                pktline::write_flush(&mut body).map_err(pktline::error)?;
+                // Only deletions go without a pack, which may be empty.
+                if !wants.is_empty() {
+                    let objects = repo.list_objects(&wants, &haves)?;
+                    body.extend(repo.build_pack(&objects)?.0);
+                }
+                let response = post(url, "git-receive-pack", body)?;
+
+                let mut input = &response[..];
+                let (lines, _) = pktline::read_section(&mut input).map_err(pktline::error)?;
+                let lines: Vec<String> = lines.iter().filter_map(Packet::text).collect();
+                match lines.first().and_then(|line| line.strip_prefix("unpack ")) {
+                    Some("ok") => {}
+                    Some(error) => {
+                        return Err(GitError::GenericError(format!(
+                            "remote unpack failed: {}",
+                            error
+                        )))
+                    }
+                    None => {
+                        return Err(GitError::GenericError(
+                            "Invalid push status from the server".to_owned(),
+                        ))
+                    }
+                }
+                let mut statuses = vec![Some("no status reported".to_owned()); commands.len()];
+                for line in &lines[1..] {
+                    let (result, rest) = line.split_once(' ').unwrap_or((line, ""));
+                    let (name, reason) = rest.split_once(' ').unwrap_or((rest, ""));
+                    if let Some(i) = commands.iter().position(|(_, _, n)| n == name) {
+                        statuses[i] = match result {
+                            "ok" => None,
+                            _ => Some(reason.to_owned()),
+                        };
+                    }
+                }
+                Ok(statuses)
+            }
+        }
+    }
+}
+
+fn local_refs(repo: &GitRepository) -> Result<Vec<RemoteRef>, GitError> {
+    let mut refs = vec![RemoteRef {
+        name: "HEAD".to_owned(),
+        oid: repo.resolve_ref("HEAD")?,
+        symref: repo.symbolic_ref("HEAD")?,
+        peeled: None,
+    }];
+    for (name, oid) in repo.list_refs("refs/")? {
+        let symref = match repo.read_ref(&name)? {
+            Some(RefTarget::Symbolic(target)) => Some(target),
+            _ => None,
+        };
+        let peeled = Some(repo.peel(&oid, None)?).filter(|peeled| *peeled != oid);
+        refs.push(RemoteRef {
+            name,
+            oid: Some(oid),
+            symref,
+            peeled,
+        });
+    }
+    Ok(refs)
+}
+
+// Parses an ls-refs line: "<oid> <name>" or "unborn <name>", then attributes.
+fn parse_ls_ref(line: String) -> Result<RemoteRef, GitError> {
+    let invalid = || GitError::GenericError(format!("Invalid ls-refs line: {}", line));
+    let mut fields = line.split(' ');
+    let oid = match fields.next().ok_or_else(invalid)? {
+        "unborn" => None,
+        oid => Some(ObjectId::from_hex(oid)?),
+    };
+    let name = fields.next().ok_or_else(invalid)?.to_owned();
+    let mut remote_ref = RemoteRef {
+        name,
+        oid,
+        symref: None,
+        peeled: None,
+    };
+    for attribute in fields {
+        if let Some(target) = attribute.strip_prefix("symref-target:") {
+            remote_ref.symref = Some(target.to_owned());
+        } else if let Some(peeled) = attribute.strip_prefix("peeled:") {
+            remote_ref.peeled = Some(ObjectId::from_hex(peeled)?);
+        }
+    }
+    Ok(remote_ref)
+}
+
+// Copies the objects reachable from `wants` but not from `haves` as a single pack built by
+// `source`.
+fn transfer(
+    source: &GitRepository,
+    target: &GitRepository,
+    wants: &[ObjectId],
+    haves: &[ObjectId],
+) -> Result<(), GitError> {
+    let haves: Vec<ObjectId> = haves
+        .iter()
+        .filter(|oid| source.has_object(oid))
+        .copied()
+        .collect();
+    let objects = source.list_objects(wants, &haves)?;
+    let (pack, _, _) = source.build_pack(&objects)?;
+    let (pack, index) = target.index_pack(pack)?;
+    target.install_pack(&pack, &index)?;
+    Ok(())
+}
+
+// The packets of GET info/refs after the "# service=" header that smart HTTP servers may add.
+fn advertisement(url: &Url, service: &str) -> Result<Vec<Packet>, GitError> {
+    let request = Request {
+        method: "GET".to_owned(),
+        target: format!("{}/info/refs?service={}", base(url), service),
+        headers: protocol_header(service),
+        body: Vec::new(),
+    };
+    let response = checked(url, http::send(url, &request))?;
+    let mut input = &response[..];
+    let (mut packets, _) = pktline::read_section(&mut input).map_err(pktline::error)?;
+    let header = packets.first().and_then(Packet::text);
+    if header.is_some_and(|line| line.starts_with("# service=")) {
+        packets = pktline::read_section(&mut input).map_err(pktline::error)?.0;
+    }
+    Ok(packets)
+}
+
+// Sends a protocol v2 command to the server and returns its response.
+fn rpc(url: &Url, service: &str, command: &[&str], args: &[&str]) -> Result<Vec<u8>, GitError> {
+    let mut body = Vec::new();
+    let agent = format!("agent={}", AGENT);
+    for line in command.iter().chain([&agent.as_str()]) {
+        pktline::write_line(&mut body, line).map_err(pktline::error)?;
+    }
+    pktline::write_delim(&mut body).map_err(pktline::error)?;
+    for line in args {
+        pktline::write_line(&mut body, line).map_err(pktline::error)?;
+    }
+    pktline::write_flush(&mut body).map_err(pktline::error)?;
+    post(url, service, body)
+}
+
+fn post(url: &Url, service: &str, body: Vec<u8>) -> Result<Vec<u8>, GitError> {
+    let mut headers = protocol_header(service);
+    headers.push((
+        "Content-Type".to_owned(),
+        format!("application/x-{}-request", service),
+    ));
+    headers.push((
+        "Accept".to_owned(),
+        format!("application/x-{}-result", service),
+    ));
+    let request = Request {
+        method: "POST".to_owned(),
+        target: format!("{}/{}", base(url), service),
+        headers,
+        body,
+    };
+    checked(url, http::send(url, &request))
+}
+
+// Fetches use protocol v2; pushes stay on v0.
+fn protocol_header(service: &str) -> Vec<(String, String)> {
+    match service {
+        "git-upload-pack" => vec![("Git-Protocol".to_owned(), "version=2".to_owned())],
+        _ => Vec::new(),
+    }
+}
+
+fn base(url: &Url) -> &str {
+    url.path.trim_end_matches('/')
+}
+
+fn checked(url: &Url, response: std::io::Result<http::Response>) -> Result<Vec<u8>, GitError> {
+    let response = response.map_err(|e| {
+        GitError::GenericError(format!("Could not reach {}:{}: {}", url.host, url.port, e))
+    })?;
+    if response.status != 200 {
+        return Err(GitError::GenericError(format!(
+            "HTTP {} from {}:{}{}: {}",
+            response.status,
+            url.host,
+            url.port,
+            url.path,
+            String::from_utf8_lossy(&response.body).trim()
+        )));
+    }
+    Ok(response.body)
+}
+
+// Takes the pack out of the side-band packets of a fetch response's packfile section, showing
+// progress and failing on errors the server sends.
+fn read_packfile(response: &[u8]) -> Result<Vec<u8>, GitError> {
+    let mut input = response;
+    let mut pack = Vec::new();
+    let mut in_packfile = false;
+    while let Some(packet) = pktline::read_packet(&mut input).map_err(pktline::error)? {
+        let data = match packet {
+            Packet::Data(data) => data,
+            _ => continue,
+        };
+        if !in_packfile {
+            let line = Packet::Data(data).text().unwrap_or_default();
+            if let Some(message) = line.strip_prefix("ERR ") {
+                return Err(GitError::GenericError(format!("remote error: {}", message)));
+            }
+            in_packfile = line == "packfile";
+            continue;
+        }
+        match data.split_first() {
+            Some((&BAND_DATA, data)) => pack.extend(data),
+            Some((&BAND_PROGRESS, message)) => {
+                eprint!("remote: {}", String::from_utf8_lossy(message))
+            }
+            Some((&BAND_ERROR, message)) => {
+                return Err(GitError::GenericError(format!(
+                    "remote error: {}",
+                    String::from_utf8_lossy(message).trim()
+                )))
+            }
+            _ => {}
+        }
+    }
+    if !in_packfile {
+        return Err(GitError::GenericError("The server sent no pack".to_owned()));
+    }
+    Ok(pack)
+}
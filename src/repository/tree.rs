@@ -0,0 +1,101 @@
+use super::object::{ObjType, Serializable, Typed};
+use super::oid::ObjectId;
+use crate::error::GitError;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeEntry {
+    pub mode: u32,
+    pub name: String,
+    pub oid: ObjectId,
+}
+
+impl TreeEntry {
+    pub fn is_tree(&self) -> bool {
+        self.mode == 0o40000
+    }
+
+    // Git orders tree entries by name, comparing directories as if they had a trailing '/'.
+    pub fn sort_key(&self) -> Vec<u8> {
+        let mut key = self.name.as_bytes().to_vec();
+        if self.is_tree() {
+            key.push(b'/');
+        }
+        key
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tree {
+    pub entries: Vec<TreeEntry>,
+}
+
+impl Tree {
+    pub fn sort(&mut self) {
+        self.entries.sort_by_key(|entry| entry.sort_key());
+    }
+
+    pub fn get(&self, name: &str) -> Option<&TreeEntry> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+}
+
+// Each entry is "<octal mode> <name>\0<20 byte hash>".
+impl Serializable for Tree {
+    fn serialize(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        for entry in &self.entries {
+            data.extend(format!("{:o} {}", entry.mode, entry.name).as_bytes());
+            data.push(b'\x00');
+            data.extend(entry.oid.as_bytes());
+        }
+        data
+    }
+
+    fn deserialize(input: &[u8]) -> Result<Self, GitError> {
+        let invalid = || GitError::ObjectError("Invalid tree object".to_owned());
+        let mut entries = Vec::new();
+        let mut i = 0;
+        while i < input.len() {
+            let space = i + input[i..]
+                .iter()
+                .position(|b| b == &b' ')
+                .ok_or_else(invalid)?;
+            let null = space
+                + input[space..]
+                    .iter()
+                    .position(|b| b == &b'\x00')
+                    .ok_or_else(invalid)?;
+            if null + 21 > input.len() {
+                return Err(invalid());
+            }
+            let mode = std::str::from_utf8(&input[i..space])
+                .ok()
+                .and_then(|mode| u32::from_str_radix(mode, 8).ok())
+                .ok_or_else(invalid)?;
+            let name = String::from_utf8(input[space + 1..null].to_vec()).map_err(|_| invalid())?;
+            if !verify_path(&name) || name.contains('/') {
+                return Err(GitError::ObjectError(format!(
+                    "Invalid tree entry name '{}'",
+                    name
+                )));
+            }
+            let oid = ObjectId::from_bytes(&input[null + 1..null + 21])?;
+            entries.push(TreeEntry { mode, name, oid });
+            i = null + 21;
+        }
+        Ok(Tree { entries })
+    }
+}
+
+impl Typed for Tree {
+    const OBJ_TYPE: ObjType = ObjType::Tree;
+}
+
+// Whether a worktree path is safe to write: no empty, "." or ".." components and nothing
+// inside ".git", compared without case as case-insensitive filesystems would.
+pub fn verify_path(path: &str) -> bool {
+    !path.contains('\0')
+        && path.split('/').all(|component| {
+            !matches!(component, "" | "." | "..") && !component.eq_ignore_ascii_case(".git")
+        })
+}
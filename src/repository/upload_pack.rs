@@ -0,0 +1,204 @@
+use super::object::ObjType;
+use super::oid::ObjectId;
+use super::refs::RefTarget;
+use super::GitRepository;
+use crate::error::GitError;
+use crate::pktline::{self, Packet, BAND_DATA, BAND_PROGRESS};
+use std::collections::HashSet;
+use std::io::{BufRead, Write};
+
+pub const AGENT: &str = concat!("git-lite/", env!("CARGO_PKG_VERSION"));
+
+impl GitRepository {
+    // Serves a protocol v2 connection to a fetching client: the capability advertisement, then
+    // ls-refs and fetch commands until the client hangs up.
+    pub fn upload_pack<R: BufRead, W: Write>(
+        &self,
+        input: &mut R,
+        output: &mut W,
+    ) -> Result<(), GitError> {
+        self.advertise_upload_pack(output)?;
+        self.serve_upload_pack(input, output, false)
+    }
+
+    pub fn advertise_upload_pack<W: Write>(&self, output: &mut W) -> Result<(), GitError> {
+        let capabilities = [
+            "version 2".to_owned(),
+            format!("agent={}", AGENT),
+            "ls-refs=unborn".to_owned(),
+            "fetch".to_owned(),
+            "object-format=sha1".to_owned(),
+        ];
+        for capability in capabilities {
+            pktline::write_line(output, &capability).map_err(pktline::error)?;
+        }
+        pktline::write_flush(output).map_err(pktline::error)?;
+        output.flush().map_err(pktline::error)
+    }
+
+    // Answers commands; `stateless` connections, as over HTTP, send only one.
+    pub fn serve_upload_pack<R: BufRead, W: Write>(
+        &self,
+        input: &mut R,
+        output: &mut W,
+        stateless: bool,
+    ) -> Result<(), GitError> {
+        loop {
+            let (request, end) = pktline::read_section(input).map_err(pktline::error)?;
+            if request.is_empty() {
+                return Ok(());
+            }
+            let lines: Vec<String> = request.iter().filter_map(Packet::text).collect();
+            let command = match lines[0].strip_prefix("command=") {
+                Some(command) => command.to_owned(),
+                None => return self.protocol_failure(output, "expected a command"),
+            };
+            let args: Vec<String> = match end {
+                Packet::Delim => {
+                    let (args, _) = pktline::read_section(input).map_err(pktline::error)?;
+                    args.iter().filter_map(Packet::text).collect()
+                }
+                _ => Vec::new(),
+            };
+            match command.as_str() {
+                "ls-refs" => self.ls_refs(&args, output)?,
+                "fetch" => self.fetch_pack(&args, output)?,
+                _ => {
+                    let message = format!("unknown command '{}'", command);
+                    return self.protocol_failure(output, &message);
+                }
+            }
+            output.flush().map_err(pktline::error)?;
+            if stateless {
+                return Ok(());
+            }
+        }
+    }
+
+    // Lists refs, HEAD first, as "<oid> <name>" with the symref targets and peeled tags the
+    // client asked for.
+    fn ls_refs<W: Write>(&self, args: &[String], output: &mut W) -> Result<(), GitError> {
+        let symrefs = args.iter().any(|arg| arg == "symrefs");
+        let peel = args.iter().any(|arg| arg == "peel");
+        let unborn = args.iter().any(|arg| arg == "unborn");
+        let prefixes: Vec<&str> = args
+            .iter()
+            .filter_map(|arg| arg.strip_prefix("ref-prefix "))
+            .collect();
+        let wanted =
+            |name: &str| prefixes.is_empty() || prefixes.iter().any(|p| name.starts_with(p));
+
+        let mut refs: Vec<(String, Option<ObjectId>)> =
+            vec![("HEAD".to_owned(), self.resolve_ref("HEAD")?)];
+        refs.extend(
+            self.list_refs("refs/")?
+                .into_iter()
+                .map(|(name, oid)| (name, Some(oid))),
+        );
+        for (name, oid) in refs {
+            if !wanted(&name) {
+                continue;
+            }
+            let mut line = match oid {
+                Some(oid) => format!("{} {}", oid, name),
+                // Only HEAD can be unborn; a client that understands it learns the branch name.
+                None if unborn => format!("unborn {}", name),
+                None => continue,
+            };
+            if symrefs {
+                if let Some(RefTarget::Symbolic(target)) = self.read_ref(&name)? {
+                    line.push_str(&format!(" symref-target:{}", target));
+                }
+            }
+            if let (true, Some(oid)) = (peel, oid) {
+                let peeled = self.peel(&oid, None)?;
+                if peeled != oid {
+                    line.push_str(&format!(" peeled:{}", peeled));
+                }
+            }
+            pktline::write_line(output, &line).map_err(pktline::error)?;
+        }
+        pktline::write_flush(output).map_err(pktline::error)
+    }
+
+    // Negotiates with the client's haves and, once the client is done or a common commit is
+    // found, sends a pack of everything the wants need that the common commits do not have.
+    fn fetch_pack<W: Write>(&self, args: &[String], output: &mut W) -> Result<(), GitError> {
+        let mut wants = Vec::new();
+        let mut common = Vec::new();
+        let mut done = false;
+        let mut include_tag = false;
+        let mut progress = true;
+        for arg in args {
+            let (name, value) = arg.split_once(' ').unwrap_or((arg, ""));
+            match name {
+                "want" => {
+                    let oid = ObjectId::from_hex(value)?;
+                    if !self.has_object(&oid) {
+                        let message = format!("upload-pack: not our ref {}", oid);
+                        return self.protocol_failure(output, &message);
+                    }
+                    wants.push(oid);
+                }
+                "have" => {
+                    let oid = ObjectId::from_hex(value)?;
+                    if self.has_object(&oid) {
+                        common.push(oid);
+                    }
+                }
+                "done" => done = true,
+                "include-tag" => include_tag = true,
+                "no-progress" => progress = false,
+                // Packs are never thin and always use offset deltas, which every client takes.
+                "thin-pack" | "ofs-delta" => {}
+                _ => {
+                    let message = format!("unexpected fetch argument '{}'", arg);
+                    return self.protocol_failure(output, &message);
+                }
+            }
+        }
+
+        if !done {
+            pktline::write_line(output, "acknowledgments").map_err(pktline::error)?;
+            if common.is_empty() {
+                pktline::write_line(output, "NAK").map_err(pktline::error)?;
+                return pktline::write_flush(output).map_err(pktline::error);
+            }
+            for oid in &common {
+                pktline::write_line(output, &format!("ACK {}", oid)).map_err(pktline::error)?;
+            }
+            pktline::write_line(output, "ready").map_err(pktline::error)?;
+            pktline::write_delim(output).map_err(pktline::error)?;
+        }
+
+        let mut objects = self.list_objects(&wants, &common)?;
+        if include_tag {
+            let sent: HashSet<ObjectId> = objects.iter().map(|(oid, _)| *oid).collect();
+            for oid in self.list_refs("refs/tags/")?.into_values() {
+                if !sent.contains(&oid)
+                    && self.read_object(&oid)?.obj_type == ObjType::Tag
+                    && sent.contains(&self.peel(&oid, None)?)
+                {
+                    objects.push((oid, String::new()));
+                }
+            }
+        }
+        let (pack, packed, deltas) = self.build_pack(&objects)?;
+
+        pktline::write_line(output, "packfile").map_err(pktline::error)?;
+        if progress {
+            let message = format!("Total {} (delta {})\n", packed.len(), deltas);
+            pktline::write_band(output, BAND_PROGRESS, message.as_bytes())
+                .map_err(pktline::error)?;
+        }
+        pktline::write_band(output, BAND_DATA, &pack).map_err(pktline::error)?;
+        pktline::write_flush(output).map_err(pktline::error)
+    }
+
+    // Tells the client why the request cannot be served, and fails.
+    fn protocol_failure<W: Write>(&self, output: &mut W, message: &str) -> Result<(), GitError> {
+        let _ =
+            pktline::write_line(output, &format!("ERR {}", message)).and_then(|_| output.flush());
+        Err(GitError::GenericError(message.to_owned()))
+    }
+}
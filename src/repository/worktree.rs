@@ -0,0 +1,295 @@
+use super::ignore::Ignores;
+use super::object::{GitObject, ObjType};
+use super::oid::ObjectId;
+use super::tree::{verify_path, Tree};
+use super::{GitIndex, GitRepository};
+use crate::error::GitError;
+use crate::files;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::os::unix::prelude::MetadataExt;
+use std::path::Path;
+
+// A flattened tree: slash separated paths of blobs (and gitlinks), mapped to their mode and id.
+pub type TreeEntries = BTreeMap<String, (u32, ObjectId)>;
+
+impl GitRepository {
+    pub fn read_tree_recursive(&self, tree: &ObjectId) -> Result<TreeEntries, GitError> {
+        let mut entries = TreeEntries::new();
+        self.collect_tree(tree, "", &mut entries)?;
+        Ok(entries)
+    }
+
+    fn collect_tree(
+        &self,
+        tree: &ObjectId,
+        prefix: &str,
+        out: &mut TreeEntries,
+    ) -> Result<(), GitError> {
+        for entry in self.read_object(tree)?.parse::<Tree>()?.entries {
+            let path = format!("{}{}", prefix, entry.name);
+            if entry.is_tree() {
+                self.collect_tree(&entry.oid, &format!("{}/", path), out)?;
+            } else {
+                out.insert(path, (entry.mode, entry.oid));
+            }
+        }
+        Ok(())
+    }
+
+    // The flattened tree of the given commit, or of HEAD when None. An unborn HEAD has no entries.
+    pub fn commit_tree_entries(&self, commit: Option<&ObjectId>) -> Result<TreeEntries, GitError> {
+        let commit = match commit {
+            Some(commit) => *commit,
+            None => match self.resolve_ref("HEAD")? {
+                Some(head) => head,
+                None => return Ok(TreeEntries::new()),
+            },
+        };
+        let tree = self.peel(&commit, Some(ObjType::Tree))?;
+        self.read_tree_recursive(&tree)
+    }
+
+    // Reads a worktree file the way it would be stored as a blob; symlinks store their target.
+    pub fn read_worktree_file(&self, path: &str) -> Result<Vec<u8>, GitError> {
+        let full_path = self.worktree.join(path);
+        let stat = fs::symlink_metadata(&full_path).map_err(|_| {
+            GitError::PathError("Could not stat file".to_owned(), full_path.clone())
+        })?;
+        if stat.file_type().is_symlink() {
+            let target = fs::read_link(&full_path).map_err(|_| {
+                GitError::PathError("Could not read link".to_owned(), full_path.clone())
+            })?;
+            return Ok(target.to_string_lossy().as_bytes().to_vec());
+        }
+        files::read_data(&full_path)
+    }
+
+    pub fn hash_worktree_file(&self, path: &str) -> Result<ObjectId, GitError> {
+        let data = self.read_worktree_file(path)?;
+        Ok(GitObject::new(ObjType::Blob, &data).id())
+    }
+
+    // Whether the worktree file still matches its index entry. Unchanged stat data is trusted,
+    // so only files whose stat data changed get rehashed.
+    pub fn worktree_matches_index(&self, entry: &GitIndex) -> Result<bool, GitError> {
+        let stat = match fs::symlink_metadata(self.worktree.join(&entry.path)) {
+            Ok(stat) => stat,
+            Err(_) => return Ok(false),
+        };
+        if GitRepository::tree_mode(stat.mode()) != GitRepository::tree_mode(entry.mode) {
+            return Ok(false);
+        }
+        if stat.size() as u32 != entry.size {
+            return Ok(false);
+        }
+        if stat.mtime() as u32 == entry.mtime_s
+            && stat.mtime_nsec() as u32 == entry.mtime_n
+            && stat.ino() as u32 == entry.ino
+        {
+            return Ok(true);
+        }
+        Ok(self.hash_worktree_file(&entry.path)? == entry.oid)
+    }
+
+    // Writes a blob into the worktree and returns an index entry with its fresh stat data.
+    pub fn checkout_entry(
+        &self,
+        path: &str,
+        mode: u32,
+        oid: &ObjectId,
+    ) -> Result<GitIndex, GitError> {
+        let full_path = self.worktree.join(path);
+        let io_error = |message: &str| GitError::PathError(message.to_owned(), full_path.clone());
+        if !verify_path(path) {
+            return Err(io_error("invalid path"));
+        }
+        if let Some(parent) = full_path.parent() {
+            // A file may be in the way of a directory the new tree needs, and a symlink must not
+            // lead the write outside the worktree.
+            let mut ancestor = parent;
+            while ancestor.starts_with(&self.worktree) && ancestor != self.worktree {
+                if ancestor.is_symlink() || ancestor.is_file() {
+                    fs::remove_file(ancestor).map_err(|_| io_error("Could not remove file"))?;
+                }
+                ancestor = ancestor.parent().unwrap();
+            }
+            fs::create_dir_all(parent).map_err(|_| io_error("Could not create directory"))?;
+        }
+        if fs::symlink_metadata(&full_path).is_ok() {
+            if full_path.is_dir() && !full_path.is_symlink() {
+                fs::remove_dir_all(&full_path)
+                    .map_err(|_| io_error("Could not remove directory"))?;
+            } else {
+                fs::remove_file(&full_path).map_err(|_| io_error("Could not remove file"))?;
+            }
+        }
+
+        let object = self.read_object(oid)?;
+        match mode {
+            0o120000 => {
+                let target = String::from_utf8_lossy(&object.data).to_string();
+                symlink(target, &full_path).map_err(|_| io_error("Could not create link"))?;
+            }
+            0o160000 => {
+                // Submodules are not supported; leave an empty directory in their place.
+                fs::create_dir_all(&full_path)
+                    .map_err(|_| io_error("Could not create directory"))?;
+            }
+            _ => {
+                fs::write(&full_path, &object.data)
+                    .map_err(|_| io_error("Could not write file"))?;
+                let permissions = if mode == 0o100755 { 0o755 } else { 0o644 };
+                fs::set_permissions(&full_path, fs::Permissions::from_mode(permissions))
+                    .map_err(|_| io_error("Could not set permissions"))?;
+            }
+        }
+        let stat = fs::symlink_metadata(&full_path).map_err(|_| io_error("Could not stat file"))?;
+        let mut entry = GitIndex::from_metadata(path, *oid, &stat);
+        entry.mode = mode;
+        Ok(entry)
+    }
+
+    // Removes a file from the worktree along with any directories left empty.
+    pub fn remove_worktree_file(&self, path: &str) -> Result<(), GitError> {
+        let full_path = self.worktree.join(path);
+        if fs::symlink_metadata(&full_path).is_ok() {
+            fs::remove_file(&full_path).map_err(|_| {
+                GitError::PathError("Could not remove file".to_owned(), full_path.clone())
+            })?;
+        }
+        let mut dir = full_path.parent();
+        while let Some(current) = dir {
+            if current == self.worktree || !current.starts_with(&self.worktree) {
+                break;
+            }
+            if fs::remove_dir(current).is_err() {
+                break;
+            }
+            dir = current.parent();
+        }
+        Ok(())
+    }
+
+    // The mode a worktree file would have in a tree, or None if there is no file at the path.
+    pub fn worktree_mode(&self, path: &str) -> Option<u32> {
+        fs::symlink_metadata(self.worktree.join(path))
+            .ok()
+            .filter(|stat| !stat.is_dir() || stat.file_type().is_symlink())
+            .map(|stat| GitRepository::tree_mode(stat.mode()))
+    }
+
+    // Whether a path exists in the worktree, without following symlinks.
+    pub fn worktree_path_exists(&self, path: &str) -> bool {
+        fs::symlink_metadata(self.worktree.join(Path::new(path))).is_ok()
+    }
+
+    // The untracked files that writing a file at `path` would destroy: a file or symlink where
+    // one of its leading directories must go, or anything inside a directory standing at `path`.
+    // `removed` holds tracked paths the update deletes anyway, which do not count.
+    pub fn untracked_in_the_way(&self, path: &str, removed: &BTreeSet<String>) -> Vec<String> {
+        let mut found = Vec::new();
+        let mut components: Vec<&str> = path.split('/').collect();
+        components.pop();
+        let mut leading = String::new();
+        for component in components {
+            leading.push_str(component);
+            match fs::symlink_metadata(self.worktree.join(&leading)) {
+                Ok(stat) if stat.is_dir() => leading.push('/'),
+                Ok(_) => {
+                    if !removed.contains(&leading) {
+                        found.push(leading);
+                    }
+                    return found;
+                }
+                Err(_) => return found,
+            }
+        }
+        let full_path = self.worktree.join(path);
+        if fs::symlink_metadata(&full_path).is_ok_and(|stat| stat.is_dir()) {
+            let prefix = format!("{}/", path);
+            self.collect_untracked(&full_path, &prefix, removed, false, &mut None, &mut found);
+        }
+        found
+    }
+
+    // Lists the worktree files that are not in the index, leaving out those `ignores` excludes.
+    // With `collapse`, a directory holding no tracked files is reported once as "dir/" instead
+    // of file by file.
+    pub fn untracked_files(
+        &self,
+        tracked: &BTreeSet<String>,
+        collapse: bool,
+        mut ignores: Option<&mut Ignores>,
+    ) -> Vec<String> {
+        let mut untracked = Vec::new();
+        self.collect_untracked(
+            &self.worktree,
+            "",
+            tracked,
+            collapse,
+            &mut ignores,
+            &mut untracked,
+        );
+        untracked
+    }
+
+    fn collect_untracked(
+        &self,
+        dir: &Path,
+        prefix: &str,
+        tracked: &BTreeSet<String>,
+        collapse: bool,
+        ignores: &mut Option<&mut Ignores>,
+        out: &mut Vec<String>,
+    ) {
+        let mut entries: Vec<_> = match fs::read_dir(dir) {
+            Ok(entries) => entries.flatten().collect(),
+            Err(_) => return,
+        };
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if prefix.is_empty() && name == ".git" {
+                continue;
+            }
+            let path = format!("{}{}", prefix, name);
+            let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+            if tracked.contains(&path) {
+                continue;
+            }
+            if let Some(ignores) = ignores.as_deref_mut() {
+                if ignores.is_ignored(&path, is_dir) {
+                    continue;
+                }
+            }
+            if !is_dir {
+                out.push(path);
+                continue;
+            }
+            let dir_prefix = format!("{}/", path);
+            let has_tracked = tracked
+                .range(dir_prefix.clone()..)
+                .next()
+                .map(|first| first.starts_with(&dir_prefix))
+                .unwrap_or(false);
+            if collapse && !has_tracked {
+                let mut inner = Vec::new();
+                self.collect_untracked(
+                    &entry.path(),
+                    &dir_prefix,
+                    tracked,
+                    false,
+                    ignores,
+                    &mut inner,
+                );
+                if !inner.is_empty() {
+                    out.push(dir_prefix);
+                }
+            } else {
+                self.collect_untracked(&entry.path(), &dir_prefix, tracked, collapse, ignores, out);
+            }
+        }
+    }
+}
@@ -5,8 +5,1611 @@ pub fn add(a: i32, b: i32) -> i32 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::diff;
+    use crate::files::wildmatch;
+    use crate::http;
+    use crate::pktline::{self, Packet};
+    use crate::repository::add::{AddChange, AddOptions};
+    use crate::repository::commit::Commit;
+    use crate::repository::config::{
+        edit_file, parse_bool, parse_int, parse_name, ConfigEdit, ConfigScope, GitConfig,
+    };
+    use crate::repository::delta::{apply_delta, create_delta};
+    use crate::repository::http_backend::serve_http;
+    use crate::repository::ident::{approxidate, parse_date, strip_crud, Ident, Role};
+    use crate::repository::ignore::IgnorePattern;
+    use crate::repository::init::InitOptions;
+    use crate::repository::merge::{FastForward, MergeResult};
+    use crate::repository::object::{GitObject, ObjType, Serializable, Typed};
+    use crate::repository::oid::ObjectId;
+    use crate::repository::pack::{encode_index, encode_pack, PackEntry, PackIndex};
+    use crate::repository::reflog::ReflogEntry;
+    use crate::repository::remote::{Refspec, UpdateStatus};
+    use crate::repository::tree::{verify_path, Tree, TreeEntry};
+    use crate::repository::{GitIndex, GitRepository};
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::ops::Deref;
+    use std::path::{Path, PathBuf};
+    use std::{fs, thread};
+
+    // A scratch directory named after the test, removed when the guard drops, even when an
+    // assertion fails halfway.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let path =
+                std::env::temp_dir().join(format!("git-lite-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            TempDir(path)
+        }
+    }
+
+    impl Deref for TempDir {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
     #[test]
     fn test_add() {
         assert_eq!(add(1, 2), 3);
     }
+
+    #[test]
+    fn test_object_round_trip() {
+        let raw = b"tree 29ff16c9c14e2652b22f8b78bb08a5a07930c147\n\
+parent 206941306e8a8af65b66eaaaea388a7ae24d49a0\n\
+parent 5a7f7e5b3c1c8d2d3f3f8a3c9e1e7d4b2c6a9f10\n\
+author Thibault Polge <thibault@thb.lt> 1527025023 +0200\n\
+committer Thibault Polge <thibault@thb.lt> 1527025044 +0200\n\
+gpgsig -----BEGIN PGP SIGNATURE-----\n \n iQIzBAABCAAdFiEExwXquOM8bWb4Q2zVGxM2FxoLkGQFAlsEjZQACgkQGxM2FxoL\n -----END PGP SIGNATURE-----\n\
+\n\
+Create first draft\n\nWith a body.\n";
+        let commit = Commit::deserialize(raw).unwrap();
+        assert_eq!(commit.parents.len(), 2);
+        assert_eq!(commit.subject(), "Create first draft");
+        assert_eq!(commit.extra_headers[0].1, "gpgsig");
+        assert_eq!(commit.serialize(), raw.to_vec());
+
+        // Unknown headers stay where they were and identities need not be UTF-8.
+        let raw = b"tree 29ff16c9c14e2652b22f8b78bb08a5a07930c147\n\
+x-early before parent\n\
+parent 206941306e8a8af65b66eaaaea388a7ae24d49a0\n\
+author J\xf6rg <j@example.com> 1527025023 +0200\n\
+x-middle between idents\n\
+committer J\xf6rg <j@example.com> 1527025044 +0200\n\
+encoding ISO-8859-1\n\
+\n\
+Gr\xfc\xdfe\n";
+        let commit = Commit::deserialize(raw).unwrap();
+        assert_eq!(commit.author_ident().unwrap().email, "j@example.com");
+        assert_eq!(commit.extra_headers[0].0, 1);
+        assert_eq!(commit.extra_headers[1].0, 3);
+        assert_eq!(commit.serialize(), raw.to_vec());
+
+        let mut raw = b"100644 a.txt\x00".to_vec();
+        raw.extend([0x11; 20]);
+        raw.extend(b"40000 dir\x00");
+        raw.extend([0x22; 20]);
+        let tree = Tree::deserialize(&raw).unwrap();
+        assert_eq!(tree.entries[1].oid.to_hex(), "22".repeat(20));
+        assert!(tree.entries[1].is_tree());
+        assert_eq!(tree.serialize(), raw);
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let old = b"fn main() {\n    a();\n    b();\n    c();\n}\n";
+        let new = b"fn main() {\n    a();\n    B();\n    c();\n}";
+        let patch = diff::unified(old, new, 1);
+        assert_eq!(
+            String::from_utf8(patch).unwrap(),
+            "@@ -2,4 +2,4 @@ fn main() {\n     a();\n-    b();\n+    B();\n     c();\n-}\n+}\n\\ No newline at end of file\n"
+        );
+    }
+
+    #[test]
+    fn test_myers_rewrite() {
+        // Every line replaced: the script must stay minimal with deletions listed first.
+        let a: Vec<String> = (0..3000).map(|i| format!("old {}", i)).collect();
+        let b: Vec<String> = (0..3000).map(|i| format!("new {}", i)).collect();
+        let edits = diff::myers(&a, &b);
+        assert_eq!(edits.len(), 6000);
+        assert!(edits[..3000]
+            .iter()
+            .all(|e| matches!(e, diff::Edit::Delete(_))));
+        assert!(edits[3000..]
+            .iter()
+            .all(|e| matches!(e, diff::Edit::Insert(_))));
+
+        let a = ["a", "b", "c", "a", "b", "b", "a"];
+        let b = ["c", "b", "a", "b", "a", "c"];
+        let edits = diff::myers(&a, &b);
+        let changes = edits
+            .iter()
+            .filter(|e| !matches!(e, diff::Edit::Equal(..)))
+            .count();
+        assert_eq!(changes, 5);
+    }
+
+    #[test]
+    fn test_merge_lines() {
+        let labels = diff::MergeLabels {
+            ours: "HEAD",
+            base: "base",
+            theirs: "topic",
+        };
+        let base = b"a\nb\nc\nd\n";
+        let (merged, conflicts) = diff::merge(
+            base,
+            b"A\nb\nc\nd\n",
+            b"a\nb\nc\nD\n",
+            &labels,
+            diff::ConflictStyle::Merge,
+        );
+        assert_eq!((merged, conflicts), (b"A\nb\nc\nD\n".to_vec(), 0));
+
+        let ours = b"a\nx\ny\nd\n";
+        let theirs = b"a\nx\nz\nd\n";
+        let (merged, conflicts) =
+            diff::merge(base, ours, theirs, &labels, diff::ConflictStyle::Merge);
+        assert_eq!(conflicts, 1);
+        assert_eq!(
+            String::from_utf8(merged).unwrap(),
+            "a\nx\n<<<<<<< HEAD\ny\n=======\nz\n>>>>>>> topic\nd\n"
+        );
+        let (merged, _) = diff::merge(base, ours, theirs, &labels, diff::ConflictStyle::Diff3);
+        assert_eq!(
+            String::from_utf8(merged).unwrap(),
+            "a\n<<<<<<< HEAD\nx\ny\n||||||| base\nb\nc\n=======\nx\nz\n>>>>>>> topic\nd\n"
+        );
+    }
+
+    #[test]
+    fn test_ident() {
+        let ident = Ident::parse("A U Thor <author@example.com> 1112911993 -0130").unwrap();
+        assert_eq!(ident.name, "A U Thor");
+        assert_eq!(ident.offset, -90);
+        assert_eq!(ident.default_date(), "Thu Apr 7 20:43:13 2005 -0130");
+        assert_eq!(
+            ident.to_string(),
+            "A U Thor <author@example.com> 1112911993 -0130"
+        );
+
+        // The formats --date and GIT_AUTHOR_DATE accept.
+        assert_eq!(parse_date("@1112911993 +0200"), Some((1112911993, 120)));
+        assert_eq!(parse_date("1112911993"), Some((1112911993, 0)));
+        assert_eq!(
+            parse_date("Thu, 07 Apr 2005 22:13:13 +0200"),
+            Some((1112904793, 120))
+        );
+        assert_eq!(
+            parse_date("2005-04-07T22:13:13-05:30"),
+            Some((1112931793, -330))
+        );
+        assert_eq!(parse_date("2005-04-07 22:13:13Z"), Some((1112911993, 0)));
+        assert_eq!(parse_date("soon"), None);
+        assert_eq!(
+            approxidate("2.days.ago", 1000000),
+            Some(1000000 - 2 * 86400)
+        );
+        assert_eq!(approxidate("1 hour 30 minutes ago", 10000), Some(4600));
+        assert_eq!(approxidate("yesterday", 86400), Some(0));
+        assert_eq!(approxidate("3 fortnights ago", 0), None);
+        assert_eq!(approxidate("9223372036854775807 years ago", 0), None);
+        assert_eq!(approxidate("9223372036854775807 seconds ago", -10), None);
+    }
+
+    #[test]
+    fn test_apply_delta() {
+        let base = b"hello, packed world\n";
+        // Sizes 20 and 17, copy 7 bytes from offset 0, insert "git ", copy 6 bytes from 14.
+        let delta = b"\x14\x11\x91\x00\x07\x04git \x91\x0e\x06";
+        assert_eq!(apply_delta(base, delta).unwrap(), b"hello, git world\n");
+        assert!(apply_delta(b"short", delta).is_err());
+        // Claimed sizes are not allocated up front, and varints past 64 bits are corrupt.
+        assert!(apply_delta(base, b"\x14\x80\x80\x80\x80\x80\x80\x80\x80\x10").is_err());
+        assert!(apply_delta(base, &[0x80; 12]).is_err());
+
+        let target = b"hello, git world\nhello, packed world\n";
+        let created = create_delta(base, target);
+        assert!(created.len() < target.len());
+        assert_eq!(apply_delta(base, &created).unwrap(), target);
+    }
+
+    #[test]
+    fn test_index_pack() {
+        let base = b"hello, packed world\n".repeat(8);
+        let mut target = base.clone();
+        target.extend(b"one more line\n");
+        let entries = vec![
+            PackEntry {
+                oid: GitObject::new(ObjType::Blob, &base).id(),
+                obj_type: ObjType::Blob,
+                data: base.clone(),
+                delta: None,
+            },
+            PackEntry {
+                oid: GitObject::new(ObjType::Blob, &target).id(),
+                obj_type: ObjType::Blob,
+                data: target.clone(),
+                delta: Some((0, create_delta(&base, &target))),
+            },
+        ];
+        let (pack, packed) = encode_pack(&entries).unwrap();
+        let checksum = ObjectId::from_bytes(&pack[pack.len() - 20..]).unwrap();
+        let repo = GitRepository::new(Path::new("/nonexistent"));
+        let (indexed, index) = repo.index_pack(pack.clone()).unwrap();
+        assert_eq!(indexed, pack);
+        assert_eq!(index, encode_index(&packed, &checksum));
+
+        let mut corrupt = pack.clone();
+        corrupt[20] ^= 0xff;
+        assert!(repo.index_pack(corrupt).is_err());
+
+        // A blob whose header claims 2^60 bytes is an error, not an allocation of that size.
+        let mut huge = b"PACK\0\0\0\x02\0\0\0\x01".to_vec();
+        huge.extend([0xb0, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x01]);
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"small").unwrap();
+        huge.extend(encoder.finish().unwrap());
+        huge.extend(ObjectId::hash(&huge).as_bytes());
+        assert!(repo.index_pack(huge).is_err());
+        let mut overlong = b"PACK\0\0\0\x02\0\0\0\x01\xb0".to_vec();
+        overlong.extend([0x80; 11]);
+        overlong.push(0x01);
+        overlong.extend(ObjectId::hash(&overlong).as_bytes());
+        assert!(repo.index_pack(overlong).is_err());
+    }
+
+    #[test]
+    fn test_pack_index_parse() {
+        let entries: Vec<PackEntry> = [&b"one\n"[..], b"two\n"]
+            .iter()
+            .map(|data| PackEntry {
+                oid: GitObject::new(ObjType::Blob, data).id(),
+                obj_type: ObjType::Blob,
+                data: data.to_vec(),
+                delta: None,
+            })
+            .collect();
+        let (pack, packed) = encode_pack(&entries).unwrap();
+        let checksum = ObjectId::from_bytes(&pack[pack.len() - 20..]).unwrap();
+        let index = encode_index(&packed, &checksum);
+        let parsed = PackIndex::parse(index.clone()).unwrap();
+        assert_eq!(parsed.len(), 2);
+        for entry in &entries {
+            assert!(parsed.find(&entry.oid).is_some());
+        }
+
+        // A fanout count that drops back down.
+        let mut unsorted = index.clone();
+        unsorted[8..12].copy_from_slice(&[0, 0, 0, 9]);
+        assert!(PackIndex::parse(unsorted).is_err());
+        // An offset pointing into a large offset table that is not there.
+        let mut large = index.clone();
+        large[8 + 256 * 4 + 2 * 24] |= 0x80;
+        assert!(PackIndex::parse(large).is_err());
+        assert!(PackIndex::parse(index[..index.len() - 1].to_vec()).is_err());
+    }
+
+    #[test]
+    fn test_refspec() {
+        let spec = Refspec::parse("+refs/heads/*:refs/remotes/origin/*").unwrap();
+        assert!(spec.force && spec.is_glob());
+        assert_eq!(
+            spec.map("refs/heads/topic/a").as_deref(),
+            Some("refs/remotes/origin/topic/a")
+        );
+        assert_eq!(spec.map("refs/tags/v1"), None);
+        assert_eq!(spec.to_string(), "+refs/heads/*:refs/remotes/origin/*");
+
+        let spec = Refspec::parse("main:refs/heads/other").unwrap();
+        assert!(!spec.force && !spec.is_glob());
+        assert_eq!(
+            spec.map("refs/heads/main").as_deref(),
+            Some("refs/heads/other")
+        );
+        assert_eq!(spec.map("refs/heads/mainline"), None);
+        assert!(Refspec::parse("refs/heads/*:refs/remotes/origin/x").is_err());
+    }
+
+    #[test]
+    fn test_pktline() {
+        let mut stream = Vec::new();
+        pktline::write_line(&mut stream, "command=ls-refs").unwrap();
+        pktline::write_delim(&mut stream).unwrap();
+        pktline::write_band(&mut stream, pktline::BAND_DATA, b"PACK").unwrap();
+        pktline::write_flush(&mut stream).unwrap();
+        assert_eq!(
+            stream,
+            b"0014command=ls-refs\n00010009\x01PACK0000".to_vec()
+        );
+
+        let mut input = &stream[..];
+        let (packets, end) = pktline::read_section(&mut input).unwrap();
+        assert_eq!(packets[0].text().as_deref(), Some("command=ls-refs"));
+        assert_eq!(end, Packet::Delim);
+        assert_eq!(
+            pktline::read_packet(&mut input).unwrap(),
+            Some(Packet::Data(b"\x01PACK".to_vec()))
+        );
+        assert_eq!(
+            pktline::read_packet(&mut input).unwrap(),
+            Some(Packet::Flush)
+        );
+        assert_eq!(pktline::read_packet(&mut input).unwrap(), None);
+        assert!(pktline::read_packet(&mut &b"zz"[..]).unwrap().is_none());
+        assert!(pktline::read_packet(&mut &b"00zz"[..]).is_err());
+    }
+
+    // Writes a commit of a single file on top of `parents`.
+    fn commit_file(repo: &GitRepository, content: &[u8], parents: Vec<ObjectId>) -> ObjectId {
+        commit_files(repo, &[("file", content)], parents)
+    }
+
+    // Commits a tree holding the given slash separated paths as regular files.
+    fn commit_files(
+        repo: &GitRepository,
+        files: &[(&str, &[u8])],
+        parents: Vec<ObjectId>,
+    ) -> ObjectId {
+        let commit = Commit {
+            tree: write_files(repo, files),
+            parents,
+            author: b"A U Thor <author@example.com> 1112911993 +0000".to_vec(),
+            committer: b"A U Thor <author@example.com> 1112911993 +0000".to_vec(),
+            extra_headers: Vec::new(),
+            message: b"change\n".to_vec(),
+        };
+        GitRepository::write_object(repo, &commit.to_object()).unwrap()
+    }
+
+    fn write_files(repo: &GitRepository, files: &[(&str, &[u8])]) -> ObjectId {
+        let mut tree = Tree::default();
+        let mut dirs: BTreeMap<&str, Vec<(&str, &[u8])>> = BTreeMap::new();
+        for (path, content) in files {
+            match path.split_once('/') {
+                Some((dir, rest)) => dirs.entry(dir).or_default().push((rest, content)),
+                None => tree.entries.push(TreeEntry {
+                    mode: 0o100644,
+                    name: path.to_string(),
+                    oid: GitRepository::write_object(repo, &GitObject::new(ObjType::Blob, content))
+                        .unwrap(),
+                }),
+            }
+        }
+        for (dir, files) in dirs {
+            tree.entries.push(TreeEntry {
+                mode: 0o40000,
+                name: dir.to_owned(),
+                oid: write_files(repo, &files),
+            });
+        }
+        tree.sort();
+        GitRepository::write_object(repo, &tree.to_object()).unwrap()
+    }
+
+    #[test]
+    fn test_http_transport() {
+        let root = TempDir::new("http");
+        let mut server = GitRepository::write_to_path(&root.join("server")).unwrap();
+        let first = commit_file(&server, b"one\n", Vec::new());
+        server
+            .update_ref("refs/heads/master", &first, None, false, "")
+            .unwrap();
+        server
+            .config
+            .set("http", None, "receivepack", "true")
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/server", listener.local_addr().unwrap());
+        let served = root.to_path_buf();
+        thread::spawn(move || serve_http(listener, served));
+
+        let mut client = GitRepository::write_to_path(&root.join("client")).unwrap();
+        let (branch, head) = client.clone_remote(&url).unwrap();
+        assert_eq!((branch.as_str(), head), ("master", Some(first)));
+        assert!(client.has_object(&first));
+
+        let second = commit_file(&client, b"two\n", vec![first]);
+        client
+            .update_ref("refs/heads/topic", &second, None, false, "")
+            .unwrap();
+        let remote = client.remote("origin").unwrap();
+        let updates = client
+            .push(&remote, &[Refspec::parse("topic").unwrap()], false)
+            .unwrap();
+        assert_eq!(updates[0].status, UpdateStatus::New);
+        assert_eq!(
+            server.resolve_ref("refs/heads/topic").unwrap(),
+            Some(second)
+        );
+        assert_eq!(
+            client.resolve_ref("refs/remotes/origin/topic").unwrap(),
+            Some(second)
+        );
+
+        // The server's worktree has master checked out.
+        let updates = client
+            .push(&remote, &[Refspec::parse("topic:master").unwrap()], false)
+            .unwrap();
+        assert_eq!(
+            updates[0].status,
+            UpdateStatus::RemoteRejected("branch is currently checked out".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_http_body_limits() {
+        let request = |head: &str, body: &[u8]| {
+            let mut input = head.as_bytes().to_vec();
+            input.extend_from_slice(body);
+            http::read_request(&mut &input[..])
+        };
+        let body = request("POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\n", b"hello")
+            .unwrap()
+            .unwrap()
+            .body;
+        assert_eq!(body, b"hello");
+        let chunked = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n";
+        let body = request(chunked, b"3\r\nhel\r\n2\r\nlo\r\n0\r\n\r\n")
+            .unwrap()
+            .unwrap()
+            .body;
+        assert_eq!(body, b"hello");
+        // Claimed sizes are neither trusted nor allocated up front.
+        assert!(request("POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\n", b"short").is_err());
+        let huge = "POST / HTTP/1.1\r\nContent-Length: 1099511627776\r\n\r\n";
+        assert!(request(huge, b"").is_err());
+        assert!(request(chunked, b"ffffffffffff\r\nhello").is_err());
+    }
+
+    #[test]
+    fn test_reachability() {
+        let root = TempDir::new("graph");
+        let repo = GitRepository::write_to_path(&root).unwrap();
+        let base = commit_file(&repo, b"base\n", Vec::new());
+        let main = commit_file(&repo, b"main\n", vec![base]);
+        let topic = commit_file(&repo, b"topic 1\n", vec![base]);
+        let topic = commit_file(&repo, b"topic 2\n", vec![topic]);
+
+        assert!(repo.is_ancestor(&base, &topic).unwrap());
+        assert!(!repo.is_ancestor(&main, &topic).unwrap());
+        assert_eq!(repo.merge_bases(&main, &topic).unwrap(), vec![base]);
+        assert_eq!(repo.ahead_behind(&topic, &main).unwrap(), (2, 1));
+        assert_eq!(repo.ahead_behind(&base, &main).unwrap(), (0, 1));
+
+        let merge = commit_file(&repo, b"merged\n", vec![main, topic]);
+        assert_eq!(repo.merge_bases(&merge, &topic).unwrap(), vec![topic]);
+        assert_eq!(
+            repo.octopus_bases(&[merge, main, topic]).unwrap(),
+            vec![base]
+        );
+    }
+
+    #[test]
+    fn test_describe() {
+        let root = TempDir::new("tag");
+        let repo = GitRepository::write_to_path(&root).unwrap();
+        let first = commit_file(&repo, b"one\n", Vec::new());
+        let second = commit_file(&repo, b"two\n", vec![first]);
+        let third = commit_file(&repo, b"three\n", vec![second]);
+        let tagger = Ident::parse("A U Thor <author@example.com> 1112911993 +0000").unwrap();
+
+        repo.create_tag("v1", &first, Some((&tagger, "one")), false)
+            .unwrap();
+        repo.create_tag("light", &second, None, false).unwrap();
+        assert!(repo.create_tag("v1", &third, None, false).is_err());
+        assert_eq!(
+            repo.peel(&repo.rev_parse("v1").unwrap(), None).unwrap(),
+            first
+        );
+        assert_eq!(
+            repo.describe(&third, false).unwrap(),
+            Some(("v1".to_owned(), 2))
+        );
+        assert_eq!(
+            repo.describe(&third, true).unwrap(),
+            Some(("light".to_owned(), 1))
+        );
+        assert_eq!(repo.list_tags(&["v*"]).unwrap().len(), 1);
+
+        assert_eq!(repo.delete_tag("light").unwrap(), second);
+        assert_eq!(
+            repo.describe(&first, true).unwrap(),
+            Some(("v1".to_owned(), 0))
+        );
+    }
+
+    #[test]
+    fn test_wildmatch() {
+        assert!(wildmatch("v1.*", "v1.2", false));
+        assert!(!wildmatch("v1.?", "v1.10", false));
+        assert!(wildmatch("[!a-c]x[[:digit:]]", "dx5", false));
+        assert!(wildmatch("a\\*", "a*", false));
+        assert!(wildmatch("*.md", "docs/a.md", false));
+        assert!(!wildmatch("*.md", "docs/a.md", true));
+        assert!(wildmatch("**/a.md", "docs/a.md", true));
+        assert!(wildmatch("docs/**", "docs/x/a.md", true));
+        assert!(wildmatch("a/**/b", "a/b", true));
+        assert!(wildmatch("a/**/b", "a/x/y/b", true));
+        assert!(!wildmatch("a/**/b", "a/x/c", true));
+    }
+
+    #[test]
+    fn test_reflog() {
+        let root = TempDir::new("reflog");
+        let repo = GitRepository::write_to_path(&root).unwrap();
+        let first = commit_file(&repo, b"one\n", Vec::new());
+        let second = commit_file(&repo, b"two\n", vec![first]);
+        let line = format!(
+            "{} {} A U Thor <author@example.com> 1112911993 +0000\tcommit: two\n",
+            first, second
+        );
+        assert_eq!(ReflogEntry::parse(line.trim_end()).unwrap().to_line(), line);
+
+        repo.update_ref("HEAD", &first, None, true, "commit (initial): one")
+            .unwrap();
+        repo.update_ref(
+            "refs/heads/master",
+            &second,
+            Some(&first),
+            false,
+            "commit: two",
+        )
+        .unwrap();
+        // HEAD points to master, so both logs record the update.
+        for name in ["HEAD", "refs/heads/master"] {
+            let entries = repo.read_reflog(name).unwrap();
+            assert_eq!(entries.len(), 2);
+            assert!(entries[0].old.is_null());
+            assert_eq!((entries[1].old, entries[1].new), (first, second));
+            assert_eq!(entries[1].message, "commit: two");
+        }
+        assert_eq!(repo.rev_parse("HEAD@{1}").unwrap(), first);
+        assert_eq!(repo.rev_parse("master@{0}").unwrap(), second);
+        assert!(repo.rev_parse("HEAD@{2}").is_err());
+
+        let dropped = repo
+            .expire_reflog("HEAD", i64::MAX, i64::MIN, false)
+            .unwrap();
+        assert_eq!(dropped.len(), 2);
+        assert!(repo.read_reflog("HEAD").unwrap().is_empty());
+        repo.delete_ref("refs/heads/master", None, false).unwrap();
+        assert!(!repo.has_reflog("refs/heads/master"));
+    }
+
+    #[test]
+    fn test_config() {
+        assert_eq!(parse_int("2k"), Some(2048));
+        assert_eq!(parse_int("1G"), Some(1 << 30));
+        assert_eq!(parse_int("x"), None);
+        assert_eq!(parse_bool(None), Some(true));
+        assert_eq!(parse_bool(Some("Off")), Some(false));
+        assert_eq!(parse_bool(Some("maybe")), None);
+        assert_eq!(
+            parse_name("Remote.Origin.url").unwrap(),
+            (
+                "remote".to_owned(),
+                Some("Origin".to_owned()),
+                "url".to_owned()
+            )
+        );
+        assert!(parse_name("nokey").is_err());
+
+        let root = TempDir::new("config");
+        let repo = GitRepository::write_to_path(&root).unwrap();
+        let config_path = repo.gitdir.join("config");
+        let text =
+            "[remote \"Origin\"] # comment\n\turl = \"a b \" ; c\n\tfetch = x\n\tfetch = y\n\
+            [Old.Style]\n\tflag\n\tlong = one \\\n  two\n\
+            [include]\n\tpath = extra\n\
+            [includeIf \"gitdir:**/\"]\n\tpath = matched\n\
+            [includeIf \"gitdir:/nowhere/\"]\n\tpath = unmatched\n";
+        fs::write(&config_path, text).unwrap();
+        fs::write(repo.gitdir.join("extra"), "[test]\n\tvalue = extra\n").unwrap();
+        fs::write(repo.gitdir.join("matched"), "[test]\n\tother = 3m\n").unwrap();
+        fs::write(repo.gitdir.join("unmatched"), "[test]\n\tvalue = wrong\n").unwrap();
+
+        let config = GitConfig::load(Some(&repo.gitdir)).unwrap();
+        assert_eq!(config.get("remote", Some("Origin"), "URL").unwrap(), "a b ");
+        assert!(config.get("remote", Some("origin"), "url").is_none());
+        let fetch: Vec<_> = config.get_all("remote", Some("Origin"), "fetch");
+        assert_eq!(fetch.len(), 2);
+        assert_eq!(config.get("remote", Some("Origin"), "fetch").unwrap(), "y");
+        assert_eq!(config.get_bool("old", Some("style"), "flag"), Some(true));
+        assert_eq!(
+            config.get("old", Some("style"), "long").unwrap(),
+            "one   two"
+        );
+        assert_eq!(config.get("test", None, "value").unwrap(), "extra");
+        assert_eq!(config.get_int("test", None, "other"), Some(3 << 20));
+        let local =
+            GitConfig::load_files(std::slice::from_ref(&config_path), ConfigScope::Local, None)
+                .unwrap();
+        assert!(local.get("test", None, "value").is_none());
+
+        // Edits keep the rest of the file as it was.
+        assert!(edit_file(
+            &config_path,
+            "remote",
+            Some("Origin"),
+            "fetch",
+            ConfigEdit::Set("z")
+        )
+        .is_err());
+        edit_file(
+            &config_path,
+            "remote",
+            Some("Origin"),
+            "fetch",
+            ConfigEdit::Add("z"),
+        )
+        .unwrap();
+        edit_file(
+            &config_path,
+            "old",
+            Some("style"),
+            "flag",
+            ConfigEdit::Unset,
+        )
+        .unwrap();
+        edit_file(&config_path, "new", None, "key", ConfigEdit::Set("#x")).unwrap();
+        let edited = fs::read_to_string(&config_path).unwrap();
+        assert!(edited.starts_with("[remote \"Origin\"] # comment\n"));
+        assert!(edited.contains("\tfetch = y\n\tfetch = z\n[Old.Style]\n\tlong"));
+        assert!(edited.ends_with("[new]\n\tkey = \"#x\"\n"));
+        assert_eq!(
+            edit_file(
+                &config_path,
+                "remote",
+                Some("Origin"),
+                "fetch",
+                ConfigEdit::UnsetAll
+            )
+            .unwrap(),
+            3
+        );
+
+        let mut repo = repo;
+        repo.config
+            .rename_section("remote", Some("Origin"), Some("up"))
+            .unwrap();
+        assert_eq!(
+            repo.config.get("remote", Some("up"), "url").unwrap(),
+            "a b "
+        );
+        fs::write(&config_path, "[broken\n").unwrap();
+        assert!(GitConfig::load(Some(&repo.gitdir)).is_err());
+    }
+
+    #[test]
+    fn test_gitfile() {
+        let root = TempDir::new("gitfile");
+        let repo = GitRepository::write_to_path(&root.join("main")).unwrap();
+        let oid = commit_file(&repo, b"one\n", Vec::new());
+        repo.update_ref("HEAD", &oid, None, true, "").unwrap();
+
+        // A worktree whose .git file points at the repository elsewhere.
+        fs::create_dir_all(root.join("linked")).unwrap();
+        fs::write(root.join("linked/.git"), "gitdir: ../main/.git\n").unwrap();
+        let linked = GitRepository::open(&root.join("linked")).unwrap();
+        assert!(!linked.is_bare());
+        assert_eq!(linked.resolve_ref("HEAD").unwrap(), Some(oid));
+        fs::write(root.join("linked/f"), "data\n").unwrap();
+        linked
+            .add_git(
+                &[root.join("linked/f").display().to_string()],
+                &AddOptions::default(),
+            )
+            .unwrap();
+        let index = repo.read_index().unwrap();
+        assert_eq!(index.len(), 1);
+        assert_eq!(index[0].path, "f");
+        assert!(linked
+            .add_git(&[root.display().to_string()], &AddOptions::default())
+            .is_err());
+
+        fs::write(root.join("linked/.git"), "nonsense\n").unwrap();
+        assert!(GitRepository::open(&root.join("linked")).is_err());
+    }
+
+    #[test]
+    fn test_init() {
+        let root = TempDir::new("init");
+        fs::create_dir_all(root.join("template/hooks")).unwrap();
+        fs::write(root.join("template/hooks/pre-commit"), "#!/bin/sh\n").unwrap();
+        fs::write(root.join("template/description"), "from template\n").unwrap();
+
+        let options = InitOptions {
+            bare: true,
+            initial_branch: Some("main".to_owned()),
+            template: Some(root.join("template")),
+            ..Default::default()
+        };
+        let (repo, reinit) = GitRepository::init(&root.join("bare.git"), &options).unwrap();
+        assert!(!reinit);
+        assert!(repo.is_bare());
+        assert_eq!(repo.config.get_bool("core", None, "bare"), Some(true));
+        assert_eq!(
+            repo.symbolic_ref("HEAD").unwrap().as_deref(),
+            Some("refs/heads/main")
+        );
+        assert!(repo.gitdir.join("hooks/pre-commit").is_file());
+        assert_eq!(
+            fs::read_to_string(repo.gitdir.join("description")).unwrap(),
+            "from template\n"
+        );
+
+        // Reinitializing keeps the refs, HEAD and config.
+        let mut repo = GitRepository::write_to_path(&root.join("work")).unwrap();
+        let oid = commit_file(&repo, b"one\n", Vec::new());
+        repo.update_ref("HEAD", &oid, None, true, "").unwrap();
+        repo.config.set("user", None, "name", "Someone").unwrap();
+        let options = InitOptions {
+            initial_branch: Some("other".to_owned()),
+            ..Default::default()
+        };
+        let (repo, reinit) = GitRepository::init(&root.join("work"), &options).unwrap();
+        assert!(reinit);
+        assert_eq!(repo.resolve_ref("HEAD").unwrap(), Some(oid));
+        assert_eq!(repo.config.get("user", None, "name").unwrap(), "Someone");
+
+        let options = InitOptions {
+            initial_branch: Some("a..b".to_owned()),
+            ..Default::default()
+        };
+        assert!(GitRepository::init(&root.join("bad"), &options).is_err());
+        assert!(!root.join("bad").exists());
+    }
+
+    #[test]
+    fn test_ignore() {
+        let pattern = IgnorePattern::parse("!/build/  ", "sub/", ".gitignore", 3).unwrap();
+        assert!(pattern.negated && pattern.dir_only && pattern.anchored);
+        assert_eq!(pattern.pattern, "build");
+        assert!(pattern.matches("sub/build", true));
+        assert!(!pattern.matches("sub/build", false));
+        assert!(!pattern.matches("sub/x/build", true));
+        assert!(IgnorePattern::parse("# comment", "", "", 1).is_none());
+        assert_eq!(
+            IgnorePattern::parse("\\#hash", "", "", 1).unwrap().pattern,
+            "#hash"
+        );
+        let globstar = IgnorePattern::parse("a/**/z", "", "", 1).unwrap();
+        assert!(globstar.matches("a/z", false) && globstar.matches("a/b/c/z", false));
+        let basename = IgnorePattern::parse("*.o", "", "", 1).unwrap();
+        assert!(basename.matches("deep/dir/x.o", false));
+
+        let root = TempDir::new("ignore");
+        let repo = GitRepository::write_to_path(&root).unwrap();
+        fs::write(root.join(".gitignore"), "*.o\n!keep.o\nbuild/\n").unwrap();
+        fs::write(root.join(".git/info/exclude"), "*.swp\n").unwrap();
+        fs::create_dir_all(root.join("src/build")).unwrap();
+        fs::write(root.join("src/.gitignore"), "!*.o\n").unwrap();
+        for file in [
+            "a.o",
+            "keep.o",
+            "f.swp",
+            "src/b.o",
+            "src/build/keep.o",
+            "src/c",
+        ] {
+            fs::write(root.join(file), "x\n").unwrap();
+        }
+
+        let mut ignores = repo.ignores().unwrap();
+        assert!(ignores.is_ignored("a.o", false));
+        assert!(!ignores.is_ignored("keep.o", false));
+        assert!(ignores.is_ignored("f.swp", false));
+        // A deeper .gitignore overrides, but nothing re-includes files of an excluded directory.
+        assert!(!ignores.is_ignored("src/b.o", false));
+        assert!(ignores.is_ignored("src/build/keep.o", false));
+        assert_eq!(ignores.matched("a.o", false).unwrap().line, 1);
+
+        let untracked = repo.untracked_files(&BTreeSet::new(), false, Some(&mut ignores));
+        assert_eq!(
+            untracked,
+            vec![".gitignore", "keep.o", "src/.gitignore", "src/b.o", "src/c"]
+        );
+    }
+
+    #[test]
+    fn test_add_files() {
+        let root = TempDir::new("add");
+        let repo = GitRepository::write_to_path(&root).unwrap();
+        fs::create_dir_all(root.join("src/sub")).unwrap();
+        fs::write(root.join(".gitignore"), "*.o\n").unwrap();
+        for file in ["a.c", "b.o", "src/x.c", "src/sub/y.c", "src/sub/z.h"] {
+            fs::write(root.join(file), "1\n").unwrap();
+        }
+        let at = |path: &str| root.join(path).display().to_string();
+
+        let pathspec = repo
+            .pathspec(&[
+                ":/src".to_owned(),
+                format!(":(exclude){}", at("src/sub/*.h")),
+            ])
+            .unwrap();
+        assert!(pathspec.matches("src/sub/y.c") && !pathspec.matches("src/sub/z.h"));
+        assert!(!pathspec.matches("a.c"));
+        let glob = repo
+            .pathspec(&[format!(":(glob){}", at("src/*.c"))])
+            .unwrap();
+        assert!(glob.matches("src/x.c") && !glob.matches("src/sub/y.c"));
+        assert!(repo
+            .pathspec(&[at("src/*.c")])
+            .unwrap()
+            .matches("src/sub/y.c"));
+
+        let dry_run = AddOptions {
+            dry_run: true,
+            ..Default::default()
+        };
+        let result = repo.add_git(&[at("src"), at("b.o")], &dry_run).unwrap();
+        assert_eq!(result.ignored, vec![at("b.o")]);
+        assert_eq!(result.changes.len(), 3);
+        assert!(repo.read_index().unwrap().is_empty());
+        assert!(repo.add_git(&[at("*.txt")], &dry_run).is_err());
+
+        repo.add_git(&[at("")], &AddOptions::default()).unwrap();
+        let index = repo.read_index().unwrap();
+        let paths: Vec<&str> = index.iter().map(|entry| entry.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec![".gitignore", "a.c", "src/sub/y.c", "src/sub/z.h", "src/x.c"]
+        );
+
+        // -u stages modifications and deletions but leaves new files alone.
+        fs::remove_file(root.join("a.c")).unwrap();
+        fs::write(root.join("src/x.c"), "2\n").unwrap();
+        fs::write(root.join("new.c"), "1\n").unwrap();
+        let update = AddOptions {
+            update: true,
+            ..Default::default()
+        };
+        let result = repo.add_git(&[], &update).unwrap();
+        assert_eq!(
+            result.changes,
+            vec![
+                AddChange::Remove("a.c".to_owned()),
+                AddChange::Add("src/x.c".to_owned())
+            ]
+        );
+        assert_eq!(repo.read_index().unwrap().len(), 4);
+
+        // Stage only the second of two changes, after splitting the hunk that holds both.
+        let lines: String = (1..=8).map(|n| format!("{}\n", n)).collect();
+        fs::write(root.join("src/x.c"), &lines).unwrap();
+        repo.add_git(&[at("src/x.c")], &AddOptions::default())
+            .unwrap();
+        fs::write(
+            root.join("src/x.c"),
+            lines.replace("3\n", "three\n").replace("5\n", "five\n"),
+        )
+        .unwrap();
+        let mut output = Vec::new();
+        repo.add_patch(&[at("src")], &mut "s\nn\ny\n".as_bytes(), &mut output)
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("(1/1) Stage this hunk [y,n,q,a,d,s,?]? Split into 2 hunks."));
+        assert!(output.contains("@@ -4,5 +4,5 @@\n 4\n-5\n+five\n"));
+        let staged = repo.read_index().unwrap();
+        let entry = staged.iter().find(|entry| entry.path == "src/x.c").unwrap();
+        assert_eq!(
+            repo.read_object(&entry.oid).unwrap().data,
+            lines.replace("5\n", "five\n").into_bytes()
+        );
+    }
+
+    #[test]
+    fn test_verify_path() {
+        assert!(verify_path("src/main.rs") && verify_path(".gitignore"));
+        for path in [
+            "",
+            "a//b",
+            "./a",
+            "a/../../b",
+            ".git/hooks/x",
+            "sub/.GIT/config",
+            "a/",
+        ] {
+            assert!(!verify_path(path), "{}", path);
+        }
+        for name in ["..", ".git", "a/b", ""] {
+            let mut raw = format!("100644 {}\x00", name).into_bytes();
+            raw.extend([0x11; 20]);
+            assert!(Tree::deserialize(&raw).is_err(), "{}", name);
+        }
+
+        let root = TempDir::new("verify");
+        let repo = GitRepository::write_to_path(&root).unwrap();
+        let blob =
+            GitRepository::write_object(&repo, &GitObject::new(ObjType::Blob, b"x\n")).unwrap();
+        assert!(repo
+            .checkout_entry(".git/hooks/pre-commit", 0o100755, &blob)
+            .is_err());
+        assert!(repo.checkout_entry("../escape", 0o100644, &blob).is_err());
+        assert!(!root.join(".git/hooks/pre-commit").exists());
+        // A symlink in the way is replaced by a directory rather than followed.
+        std::os::unix::fs::symlink(std::env::temp_dir(), root.join("link")).unwrap();
+        repo.checkout_entry("link/f", 0o100644, &blob).unwrap();
+        assert!(root.join("link").is_dir() && !root.join("link").is_symlink());
+    }
+
+    #[test]
+    fn test_push_connectivity() {
+        let root = TempDir::new("connect");
+        let repo = GitRepository::init(
+            &root,
+            &InitOptions {
+                bare: true,
+                ..Default::default()
+            },
+        )
+        .unwrap()
+        .0;
+        let base = commit_file(&repo, b"one\n", Vec::new());
+        repo.update_ref("refs/heads/master", &base, None, true, "")
+            .unwrap();
+
+        // A commit whose blob never arrived must not become a branch tip.
+        let broken = commit_file(&repo, b"two\n", vec![base]);
+        let blob = GitObject::new(ObjType::Blob, b"two\n").id().to_hex();
+        fs::remove_file(root.join(format!("objects/{}/{}", &blob[..2], &blob[2..]))).unwrap();
+        let null = ObjectId::default();
+        let results = repo
+            .apply_push(&[
+                (null, broken, "refs/heads/broken".to_owned()),
+                (null, base, "refs/heads/copy".to_owned()),
+            ])
+            .unwrap();
+        assert_eq!(
+            results,
+            vec![Some("missing necessary objects".to_owned()), None]
+        );
+        assert_eq!(repo.resolve_ref("refs/heads/broken").unwrap(), None);
+    }
+
+    #[test]
+    fn test_ref_lock() {
+        let root = TempDir::new("reflock");
+        let repo = GitRepository::write_to_path(&root).unwrap();
+        let one = commit_file(&repo, b"one\n", Vec::new());
+        let two = commit_file(&repo, b"two\n", vec![one]);
+        let name = "refs/heads/topic";
+        repo.update_ref(name, &one, Some(&ObjectId::default()), false, "")
+            .unwrap();
+
+        // Someone else holding the lock keeps both updates and deletions out.
+        fs::write(root.join(".git/refs/heads/topic.lock"), "").unwrap();
+        assert!(repo.update_ref(name, &two, Some(&one), false, "").is_err());
+        assert!(repo.delete_ref(name, Some(&one), false).is_err());
+        fs::remove_file(root.join(".git/refs/heads/topic.lock")).unwrap();
+
+        // A failed compare gives the lock back.
+        assert!(repo.update_ref(name, &two, Some(&two), false, "").is_err());
+        assert!(!root.join(".git/refs/heads/topic.lock").exists());
+        repo.update_ref(name, &two, Some(&one), false, "").unwrap();
+        assert!(repo.delete_ref(name, Some(&one), false).is_err());
+        repo.delete_ref(name, Some(&two), false).unwrap();
+        assert_eq!(repo.resolve_ref(name).unwrap(), None);
+        assert!(!root.join(".git/refs/heads/topic.lock").exists());
+    }
+
+    #[test]
+    fn test_commit() {
+        let root = TempDir::new("commit");
+        let repo = GitRepository::write_to_path(&root).unwrap();
+        let ident = Ident {
+            name: "A U Thor".to_owned(),
+            email: "author@example.com".to_owned(),
+            time: 1112911993,
+            offset: 0,
+        };
+        fs::write(root.join("f"), "one\n").unwrap();
+        repo.add_git(
+            &[root.join("f").display().to_string()],
+            &AddOptions::default(),
+        )
+        .unwrap();
+        let first = repo.commit("first".to_owned(), &ident, &ident).unwrap();
+        assert_eq!(repo.resolve_ref("refs/heads/master").unwrap(), Some(first));
+        let commit = repo.read_object(&first).unwrap().parse::<Commit>().unwrap();
+        assert!(commit.parents.is_empty());
+        assert_eq!(commit.message, b"first\n");
+
+        // Unmerged entries make committing an error, not an exit.
+        let blob = repo.read_index().unwrap()[0].oid;
+        repo.write_index(&[
+            GitIndex::unmerged("f", 2, 0o100644, blob),
+            GitIndex::unmerged("f", 3, 0o100644, blob),
+        ])
+        .unwrap();
+        assert!(repo.commit("second".to_owned(), &ident, &ident).is_err());
+        assert_eq!(repo.resolve_ref("HEAD").unwrap(), Some(first));
+    }
+
+    #[test]
+    fn test_rev_parse() {
+        let root = TempDir::new("rev");
+        let repo = GitRepository::write_to_path(&root).unwrap();
+        let first = commit_file(&repo, b"one\n", Vec::new());
+        let second = commit_file(&repo, b"two\n", vec![first]);
+        let side = commit_file(&repo, b"side\n", vec![first]);
+        let merge = commit_file(&repo, b"merged\n", vec![second, side]);
+        repo.update_ref("HEAD", &merge, None, true, "").unwrap();
+        repo.update_ref("refs/heads/side", &side, None, false, "")
+            .unwrap();
+
+        assert_eq!(repo.rev_parse("HEAD").unwrap(), merge);
+        assert_eq!(repo.rev_parse("@").unwrap(), merge);
+        assert_eq!(repo.rev_parse("master").unwrap(), merge);
+        assert_eq!(repo.rev_parse("refs/heads/side").unwrap(), side);
+        assert_eq!(repo.rev_parse("HEAD^").unwrap(), second);
+        assert_eq!(repo.rev_parse("HEAD^2").unwrap(), side);
+        assert_eq!(repo.rev_parse("HEAD~2").unwrap(), first);
+        assert_eq!(repo.rev_parse("HEAD^2~1").unwrap(), first);
+        assert_eq!(repo.rev_parse(&merge.to_hex()[..7]).unwrap(), merge);
+        assert_eq!(repo.rev_parse(&merge.to_hex()).unwrap(), merge);
+
+        let tree = repo.rev_parse("side^{tree}").unwrap();
+        let blob = repo.rev_parse("side:file").unwrap();
+        assert_eq!(repo.lookup_path(&tree, "file").unwrap().unwrap().oid, blob);
+        assert_eq!(repo.read_object(&blob).unwrap().data, b"side\n");
+        assert_eq!(
+            repo.find_object("HEAD", &ObjType::Tree).unwrap(),
+            repo.rev_parse("HEAD^{tree}").unwrap()
+        );
+
+        assert!(repo.rev_parse("HEAD^3").is_err());
+        assert!(repo.rev_parse("HEAD~4").is_err());
+        assert!(repo.rev_parse("nosuchbranch").is_err());
+        assert!(repo.rev_parse("HEAD:missing").is_err());
+    }
+
+    #[test]
+    fn test_symbolic_ref() {
+        let root = TempDir::new("symref");
+        let repo = GitRepository::write_to_path(&root).unwrap();
+        let one = commit_file(&repo, b"one\n", Vec::new());
+        let two = commit_file(&repo, b"two\n", vec![one]);
+        assert_eq!(
+            repo.symbolic_ref("HEAD").unwrap().as_deref(),
+            Some("refs/heads/master")
+        );
+        // An unborn branch leaves HEAD symbolic but unresolved.
+        assert_eq!(repo.resolve_ref("HEAD").unwrap(), None);
+
+        repo.set_symbolic_ref("HEAD", "refs/heads/topic", None)
+            .unwrap();
+        assert_eq!(repo.current_branch().as_deref(), Some("topic"));
+        assert!(repo.set_symbolic_ref("HEAD", "master", None).is_err());
+        assert!(repo
+            .set_symbolic_ref("HEAD", "refs/heads/bad..name", None)
+            .is_err());
+
+        // Through HEAD, updates land on the branch it points to.
+        repo.update_ref("HEAD", &one, Some(&ObjectId::default()), true, "")
+            .unwrap();
+        assert_eq!(repo.resolve_ref("refs/heads/topic").unwrap(), Some(one));
+        assert!(repo
+            .update_ref("HEAD", &two, Some(&ObjectId::default()), true, "")
+            .is_err());
+        repo.update_ref("HEAD", &two, Some(&one), true, "").unwrap();
+        assert_eq!(repo.resolve_ref("refs/heads/topic").unwrap(), Some(two));
+        assert_eq!(repo.current_branch().as_deref(), Some("topic"));
+
+        // Without deref, HEAD itself is overwritten and becomes detached.
+        repo.update_ref("HEAD", &one, None, false, "").unwrap();
+        assert_eq!(repo.symbolic_ref("HEAD").unwrap(), None);
+        assert_eq!(repo.current_branch(), None);
+        assert_eq!(repo.resolve_ref("HEAD").unwrap(), Some(one));
+        assert_eq!(repo.resolve_ref("refs/heads/topic").unwrap(), Some(two));
+
+        repo.set_symbolic_ref("HEAD", "refs/heads/topic", None)
+            .unwrap();
+        repo.delete_ref("HEAD", Some(&two), true).unwrap();
+        assert_eq!(repo.resolve_ref("refs/heads/topic").unwrap(), None);
+        assert!(repo.delete_ref("refs/heads/topic", None, false).is_err());
+    }
+
+    #[test]
+    fn test_branch() {
+        let root = TempDir::new("branch");
+        let mut repo = GitRepository::write_to_path(&root).unwrap();
+        let one = commit_file(&repo, b"one\n", Vec::new());
+        let two = commit_file(&repo, b"two\n", vec![one]);
+        let side = commit_file(&repo, b"side\n", vec![one]);
+        repo.update_ref("HEAD", &two, None, true, "").unwrap();
+
+        assert_eq!(repo.create_branch("topic", "HEAD~1", false).unwrap(), one);
+        assert!(repo.create_branch("topic", "HEAD", false).is_err());
+        assert!(repo.create_branch("-topic", "HEAD", false).is_err());
+        assert!(repo.create_branch("bad..name", "HEAD", false).is_err());
+        assert!(repo.create_branch("master", "topic", true).is_err());
+        assert_eq!(repo.create_branch("topic", "HEAD", true).unwrap(), two);
+        let names: Vec<String> = repo
+            .list_branches()
+            .unwrap()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(names, ["master", "topic"]);
+
+        // Renaming carries the reflog and config along, and moves HEAD with the current branch.
+        repo.config
+            .set("branch", Some("topic"), "remote", "origin")
+            .unwrap();
+        repo.rename_branch("topic", "renamed", false).unwrap();
+        assert_eq!(repo.resolve_ref("refs/heads/topic").unwrap(), None);
+        assert_eq!(repo.resolve_ref("refs/heads/renamed").unwrap(), Some(two));
+        assert_eq!(repo.read_reflog("refs/heads/renamed").unwrap().len(), 3);
+        assert_eq!(repo.config.get("branch", Some("topic"), "remote"), None);
+        assert_eq!(
+            repo.config
+                .get("branch", Some("renamed"), "remote")
+                .as_deref(),
+            Some("origin")
+        );
+        assert!(repo.rename_branch("renamed", "master", false).is_err());
+        repo.rename_branch("master", "main", false).unwrap();
+        assert_eq!(repo.current_branch().as_deref(), Some("main"));
+
+        // Only merged branches are deleted without force, and never the current one.
+        repo.update_ref("refs/heads/side", &side, None, false, "")
+            .unwrap();
+        assert!(repo.delete_branch("side", false).is_err());
+        assert!(repo.delete_branch("main", true).is_err());
+        assert_eq!(repo.delete_branch("renamed", false).unwrap(), two);
+        assert_eq!(repo.delete_branch("side", true).unwrap(), side);
+        assert!(repo.delete_branch("side", true).is_err());
+        assert_eq!(repo.list_branches().unwrap(), [("main".to_owned(), two)]);
+    }
+
+    #[test]
+    fn test_checkout() {
+        let root = TempDir::new("checkout");
+        let repo = GitRepository::write_to_path(&root).unwrap();
+        let one = commit_file(&repo, b"one\n", Vec::new());
+        let two = commit_file(&repo, b"two\n", vec![one]);
+        repo.update_ref("refs/heads/side", &one, None, false, "")
+            .unwrap();
+        repo.update_ref("refs/heads/next", &two, None, false, "")
+            .unwrap();
+        let file = root.join("file");
+
+        assert_eq!(
+            repo.switch_to("side", false, false).unwrap(),
+            "Switched to branch 'side'"
+        );
+        assert_eq!(fs::read(&file).unwrap(), b"one\n");
+        assert_eq!(repo.read_index().unwrap()[0].path, "file");
+        assert_eq!(
+            repo.switch_to("side", false, false).unwrap(),
+            "Already on 'side'"
+        );
+        repo.switch_to("next", false, false).unwrap();
+        assert_eq!(fs::read(&file).unwrap(), b"two\n");
+        assert_eq!(repo.current_branch().as_deref(), Some("next"));
+
+        // Local changes to a path that differs between the commits stop the switch.
+        fs::write(&file, "dirty\n").unwrap();
+        assert!(repo.switch_to("side", false, false).is_err());
+        assert_eq!(fs::read(&file).unwrap(), b"dirty\n");
+        assert_eq!(repo.current_branch().as_deref(), Some("next"));
+
+        // An existing branch given to -b leaves HEAD, the index and the worktree alone.
+        assert!(repo.checkout_new_branch("side", "HEAD~1", true).is_err());
+        assert_eq!(fs::read(&file).unwrap(), b"dirty\n");
+        assert_eq!(repo.current_branch().as_deref(), Some("next"));
+        assert!(repo
+            .checkout_new_branch("bad..name", "HEAD~1", true)
+            .is_err());
+        assert_eq!(fs::read(&file).unwrap(), b"dirty\n");
+
+        assert_eq!(
+            repo.checkout_new_branch("topic", "side", true).unwrap(),
+            "Switched to a new branch 'topic'"
+        );
+        assert_eq!(fs::read(&file).unwrap(), b"one\n");
+        assert_eq!(repo.current_branch().as_deref(), Some("topic"));
+        assert_eq!(repo.resolve_ref("refs/heads/topic").unwrap(), Some(one));
+
+        let message = repo.switch_to("next", true, false).unwrap();
+        assert_eq!(message, format!("HEAD is now at {:.7} change", two));
+        assert_eq!(repo.current_branch(), None);
+        assert_eq!(repo.resolve_ref("HEAD").unwrap(), Some(two));
+        assert_eq!(fs::read(&file).unwrap(), b"two\n");
+    }
+
+    #[test]
+    fn test_checkout_untracked() {
+        let root = TempDir::new("untracked");
+        let repo = GitRepository::write_to_path(&root).unwrap();
+        let base = commit_files(&repo, &[("file", b"one\n")], Vec::new());
+        let nested = commit_files(&repo, &[("file", b"one\n"), ("u/x", b"x\n")], vec![base]);
+        let dir = commit_files(&repo, &[("file", b"one\n"), ("d/x", b"x\n")], vec![base]);
+        let file = commit_files(&repo, &[("file", b"one\n"), ("d", b"d\n")], vec![dir]);
+        for (name, oid) in [
+            ("base", base),
+            ("nested", nested),
+            ("dir", dir),
+            ("file", file),
+        ] {
+            repo.update_ref(&format!("refs/heads/{}", name), &oid, None, false, "")
+                .unwrap();
+        }
+        repo.switch_to("base", false, false).unwrap();
+
+        // An untracked file where the new tree needs a directory is kept unless forced.
+        fs::write(root.join("u"), "mine\n").unwrap();
+        assert!(repo.switch_to("nested", false, false).is_err());
+        assert_eq!(fs::read(root.join("u")).unwrap(), b"mine\n");
+        assert_eq!(repo.current_branch().as_deref(), Some("base"));
+        repo.switch_to("nested", false, true).unwrap();
+        assert_eq!(fs::read(root.join("u/x")).unwrap(), b"x\n");
+        repo.switch_to("base", false, false).unwrap();
+        assert!(!root.join("u").exists());
+
+        // So is an untracked directory where it needs a file.
+        fs::create_dir(root.join("d")).unwrap();
+        fs::write(root.join("d/mine"), "mine\n").unwrap();
+        assert!(repo.switch_to("file", false, false).is_err());
+        assert_eq!(fs::read(root.join("d/mine")).unwrap(), b"mine\n");
+        fs::remove_dir_all(root.join("d")).unwrap();
+
+        // A clean tracked directory gives way to a file and back, but not with untracked
+        // files inside it.
+        repo.switch_to("dir", false, false).unwrap();
+        repo.switch_to("file", false, false).unwrap();
+        assert_eq!(fs::read(root.join("d")).unwrap(), b"d\n");
+        repo.switch_to("dir", false, false).unwrap();
+        assert_eq!(fs::read(root.join("d/x")).unwrap(), b"x\n");
+        fs::write(root.join("d/mine"), "mine\n").unwrap();
+        assert!(repo.switch_to("file", false, false).is_err());
+        assert_eq!(fs::read(root.join("d/mine")).unwrap(), b"mine\n");
+        fs::remove_file(root.join("d/mine")).unwrap();
+
+        // A merge checks the same way before touching the worktree.
+        repo.switch_to("base", false, false).unwrap();
+        let ours = commit_files(&repo, &[("file", b"two\n")], vec![base]);
+        repo.checkout_commit(&ours, false).unwrap();
+        repo.update_ref("HEAD", &ours, None, true, "").unwrap();
+        fs::write(root.join("u"), "mine\n").unwrap();
+        let error = repo
+            .merge(
+                &nested,
+                "nested",
+                FastForward::Never,
+                diff::ConflictStyle::Merge,
+            )
+            .unwrap_err();
+        assert!(format!("{:?}", error).contains("untracked working tree files"));
+        assert_eq!(fs::read(root.join("u")).unwrap(), b"mine\n");
+        assert!(!root.join(".git/MERGE_HEAD").exists());
+    }
+
+    #[test]
+    fn test_merge_directory_file() {
+        let root = TempDir::new("dirfile");
+        let repo = GitRepository::write_to_path(&root).unwrap();
+        let base = commit_files(&repo, &[("base", b"base\n")], Vec::new());
+        let file = commit_files(&repo, &[("a", b"a\n"), ("base", b"base\n")], vec![base]);
+        let dir = commit_files(&repo, &[("a/b", b"b\n"), ("base", b"base\n")], vec![base]);
+        let stages = |repo: &GitRepository| -> Vec<(String, u16)> {
+            repo.read_index()
+                .unwrap()
+                .iter()
+                .map(|entry| (entry.path.clone(), entry.stage()))
+                .collect()
+        };
+
+        // Our file moves aside for their directory, keeping stage 2.
+        repo.checkout_commit(&file, false).unwrap();
+        repo.update_ref("HEAD", &file, None, true, "").unwrap();
+        let result = repo
+            .merge(&dir, "dir", FastForward::Never, diff::ConflictStyle::Merge)
+            .unwrap();
+        assert_eq!(
+            result,
+            MergeResult::Merged {
+                messages: vec!["CONFLICT (file/directory): directory in the way of a from HEAD; moving it to a~HEAD instead.".to_owned()],
+                conflicts: vec!["a~HEAD".to_owned()],
+            }
+        );
+        let expected = [("a/b", 0), ("a~HEAD", 2), ("base", 0)];
+        assert_eq!(
+            stages(&repo),
+            expected.map(|(path, stage)| (path.to_owned(), stage))
+        );
+        assert_eq!(fs::read(root.join("a/b")).unwrap(), b"b\n");
+        assert_eq!(fs::read(root.join("a~HEAD")).unwrap(), b"a\n");
+        repo.abort_merge().unwrap();
+        assert_eq!(fs::read(root.join("a")).unwrap(), b"a\n");
+
+        // Their file moves aside for our directory, keeping stage 3.
+        repo.checkout_commit(&dir, false).unwrap();
+        repo.update_ref("HEAD", &dir, None, true, "").unwrap();
+        repo.merge(
+            &file,
+            "file",
+            FastForward::Never,
+            diff::ConflictStyle::Merge,
+        )
+        .unwrap();
+        let expected = [("a/b", 0), ("a~file", 3), ("base", 0)];
+        assert_eq!(
+            stages(&repo),
+            expected.map(|(path, stage)| (path.to_owned(), stage))
+        );
+        assert_eq!(fs::read(root.join("a/b")).unwrap(), b"b\n");
+        assert_eq!(fs::read(root.join("a~file")).unwrap(), b"a\n");
+    }
+
+    #[test]
+    fn test_status() {
+        let root = TempDir::new("status");
+        let repo = GitRepository::write_to_path(&root).unwrap();
+        let status = repo.status(Some(false)).unwrap();
+        assert_eq!(status.short_format(true), "## No commits yet on master\n");
+        assert_eq!(
+            status.porcelain_v2(true),
+            "# branch.oid (initial)\n# branch.head master\n"
+        );
+
+        let one = commit_file(&repo, b"one\n", Vec::new());
+        repo.update_ref("refs/heads/side", &one, None, false, "")
+            .unwrap();
+        repo.switch_to("side", false, false).unwrap();
+        assert_eq!(
+            repo.status(Some(false)).unwrap().short_format(true),
+            "## side\n"
+        );
+
+        // "file" is staged and then changed again, "new" is added, the rest is untracked.
+        let add = |path: &str, content: &str| {
+            fs::create_dir_all(root.join(path).parent().unwrap()).unwrap();
+            fs::write(root.join(path), content).unwrap();
+        };
+        add("file", "two\n");
+        add("new", "new\n");
+        let paths = [root.join("file"), root.join("new")].map(|p| p.display().to_string());
+        repo.add_git(&paths, &AddOptions::default()).unwrap();
+        add("file", "three\n");
+        add("untracked", "");
+        add("dir/a", "");
+        add("dir/b", "");
+
+        let status = repo.status(Some(false)).unwrap();
+        assert!(status.has_staged() && status.has_unstaged());
+        assert_eq!(
+            status.short_format(false),
+            "MM file\nA  new\n?? dir/\n?? untracked\n"
+        );
+        let blob = |content: &[u8]| GitObject::new(ObjType::Blob, content).id();
+        assert_eq!(
+            status.porcelain_v2(false),
+            format!(
+                "1 MM N... 100644 100644 100644 {} {} file\n\
+                 1 A. N... 000000 100644 100644 {} {} new\n\
+                 ? dir/\n\
+                 ? untracked\n",
+                blob(b"one\n"),
+                blob(b"two\n"),
+                ObjectId::default(),
+                blob(b"new\n"),
+            )
+        );
+        assert_eq!(
+            repo.status(Some(true)).unwrap().untracked,
+            ["dir/a", "dir/b", "untracked"]
+        );
+        assert!(repo.status(None).unwrap().untracked.is_empty());
+
+        // A deleted file shows as unstaged, and detaching HEAD drops the branch.
+        fs::remove_file(root.join("file")).unwrap();
+        repo.update_ref("HEAD", &one, None, false, "").unwrap();
+        let status = repo.status(None).unwrap();
+        assert_eq!(
+            status.short_format(true),
+            "## HEAD (no branch)\nMD file\nA  new\n"
+        );
+    }
+
+    #[test]
+    fn test_write_tree() {
+        let root = TempDir::new("writetree");
+        let repo = GitRepository::write_to_path(&root).unwrap();
+        fs::create_dir_all(root.join("dir/sub")).unwrap();
+        for (path, content) in [
+            ("a", "a\n"),
+            ("dir/b", "b\n"),
+            ("dir/sub/c", "c\n"),
+            ("dir.txt", "t\n"),
+        ] {
+            fs::write(root.join(path), content).unwrap();
+        }
+        let mut permissions = fs::metadata(root.join("dir/b")).unwrap().permissions();
+        std::os::unix::fs::PermissionsExt::set_mode(&mut permissions, 0o755);
+        fs::set_permissions(root.join("dir/b"), permissions).unwrap();
+        repo.add_git(&[root.display().to_string()], &AddOptions::default())
+            .unwrap();
+
+        // The same tree git writes: "dir.txt" sorts before the "dir" tree, and each directory
+        // gets a tree of its own.
+        let tree = repo.write_tree().unwrap();
+        assert_eq!(tree.to_hex(), "42c068c7ec963ff5ff579453bd7f8e94a9dbdb92");
+        let names: Vec<String> = repo
+            .read_object(&tree)
+            .unwrap()
+            .parse::<Tree>()
+            .unwrap()
+            .entries
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect();
+        assert_eq!(names, ["a", "dir.txt", "dir"]);
+        let dir = repo.lookup_path(&tree, "dir").unwrap().unwrap();
+        assert_eq!(
+            (dir.mode, dir.oid.to_hex().as_str()),
+            (0o40000, "99ab93ea43949e9bc70892bcb340d869e4d542d1")
+        );
+        assert_eq!(
+            repo.lookup_path(&tree, "dir/b").unwrap().unwrap().mode,
+            0o100755
+        );
+        assert!(repo.lookup_path(&tree, "dir/sub/c").unwrap().is_some());
+    }
+
+    #[test]
+    fn test_object_id() {
+        let hex = "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391";
+        let oid = ObjectId::from_hex(hex).unwrap();
+        assert_eq!(ObjectId::hash(b"blob 0\0"), oid);
+        assert_eq!(oid.to_hex(), hex);
+        assert_eq!(ObjectId::from_hex(&hex.to_uppercase()).unwrap(), oid);
+        assert_eq!(hex.parse::<ObjectId>().unwrap(), oid);
+        assert_eq!(ObjectId::from_bytes(oid.as_bytes()).unwrap(), oid);
+        assert_eq!(ObjectId::from_array(*oid.as_bytes()), oid);
+
+        // Precision abbreviates the hex form, as in "{:.7}".
+        assert_eq!(format!("{}", oid), hex);
+        assert_eq!(format!("{:.7}", oid), "e69de29");
+        assert_eq!(format!("{:.7} x", oid), "e69de29 x");
+        assert_eq!(format!("{:?}", oid), format!("ObjectId({})", hex));
+
+        assert!(ObjectId::from_hex(&hex[..39]).is_err());
+        assert!(ObjectId::from_hex(&format!("{}0", hex)).is_err());
+        assert!(ObjectId::from_hex(&hex.replace('e', "g")).is_err());
+        assert!(ObjectId::from_bytes(&oid.as_bytes()[..19]).is_err());
+        assert!(ObjectId::default().is_null() && !oid.is_null());
+        assert_eq!(ObjectId::default().to_hex(), "0".repeat(40));
+    }
+
+    #[test]
+    fn test_repack() {
+        let root = TempDir::new("repack");
+        let repo = GitRepository::write_to_path(&root).unwrap();
+        // Growing versions of one file, so later ones pack as deltas.
+        let mut content = b"a line that is repeated in every version\n".repeat(20);
+        let mut head = commit_file(&repo, &content, Vec::new());
+        for i in 0..4 {
+            content.extend(format!("line {}\n", i).bytes());
+            head = commit_file(&repo, &content, vec![head]);
+        }
+        repo.update_ref("refs/heads/master", &head, None, false, "")
+            .unwrap();
+        let unreachable =
+            GitRepository::write_object(&repo, &GitObject::new(ObjType::Blob, b"unreachable\n"))
+                .unwrap();
+        let objects = repo.reachable_objects().unwrap();
+        assert_eq!(objects.len(), 15);
+        let before: Vec<GitObject> = objects
+            .iter()
+            .map(|(oid, _)| repo.read_object(oid).unwrap())
+            .collect();
+
+        // The built pack indexes back to the same objects.
+        let (pack, packed, deltas) = repo.build_pack(&objects).unwrap();
+        assert!(deltas > 0);
+        let checksum = ObjectId::from_bytes(&pack[pack.len() - 20..]).unwrap();
+        let (_, index) = repo.index_pack(pack).unwrap();
+        assert_eq!(index, encode_index(&packed, &checksum));
+
+        let summary = repo.repack(true, true).unwrap();
+        assert_eq!((summary.objects, summary.removed_loose), (15, 15));
+        assert!(summary.deltas > 0);
+        assert_eq!(repo.loose_object_ids().unwrap(), [unreachable]);
+        for ((oid, _), object) in objects.iter().zip(&before) {
+            let read = repo.read_object(oid).unwrap();
+            assert_eq!((read.obj_type, &read.data), (object.obj_type, &object.data));
+        }
+        assert_eq!(repo.repack(false, true).unwrap().pack, None);
+
+        // New loose objects go into a pack of their own, until everything is packed again.
+        let next = commit_file(&repo, b"next\n", vec![head]);
+        repo.update_ref("refs/heads/master", &next, None, false, "")
+            .unwrap();
+        assert_eq!(repo.repack(false, false).unwrap().objects, 3);
+        assert_eq!(repo.packs().unwrap().len(), 2);
+        let summary = repo.repack(true, true).unwrap();
+        assert_eq!((summary.objects, summary.removed_packs), (18, 2));
+        assert_eq!(repo.packs().unwrap().len(), 1);
+        assert_eq!(repo.resolve_ref("HEAD").unwrap(), Some(next));
+        assert_eq!(repo.read_object(&next).unwrap().obj_type, ObjType::Commit);
+    }
+
+    #[test]
+    fn test_ident_sources() {
+        let utc = Ident::parse("A U Thor <author@example.com> 1112911993 +0000").unwrap();
+        assert_eq!(utc.offset, 0);
+        assert_eq!(utc.offset_string(), "+0000");
+        assert_eq!(utc.iso_date(), "2005-04-07 22:13:13 +0000");
+        let behind = Ident {
+            offset: -30,
+            ..utc.clone()
+        };
+        assert_eq!(behind.offset_string(), "-0030");
+        let extra = Ident::parse("A <b> <c> 1112911993 +0100").unwrap();
+        assert_eq!((extra.name.as_str(), extra.email.as_str()), ("A", "b"));
+        assert_eq!((extra.time, extra.offset), (1112911993, 60));
+        assert_eq!(strip_crud(" \"A. U. Thor,\" "), "A. U. Thor");
+
+        // Only the author variables are touched; no other test reads them.
+        let root = TempDir::new("ident");
+        let mut repo = GitRepository::write_to_path(&root).unwrap();
+        for key in ["GIT_AUTHOR_NAME", "GIT_AUTHOR_EMAIL"] {
+            std::env::remove_var(key);
+        }
+        std::env::set_var("GIT_AUTHOR_DATE", "@1112911993 +0000");
+        repo.config.set("user", None, "name", "User").unwrap();
+        repo.config
+            .set("user", None, "email", "user@example.com")
+            .unwrap();
+        assert_eq!(
+            repo.ident(Role::Author).unwrap().to_string(),
+            "User <user@example.com> 1112911993 +0000"
+        );
+        repo.config.set("author", None, "name", "Author").unwrap();
+        assert_eq!(
+            repo.ident_name(Role::Author).unwrap(),
+            ("Author".to_owned(), "user@example.com".to_owned())
+        );
+        std::env::set_var("GIT_AUTHOR_NAME", "Env");
+        std::env::set_var("GIT_AUTHOR_EMAIL", "env@example.com");
+        assert_eq!(
+            repo.ident_name(Role::Author).unwrap(),
+            ("Env".to_owned(), "env@example.com".to_owned())
+        );
+        // Nothing from the environment can break out of the header or the email.
+        std::env::set_var("GIT_AUTHOR_NAME", "Evil>\ncommitter X <x");
+        std::env::set_var("GIT_AUTHOR_EMAIL", " <env@example.com>\n");
+        assert_eq!(
+            repo.ident_name(Role::Author).unwrap(),
+            ("Evilcommitter X x".to_owned(), "env@example.com".to_owned())
+        );
+        std::env::set_var("GIT_AUTHOR_NAME", " ");
+        assert!(repo.ident_name(Role::Author).is_err());
+        std::env::set_var("GIT_AUTHOR_NAME", "<>.");
+        assert!(repo.ident_name(Role::Author).is_err());
+        for key in ["GIT_AUTHOR_NAME", "GIT_AUTHOR_EMAIL", "GIT_AUTHOR_DATE"] {
+            std::env::remove_var(key);
+        }
+    }
 }